@@ -0,0 +1,198 @@
+//! Operational cleanup through Faktory's mutate API: purging a queue,
+//! requeuing the retry set and discarding the dead set. The submission audit
+//! log is also searched here, the configuration can be reloaded and
+//! maintenance mode switched on and off. The dead jobs recorded by workers
+//! (`/dead`) are listed and retried here too, since they carry every tenant's
+//! payloads and errors.
+//!
+//! Only callers named in `ADMIN_SUBJECTS` (API key names or JWT subjects) may
+//! use these routes, so they're unavailable without authentication.
+
+use crate::auth::Principal;
+use crate::{audit, dead, maintenance, reload};
+use crate::{faktory_client, AppState, ErrorResponse};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Path, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{delete, get, post},
+    Json, Router,
+};
+use faktory::mutate::{Filter, JobSet};
+use serde::Serialize;
+use service_config::Config;
+use std::collections::HashSet;
+use std::sync::Arc;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Who may use the admin routes
+#[derive(Debug, Clone, Default)]
+pub struct AdminConfig {
+    subjects: HashSet<String>,
+}
+
+impl AdminConfig {
+    /// Read `ADMIN_SUBJECTS` (comma-separated)
+    pub fn from_config(config: &Config) -> Self {
+        let subjects = config
+            .string("ADMIN_SUBJECTS")
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        Self { subjects }
+    }
+
+    pub fn allows(&self, principal: Option<&Principal>) -> bool {
+        principal.is_some_and(|p| self.subjects.contains(&p.subject))
+    }
+}
+
+/// Outcome of an admin action
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AdminResponse {
+    message: String,
+}
+
+/// Admin routes, each checked against `ADMIN_SUBJECTS`. Mount them where
+/// `require_auth` has already identified the caller.
+pub fn routes(state: Arc<AppState>) -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/admin/queues/{name}", delete(purge_queue_handler))
+        .route("/admin/retries/requeue", post(requeue_retries_handler))
+        .route("/admin/dead", delete(discard_dead_handler))
+        .route("/dead", get(dead::list_dead_handler))
+        .route("/dead/{jid}/retry", post(dead::retry_dead_handler))
+        .route("/admin/audit", get(audit::audit_handler))
+        .route("/admin/reload", post(reload::reload_handler))
+        .route(
+            "/admin/maintenance",
+            get(maintenance::get_maintenance_handler).put(maintenance::set_maintenance_handler),
+        )
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+async fn require_admin(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let principal = request.extensions().get::<Principal>();
+    if !state.admin_config.allows(principal) {
+        warn!(
+            "Refused admin request to {} from {}",
+            request.uri().path(),
+            principal.map_or("anonymous caller", |p| p.subject.as_str())
+        );
+        let response = ErrorResponse {
+            error: "Admin access required (caller is not in ADMIN_SUBJECTS)".to_string(),
+        };
+        return (StatusCode::FORBIDDEN, Json(response)).into_response();
+    }
+    let subject = principal.map(|p| p.subject.clone()).unwrap_or_default();
+    info!(
+        "Admin {} {} by {}",
+        request.method(),
+        request.uri().path(),
+        subject
+    );
+    next.run(request).await
+}
+
+/// DELETE /admin/queues/{name} - Remove a queue and every job waiting in it
+#[utoipa::path(
+    delete,
+    path = "/admin/queues/{name}",
+    tag = "admin",
+    params(("name" = String, Path, description = "Queue name")),
+    responses(
+        (status = 200, description = "The queue was purged", body = AdminResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 503, description = "Faktory is unreachable", body = ErrorResponse),
+    )
+)]
+pub async fn purge_queue_handler(
+    State(state): State<Arc<AppState>>,
+    Path(name): Path<String>,
+) -> Response {
+    let result = async {
+        let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+        client
+            .queue_remove(&[&name])
+            .await
+            .with_context(|| format!("Failed to purge queue {}", name))?;
+        Ok(format!("Purged queue {}", name))
+    };
+    respond(result.await)
+}
+
+/// POST /admin/retries/requeue - Move every job in the retry set back onto
+/// its queue to run now
+#[utoipa::path(
+    post,
+    path = "/admin/retries/requeue",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The retry set was requeued", body = AdminResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 503, description = "Faktory is unreachable", body = ErrorResponse),
+    )
+)]
+pub async fn requeue_retries_handler(State(state): State<Arc<AppState>>) -> Response {
+    let result = async {
+        let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+        client
+            .requeue(JobSet::Retries, Filter::empty())
+            .await
+            .context("Failed to requeue the retry set")?;
+        Ok("Requeued the retry set".to_string())
+    };
+    respond(result.await)
+}
+
+/// DELETE /admin/dead - Discard every job in the dead set
+#[utoipa::path(
+    delete,
+    path = "/admin/dead",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The dead set was discarded", body = AdminResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 503, description = "Faktory is unreachable", body = ErrorResponse),
+    )
+)]
+pub async fn discard_dead_handler(State(state): State<Arc<AppState>>) -> Response {
+    let result = async {
+        let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+        client
+            .clear(JobSet::Dead)
+            .await
+            .context("Failed to discard the dead set")?;
+        Ok("Discarded the dead set".to_string())
+    };
+    respond(result.await)
+}
+
+/// 200 with the action's message, or 503 if Faktory couldn't carry it out
+fn respond(result: Result<String>) -> Response {
+    match result {
+        Ok(message) => {
+            info!("{}", message);
+            Json(AdminResponse { message }).into_response()
+        }
+        Err(e) => {
+            warn!("Admin request failed: {:#}", e);
+            let response = ErrorResponse {
+                error: format!("{:#}", e),
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response()
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Audit log of accepted submissions.
+//!
+//! With `AUDIT_SINK` set, every job the API accepts is recorded with who
+//! submitted it, its payload and when, in an append-only JSON lines file
+//! (`file`) or the Postgres result store's `submission_audit` table
+//! (`postgres`). Admins can search the log with `GET /admin/audit`.
+
+use crate::{AppState, ErrorResponse};
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use faktory::Job;
+use job_types::REQUEST_ID_FIELD;
+use result_store::{AuditQuery, AuditRecord, ResultStore, StoreBackend, StoreConfig};
+use serde::Deserialize;
+use service_config::Config;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{error, warn};
+
+/// Audit records returned when the request doesn't say
+const DEFAULT_QUERY_LIMIT: usize = 100;
+
+/// Most audit records returned per request
+const MAX_QUERY_LIMIT: usize = 1000;
+
+/// Where audit records are written
+#[derive(Debug, Clone)]
+pub enum AuditSink {
+    File(PathBuf),
+    Postgres,
+}
+
+impl AuditSink {
+    /// Read `AUDIT_SINK` (`file` or `postgres`; off when unset) and, for
+    /// files, `AUDIT_LOG_PATH`
+    pub fn from_config(config: &Config, store_config: &StoreConfig) -> Result<Option<Self>> {
+        let Some(sink) = config.string("AUDIT_SINK") else {
+            return Ok(None);
+        };
+        match sink.as_str() {
+            "file" => {
+                let path = config
+                    .string("AUDIT_LOG_PATH")
+                    .context("AUDIT_SINK=file requires AUDIT_LOG_PATH")?;
+                Ok(Some(AuditSink::File(path.into())))
+            }
+            "postgres" => {
+                if store_config.backend != StoreBackend::Postgres {
+                    bail!("AUDIT_SINK=postgres requires RESULT_STORE=postgres");
+                }
+                Ok(Some(AuditSink::Postgres))
+            }
+            other => bail!("Invalid AUDIT_SINK {:?} (expected file or postgres)", other),
+        }
+    }
+}
+
+enum Sink {
+    File { path: PathBuf, file: Mutex<File> },
+    Store(Arc<dyn ResultStore>),
+}
+
+/// Append-only record of accepted submissions
+pub struct AuditLog {
+    sink: Sink,
+}
+
+impl AuditLog {
+    pub async fn open(sink: AuditSink, result_store: Arc<dyn ResultStore>) -> Result<Self> {
+        let sink = match sink {
+            AuditSink::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await
+                    .with_context(|| format!("Failed to open {}", path.display()))?;
+                Sink::File {
+                    path,
+                    file: Mutex::new(file),
+                }
+            }
+            AuditSink::Postgres => Sink::Store(result_store),
+        };
+        Ok(Self { sink })
+    }
+
+    /// Where records go, for the startup log
+    pub fn describe(&self) -> String {
+        match &self.sink {
+            Sink::File { path, .. } => path.display().to_string(),
+            Sink::Store(_) => "Postgres table submission_audit".to_string(),
+        }
+    }
+
+    async fn append(&self, records: &[AuditRecord]) -> Result<()> {
+        match &self.sink {
+            Sink::File { file, .. } => {
+                let mut lines = String::new();
+                for record in records {
+                    lines.push_str(&serde_json::to_string(record)?);
+                    lines.push('\n');
+                }
+                let mut file = file.lock().await;
+                file.write_all(lines.as_bytes())
+                    .await
+                    .context("Failed to write audit log")?;
+                file.sync_data().await.context("Failed to sync audit log")?;
+                Ok(())
+            }
+            Sink::Store(store) => store.append_audit(records).await,
+        }
+    }
+
+    async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>> {
+        match &self.sink {
+            Sink::File { path, .. } => {
+                let contents = fs::read_to_string(path)
+                    .await
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                let mut records = Vec::new();
+                let lines = contents.lines().rev().filter(|l| !l.trim().is_empty());
+                for line in lines {
+                    if records.len() == query.limit {
+                        break;
+                    }
+                    match serde_json::from_str::<AuditRecord>(line) {
+                        Ok(record) if query.matches(&record) => records.push(record),
+                        Ok(_) => {}
+                        // A torn final write from a crash
+                        Err(e) => warn!("Skipping unreadable audit record: {}", e),
+                    }
+                }
+                Ok(records)
+            }
+            Sink::Store(store) => store.query_audit(query).await,
+        }
+    }
+}
+
+/// A string field of `job`'s custom data
+pub fn custom_str(job: &Job, field: &str) -> Option<String> {
+    job.custom
+        .get(field)
+        .and_then(|v| v.as_str())
+        .map(String::from)
+}
+
+/// Add accepted jobs to the audit log, if it's on. Jobs are already
+/// accepted by now, so a failed write is logged rather than returned.
+pub async fn record(state: &AppState, jobs: &[Job]) {
+    let Some(audit) = &state.audit else {
+        return;
+    };
+    let submitted_at = Utc::now();
+    let records: Vec<AuditRecord> = jobs
+        .iter()
+        .map(|job| AuditRecord {
+            job_id: job.id().to_string(),
+            job_type: job.kind().to_string(),
+            payload: job.args().first().cloned().unwrap_or_default(),
+            submitted_by: custom_str(job, "submitted_by"),
+            tenant: custom_str(job, "tenant"),
+            request_id: custom_str(job, REQUEST_ID_FIELD),
+            submitted_at,
+        })
+        .collect();
+    if let Err(e) = audit.append(&records).await {
+        error!(
+            "Failed to write {} submissions to the audit log: {:#}",
+            records.len(),
+            e
+        );
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuditParams {
+    submitted_by: Option<String>,
+    job_id: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    limit: Option<usize>,
+}
+
+/// GET /admin/audit - Search the audit log of accepted submissions, newest
+/// first
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    tag = "admin",
+    params(
+        ("submitted_by" = Option<String>, Query, description = "API key name or token subject"),
+        ("job_id" = Option<String>, Query, description = "Job id"),
+        ("since" = Option<DateTime<Utc>>, Query, description = "Earliest submission time (RFC 3339), inclusive"),
+        ("until" = Option<DateTime<Utc>>, Query, description = "Latest submission time (RFC 3339), exclusive"),
+        ("limit" = Option<usize>, Query, description = "Most records to return (default 100, at most 1000)"),
+    ),
+    responses(
+        (status = 200, description = "Matching submissions", body = Vec<AuditRecord>),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 404, description = "The audit log is off", body = ErrorResponse),
+        (status = 500, description = "The audit log couldn't be read", body = ErrorResponse),
+    )
+)]
+pub async fn audit_handler(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<AuditParams>,
+) -> Response {
+    let Some(audit) = &state.audit else {
+        let response = ErrorResponse {
+            error: "The audit log is off (AUDIT_SINK is unset)".to_string(),
+        };
+        return (StatusCode::NOT_FOUND, Json(response)).into_response();
+    };
+    let query = AuditQuery {
+        submitted_by: params.submitted_by,
+        job_id: params.job_id,
+        since: params.since,
+        until: params.until,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_QUERY_LIMIT)
+            .min(MAX_QUERY_LIMIT),
+    };
+    match audit.query(&query).await {
+        Ok(records) => Json(records).into_response(),
+        Err(e) => {
+            warn!("Failed to read audit log: {:#}", e);
+            let response = ErrorResponse {
+                error: format!("Failed to read audit log: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
+        }
+    }
+}
@@ -0,0 +1,468 @@
+//! Request authentication.
+//!
+//! With `AUTH_MODE=api_key`, requests must carry an `X-API-Key` header with
+//! one of the keys configured in `API_KEYS`; the key's name identifies the
+//! caller.
+//!
+//! With `AUTH_MODE=jwt`, requests must carry an `Authorization: Bearer` JWT
+//! issued by the configured OIDC provider. Tokens are verified against the
+//! provider's JWKS (fetched on startup and refreshed when an unknown key id
+//! shows up), and the `sub` and tenant claims are attached to submitted jobs.
+//!
+//! With `AUTH_MODE=hmac`, requests must be signed with a per-client secret
+//! (see [`crate::signing`]).
+
+use crate::signing::{self, HmacVerifier};
+use crate::{AppState, ErrorResponse};
+use anyhow::{anyhow, bail, Context, Result};
+use axum::{
+    body::{to_bytes, Body},
+    extract::{Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use service_config::Config;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::RwLock;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Minimum time between JWKS refresh attempts, successful or not
+const JWKS_MIN_REFRESH: Duration = Duration::from_secs(30);
+
+/// Header carrying the caller's API key
+const API_KEY_HEADER: &str = "x-api-key";
+
+/// How requests are authenticated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMode {
+    /// No authentication (default)
+    None,
+    /// Static API keys from `API_KEYS`
+    ApiKey,
+    /// JWT bearer tokens verified against an OIDC provider's JWKS
+    Jwt,
+    /// Requests signed with per-client secrets from `HMAC_SECRETS`
+    Hmac,
+}
+
+impl FromStr for AuthMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(AuthMode::None),
+            "api_key" | "api-key" => Ok(AuthMode::ApiKey),
+            "jwt" => Ok(AuthMode::Jwt),
+            "hmac" => Ok(AuthMode::Hmac),
+            other => bail!(
+                "Unknown auth mode: {} (expected none, api_key, jwt or hmac)",
+                other
+            ),
+        }
+    }
+}
+
+/// JWT validation settings
+#[derive(Debug, Clone)]
+pub struct JwtConfig {
+    /// Expected `iss` claim; also used for OIDC discovery when no JWKS URL is set
+    pub issuer: Option<String>,
+    /// Accepted `aud` values (not checked when empty)
+    pub audience: Vec<String>,
+    /// JWKS endpoint of the provider
+    pub jwks_url: Option<String>,
+    /// Claim holding the caller's tenant
+    pub tenant_claim: String,
+    /// How long fetched keys are used before they're refreshed
+    pub jwks_cache_ttl: Duration,
+}
+
+/// Authentication configuration, read from the environment
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub mode: AuthMode,
+    /// API key -> key name
+    pub api_keys: HashMap<String, String>,
+    pub jwt: JwtConfig,
+    /// Client id -> HMAC secret
+    pub hmac_secrets: HashMap<String, String>,
+    /// How far a signature's timestamp may be from the server's clock
+    pub hmac_max_skew: Duration,
+}
+
+impl AuthConfig {
+    /// Read `AUTH_MODE`, `API_KEYS`, the `JWT_*` settings, `HMAC_SECRETS` and
+    /// `HMAC_MAX_SKEW_SECS` (default 300)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mode = config.string_or("AUTH_MODE", "none").parse()?;
+        let audience = config
+            .string("JWT_AUDIENCE")
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let jwks_cache_secs = config.parse_or("JWT_JWKS_CACHE_SECS", 3600);
+
+        let api_keys = match config.secret("API_KEYS") {
+            Some(v) => parse_api_keys(&v)?,
+            None => HashMap::new(),
+        };
+
+        let hmac_secrets = match config.secret("HMAC_SECRETS") {
+            Some(v) => signing::parse_secrets(&v)?,
+            None => HashMap::new(),
+        };
+
+        Ok(Self {
+            mode,
+            api_keys,
+            hmac_secrets,
+            hmac_max_skew: Duration::from_secs(config.parse_or("HMAC_MAX_SKEW_SECS", 300)),
+            jwt: JwtConfig {
+                issuer: config.string("JWT_ISSUER"),
+                audience,
+                jwks_url: config.string("JWT_JWKS_URL"),
+                tenant_claim: config.string_or("JWT_TENANT_CLAIM", "tenant"),
+                jwks_cache_ttl: Duration::from_secs(jwks_cache_secs),
+            },
+        })
+    }
+}
+
+/// Parse `name:key` pairs separated by commas
+fn parse_api_keys(value: &str) -> Result<HashMap<String, String>> {
+    let mut keys = HashMap::new();
+    for (i, entry) in value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .enumerate()
+    {
+        // Don't echo entries back: a malformed one may be a bare key
+        let (name, key) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("API_KEYS entry {} is not in name:key form", i + 1))?;
+        if keys.insert(key.to_string(), name.to_string()).is_some() {
+            bail!("Duplicate key in API_KEYS for {}", name);
+        }
+    }
+    Ok(keys)
+}
+
+/// The authenticated caller
+#[derive(Debug, Clone, Serialize)]
+pub struct Principal {
+    /// API key name or token subject (`sub` claim)
+    pub subject: String,
+    /// Tenant claim, if the token carries one
+    pub tenant: Option<String>,
+}
+
+/// Verifies incoming requests according to the configured mode
+pub enum Authenticator {
+    Disabled,
+    /// API key -> key name
+    ApiKeys(HashMap<String, String>),
+    Jwt(Box<JwtVerifier>),
+    Hmac(HmacVerifier),
+}
+
+impl Authenticator {
+    /// Build the authenticator, fetching signing keys up front for JWT mode
+    pub async fn new(config: AuthConfig) -> Result<Self> {
+        match config.mode {
+            AuthMode::None => Ok(Authenticator::Disabled),
+            AuthMode::ApiKey => {
+                if config.api_keys.is_empty() {
+                    bail!("AUTH_MODE=api_key requires API_KEYS");
+                }
+                info!("Loaded {} API keys", config.api_keys.len());
+                Ok(Authenticator::ApiKeys(config.api_keys))
+            }
+            AuthMode::Jwt => Ok(Authenticator::Jwt(Box::new(
+                JwtVerifier::new(config.jwt).await?,
+            ))),
+            AuthMode::Hmac => {
+                if config.hmac_secrets.is_empty() {
+                    bail!("AUTH_MODE=hmac requires HMAC_SECRETS");
+                }
+                let verifier = HmacVerifier::new(config.hmac_secrets, config.hmac_max_skew);
+                info!("Loaded HMAC secrets for {} clients", verifier.clients());
+                Ok(Authenticator::Hmac(verifier))
+            }
+        }
+    }
+
+    /// Authenticate a request from its headers. Returns `None` when
+    /// authentication is disabled. Signed requests need their body too, so
+    /// they're only accepted by [`require_auth`].
+    pub async fn authenticate(&self, headers: &HeaderMap) -> Result<Option<Principal>> {
+        match self {
+            Authenticator::Disabled => Ok(None),
+            Authenticator::ApiKeys(keys) => {
+                let key = headers
+                    .get(API_KEY_HEADER)
+                    .and_then(|v| v.to_str().ok())
+                    .ok_or_else(|| anyhow!("Missing X-API-Key header"))?;
+                let name = keys.get(key).ok_or_else(|| anyhow!("Unknown API key"))?;
+                Ok(Some(Principal {
+                    subject: name.clone(),
+                    tenant: None,
+                }))
+            }
+            Authenticator::Jwt(verifier) => {
+                let token = bearer_token(headers).ok_or_else(|| anyhow!("Missing bearer token"))?;
+                verifier.verify(token).await.map(Some)
+            }
+            Authenticator::Hmac(_) => bail!("Signed requests are only accepted over HTTP"),
+        }
+    }
+}
+
+/// Signing keys fetched from the provider
+struct CachedKeys {
+    keys: JwkSet,
+    fetched_at: Instant,
+    /// Last refresh attempt, failed ones included
+    attempted_at: Instant,
+}
+
+/// Validates JWTs against a provider's JWKS
+pub struct JwtVerifier {
+    config: JwtConfig,
+    jwks_url: String,
+    http: reqwest::Client,
+    keys: RwLock<CachedKeys>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OidcDiscovery {
+    jwks_uri: String,
+}
+
+impl JwtVerifier {
+    async fn new(config: JwtConfig) -> Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .context("Failed to build JWKS HTTP client")?;
+
+        let jwks_url = match (&config.jwks_url, &config.issuer) {
+            (Some(url), _) => url.clone(),
+            (None, Some(issuer)) => discover_jwks_url(&http, issuer).await?,
+            (None, None) => bail!("AUTH_MODE=jwt requires JWT_JWKS_URL or JWT_ISSUER"),
+        };
+        info!("Using JWKS from {}", jwks_url);
+
+        let keys = fetch_jwks(&http, &jwks_url).await?;
+        info!("Loaded {} signing keys", keys.keys.len());
+
+        Ok(Self {
+            config,
+            jwks_url,
+            http,
+            keys: RwLock::new(CachedKeys {
+                keys,
+                fetched_at: Instant::now(),
+                attempted_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// Verify a token's signature, expiry, issuer and audience
+    async fn verify(&self, token: &str) -> Result<Principal> {
+        let header = decode_header(token).context("Malformed token")?;
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            bail!("Unsupported token algorithm {:?}", header.alg);
+        }
+        let kid = header.kid.ok_or_else(|| anyhow!("Token has no key id"))?;
+        let key = self.decoding_key(&kid).await?;
+
+        let mut validation = Validation::new(header.alg);
+        if let Some(issuer) = &self.config.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        if self.config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&self.config.audience);
+        }
+        validation.set_required_spec_claims(&["exp", "sub"]);
+
+        let claims = decode::<serde_json::Map<String, serde_json::Value>>(token, &key, &validation)
+            .context("Invalid token")?
+            .claims;
+
+        let subject = claims
+            .get("sub")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| anyhow!("Token has no subject"))?
+            .to_string();
+        let tenant = claims
+            .get(&self.config.tenant_claim)
+            .and_then(|v| v.as_str())
+            .map(String::from);
+
+        Ok(Principal { subject, tenant })
+    }
+
+    /// Look up the key for `kid`, refreshing the JWKS if it's stale or the key
+    /// is unknown (the provider may have rotated keys). Refreshes are tried
+    /// at most every [`JWKS_MIN_REFRESH`], by one request at a time; the keys
+    /// we have are served in between, even stale ones while the provider is
+    /// unreachable.
+    async fn decoding_key(&self, kid: &str) -> Result<DecodingKey> {
+        {
+            let cached = self.keys.read().await;
+            let fresh = cached.fetched_at.elapsed() < self.config.jwks_cache_ttl;
+            let refresh_due = cached.attempted_at.elapsed() >= JWKS_MIN_REFRESH;
+            match cached.keys.find(kid) {
+                Some(jwk) if fresh || !refresh_due => {
+                    return DecodingKey::from_jwk(jwk).context("Unusable signing key");
+                }
+                None if !refresh_due => bail!("Unknown signing key {}", kid),
+                _ => {}
+            }
+        }
+
+        {
+            let mut cached = self.keys.write().await;
+            // Another request may have started a refresh while we waited for
+            // the lock
+            if cached.attempted_at.elapsed() < JWKS_MIN_REFRESH {
+                let jwk = cached
+                    .keys
+                    .find(kid)
+                    .ok_or_else(|| anyhow!("Unknown signing key {}", kid))?;
+                return DecodingKey::from_jwk(jwk).context("Unusable signing key");
+            }
+            cached.attempted_at = Instant::now();
+        }
+
+        // Fetched without holding the lock, so other requests keep using the
+        // current keys meanwhile
+        match fetch_jwks(&self.http, &self.jwks_url).await {
+            Ok(keys) => {
+                let mut cached = self.keys.write().await;
+                cached.keys = keys;
+                cached.fetched_at = Instant::now();
+            }
+            // Keep serving the keys we have if the provider is unreachable
+            Err(e) => warn!("Failed to refresh JWKS: {:#}", e),
+        }
+
+        let cached = self.keys.read().await;
+        let jwk = cached
+            .keys
+            .find(kid)
+            .ok_or_else(|| anyhow!("Unknown signing key {}", kid))?;
+        DecodingKey::from_jwk(jwk).context("Unusable signing key")
+    }
+}
+
+async fn discover_jwks_url(http: &reqwest::Client, issuer: &str) -> Result<String> {
+    let url = format!(
+        "{}/.well-known/openid-configuration",
+        issuer.trim_end_matches('/')
+    );
+    let discovery: OidcDiscovery = http
+        .get(&url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to fetch OIDC discovery document from {}", url))?
+        .json()
+        .await
+        .context("Failed to parse OIDC discovery document")?;
+    Ok(discovery.jwks_uri)
+}
+
+async fn fetch_jwks(http: &reqwest::Client, url: &str) -> Result<JwkSet> {
+    http.get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .with_context(|| format!("Failed to fetch JWKS from {}", url))?
+        .json()
+        .await
+        .context("Failed to parse JWKS")
+}
+
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+/// Middleware that rejects unauthenticated requests and makes the caller's
+/// [`Principal`] available to handlers as a request extension
+pub async fn require_auth(
+    State(state): State<Arc<AppState>>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let authenticated = match state.auth.as_ref() {
+        Authenticator::Hmac(verifier) => {
+            // The signature covers the body, so it's read here and put back
+            let (parts, body) = request.into_parts();
+            let (verified, body) = match to_bytes(body, state.limits.max_body_bytes).await {
+                Ok(bytes) => {
+                    let path = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+                    let verified = verifier.verify(&parts.method, path, &parts.headers, &bytes);
+                    (verified.map(Some), Body::from(bytes))
+                }
+                Err(e) => (
+                    Err(anyhow!("Failed to read request body: {}", e)),
+                    Body::empty(),
+                ),
+            };
+            request = Request::from_parts(parts, body);
+            verified
+        }
+        auth => auth.authenticate(request.headers()).await,
+    };
+    match authenticated {
+        Ok(Some(mut principal)) => {
+            if let Err(e) = state.tenancy.apply(&mut principal, request.headers()) {
+                warn!("Rejected request to {}: {:#}", request.uri().path(), e);
+                let response = ErrorResponse {
+                    error: format!("{:#}", e),
+                };
+                return (StatusCode::BAD_REQUEST, Json(response)).into_response();
+            }
+            request.extensions_mut().insert(principal);
+            next.run(request).await
+        }
+        Ok(None) => next.run(request).await,
+        Err(e) => {
+            warn!("Rejected request to {}: {:#}", request.uri().path(), e);
+            let response = ErrorResponse {
+                error: format!("Unauthorized: {}", e),
+            };
+            (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                Json(response),
+            )
+                .into_response()
+        }
+    }
+}
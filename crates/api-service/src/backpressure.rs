@@ -0,0 +1,108 @@
+//! Backpressure from queue depth.
+//!
+//! Queue depths come from the [`QueueSampler`]. While a queue holds more than
+//! `BACKPRESSURE_MAX_DEPTH` jobs, new jobs for it are diverted to
+//! `BACKPRESSURE_DIVERT_QUEUE` if that's set and has room, or rejected with
+//! 429 and a `Retry-After` so clients slow down instead of piling onto the
+//! backlog. The settings can be changed at runtime by a reload.
+
+use crate::sampler::QueueSampler;
+use service_config::Config;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+/// Backpressure settings
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackpressureConfig {
+    /// Queue depth above which submissions are diverted or rejected; off
+    /// when `None`
+    pub max_depth: Option<u64>,
+    /// Seconds clients are told to wait before retrying
+    pub retry_after_secs: u64,
+    /// Queue that takes jobs for backlogged queues instead of rejecting them
+    pub divert_queue: Option<String>,
+}
+
+impl BackpressureConfig {
+    /// Read `BACKPRESSURE_MAX_DEPTH`, `BACKPRESSURE_RETRY_AFTER_SECS`
+    /// (default 5) and `BACKPRESSURE_DIVERT_QUEUE`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_depth: config.parse("BACKPRESSURE_MAX_DEPTH"),
+            retry_after_secs: config.parse_or("BACKPRESSURE_RETRY_AFTER_SECS", 5),
+            divert_queue: config.string("BACKPRESSURE_DIVERT_QUEUE"),
+        }
+    }
+}
+
+/// Returned (inside `anyhow::Error`) when a job's queue is backlogged
+#[derive(Debug)]
+pub struct Backlogged {
+    queue: String,
+    depth: u64,
+    limit: u64,
+    /// Seconds until the client should try again
+    pub retry_after: u64,
+}
+
+impl fmt::Display for Backlogged {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Queue {} is backlogged ({} jobs waiting, limit {}); retry in {}s",
+            self.queue, self.depth, self.limit, self.retry_after
+        )
+    }
+}
+
+impl std::error::Error for Backlogged {}
+
+/// Holds back submissions to queues the sampler found backlogged
+pub struct Backpressure {
+    config: RwLock<BackpressureConfig>,
+    sampler: Arc<QueueSampler>,
+}
+
+impl Backpressure {
+    pub fn new(config: BackpressureConfig, sampler: Arc<QueueSampler>) -> Self {
+        Self {
+            config: RwLock::new(config),
+            sampler,
+        }
+    }
+
+    pub fn config(&self) -> BackpressureConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Swap in new settings; later submissions use them
+    pub fn reconfigure(&self, config: BackpressureConfig) {
+        *self.config.write().unwrap() = config;
+    }
+
+    pub fn divert_queue(&self) -> Option<String> {
+        self.config.read().unwrap().divert_queue.clone()
+    }
+
+    /// Queue to push a job for `queue` to: `queue` itself unless it's
+    /// backlogged, then `divert` if that isn't backlogged too
+    pub fn admit(&self, queue: String, divert: Option<String>) -> Result<String, Backlogged> {
+        let config = self.config.read().unwrap();
+        let Some(limit) = config.max_depth else {
+            return Ok(queue);
+        };
+        let queue_depth = self.sampler.depth(&queue);
+        if queue_depth <= limit {
+            return Ok(queue);
+        }
+        match divert {
+            Some(divert) if divert != queue && self.sampler.depth(&divert) <= limit => Ok(divert),
+            _ => Err(Backlogged {
+                queue,
+                depth: queue_depth,
+                limit,
+                retry_after: config.retry_after_secs,
+            }),
+        }
+    }
+}
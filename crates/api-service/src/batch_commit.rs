@@ -0,0 +1,231 @@
+//! All-or-nothing pushes for `/jobs/batch` (and the bulk and upload routes
+//! built on it).
+//!
+//! A batch's jobs are first staged in Faktory's scheduled set, held a year
+//! ahead, with one bulk push (`PUSHB`). Once Faktory has accepted every one
+//! of them, a single `MUTATE` requeues them by id and they become runnable
+//! together. If Faktory refuses any job, or the requeue fails, the staged
+//! jobs are discarded again and the request fails with nothing enqueued.
+//! Batches with a `run_at` are staged for that time and aren't requeued.
+//!
+//! With `BATCH_ENTERPRISE` set, each request also opens a Faktory Enterprise
+//! batch (`BATCH NEW`), whose id is the request's `batch_id`, and commits it
+//! (`BATCH COMMIT`) once its jobs are runnable. Faktory then reports the
+//! batch's progress and pushes a `batch_complete` job to
+//! `BATCH_CALLBACK_QUEUE` when all of them have finished. A batch that's
+//! never committed expires on Faktory without a callback.
+//!
+//! If the API stops between staging and requeuing, the staged jobs stay in
+//! the scheduled set, tagged with their `batch_id`, until an admin requeues
+//! or discards them.
+
+use crate::metrics::EnqueueMode;
+use crate::{faktory_client, push_retry, AppState, FaktoryManager};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use deadpool::managed::Pool;
+use faktory::ent::{Batch, BatchId};
+use faktory::mutate::{Filter, JobSet};
+use faktory::{Client, Job, JobId};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Instant;
+use tracing::{info, warn};
+
+/// How long staged jobs are held; far enough that a batch the API never
+/// finished committing doesn't run on its own
+const STAGING_HOLD_DAYS: i64 = 365;
+
+/// Job type of the callback Faktory pushes once an Enterprise batch finishes
+const CALLBACK_JOB_TYPE: &str = "batch_complete";
+
+/// Custom job field Faktory Enterprise keys batch membership on
+const BID_FIELD: &str = "bid";
+
+/// Faktory refused some jobs of a batch, so none of them were enqueued
+#[derive(Debug)]
+pub struct BatchRejected {
+    /// Job id -> why Faktory refused it
+    pub rejected: HashMap<String, String>,
+    /// Jobs in the batch
+    pub total: usize,
+}
+
+impl fmt::Display for BatchRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Faktory refused {} of {} jobs, so none were enqueued",
+            self.rejected.len(),
+            self.total
+        )?;
+        if let Some((job_id, error)) = self.rejected.iter().next() {
+            write!(f, " (job {}: {})", job_id, error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BatchRejected {}
+
+/// Open a Faktory Enterprise batch for `jobs` jobs, returning its id
+pub async fn open(state: &AppState, jobs: usize) -> Result<String> {
+    let mut callback = Job::new(CALLBACK_JOB_TYPE, Vec::<serde_json::Value>::new());
+    callback.queue = state.batch_config.callback_queue.clone();
+    let batch = Batch::builder()
+        .description(format!("/jobs/batch of {} jobs", jobs))
+        .with_complete_callback(callback);
+
+    let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+    match client.start_batch(batch).await {
+        Ok(handle) => Ok(handle.id().to_string()),
+        Err(e) => {
+            push_retry::discard_if_broken(client, &e);
+            Err(anyhow::Error::new(e).context("Failed to open Faktory batch"))
+        }
+    }
+}
+
+/// Push `jobs` so that either all of them are enqueued or none are. `bid` is
+/// the Faktory Enterprise batch they join, committed once they're runnable.
+pub async fn push(state: &AppState, jobs: Vec<Job>, bid: Option<&str>) -> Result<()> {
+    let metrics = &state.metrics;
+    if jobs.is_empty() {
+        return Ok(());
+    }
+
+    // Jobs with a `run_at` are already held until then
+    let held: Vec<&JobId> = jobs
+        .iter()
+        .filter(|job| job.at.is_none())
+        .map(|job| job.id())
+        .collect();
+    let hold_until = Utc::now() + chrono::Duration::days(STAGING_HOLD_DAYS);
+    let staged: Vec<Job> = jobs
+        .iter()
+        .map(|job| {
+            let mut job = job.clone();
+            job.at.get_or_insert(hold_until);
+            if let Some(bid) = bid {
+                job.custom.insert(BID_FIELD.to_string(), bid.into());
+            }
+            job
+        })
+        .collect();
+
+    let pool = state.tenant_pools.pool(&state.faktory_pool, &jobs);
+
+    let started = Instant::now();
+    let pushed: Result<HashMap<String, String>> = state
+        .push_retry
+        .run(
+            || async {
+                let mut client = faktory_client(&pool, metrics).await?;
+                match client.enqueue_many(staged.clone()).await {
+                    Ok((_, rejected)) => Ok(rejected.unwrap_or_default()),
+                    Err(e) => {
+                        push_retry::discard_if_broken(client, &e);
+                        Err(anyhow::Error::new(e).context("Failed to stage jobs in batch"))
+                    }
+                }
+            },
+            || metrics.enqueue_retried(EnqueueMode::Batch),
+        )
+        .await;
+    let committed = match pushed {
+        Ok(rejected) if rejected.is_empty() => match release(state, &pool, &held).await {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                let staged: Vec<&JobId> = jobs.iter().map(|job| job.id()).collect();
+                discard(state, &pool, &staged).await;
+                Err(e)
+            }
+        },
+        Ok(rejected) => {
+            let accepted: Vec<&JobId> = jobs
+                .iter()
+                .map(|job| job.id())
+                .filter(|job_id| !rejected.contains_key(job_id.as_str()))
+                .collect();
+            discard(state, &pool, &accepted).await;
+            Err(BatchRejected {
+                rejected,
+                total: jobs.len(),
+            }
+            .into())
+        }
+        Err(e) => Err(e),
+    };
+    metrics.observe_enqueue(EnqueueMode::Batch, started.elapsed());
+    if let Err(e) = committed {
+        metrics.enqueue_failed(EnqueueMode::Batch);
+        return Err(e);
+    }
+
+    if let Some(bid) = bid {
+        // The jobs are already runnable, so only Faktory's tracking is lost
+        if let Err(e) = commit_batch(state, bid).await {
+            warn!("Failed to commit Faktory batch {}: {:#}", bid, e);
+        }
+    }
+
+    for job in &jobs {
+        metrics.job_enqueued(job.kind());
+    }
+    if let Some(shadow) = &state.shadow {
+        shadow.mirror(&jobs);
+    }
+    info!("Enqueued batch of {} jobs", jobs.len());
+    Ok(())
+}
+
+/// Make the staged jobs `held` runnable now
+async fn release(state: &AppState, pool: &Pool<FaktoryManager>, held: &[&JobId]) -> Result<()> {
+    if held.is_empty() {
+        return Ok(());
+    }
+    let mut client = faktory_client(pool, &state.metrics).await?;
+    if let Err(e) = client
+        .requeue(JobSet::Scheduled, Filter::from_ids(held))
+        .await
+    {
+        push_retry::discard_if_broken(client, &e);
+        return Err(anyhow::Error::new(e).context("Failed to release staged jobs"));
+    }
+    Ok(())
+}
+
+/// Remove staged jobs from the scheduled set again, as far as Faktory lets us
+async fn discard(state: &AppState, pool: &Pool<FaktoryManager>, staged: &[&JobId]) {
+    if staged.is_empty() {
+        return;
+    }
+    let discarded = async {
+        let mut client = faktory_client(pool, &state.metrics).await?;
+        client
+            .discard(JobSet::Scheduled, Filter::from_ids(staged))
+            .await
+            .context("Failed to discard staged jobs")
+    };
+    if let Err(e) = discarded.await {
+        warn!(
+            "{} staged jobs are left in Faktory's scheduled set: {:#}",
+            staged.len(),
+            e
+        );
+    }
+}
+
+async fn commit_batch(state: &AppState, bid: &str) -> Result<()> {
+    let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+    let client: &mut Client = &mut client;
+    let handle = client
+        .open_batch(&BatchId::new(bid))
+        .await
+        .context("Failed to open Faktory batch")?
+        .with_context(|| format!("Faktory batch {} has expired", bid))?;
+    handle
+        .commit()
+        .await
+        .context("Failed to commit Faktory batch")
+}
@@ -0,0 +1,145 @@
+//! Circuit breaker around Faktory pushes.
+//!
+//! After `failure_threshold` consecutive failed pushes the circuit opens and
+//! submissions stop trying Faktory (they're spilled to disk, or rejected with
+//! 503). After `cooldown` one trial push is let through; if it succeeds the
+//! circuit closes again. A trial that never reports back (its request was
+//! dropped mid-push) is given up on after another `cooldown`, and the next
+//! push becomes the trial.
+
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+/// Circuit breaker settings
+#[derive(Debug, Clone, Copy)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the circuit
+    pub failure_threshold: u32,
+    /// How long the circuit stays open before a trial push
+    pub cooldown: Duration,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// A trial push is in flight
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::Closed { failures: 0 }),
+        }
+    }
+
+    /// Whether a push to Faktory should be attempted now
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        match *state {
+            State::Closed { .. } => true,
+            State::Open { until } if now >= until => {
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::HalfOpen { since } if now >= since + self.config.cooldown => {
+                warn!("Faktory circuit trial push never finished; trying another");
+                *state = State::HalfOpen { since: now };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    /// `closed`, `open` or `half_open`, for health reports
+    pub fn state(&self) -> &'static str {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => "closed",
+            State::Open { .. } => "open",
+            State::HalfOpen { .. } => "half_open",
+        }
+    }
+
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, State::Closed { .. }) {
+            info!("Faktory circuit closed");
+        }
+        *state = State::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            State::Closed { failures } => failures + 1,
+            // A failed trial reopens the circuit straight away
+            State::HalfOpen { .. } | State::Open { .. } => self.config.failure_threshold,
+        };
+        *state = if failures >= self.config.failure_threshold {
+            warn!(
+                "Faktory circuit open for {:?} after {} failed pushes",
+                self.config.cooldown, failures
+            );
+            State::Open {
+                until: Instant::now() + self.config.cooldown,
+            }
+        } else {
+            State::Closed { failures }
+        };
+    }
+}
+
+/// Returned (inside `anyhow::Error`) when the circuit is open and there's no
+/// spill buffer to hold the jobs
+#[derive(Debug)]
+pub struct CircuitOpen;
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Faktory is unavailable; try again later")
+    }
+}
+
+impl std::error::Error for CircuitOpen {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_abandoned_trial_is_retried_after_cooldown() {
+        let breaker = CircuitBreaker::new(BreakerConfig {
+            failure_threshold: 1,
+            cooldown: Duration::from_millis(20),
+        });
+        breaker.record_failure();
+        assert!(!breaker.allow());
+
+        std::thread::sleep(Duration::from_millis(25));
+        // The trial, whose request is then dropped before it reports back
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        assert_eq!(breaker.state(), "half_open");
+
+        std::thread::sleep(Duration::from_millis(25));
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert_eq!(breaker.state(), "closed");
+    }
+}
@@ -0,0 +1,215 @@
+//! Vectorized submission of math jobs.
+//!
+//! `POST /jobs/{name}/bulk` takes the operands as parallel arrays, e.g.
+//! `{"a": [1, 2, 3], "b": 10}`, and expands them server-side into one job
+//! per element, so producers don't have to build and send each request
+//! themselves. A single number is used for every job. The jobs are enqueued
+//! together as one batch, and elements that fail validation (e.g. a zero
+//! divisor) are reported by index without failing the rest.
+
+use crate::auth::Principal;
+use crate::idempotency;
+use crate::{
+    bad_request, enqueue_batch_jobs, submission_error, validation_error, AppState, ErrorResponse,
+    JobOptions, ValidationErrorResponse,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{post, MethodRouter},
+    Extension, Json, Router,
+};
+use job_types::{BulkMathArgs, FieldError, JobKind, JobPayload};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Operands for many jobs of one type
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct BulkJobRequest {
+    #[serde(flatten)]
+    args: BulkMathArgs,
+    /// Options applied to every job
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
+/// The jobs a bulk submission was expanded into
+#[derive(Debug, Serialize, ToSchema)]
+pub struct BulkJobResponse {
+    /// Shared by the jobs (their `batch_id` custom field)
+    batch_id: String,
+    message: String,
+    /// Elements in the operand arrays
+    total_jobs: usize,
+    total_enqueued: usize,
+    /// Faktory was unavailable; the jobs are held locally and will be
+    /// enqueued when it recovers
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deferred: bool,
+    /// One per element, in array order; `null` where the element was rejected
+    job_ids: Vec<Option<String>>,
+    /// Elements that were invalid
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    rejected: Vec<RejectedElement>,
+}
+
+/// An element of a bulk submission that wasn't enqueued
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RejectedElement {
+    /// Position in the operand arrays
+    index: usize,
+    error: String,
+}
+
+/// POST /jobs/{kind}/bulk - Submit one job of a type per element of the
+/// operand arrays. Mounted once per entry in `JobPayload::KINDS` by `routes`.
+#[utoipa::path(
+    post,
+    path = "/jobs/{kind}/bulk",
+    tag = "jobs",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Return the original jobs instead of enqueuing duplicates")),
+    request_body(content = BulkJobRequest, example = json!({"a": [1, 2, 3], "b": 10, "request_ids": ["r-1", "r-2", "r-3"]})),
+    responses(
+        (status = 202, description = "Every job was enqueued", body = BulkJobResponse),
+        (status = 207, description = "Some elements were rejected (see `rejected`); the rest were enqueued", body = BulkJobResponse),
+        (status = 400, description = "Invalid job options", body = ErrorResponse),
+        (status = 413, description = "Request body is larger than `REQUEST_MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 422, description = "Mismatched arrays, more jobs than `BATCH_BULK_MAX_JOBS`, or no valid elements", body = ValidationErrorResponse),
+        (status = 429, description = "Quota exceeded or queue backlogged (see `Retry-After`)", body = ErrorResponse),
+        (status = 500, description = "Failed to enqueue", body = ErrorResponse),
+        (status = 503, description = "Faktory is unavailable and no spill buffer is configured", body = ErrorResponse),
+    )
+)]
+pub async fn bulk_job_handler(
+    state: Arc<AppState>,
+    kind: JobKind,
+    principal: Option<Principal>,
+    headers: HeaderMap,
+    mut req: BulkJobRequest,
+) -> Response {
+    req.options.principal = principal;
+    req.options.idempotency_key = idempotency::key_from_headers(&headers);
+    if let Err(e) = req.options.resolve(&state) {
+        return bad_request(e);
+    }
+
+    let max_jobs = state.batch_config.max_bulk_jobs;
+    match req.args.job_count() {
+        Ok(count) if count > max_jobs => {
+            return validation_error(vec![FieldError::new(
+                "a",
+                format!("At most {} jobs per bulk request, got {}", max_jobs, count),
+            )])
+        }
+        Ok(_) => {}
+        Err(e) => return validation_error(vec![e]),
+    }
+    let args = match req.args.expand() {
+        Ok(args) => args,
+        Err(e) => return validation_error(vec![e]),
+    };
+    let total_jobs = args.len();
+
+    // Invalid elements are reported by index; the valid ones are still enqueued
+    let mut indices = Vec::with_capacity(total_jobs);
+    let mut payloads = Vec::with_capacity(total_jobs);
+    let mut invalid = Vec::new();
+    for (index, args) in args.into_iter().enumerate() {
+        let Some(payload) = kind.with_math_args(args) else {
+            return validation_error(vec![FieldError::new(
+                "type",
+                format!("{} jobs can't be submitted in bulk", kind.tag()),
+            )]);
+        };
+        let errors = payload.validate();
+        if errors.is_empty() {
+            indices.push(index);
+            payloads.push(payload);
+        } else {
+            invalid.push((index, errors));
+        }
+    }
+    if payloads.is_empty() {
+        return validation_error(
+            invalid
+                .into_iter()
+                .flat_map(|(index, errors)| {
+                    errors
+                        .into_iter()
+                        .map(move |e| FieldError::new(format!("{}[{}]", e.field, index), e.message))
+                })
+                .collect(),
+        );
+    }
+
+    let outcome = match enqueue_batch_jobs(&state, payloads, &req.options).await {
+        Ok(outcome) => outcome,
+        Err(e) => return submission_error("Failed to enqueue bulk jobs", e),
+    };
+
+    let mut job_ids = vec![None; total_jobs];
+    let rejected: Vec<RejectedElement> = invalid
+        .into_iter()
+        .map(|(index, errors)| {
+            let errors: Vec<_> = errors
+                .iter()
+                .map(|e| format!("{}: {}", e.field, e.message))
+                .collect();
+            RejectedElement {
+                index,
+                error: errors.join("; "),
+            }
+        })
+        .collect();
+    for (index, job_id) in indices.into_iter().zip(outcome.job_ids) {
+        job_ids[index] = Some(job_id);
+    }
+
+    let total_enqueued = job_ids.iter().flatten().count();
+    let message = if outcome.deferred {
+        format!(
+            "Faktory is unavailable; {} jobs will be enqueued when it recovers",
+            total_enqueued
+        )
+    } else {
+        format!("Enqueued {} of {} jobs", total_enqueued, total_jobs)
+    };
+    let status = if rejected.is_empty() {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+    let response = BulkJobResponse {
+        batch_id: outcome.batch_id,
+        message,
+        total_jobs,
+        total_enqueued,
+        deferred: outcome.deferred,
+        job_ids,
+        rejected,
+    };
+    (status, Json(response)).into_response()
+}
+
+/// `POST /jobs/{name}/bulk` for every job type in the registry, each wrapped
+/// by `limit`
+pub fn routes(
+    limit: impl Fn(&str, MethodRouter<Arc<AppState>>) -> MethodRouter<Arc<AppState>>,
+) -> Router<Arc<AppState>> {
+    JobPayload::KINDS
+        .iter()
+        .fold(Router::new(), |router, &kind| {
+            let path = format!("/jobs/{}/bulk", kind.name);
+            let route = post(
+                move |State(state): State<Arc<AppState>>,
+                      principal: Option<Extension<Principal>>,
+                      headers: HeaderMap,
+                      Json(req): Json<BulkJobRequest>| {
+                    bulk_job_handler(state, kind, principal.map(|Extension(p)| p), headers, req)
+                },
+            );
+            router.route(&path, limit(&path, route))
+        })
+}
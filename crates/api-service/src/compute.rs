@@ -0,0 +1,181 @@
+//! Synchronous compute endpoints: enqueue a job and wait for its result.
+
+use crate::auth::Principal;
+use crate::idempotency;
+use crate::{
+    bad_request, enqueue_job, submission_error, validation_error, AppState, ErrorResponse,
+    MathRequest, ValidationErrorResponse,
+};
+use anyhow::Result;
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use job_types::{JobPayload, MathArgs};
+use result_store::{JobResult, JobStatus};
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Configuration for synchronous compute requests
+#[derive(Clone)]
+pub struct ComputeConfig {
+    /// How long to wait for the worker before giving up
+    pub timeout: Duration,
+    /// How often to check the result store while waiting
+    pub poll_interval: Duration,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComputeResponse {
+    job_id: String,
+    result: serde_json::Value,
+}
+
+/// Returned when the job doesn't finish in time; the caller can keep polling
+/// `GET /jobs/{id}` with the job id.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ComputeTimeoutResponse {
+    job_id: String,
+    error: String,
+}
+
+/// Build the payload for a math operation name
+fn math_payload(op: &str, args: MathArgs) -> Option<JobPayload> {
+    let kind = JobPayload::kind(op)?;
+    JobPayload::from_tagged(kind.tag(), serde_json::to_value(args).ok()?).ok()
+}
+
+/// Poll the result store until the job completes or fails with no retries
+/// left, or the timeout elapses. Returns `None` on timeout.
+pub async fn wait_for_result(
+    state: &AppState,
+    job_id: &str,
+    timeout: Duration,
+) -> Result<Option<JobResult>> {
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if let Some(result) = state.result_store.get(job_id).await? {
+            if result.is_final() {
+                return Ok(Some(result));
+            }
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        sleep(state.compute_config.poll_interval).await;
+    }
+}
+
+/// POST /compute/{op} - Enqueue a math job and return its computed value
+#[utoipa::path(
+    post,
+    path = "/compute/{op}",
+    tag = "compute",
+    params(("op" = String, Path, description = "`add`, `subtract`, `multiply` or `divide`"),
+        ("Idempotency-Key" = Option<String>, Header, description = "Return the original job instead of enqueuing a duplicate")),
+    request_body = MathRequest,
+    responses(
+        (status = 200, description = "The job completed", body = ComputeResponse),
+        (status = 400, description = "Invalid job options", body = ErrorResponse),
+        (status = 404, description = "Unknown operation", body = ErrorResponse),
+        (status = 422, description = "Invalid input, or the job failed (without `details`)", body = ValidationErrorResponse),
+        (status = 202, description = "Faktory is unavailable; the job was deferred", body = ComputeTimeoutResponse),
+        (status = 429, description = "Quota exceeded", body = ErrorResponse),
+        (status = 504, description = "The job did not finish in time", body = ComputeTimeoutResponse),
+    )
+)]
+pub async fn compute_handler(
+    State(state): State<Arc<AppState>>,
+    Path(op): Path<String>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    Json(mut req): Json<MathRequest>,
+) -> impl IntoResponse {
+    req.options.principal = principal.map(|Extension(p)| p);
+    req.options.idempotency_key = idempotency::key_from_headers(&headers);
+    if req.options.run_at.is_some() || req.options.delay_seconds.is_some() {
+        let response = ErrorResponse {
+            error: "Delayed jobs can't be computed synchronously; use /jobs instead".to_string(),
+        };
+        return (StatusCode::BAD_REQUEST, Json(response)).into_response();
+    }
+    if let Err(e) = req.options.resolve(&state) {
+        return bad_request(e);
+    }
+
+    let args = MathArgs {
+        a: req.a,
+        b: req.b,
+        request_id: req.request_id.clone(),
+    };
+    let Some(payload) = math_payload(&op, args) else {
+        let response = ErrorResponse {
+            error: format!("Unknown operation: {}", op),
+        };
+        return (StatusCode::NOT_FOUND, Json(response)).into_response();
+    };
+    let errors = payload.validate();
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    // Skip auto-batching: the caller is waiting, so don't add flush delay
+    let job_id = match enqueue_job(&state, payload, &req.options).await {
+        Ok(submitted) if submitted.deferred => {
+            // There's no point waiting while Faktory is down
+            let response = ComputeTimeoutResponse {
+                job_id: submitted.job_id,
+                error: "Faktory is unavailable; the job will be enqueued when it recovers"
+                    .to_string(),
+            };
+            return (StatusCode::ACCEPTED, Json(response)).into_response();
+        }
+        Ok(submitted) => submitted.job_id,
+        Err(e) => return submission_error("Failed to enqueue job", e),
+    };
+
+    match wait_for_result(&state, &job_id, state.compute_config.timeout).await {
+        Ok(Some(result)) if result.status == JobStatus::Completed => {
+            let response = ComputeResponse {
+                job_id,
+                result: result.result.unwrap_or_default(),
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Ok(Some(result)) => {
+            let response = ErrorResponse {
+                error: format!(
+                    "Job {} failed: {}",
+                    job_id,
+                    result.error.unwrap_or_default()
+                ),
+            };
+            (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
+        }
+        Ok(None) => {
+            let response = ComputeTimeoutResponse {
+                error: format!(
+                    "Job did not finish within {}ms",
+                    state.compute_config.timeout.as_millis()
+                ),
+                job_id,
+            };
+            (StatusCode::GATEWAY_TIMEOUT, Json(response)).into_response()
+        }
+        Err(e) => {
+            warn!("Failed to read job result: {:#}", e);
+            let response = ErrorResponse {
+                error: format!("Failed to read job result: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
+        }
+    }
+}
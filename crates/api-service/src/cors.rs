@@ -0,0 +1,107 @@
+//! Cross-origin access for browser clients.
+//!
+//! Off unless `CORS_ALLOWED_ORIGINS` is set, so single-page apps have to be
+//! allowed explicitly before they can call the API from another origin.
+
+use anyhow::{bail, Context, Result};
+use axum::http::{HeaderName, HeaderValue, Method};
+use service_config::Config;
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+const DEFAULT_METHODS: &str = "GET,POST,PUT,DELETE";
+
+const DEFAULT_HEADERS: &str =
+    "content-type,content-encoding,authorization,x-api-key,x-client-id,x-signature-timestamp,x-signature-nonce,x-signature,idempotency-key,traceparent,tracestate,x-request-id";
+
+/// CORS configuration
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// `None` allows any origin
+    origins: Option<Vec<HeaderValue>>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    max_age: Duration,
+}
+
+impl CorsConfig {
+    /// Read `CORS_ALLOWED_ORIGINS` (comma-separated, or `*`),
+    /// `CORS_ALLOWED_METHODS`, `CORS_ALLOWED_HEADERS` and `CORS_MAX_AGE_SECS`.
+    /// Returns `None` when no origins are configured.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let Some(origins) = config.string("CORS_ALLOWED_ORIGINS") else {
+            return Ok(None);
+        };
+        let origins = if origins.trim() == "*" {
+            None
+        } else {
+            Some(
+                list(&origins)
+                    .map(|origin| {
+                        if !origin.starts_with("http://") && !origin.starts_with("https://") {
+                            bail!(
+                                "Invalid CORS_ALLOWED_ORIGINS entry {:?} (expected e.g. https://app.example.com)",
+                                origin
+                            );
+                        }
+                        HeaderValue::from_str(origin.trim_end_matches('/')).with_context(|| {
+                            format!("Invalid CORS_ALLOWED_ORIGINS entry {:?}", origin)
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            )
+        };
+
+        let methods = list(&config.string_or("CORS_ALLOWED_METHODS", DEFAULT_METHODS))
+            .map(|method| {
+                Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                    .with_context(|| format!("Invalid CORS_ALLOWED_METHODS entry {:?}", method))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let headers = list(&config.string_or("CORS_ALLOWED_HEADERS", DEFAULT_HEADERS))
+            .map(|header| {
+                HeaderName::from_bytes(header.as_bytes())
+                    .with_context(|| format!("Invalid CORS_ALLOWED_HEADERS entry {:?}", header))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let max_age = Duration::from_secs(config.parse_or("CORS_MAX_AGE_SECS", 600));
+
+        Ok(Some(Self {
+            origins,
+            methods,
+            headers,
+            max_age,
+        }))
+    }
+
+    /// The middleware answering preflight requests and tagging responses
+    pub fn layer(&self) -> CorsLayer {
+        let origins = match &self.origins {
+            Some(origins) => AllowOrigin::list(origins.clone()),
+            None => AllowOrigin::any(),
+        };
+        CorsLayer::new()
+            .allow_origin(origins)
+            .allow_methods(self.methods.clone())
+            .allow_headers(self.headers.clone())
+            // So browser clients can quote the request ID
+            .expose_headers([HeaderName::from_static("x-request-id")])
+            .max_age(self.max_age)
+    }
+
+    /// Allowed origins, for the startup log
+    pub fn describe_origins(&self) -> String {
+        match &self.origins {
+            Some(origins) => origins
+                .iter()
+                .filter_map(|origin| origin.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", "),
+            None => "any origin".to_string(),
+        }
+    }
+}
+
+fn list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
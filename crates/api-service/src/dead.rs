@@ -0,0 +1,144 @@
+//! Jobs that ran out of retries.
+//!
+//! Faktory's protocol can't list its dead set, so workers also record each
+//! job's final failure, and the errors of its earlier attempts, in the result
+//! store. Retrying one removes it from
+//! Faktory's dead set (if it's there) and pushes it again with a fresh set of
+//! retries.
+//!
+//! Both routes are admin routes (see `admin::routes`).
+
+use crate::{faktory_client, AppState, ErrorResponse};
+use anyhow::Context;
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use faktory::mutate::{Filter, JobSet};
+use faktory::{Job, JobId};
+use result_store::DeadJob;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Dead jobs listed when the request doesn't say
+const DEFAULT_LIST_LIMIT: usize = 50;
+
+/// Most dead jobs listed per request
+const MAX_LIST_LIMIT: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ListQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DeadRetryResponse {
+    job_id: String,
+    message: String,
+}
+
+fn error_response(status: StatusCode, error: String) -> Response {
+    (status, Json(ErrorResponse { error })).into_response()
+}
+
+/// GET /dead - Jobs that failed their last attempt, newest first
+#[utoipa::path(
+    get,
+    path = "/dead",
+    tag = "dead",
+    params(("limit" = Option<usize>, Query, description = "Most jobs to return (default 50, at most 1000)")),
+    responses(
+        (status = 200, description = "Dead jobs with their payload and last error", body = Vec<DeadJob>),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn list_dead_handler(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<ListQuery>,
+) -> Response {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LIST_LIMIT)
+        .min(MAX_LIST_LIMIT);
+    match state.result_store.list_dead_jobs(limit).await {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(e) => {
+            warn!("Failed to list dead jobs: {:#}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list dead jobs: {}", e),
+            )
+        }
+    }
+}
+
+/// POST /dead/{jid}/retry - Push a dead job again
+#[utoipa::path(
+    post,
+    path = "/dead/{jid}/retry",
+    tag = "dead",
+    params(("jid" = String, Path, description = "Job id")),
+    responses(
+        (status = 202, description = "The job was enqueued again", body = DeadRetryResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 404, description = "No dead job with this id", body = ErrorResponse),
+        (status = 503, description = "Faktory is unreachable", body = ErrorResponse),
+    )
+)]
+pub async fn retry_dead_handler(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Response {
+    let dead = match state.result_store.get_dead_job(&job_id).await {
+        Ok(Some(dead)) => dead,
+        Ok(None) => {
+            return error_response(StatusCode::NOT_FOUND, format!("No dead job {}", job_id))
+        }
+        Err(e) => {
+            warn!("Failed to read dead job: {:#}", e);
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to read dead job: {}", e),
+            );
+        }
+    };
+    let job: Job = match serde_json::from_value(dead.job) {
+        Ok(job) => job,
+        Err(e) => {
+            return error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Stored job {} is invalid: {}", job_id, e),
+            )
+        }
+    };
+
+    let requeued = async {
+        let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+        // Otherwise requeuing Faktory's dead set later would run it twice
+        let jid = JobId::new(job_id.clone());
+        client
+            .discard(JobSet::Dead, Filter::from_ids(&[&jid]))
+            .await
+            .context("Failed to remove job from Faktory's dead set")?;
+        client.enqueue(job).await.context("Failed to enqueue job")
+    };
+    if let Err(e) = requeued.await {
+        warn!("Failed to retry dead job {}: {:#}", job_id, e);
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, format!("{:#}", e));
+    }
+
+    // The job is queued again, so a stale record only means it's listed twice
+    if let Err(e) = state.result_store.delete_dead_job(&job_id).await {
+        warn!("Failed to delete dead job record {}: {:#}", job_id, e);
+    }
+    info!("Retried dead job {}", job_id);
+    let response = DeadRetryResponse {
+        message: format!("Dead job {} enqueued again", job_id),
+        job_id,
+    };
+    (StatusCode::ACCEPTED, Json(response)).into_response()
+}
@@ -0,0 +1,108 @@
+//! Deduplication of submissions by their `request_id`.
+//!
+//! Upstream systems that deliver events at least once can submit the same
+//! job more than once. With `DEDUP_WINDOW_SECS` set, a job whose `request_id`
+//! the same caller already used within that window isn't enqueued again: the
+//! original job id comes back with `deduplicated: true`. The window slides:
+//! each duplicate restarts it, so a producer redelivering an event keeps
+//! getting the original job back for as long as it keeps redelivering
+//! within the window. Claims are kept in the result store next to
+//! idempotency keys, so replicas sharing a store share the window. Jobs
+//! without a `request_id` and batch submissions are never deduplicated.
+
+use crate::{AppState, JobOptions};
+use job_types::JobPayload;
+use service_config::Config;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long a `request_id` maps to the job first submitted with it
+#[derive(Debug, Clone)]
+pub struct DedupConfig {
+    window: Option<Duration>,
+}
+
+impl DedupConfig {
+    /// Read `DEDUP_WINDOW_SECS` (default 0, off)
+    pub fn from_config(config: &Config) -> Self {
+        let secs: u64 = config.parse_or("DEDUP_WINDOW_SECS", 0);
+        Self {
+            window: (secs > 0).then(|| Duration::from_secs(secs)),
+        }
+    }
+}
+
+/// Store key of a request id, scoped to the caller
+fn store_key(options: &JobOptions, request_id: &str) -> String {
+    let caller = options
+        .principal
+        .as_ref()
+        .map_or("anonymous", |p| p.subject.as_str());
+    format!("{}:request_id:{}", caller, request_id)
+}
+
+/// The request id to deduplicate `payload` on, if deduplication applies
+fn request_id<'a>(
+    state: &AppState,
+    options: &JobOptions,
+    payload: &'a JobPayload,
+) -> Option<(&'a str, Duration)> {
+    let window = state.dedup.window?;
+    if options.skip_dedup {
+        return None;
+    }
+    Some((payload.request_id()?, window))
+}
+
+/// Claim the payload's `request_id` for `job_id`. Returns the id of the job
+/// submitted with it earlier in the window, restarting the window. If the
+/// store is unavailable the job is treated as new.
+pub async fn claim(
+    state: &AppState,
+    options: &JobOptions,
+    payload: &JobPayload,
+    job_id: &str,
+) -> Option<String> {
+    let (request_id, window) = request_id(state, options, payload)?;
+    match state
+        .result_store
+        .claim_idempotency_key(&store_key(options, request_id), job_id, window)
+        .await
+    {
+        Ok(Some(original)) => {
+            if let Err(e) = state
+                .result_store
+                .extend_idempotency_key(&store_key(options, request_id), window)
+                .await
+            {
+                warn!("Failed to extend request_id {:?}: {:#}", request_id, e);
+            }
+            info!(
+                "Duplicate request_id {:?}, returning job {}",
+                request_id, original
+            );
+            state.metrics.job_deduplicated(payload.job_type());
+            Some(original)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Failed to claim request_id {:?}: {:#}", request_id, e);
+            None
+        }
+    }
+}
+
+/// Free the payload's `request_id` after its submission failed, so a retry
+/// can enqueue the job
+pub async fn release(state: &AppState, options: &JobOptions, payload: &JobPayload) {
+    let Some((request_id, _)) = request_id(state, options, payload) else {
+        return;
+    };
+    if let Err(e) = state
+        .result_store
+        .release_idempotency_key(&store_key(options, request_id))
+        .await
+    {
+        warn!("Failed to release request_id {:?}: {:#}", request_id, e);
+    }
+}
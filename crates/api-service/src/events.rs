@@ -0,0 +1,194 @@
+//! Server-Sent Events stream of job status transitions.
+//!
+//! Status and progress are polled from the result store. With completion
+//! events on (`COMPLETION_EVENTS_URL`), a stream also ends as soon as the
+//! worker publishes the job's result, rather than at the next poll. A failed
+//! attempt Faktory will retry is reported, but the stream carries on until
+//! the job completes or runs out of retries.
+
+use crate::AppState;
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::{DateTime, Utc};
+use futures_util::Stream;
+use result_store::{JobProgress, JobResult, JobStatus};
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+
+/// Configuration for job event streams
+#[derive(Clone)]
+pub struct EventsConfig {
+    /// How often to check the result store for status changes
+    pub poll_interval: Duration,
+    /// Streams are closed after this long even if the job hasn't finished
+    pub max_duration: Duration,
+}
+
+fn status_event(job_id: &str, status: JobStatus) -> Event {
+    Event::default().event("status").data(
+        serde_json::json!({
+            "job_id": job_id,
+            "status": status,
+        })
+        .to_string(),
+    )
+}
+
+fn progress_event(job_id: &str, progress: &JobProgress) -> Event {
+    Event::default().event("progress").data(
+        serde_json::json!({
+            "job_id": job_id,
+            "percent": progress.percent,
+            "message": progress.message,
+            "updated_at": progress.updated_at,
+        })
+        .to_string(),
+    )
+}
+
+fn result_event(result: &JobResult) -> Event {
+    let data = serde_json::to_string(result).unwrap_or_default();
+    Event::default().event("status").data(data)
+}
+
+/// Wait out one poll interval, returning early with the job's result if its
+/// completion event arrives first
+async fn next_poll(
+    completions: Option<&mut broadcast::Receiver<Arc<JobResult>>>,
+    job_id: &str,
+    interval: Duration,
+) -> Option<Arc<JobResult>> {
+    let wait = sleep(interval);
+    let Some(completions) = completions else {
+        wait.await;
+        return None;
+    };
+    tokio::pin!(wait);
+    loop {
+        tokio::select! {
+            _ = &mut wait => return None,
+            event = completions.recv() => match event {
+                Ok(result) if result.job_id == job_id => return Some(result),
+                // Missed events are caught by the next poll
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => {
+                    wait.await;
+                    return None;
+                }
+            },
+        }
+    }
+}
+
+/// GET /jobs/{id}/events - Stream status transitions until the job finishes
+/// for good.
+///
+/// Emits a `status` event for each transition (`enqueued`, `running`, then
+/// `completed`/`failed` carrying the full result, with `retrying` set on a
+/// failure the job will be retried after), a `progress` event each time a
+/// running job reports progress, or a `timeout` event if the job doesn't
+/// finish within the configured maximum duration.
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}/events",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "Event stream of job status changes", content_type = "text/event-stream", body = String),
+    )
+)]
+pub async fn job_events_handler(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let config = state.events_config.clone();
+    // Subscribed before the first poll, so a result published in between
+    // isn't missed
+    let mut completions = state.completions.as_ref().map(|feed| feed.subscribe());
+
+    let stream = async_stream::stream! {
+        let deadline = Instant::now() + config.max_duration;
+        // Nothing recorded yet means the job is still waiting in the queue
+        let mut last_status = JobStatus::Enqueued;
+        let mut last_progress = None;
+        // When the last failed attempt that will be retried finished, so each
+        // is reported once
+        let mut last_retried: Option<DateTime<Utc>> = None;
+        yield Ok(status_event(&job_id, last_status));
+
+        loop {
+            match state.result_store.status(&job_id).await {
+                Ok(Some(status)) if status.is_terminal() => {
+                    match state.result_store.get(&job_id).await {
+                        Ok(Some(result)) if !result.is_final() => {
+                            last_status = status;
+                            if last_retried != Some(result.finished_at) {
+                                last_retried = Some(result.finished_at);
+                                yield Ok(result_event(&result));
+                            }
+                        }
+                        Ok(Some(result)) => {
+                            yield Ok(result_event(&result));
+                            break;
+                        }
+                        _ => {
+                            yield Ok(status_event(&job_id, status));
+                            break;
+                        }
+                    }
+                }
+                Ok(Some(status)) if status != last_status => {
+                    last_status = status;
+                    yield Ok(status_event(&job_id, status));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    warn!("Failed to read status of job {}: {:#}", job_id, e);
+                }
+            }
+
+            if last_status == JobStatus::Running {
+                match state.result_store.progress(&job_id).await {
+                    Ok(Some(progress)) if last_progress.as_ref() != Some(&progress) => {
+                        yield Ok(progress_event(&job_id, &progress));
+                        last_progress = Some(progress);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Failed to read progress of job {}: {:#}", job_id, e);
+                    }
+                }
+            }
+
+            if Instant::now() >= deadline {
+                yield Ok(Event::default().event("timeout").data(
+                    serde_json::json!({
+                        "job_id": job_id,
+                        "status": last_status,
+                    })
+                    .to_string(),
+                ));
+                break;
+            }
+            if let Some(result) = next_poll(completions.as_mut(), &job_id, config.poll_interval).await {
+                if result.is_final() {
+                    yield Ok(result_event(&result));
+                    break;
+                }
+                if last_retried != Some(result.finished_at) {
+                    last_retried = Some(result.finished_at);
+                    last_status = result.status;
+                    yield Ok(result_event(&result));
+                }
+            }
+        }
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
@@ -0,0 +1,457 @@
+//! GraphQL API at `/graphql`, with subscriptions over `/graphql/ws`.
+//!
+//! Mutations go through the same option checks, validation and enqueue path
+//! as the REST endpoints; queries and subscriptions read the result store.
+
+use crate::auth::Principal;
+use crate::backpressure::Backlogged;
+use crate::breaker::CircuitOpen;
+use crate::maintenance::UnderMaintenance;
+use crate::usage::QuotaExceeded;
+use crate::{enqueue_batch_jobs, submit_job, AppState, Backoff, JobOptions};
+use async_graphql::http::{
+    GraphiQLSource, WebSocket as GqlWebSocket, WebSocketProtocols, WsMessage,
+    ALL_WEBSOCKET_PROTOCOLS,
+};
+use async_graphql::{
+    Context, Data, Enum, Error, ErrorExtensions, InputObject, Json, Object, Schema, SimpleObject,
+    Subscription,
+};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::{Html, IntoResponse},
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{future, SinkExt, Stream, StreamExt};
+use job_types::{BackoffStrategy, JobPayload};
+use result_store::{JobResult, JobStatus};
+use std::sync::Arc;
+use tokio::time::{sleep, Instant};
+use tracing::warn;
+
+/// Most jobs a `jobs` query returns
+const MAX_LIST_LIMIT: usize = 1000;
+
+pub type JobSchema = Schema<QueryRoot, MutationRoot, SubscriptionRoot>;
+
+/// Build the schema; resolvers reach the rest of the service through `state`
+pub fn schema(state: Arc<AppState>) -> JobSchema {
+    Schema::build(QueryRoot, MutationRoot, SubscriptionRoot)
+        .data(state)
+        .finish()
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "result_store::JobStatus")]
+enum Status {
+    Enqueued,
+    Running,
+    Completed,
+    Failed,
+}
+
+/// A finished job, as kept in the result store
+#[derive(SimpleObject)]
+struct Job {
+    job_id: String,
+    job_type: String,
+    status: Status,
+    result: Option<Json<serde_json::Value>>,
+    error: Option<String>,
+    finished_at: DateTime<Utc>,
+}
+
+impl From<JobResult> for Job {
+    fn from(result: JobResult) -> Self {
+        Self {
+            job_id: result.job_id,
+            job_type: result.job_type,
+            status: result.status.into(),
+            result: result.result.map(Json),
+            error: result.error,
+            finished_at: result.finished_at,
+        }
+    }
+}
+
+/// A job to submit: its type (e.g. `Add`) and arguments
+#[derive(InputObject)]
+struct JobInput {
+    #[graphql(name = "type")]
+    job_type: String,
+    args: Json<serde_json::Value>,
+}
+
+#[derive(InputObject)]
+struct BackoffInput {
+    /// `fixed` or `exponential`
+    strategy: String,
+    delay_seconds: u64,
+}
+
+/// Options for every job of a submission; see `POST /jobs`
+#[derive(InputObject, Default)]
+struct JobOptionsInput {
+    callback_url: Option<String>,
+    run_at: Option<DateTime<Utc>>,
+    delay_seconds: Option<u64>,
+    priority: Option<u8>,
+    queue: Option<String>,
+    retries: Option<u32>,
+    backoff: Option<BackoffInput>,
+}
+
+#[derive(SimpleObject)]
+struct SubmittedJob {
+    job_id: String,
+    /// Faktory is unavailable; the job will be enqueued when it recovers
+    deferred: bool,
+    /// A job with the same `request_id` was submitted within the dedup
+    /// window; `jobId` is that job and nothing was enqueued
+    deduplicated: bool,
+    scheduled_at: Option<DateTime<Utc>>,
+    priority: u8,
+}
+
+#[derive(SimpleObject)]
+struct SubmittedBatch {
+    batch_id: String,
+    /// Jobs that were enqueued (or deferred)
+    job_ids: Vec<String>,
+    deferred: bool,
+    scheduled_at: Option<DateTime<Utc>>,
+    priority: u8,
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// A finished job, or null while it's pending, unknown or expired
+    async fn job(&self, ctx: &Context<'_>, id: String) -> async_graphql::Result<Option<Job>> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let result = state
+            .result_store
+            .get(&id)
+            .await
+            .map_err(|e| internal("Failed to read job result", e))?;
+        Ok(result.map(Job::from))
+    }
+
+    /// Where a job is in its lifecycle, or null if it's unknown or expired
+    async fn job_status(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+    ) -> async_graphql::Result<Option<Status>> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let status = state
+            .result_store
+            .status(&id)
+            .await
+            .map_err(|e| internal("Failed to read job status", e))?;
+        Ok(status.map(Status::from))
+    }
+
+    /// Recently finished jobs, newest first. The filters apply to the
+    /// `limit` most recent results.
+    async fn jobs(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 50)] limit: usize,
+        status: Option<Status>,
+        job_type: Option<String>,
+    ) -> async_graphql::Result<Vec<Job>> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let results = state
+            .result_store
+            .list(limit.min(MAX_LIST_LIMIT))
+            .await
+            .map_err(|e| internal("Failed to list job results", e))?;
+        let status = status.map(JobStatus::from);
+        Ok(results
+            .into_iter()
+            .filter(|result| status.is_none_or(|status| result.status == status))
+            .filter(|result| job_type.as_ref().is_none_or(|t| &result.job_type == t))
+            .map(Job::from)
+            .collect())
+    }
+}
+
+pub struct MutationRoot;
+
+#[Object]
+impl MutationRoot {
+    /// Submit one job
+    async fn submit_job(
+        &self,
+        ctx: &Context<'_>,
+        job: JobInput,
+        options: Option<JobOptionsInput>,
+    ) -> async_graphql::Result<SubmittedJob> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let options = job_options(ctx, state, options)?;
+        let payload = payload(job, "job")?;
+
+        let submitted = submit_job(state, payload, &options)
+            .await
+            .map_err(|e| submission_error("Failed to enqueue job", e))?;
+        Ok(SubmittedJob {
+            job_id: submitted.job_id,
+            deferred: submitted.deferred,
+            deduplicated: submitted.deduplicated,
+            scheduled_at: options.run_at,
+            priority: options.effective_priority(),
+        })
+    }
+
+    /// Submit several jobs in one push, as `POST /jobs/batch` does
+    async fn submit_batch(
+        &self,
+        ctx: &Context<'_>,
+        jobs: Vec<JobInput>,
+        options: Option<JobOptionsInput>,
+    ) -> async_graphql::Result<SubmittedBatch> {
+        let state = ctx.data::<Arc<AppState>>()?;
+        let options = job_options(ctx, state, options)?;
+        let max_jobs = state.batch_config.max_request_jobs;
+        if jobs.is_empty() {
+            return Err(validation("Batch request must contain at least one job"));
+        }
+        if jobs.len() > max_jobs {
+            return Err(validation(format!(
+                "jobs: At most {} jobs per batch, got {}",
+                max_jobs,
+                jobs.len()
+            )));
+        }
+        let payloads = jobs
+            .into_iter()
+            .enumerate()
+            .map(|(i, job)| payload(job, &format!("jobs[{}]", i)))
+            .collect::<async_graphql::Result<Vec<_>>>()?;
+
+        let outcome = enqueue_batch_jobs(state, payloads, &options)
+            .await
+            .map_err(|e| submission_error("Failed to enqueue batch jobs", e))?;
+        Ok(SubmittedBatch {
+            job_ids: outcome.job_ids,
+            batch_id: outcome.batch_id,
+            deferred: outcome.deferred,
+            scheduled_at: options.run_at,
+            priority: options.effective_priority(),
+        })
+    }
+}
+
+pub struct SubscriptionRoot;
+
+#[Subscription]
+impl SubscriptionRoot {
+    /// Emits the job once it completes or fails with no retries left, then
+    /// ends. Errors if it doesn't finish within the event stream's maximum
+    /// duration.
+    async fn job_completed(
+        &self,
+        ctx: &Context<'_>,
+        id: String,
+    ) -> async_graphql::Result<impl Stream<Item = async_graphql::Result<Job>>> {
+        let state = ctx.data::<Arc<AppState>>()?.clone();
+        let config = state.events_config.clone();
+
+        Ok(async_stream::stream! {
+            let deadline = Instant::now() + config.max_duration;
+            loop {
+                match state.result_store.get(&id).await {
+                    Ok(Some(result)) if result.is_final() => {
+                        yield Ok(Job::from(result));
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(e) => warn!("Failed to read result of job {}: {:#}", id, e),
+                }
+
+                if Instant::now() >= deadline {
+                    yield Err(Error::new(format!(
+                        "Job {} didn't finish within {}s",
+                        id,
+                        config.max_duration.as_secs()
+                    ))
+                    .extend_with(|_, e| e.set("code", "TIMEOUT")));
+                    break;
+                }
+                sleep(config.poll_interval).await;
+            }
+        })
+    }
+}
+
+/// POST /graphql - Execute a query or mutation
+pub async fn graphql_handler(
+    Extension(schema): Extension<JobSchema>,
+    principal: Option<Extension<Principal>>,
+    axum::Json(request): axum::Json<async_graphql::Request>,
+) -> axum::Json<async_graphql::Response> {
+    let mut request = request;
+    if let Some(Extension(principal)) = principal {
+        request = request.data(principal);
+    }
+    axum::Json(schema.execute(request).await)
+}
+
+/// GET /graphql - GraphiQL explorer
+pub async fn graphiql_handler() -> Html<String> {
+    Html(
+        GraphiQLSource::build()
+            .endpoint("/graphql")
+            .subscription_endpoint("/graphql/ws")
+            .finish(),
+    )
+}
+
+/// GET /graphql/ws - Subscriptions over `graphql-transport-ws` or the older
+/// `graphql-ws` protocol
+pub async fn graphql_ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    Extension(schema): Extension<JobSchema>,
+    principal: Option<Extension<Principal>>,
+) -> impl IntoResponse {
+    let ws = ws
+        .protocols(ALL_WEBSOCKET_PROTOCOLS)
+        .max_message_size(state.limits.max_body_bytes);
+    // Clients that don't ask for a protocol get the current one
+    let protocol = ws
+        .selected_protocol()
+        .and_then(|protocol| protocol.to_str().ok())
+        .and_then(|protocol| protocol.parse().ok())
+        .unwrap_or(WebSocketProtocols::GraphQLWS);
+    let mut data = Data::default();
+    if let Some(Extension(principal)) = principal {
+        data.insert(principal);
+    }
+    ws.on_upgrade(move |socket| handle_socket(socket, schema, protocol, data))
+}
+
+async fn handle_socket(
+    socket: WebSocket,
+    schema: JobSchema,
+    protocol: WebSocketProtocols,
+    data: Data,
+) {
+    let (mut sink, stream) = socket.split();
+    let incoming = stream
+        .take_while(|msg| future::ready(msg.is_ok()))
+        .filter_map(|msg| {
+            future::ready(match msg {
+                Ok(msg @ (Message::Text(_) | Message::Binary(_))) => Some(msg.into_data()),
+                _ => None,
+            })
+        });
+
+    let mut outgoing = GqlWebSocket::new(schema, incoming, protocol)
+        .connection_data(data)
+        .map(|msg| match msg {
+            WsMessage::Text(text) => Message::Text(text.into()),
+            WsMessage::Close(code, reason) => Message::Close(Some(CloseFrame {
+                code,
+                reason: reason.into(),
+            })),
+        });
+    while let Some(msg) = outgoing.next().await {
+        if sink.send(msg).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Options for a submission, with the caller filled in and checked as for
+/// REST requests
+fn job_options(
+    ctx: &Context<'_>,
+    state: &AppState,
+    options: Option<JobOptionsInput>,
+) -> async_graphql::Result<JobOptions> {
+    let options = options.unwrap_or_default();
+    let backoff = options
+        .backoff
+        .map(|backoff| {
+            serde_json::from_value::<BackoffStrategy>(serde_json::Value::String(backoff.strategy))
+                .map(|strategy| Backoff {
+                    strategy,
+                    delay_seconds: backoff.delay_seconds,
+                })
+                .map_err(|e| validation(format!("options.backoff.strategy: {}", e)))
+        })
+        .transpose()?;
+    let mut options = JobOptions {
+        callback_url: options
+            .callback_url
+            .map(|url| url.parse())
+            .transpose()
+            .map_err(|e| validation(format!("options.callbackUrl: {}", e)))?,
+        run_at: options.run_at,
+        delay_seconds: options.delay_seconds,
+        priority: options.priority,
+        queue: options.queue,
+        retries: options.retries,
+        backoff,
+        principal: ctx.data_opt::<Principal>().cloned(),
+        ..JobOptions::default()
+    };
+    options
+        .resolve(state)
+        .map_err(|e| validation(format!("{:#}", e)))?;
+    Ok(options)
+}
+
+/// Build and validate a job's payload. `field` names the job in errors.
+fn payload(job: JobInput, field: &str) -> async_graphql::Result<JobPayload> {
+    let payload = JobPayload::from_tagged(&job.job_type, job.args.0)
+        .map_err(|e| validation(format!("{}: {}", field, e)))?;
+    let errors: Vec<String> = payload
+        .validate()
+        .into_iter()
+        .map(|e| format!("{}.args.{}: {}", field, e.field, e.message))
+        .collect();
+    if !errors.is_empty() {
+        return Err(validation(errors.join("; ")));
+    }
+    Ok(payload)
+}
+
+fn validation(message: impl Into<String>) -> Error {
+    Error::new(message).extend_with(|_, e| e.set("code", "VALIDATION"))
+}
+
+fn internal(message: &str, e: anyhow::Error) -> Error {
+    warn!("{}: {:#}", message, e);
+    Error::new(format!("{}: {}", message, e)).extend_with(|_, e| e.set("code", "INTERNAL"))
+}
+
+/// GraphQL counterpart of `submission_error`
+fn submission_error(message: &str, e: anyhow::Error) -> Error {
+    if let Some(exceeded) = e.downcast_ref::<QuotaExceeded>() {
+        return Error::new(exceeded.to_string())
+            .extend_with(|_, e| e.set("code", "QUOTA_EXCEEDED"));
+    }
+    if let Some(backlogged) = e.downcast_ref::<Backlogged>() {
+        let retry_after = backlogged.retry_after;
+        return Error::new(backlogged.to_string()).extend_with(|_, e| {
+            e.set("code", "BACKLOGGED");
+            e.set("retryAfter", retry_after);
+        });
+    }
+    if let Some(open) = e.downcast_ref::<CircuitOpen>() {
+        return Error::new(open.to_string()).extend_with(|_, e| e.set("code", "UNAVAILABLE"));
+    }
+    if let Some(maintenance) = e.downcast_ref::<UnderMaintenance>() {
+        return Error::new(maintenance.to_string())
+            .extend_with(|_, e| e.set("code", "MAINTENANCE"));
+    }
+    internal(message, e)
+}
@@ -0,0 +1,255 @@
+//! gRPC job API (`proto/jobs.proto`), served on `GRPC_BIND_ADDR`.
+//!
+//! Each call goes through the same authentication, option checks, validation
+//! and enqueue path as its REST counterpart; only the wire format differs.
+
+use crate::auth::Principal;
+use crate::backpressure::Backlogged;
+use crate::breaker::CircuitOpen;
+use crate::maintenance::UnderMaintenance;
+use crate::usage::QuotaExceeded;
+use crate::{
+    enqueue_batch_jobs, idempotency, submit_job, AppState, Backoff, JobOptions, JobPayload,
+};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use proto::job_service_server::{JobService, JobServiceServer};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tonic::metadata::MetadataMap;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use tracing::{info, warn};
+
+pub mod proto {
+    tonic::include_proto!("workfactory.jobs.v1");
+}
+
+struct GrpcJobs {
+    state: Arc<AppState>,
+}
+
+/// Bind `addr` and serve the gRPC API in the background until `shutdown` is
+/// notified
+pub fn start(
+    state: Arc<AppState>,
+    addr: SocketAddr,
+    shutdown: Arc<Notify>,
+) -> Result<JoinHandle<Result<()>>> {
+    let incoming =
+        TcpIncoming::bind(addr).with_context(|| format!("Failed to bind gRPC address {}", addr))?;
+    info!("Starting gRPC service on {}", addr);
+    Ok(tokio::spawn(async move {
+        tonic::transport::Server::builder()
+            .add_service(JobServiceServer::new(GrpcJobs { state }))
+            .serve_with_incoming_shutdown(incoming, shutdown.notified())
+            .await?;
+        Ok(())
+    }))
+}
+
+impl GrpcJobs {
+    async fn authenticate(&self, metadata: &MetadataMap) -> Result<Option<Principal>, Status> {
+        let headers = metadata.clone().into_headers();
+        let mut principal = self
+            .state
+            .auth
+            .authenticate(&headers)
+            .await
+            .map_err(|e| Status::unauthenticated(format!("Unauthorized: {}", e)))?;
+        if let Some(principal) = &mut principal {
+            self.state
+                .tenancy
+                .apply(principal, &headers)
+                .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        }
+        Ok(principal)
+    }
+
+    /// Options for a submission, with the caller and idempotency key filled
+    /// in and checked as for REST requests
+    async fn options(
+        &self,
+        metadata: &MetadataMap,
+        options: Option<proto::JobOptions>,
+    ) -> Result<JobOptions, Status> {
+        let mut options = job_options(options.unwrap_or_default())?;
+        options.principal = self.authenticate(metadata).await?;
+        options.idempotency_key = idempotency::key_from_headers(&metadata.clone().into_headers());
+        options
+            .resolve(&self.state)
+            .map_err(|e| Status::invalid_argument(format!("{:#}", e)))?;
+        Ok(options)
+    }
+}
+
+#[tonic::async_trait]
+impl JobService for GrpcJobs {
+    async fn submit_job(
+        &self,
+        request: Request<proto::SubmitJobRequest>,
+    ) -> Result<Response<proto::SubmitJobResponse>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        let options = self.options(&metadata, request.options).await?;
+        let payload = payload(request.job, "job")?;
+
+        let submitted = submit_job(&self.state, payload, &options)
+            .await
+            .map_err(|e| submission_status("Failed to enqueue job", e))?;
+        Ok(Response::new(proto::SubmitJobResponse {
+            job_id: submitted.job_id,
+            deferred: submitted.deferred,
+            deduplicated: submitted.deduplicated,
+            priority: options.effective_priority().into(),
+            scheduled_at: options.run_at.map(|t| t.to_rfc3339()),
+        }))
+    }
+
+    async fn submit_batch(
+        &self,
+        request: Request<proto::SubmitBatchRequest>,
+    ) -> Result<Response<proto::SubmitBatchResponse>, Status> {
+        let (metadata, _, request) = request.into_parts();
+        let options = self.options(&metadata, request.options).await?;
+        let max_jobs = self.state.batch_config.max_request_jobs;
+        if request.jobs.is_empty() {
+            return Err(Status::invalid_argument(
+                "Batch request must contain at least one job",
+            ));
+        }
+        if request.jobs.len() > max_jobs {
+            return Err(Status::invalid_argument(format!(
+                "jobs: At most {} jobs per batch, got {}",
+                max_jobs,
+                request.jobs.len()
+            )));
+        }
+        let payloads = request
+            .jobs
+            .into_iter()
+            .enumerate()
+            .map(|(i, job)| payload(Some(job), &format!("jobs[{}]", i)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let outcome = enqueue_batch_jobs(&self.state, payloads, &options)
+            .await
+            .map_err(|e| submission_status("Failed to enqueue batch jobs", e))?;
+        Ok(Response::new(proto::SubmitBatchResponse {
+            job_ids: outcome.job_ids,
+            batch_id: outcome.batch_id,
+            deferred: outcome.deferred,
+            priority: options.effective_priority().into(),
+            scheduled_at: options.run_at.map(|t| t.to_rfc3339()),
+        }))
+    }
+
+    async fn get_job_status(
+        &self,
+        request: Request<proto::GetJobStatusRequest>,
+    ) -> Result<Response<proto::GetJobStatusResponse>, Status> {
+        self.authenticate(request.metadata()).await?;
+        let job_id = request.into_inner().job_id;
+        match self.state.result_store.get(&job_id).await {
+            Ok(Some(result)) => Ok(Response::new(proto::GetJobStatusResponse {
+                job_id: result.job_id,
+                job_type: result.job_type,
+                status: result.status.as_str().to_string(),
+                result_json: result.result.map(|value| value.to_string()),
+                error: result.error,
+                finished_at: result.finished_at.to_rfc3339(),
+            })),
+            Ok(None) => Err(Status::not_found(format!(
+                "No result for job {} (pending, unknown or expired)",
+                job_id
+            ))),
+            Err(e) => {
+                warn!("Failed to read job result: {:#}", e);
+                Err(Status::internal(format!(
+                    "Failed to read job result: {}",
+                    e
+                )))
+            }
+        }
+    }
+}
+
+fn invalid(field: &str, e: impl fmt::Display) -> Status {
+    Status::invalid_argument(format!("{}: {}", field, e))
+}
+
+/// Build and validate a job's payload. `field` names the job in errors.
+fn payload(job: Option<proto::Job>, field: &str) -> Result<JobPayload, Status> {
+    let job = job.ok_or_else(|| invalid(field, "required"))?;
+    let args = serde_json::from_str(&job.args_json)
+        .map_err(|e| invalid(&format!("{}.args_json", field), e))?;
+    let payload = JobPayload::from_tagged(&job.r#type, args).map_err(|e| invalid(field, e))?;
+
+    let errors: Vec<String> = payload
+        .validate()
+        .into_iter()
+        .map(|e| format!("{}.args.{}: {}", field, e.field, e.message))
+        .collect();
+    if !errors.is_empty() {
+        return Err(Status::invalid_argument(errors.join("; ")));
+    }
+    Ok(payload)
+}
+
+fn job_options(options: proto::JobOptions) -> Result<JobOptions, Status> {
+    let run_at = options
+        .run_at
+        .map(|run_at| DateTime::parse_from_rfc3339(&run_at).map(|t| t.with_timezone(&Utc)))
+        .transpose()
+        .map_err(|e| invalid("options.run_at", e))?;
+    let priority = options
+        .priority
+        .map(u8::try_from)
+        .transpose()
+        .map_err(|e| invalid("options.priority", e))?;
+    let backoff = options
+        .backoff
+        .map(|backoff| {
+            serde_json::from_value(serde_json::Value::String(backoff.strategy))
+                .map(|strategy| Backoff {
+                    strategy,
+                    delay_seconds: backoff.delay_seconds,
+                })
+                .map_err(|e| invalid("options.backoff.strategy", e))
+        })
+        .transpose()?;
+    Ok(JobOptions {
+        callback_url: options
+            .callback_url
+            .map(|url| url.parse())
+            .transpose()
+            .map_err(|e| invalid("options.callback_url", e))?,
+        run_at,
+        delay_seconds: options.delay_seconds,
+        priority,
+        queue: options.queue,
+        retries: options.retries,
+        backoff,
+        ..JobOptions::default()
+    })
+}
+
+/// gRPC counterpart of `submission_error`
+fn submission_status(message: &str, e: anyhow::Error) -> Status {
+    if let Some(exceeded) = e.downcast_ref::<QuotaExceeded>() {
+        return Status::resource_exhausted(exceeded.to_string());
+    }
+    if let Some(backlogged) = e.downcast_ref::<Backlogged>() {
+        return Status::resource_exhausted(backlogged.to_string());
+    }
+    if let Some(open) = e.downcast_ref::<CircuitOpen>() {
+        return Status::unavailable(open.to_string());
+    }
+    if let Some(maintenance) = e.downcast_ref::<UnderMaintenance>() {
+        return Status::unavailable(maintenance.to_string());
+    }
+    warn!("{}: {:#}", message, e);
+    Status::internal(format!("{}: {}", message, e))
+}
@@ -0,0 +1,169 @@
+//! Listing of recently finished jobs, and of those still queued or running.
+//!
+//! Support can find a customer's job from the request ID they were given
+//! (`X-Request-ID`) or narrow the listing by status and type, without access
+//! to the result store. Pages are keyed by the last job on the previous page,
+//! so results finishing in the meantime don't shift them. Jobs that haven't
+//! finished come from the job history of submissions, which only the Postgres
+//! result store keeps.
+//!
+//! With multi-tenancy enabled, callers other than admins only see their own
+//! tenant's jobs, or without a tenant, the jobs on the shared queues.
+
+use crate::auth::Principal;
+use crate::{AppState, ErrorResponse};
+use axum::{
+    extract::{Extension, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use result_store::{JobPage, JobQuery, JobRecord, JobStatus};
+use serde::Deserialize;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Jobs listed per page when the request doesn't say
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Most jobs listed per page
+const MAX_PAGE_SIZE: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct ListParams {
+    status: Option<String>,
+    #[serde(rename = "type")]
+    job_type: Option<String>,
+    request_id: Option<String>,
+    page: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PendingParams {
+    status: Option<String>,
+    #[serde(rename = "type")]
+    job_type: Option<String>,
+    limit: Option<usize>,
+}
+
+fn error_response(status: StatusCode, error: String) -> Response {
+    (status, Json(ErrorResponse { error })).into_response()
+}
+
+/// The tenant whose jobs `principal` may list; `None` when it may list all
+pub fn listed_tenant(state: &AppState, principal: Option<Principal>) -> Option<Option<String>> {
+    (state.tenancy.enabled && !state.admin_config.allows(principal.as_ref()))
+        .then(|| principal.and_then(|p| p.tenant))
+}
+
+/// GET /jobs - Recently finished jobs, newest first
+#[utoipa::path(
+    get,
+    path = "/jobs",
+    tag = "jobs",
+    params(
+        ("status" = Option<String>, Query, description = "completed or failed"),
+        ("type" = Option<String>, Query, description = "Job type (e.g. math_add)"),
+        ("request_id" = Option<String>, Query, description = "ID of the request that submitted the job"),
+        ("page" = Option<String>, Query, description = "next_page token from the previous page"),
+        ("limit" = Option<usize>, Query, description = "Most jobs per page (default 50, at most 1000)"),
+    ),
+    responses(
+        (status = 200, description = "A page of finished jobs", body = JobPage),
+        (status = 400, description = "Invalid status or page token", body = ErrorResponse),
+        (status = 500, description = "The result store couldn't be read", body = ErrorResponse),
+    )
+)]
+pub async fn list_jobs_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<ListParams>,
+) -> Response {
+    let status = match params.status.as_deref().map(str::parse::<JobStatus>) {
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+        Some(Ok(status)) => Some(status),
+        None => None,
+    };
+    let after = match params.page.as_deref().map(str::parse) {
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, format!("{:#}", e)),
+        Some(Ok(after)) => Some(after),
+        None => None,
+    };
+    let query = JobQuery {
+        status,
+        job_type: params.job_type,
+        request_id: params.request_id,
+        tenant: listed_tenant(&state, principal.map(|Extension(p)| p)),
+        after,
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE),
+    };
+    match state.result_store.list_jobs(&query).await {
+        Ok(page) => Json(page).into_response(),
+        Err(e) => {
+            warn!("Failed to list jobs: {:#}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list jobs: {}", e),
+            )
+        }
+    }
+}
+
+/// GET /jobs/pending - Jobs accepted but not finished, most recently
+/// submitted first
+#[utoipa::path(
+    get,
+    path = "/jobs/pending",
+    tag = "jobs",
+    params(
+        ("status" = Option<String>, Query, description = "enqueued or running"),
+        ("type" = Option<String>, Query, description = "Job type (e.g. math_add)"),
+        ("limit" = Option<usize>, Query, description = "Most jobs listed (default 50, at most 1000)"),
+    ),
+    responses(
+        (status = 200, description = "Queued and running jobs; empty unless the result store keeps job history", body = Vec<JobRecord>),
+        (status = 400, description = "Invalid status", body = ErrorResponse),
+        (status = 500, description = "The result store couldn't be read", body = ErrorResponse),
+    )
+)]
+pub async fn list_pending_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Query(params): Query<PendingParams>,
+) -> Response {
+    let status = match params.status.as_deref().map(str::parse::<JobStatus>) {
+        Some(Ok(status)) if status.is_terminal() => {
+            return error_response(
+                StatusCode::BAD_REQUEST,
+                format!("Job status {} isn't pending", status.as_str()),
+            )
+        }
+        Some(Err(e)) => return error_response(StatusCode::BAD_REQUEST, e.to_string()),
+        Some(Ok(status)) => Some(status),
+        None => None,
+    };
+    let query = JobQuery {
+        status,
+        job_type: params.job_type,
+        tenant: listed_tenant(&state, principal.map(|Extension(p)| p)),
+        limit: params
+            .limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE),
+        ..Default::default()
+    };
+    match state.result_store.list_pending(&query).await {
+        Ok(jobs) => Json(jobs).into_response(),
+        Err(e) => {
+            warn!("Failed to list pending jobs: {:#}", e);
+            error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to list pending jobs: {}", e),
+            )
+        }
+    }
+}
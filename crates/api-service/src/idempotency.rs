@@ -0,0 +1,113 @@
+//! `Idempotency-Key` support: a retried submission gets the original job
+//! id(s) back instead of enqueuing duplicates.
+//!
+//! Keys are scoped to the caller and to single-job vs batch submissions, and
+//! kept in the result store for a configurable window.
+
+use crate::{AppState, JobOptions};
+use axum::http::HeaderMap;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Request header carrying the key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Longest key accepted
+pub const MAX_KEY_LEN: usize = 255;
+
+/// Configuration for idempotent submissions
+#[derive(Clone)]
+pub struct IdempotencyConfig {
+    /// How long a key maps to its original submission
+    pub ttl: Duration,
+}
+
+/// What kind of submission a key was used for
+#[derive(Clone, Copy)]
+pub enum Scope {
+    Job,
+    Batch,
+}
+
+/// Idempotency key sent with the request, if any
+pub fn key_from_headers(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+fn store_key(options: &JobOptions, scope: Scope, key: &str) -> String {
+    let caller = options
+        .principal
+        .as_ref()
+        .map_or("anonymous", |p| p.subject.as_str());
+    let scope = match scope {
+        Scope::Job => "job",
+        Scope::Batch => "batch",
+    };
+    format!("{}:{}:{}", caller, scope, key)
+}
+
+/// Claim the request's idempotency key for `value` (the new job id, or the
+/// batch's `BatchOutcome` as JSON). Returns the value recorded by an
+/// earlier request with the same key. If the store is unavailable the
+/// request is treated as new.
+pub async fn claim(
+    state: &AppState,
+    options: &JobOptions,
+    scope: Scope,
+    value: &str,
+) -> Option<String> {
+    let key = options.idempotency_key.as_deref()?;
+    match state
+        .result_store
+        .claim_idempotency_key(
+            &store_key(options, scope, key),
+            value,
+            state.idempotency_config.ttl,
+        )
+        .await
+    {
+        Ok(Some(original)) => {
+            info!("Idempotency key {:?} replayed, returning {}", key, original);
+            Some(original)
+        }
+        Ok(None) => None,
+        Err(e) => {
+            warn!("Failed to claim idempotency key {:?}: {:#}", key, e);
+            None
+        }
+    }
+}
+
+/// Replace the value recorded for the request's key, once the submission
+/// it claimed has changed (e.g. got its Faktory batch id)
+pub async fn update(state: &AppState, options: &JobOptions, scope: Scope, value: &str) {
+    let Some(key) = options.idempotency_key.as_deref() else {
+        return;
+    };
+    if let Err(e) = state
+        .result_store
+        .update_idempotency_key(&store_key(options, scope, key), value)
+        .await
+    {
+        warn!("Failed to update idempotency key {:?}: {:#}", key, e);
+    }
+}
+
+/// Free the request's key after its submission failed, so a retry can
+/// enqueue the job
+pub async fn release(state: &AppState, options: &JobOptions, scope: Scope) {
+    let Some(key) = options.idempotency_key.as_deref() else {
+        return;
+    };
+    if let Err(e) = state
+        .result_store
+        .release_idempotency_key(&store_key(options, scope, key))
+        .await
+    {
+        warn!("Failed to release idempotency key {:?}: {:#}", key, e);
+    }
+}
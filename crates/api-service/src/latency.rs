@@ -0,0 +1,146 @@
+//! Per-route request latency and slow-request logging.
+//!
+//! Every request's duration is recorded in `api_request_duration_seconds`
+//! by method, route and status. Requests slower than `SLOW_REQUEST_MS` are
+//! also logged with a summary of what they carried (body size and type,
+//! query string, and the jobs they built by type) so the operations behind
+//! tail latency can be picked out of a load test. Durations run until the
+//! response headers are ready, so streamed bodies aren't included.
+
+use crate::AppState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderMap, HeaderName},
+    middleware::Next,
+    response::Response,
+};
+use service_config::Config;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+tokio::task_local! {
+    /// Jobs built by the request being handled, by type
+    static JOB_TYPES: RefCell<BTreeMap<String, usize>>;
+}
+
+/// Slow-request logging settings
+#[derive(Debug, Clone, Default)]
+pub struct SlowRequestConfig {
+    /// Requests taking longer are logged; off when `None`
+    pub threshold: Option<Duration>,
+}
+
+impl SlowRequestConfig {
+    /// Read `SLOW_REQUEST_MS`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            threshold: config.parse("SLOW_REQUEST_MS").map(Duration::from_millis),
+        }
+    }
+}
+
+/// Note that the current request built a job of `job_type`, for the
+/// slow-request summary
+pub fn note_job(job_type: &str) {
+    let _ = JOB_TYPES.try_with(|types| {
+        *types.borrow_mut().entry(job_type.to_string()).or_default() += 1;
+    });
+}
+
+/// What a slow request carried
+struct PayloadSummary {
+    content_type: Option<String>,
+    content_length: Option<String>,
+    query: Option<String>,
+    jobs: BTreeMap<String, usize>,
+}
+
+impl fmt::Display for PayloadSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "body {} bytes",
+            self.content_length.as_deref().unwrap_or("?")
+        )?;
+        if let Some(content_type) = &self.content_type {
+            write!(f, " ({})", content_type)?;
+        }
+        if let Some(query) = &self.query {
+            write!(f, ", query {:?}", query)?;
+        }
+        if !self.jobs.is_empty() {
+            let total: usize = self.jobs.values().sum();
+            let types: Vec<String> = self
+                .jobs
+                .iter()
+                .map(|(job_type, count)| format!("{} x{}", job_type, count))
+                .collect();
+            write!(f, ", {} jobs ({})", total, types.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+fn header_str(headers: &HeaderMap, name: HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+}
+
+/// Middleware timing each request against its route
+pub async fn observe_latency(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let method = request.method().clone();
+    // Unmatched paths would give every 404 its own series
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", |p| p.as_str())
+        .to_string();
+    let content_type = header_str(request.headers(), header::CONTENT_TYPE);
+    let content_length = header_str(request.headers(), header::CONTENT_LENGTH);
+    let query = request.uri().query().map(String::from);
+
+    let started = Instant::now();
+    let (response, jobs) = JOB_TYPES
+        .scope(RefCell::new(BTreeMap::new()), async {
+            let response = next.run(request).await;
+            (response, JOB_TYPES.with(|types| types.take()))
+        })
+        .await;
+    let elapsed = started.elapsed();
+
+    let status = response.status();
+    state
+        .metrics
+        .observe_request(method.as_str(), &route, status.as_u16(), elapsed);
+    if state
+        .slow_requests
+        .threshold
+        .is_some_and(|threshold| elapsed > threshold)
+    {
+        let summary = PayloadSummary {
+            content_type,
+            content_length,
+            query,
+            jobs,
+        };
+        warn!(
+            "Slow request: {} {} took {:.3}s ({}): {}",
+            method,
+            route,
+            elapsed.as_secs_f64(),
+            status.as_u16(),
+            summary
+        );
+    }
+    response
+}
@@ -0,0 +1,50 @@
+//! Request size limits.
+//!
+//! Buffered bodies (JSON, CSV) are capped at `max_body_bytes` as they're
+//! read, so an oversized request fails once the limit is reached instead of
+//! after the whole body is in memory. The cap applies after decompression.
+//! `/jobs/stream` isn't buffered and limits each line instead.
+
+use crate::{AppState, ErrorResponse};
+use axum::{
+    extract::{Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use std::sync::Arc;
+
+/// Request size limits
+#[derive(Debug, Clone, Copy)]
+pub struct LimitsConfig {
+    /// Largest buffered request body, and largest WebSocket message
+    pub max_body_bytes: usize,
+    /// Longest line accepted by `/jobs/stream`
+    pub max_stream_line_bytes: usize,
+}
+
+/// Replace the plain-text 413 from body extractors with a JSON error that
+/// names the limit
+pub async fn explain_body_limit(
+    State(state): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|value| value.as_bytes().starts_with(b"application/json"));
+    if response.status() != StatusCode::PAYLOAD_TOO_LARGE || is_json {
+        return response;
+    }
+
+    let response = ErrorResponse {
+        error: format!(
+            "Request body is larger than the {}-byte limit; split it into smaller requests or use POST /jobs/stream",
+            state.limits.max_body_bytes
+        ),
+    };
+    (StatusCode::PAYLOAD_TOO_LARGE, Json(response)).into_response()
+}
@@ -1,18 +1,148 @@
+mod admin;
+mod audit;
+mod auth;
+mod backpressure;
+mod batch_commit;
+mod breaker;
+mod bulk;
+mod compute;
+mod cors;
+mod dead;
+mod dedup;
+mod events;
+mod graphql;
+mod grpc;
+mod history;
+mod idempotency;
+mod latency;
+mod limits;
+mod maintenance;
+mod metrics;
+mod ndjson;
+mod openapi;
+mod push_retry;
+mod queues;
+mod reload;
+mod sampler;
+mod schedules;
+mod schema;
+mod shadow;
+mod shedding;
+mod signing;
+mod telemetry;
+mod tenant_pools;
+mod tenants;
+mod upload;
+mod usage;
+mod validate;
+mod wal;
+mod workers;
+mod ws;
+
+use admin::AdminConfig;
 use anyhow::{Context, Result};
-use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::post, Json, Router};
-use deadpool::managed::{Manager, Pool, RecycleResult};
+use audit::{AuditLog, AuditSink};
+use auth::{AuthConfig, Authenticator, Principal};
+use axum::{
+    extract::{DefaultBodyLimit, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware,
+    response::{IntoResponse, Response},
+    routing::{get, post, MethodRouter},
+    Extension, Json, Router,
+};
+use backpressure::{Backlogged, Backpressure, BackpressureConfig};
+use breaker::{BreakerConfig, CircuitBreaker, CircuitOpen};
+use chrono::{DateTime, Utc};
+use compute::ComputeConfig;
+use cors::CorsConfig;
+use deadpool::managed::{Manager, Object, Pool, PoolError, RecycleResult};
+use deadpool::Runtime;
+use dedup::DedupConfig;
+use events::EventsConfig;
 use faktory::{Client, Job};
-use job_types::{JobPayload, MathArgs};
+use idempotency::{IdempotencyConfig, Scope};
+use job_types::{BackoffStrategy, FieldError, JobKind, JobPayload, RetryPolicy, VersionInfo};
+use latency::SlowRequestConfig;
+use limits::LimitsConfig;
+use maintenance::{Maintenance, UnderMaintenance};
+use metrics::{EnqueueMode, FlushTrigger, Metrics};
+use push_retry::PushRetry;
+use queues::QueueConfig;
+use result_store::{
+    CompletionConfig, CompletionFeed, JobProgress, JobResult, JobStatus, ResultStore, StoreConfig,
+};
+use sampler::QueueSampler;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::Mutex;
+use service_config::Config;
+use service_tls::faktory::FaktoryConfig;
+use service_tls::TlsConfig;
+use shadow::{Shadow, ShadowConfig};
+use shedding::ConcurrencyLimits;
+use std::collections::HashMap;
+use std::future::{Future, IntoFuture};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tenant_pools::TenantPools;
+use tenants::TenancyConfig;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{oneshot, Mutex, Notify};
 use tokio::time::sleep;
-use tracing::{info, warn};
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
+use tracing::{info, instrument, warn};
+use url::Url;
+use usage::{QuotaConfig, QuotaExceeded};
+use utoipa::ToSchema;
+use wal::JobWal;
+use ws::WsConfig;
+
+/// Sizing and timeouts for a Faktory connection pool
+#[derive(Clone)]
+struct PoolConfig {
+    max_size: usize,
+    wait_timeout: Option<Duration>,
+    create_timeout: Option<Duration>,
+}
 
-/// Connection pool manager for Faktory clients
+impl PoolConfig {
+    /// Read `FAKTORY_POOL_MAX_SIZE` (default 50),
+    /// `FAKTORY_POOL_WAIT_TIMEOUT_MS` and `FAKTORY_POOL_CREATE_TIMEOUT_MS`
+    /// (default 5000 each; 0 disables the timeout)
+    fn from_config(config: &Config) -> Self {
+        let timeout = |key: &str| {
+            let ms: u64 = config.parse_or(key, 5000);
+            (ms > 0).then(|| Duration::from_millis(ms))
+        };
+        Self {
+            max_size: config.parse_or("FAKTORY_POOL_MAX_SIZE", 50),
+            wait_timeout: timeout("FAKTORY_POOL_WAIT_TIMEOUT_MS"),
+            create_timeout: timeout("FAKTORY_POOL_CREATE_TIMEOUT_MS"),
+        }
+    }
+}
+
+/// Connection pool manager for Faktory clients. Each manager connects to
+/// its own server, so pools for different Faktory servers can live in one
+/// process.
 struct FaktoryManager {
-    faktory_url: String,
+    faktory: FaktoryConfig,
+}
+
+impl FaktoryManager {
+    /// Build a pool of connections to the Faktory server `faktory`
+    fn pool(faktory: FaktoryConfig, config: &PoolConfig) -> Result<Pool<FaktoryManager>> {
+        let manager = FaktoryManager { faktory };
+        Pool::builder(manager)
+            .max_size(config.max_size)
+            .wait_timeout(config.wait_timeout)
+            .create_timeout(config.create_timeout)
+            .runtime(Runtime::Tokio1)
+            .build()
+            .context("Failed to create Faktory connection pool")
+    }
 }
 
 impl Manager for FaktoryManager {
@@ -20,8 +150,7 @@ impl Manager for FaktoryManager {
     type Error = faktory::Error;
 
     async fn create(&self) -> Result<Client, faktory::Error> {
-        std::env::set_var("FAKTORY_URL", &self.faktory_url);
-        Client::connect().await
+        self.faktory.client().await
     }
 
     async fn recycle(
@@ -43,11 +172,86 @@ struct BatchConfig {
     max_batch_delay_ms: u64,
     /// Whether to enable auto-batching for individual job endpoints
     auto_batch_enabled: bool,
+    /// Maximum number of jobs accepted in one `/jobs/batch` request
+    max_request_jobs: usize,
+    /// Maximum number of jobs one `/jobs/{name}/bulk` request expands into
+    max_bulk_jobs: usize,
+    /// When auto-batched jobs are acknowledged, unless the request says
+    ack_mode: AckMode,
+    /// Open each `/jobs/batch` request as a Faktory Enterprise batch
+    enterprise: bool,
+    /// Queue Faktory pushes an Enterprise batch's callback job to
+    callback_queue: String,
+}
+
+impl BatchConfig {
+    /// Read `BATCH_MAX_SIZE` (default 100), `BATCH_MAX_DELAY_MS` (default
+    /// 50), `BATCH_AUTO_ENABLED` (default true), `BATCH_REQUEST_MAX_JOBS`
+    /// (default 1000), `BATCH_BULK_MAX_JOBS` (default 100000), `BATCH_ACK`
+    /// (default fast), `BATCH_ENTERPRISE` (default false) and
+    /// `BATCH_CALLBACK_QUEUE` (default batch_callbacks)
+    fn from_config(config: &Config) -> Self {
+        Self {
+            max_batch_size: config.parse_or("BATCH_MAX_SIZE", 100),
+            max_batch_delay_ms: config.parse_or("BATCH_MAX_DELAY_MS", 50),
+            auto_batch_enabled: config.parse_or("BATCH_AUTO_ENABLED", true),
+            max_request_jobs: config.parse_or("BATCH_REQUEST_MAX_JOBS", 1000),
+            max_bulk_jobs: config.parse_or("BATCH_BULK_MAX_JOBS", 100_000),
+            ack_mode: config.parse_or("BATCH_ACK", AckMode::Fast),
+            enterprise: config.parse_or("BATCH_ENTERPRISE", false),
+            callback_queue: config.string_or("BATCH_CALLBACK_QUEUE", "batch_callbacks"),
+        }
+    }
+}
+
+/// When an auto-batched job is acknowledged to the client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+enum AckMode {
+    /// As soon as it's queued for the next flush
+    Fast,
+    /// Once its batch has been pushed to Faktory (or spilled)
+    Strong,
+}
+
+impl std::str::FromStr for AckMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fast" => Ok(AckMode::Fast),
+            "strong" => Ok(AckMode::Strong),
+            _ => anyhow::bail!("Unknown ack mode: {} (expected fast or strong)", s),
+        }
+    }
+}
+
+impl std::fmt::Display for AckMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AckMode::Fast => "fast",
+            AckMode::Strong => "strong",
+        })
+    }
+}
+
+/// How an auto-batched job left the queue, for requests waiting on a strong
+/// acknowledgement or flushing the batch themselves
+#[derive(Debug)]
+enum Flushed {
+    Enqueued,
+    /// Faktory was unreachable and the job went to the spill buffer
+    Deferred,
+    /// Faktory refused the job, or the push failed
+    Failed(String),
 }
 
 /// Batching queue for collecting jobs
 struct BatchQueue {
-    pending_jobs: Vec<JobPayload>,
+    pending_jobs: Vec<Job>,
+    /// Job id -> request waiting for the job's batch to be flushed
+    waiting: HashMap<String, oneshot::Sender<Flushed>>,
+    /// Current settings; batch size and delay change on reload
     config: BatchConfig,
 }
 
@@ -55,311 +259,1195 @@ impl BatchQueue {
     fn new(config: BatchConfig) -> Self {
         Self {
             pending_jobs: Vec::with_capacity(config.max_batch_size),
+            waiting: HashMap::new(),
             config,
         }
     }
 
-    fn add(&mut self, job: JobPayload) {
+    fn add(&mut self, job: Job) {
         self.pending_jobs.push(job);
     }
 
+    /// Queue a job and get notified once its batch is flushed
+    fn add_and_wait(&mut self, job: Job) -> oneshot::Receiver<Flushed> {
+        let rx = self.wait(job.id().as_str());
+        self.add(job);
+        rx
+    }
+
+    /// Get notified once the batch holding a queued job is flushed
+    fn wait(&mut self, job_id: &str) -> oneshot::Receiver<Flushed> {
+        let (tx, rx) = oneshot::channel();
+        self.waiting.insert(job_id.to_string(), tx);
+        rx
+    }
+
     fn should_flush(&self) -> bool {
         self.pending_jobs.len() >= self.config.max_batch_size
     }
 
-    fn flush(&mut self) -> Vec<JobPayload> {
-        std::mem::replace(
-            &mut self.pending_jobs,
-            Vec::with_capacity(self.config.max_batch_size),
-        )
+    fn flush(&mut self) -> PendingBatch {
+        PendingBatch {
+            jobs: std::mem::replace(
+                &mut self.pending_jobs,
+                Vec::with_capacity(self.config.max_batch_size),
+            ),
+            waiting: std::mem::take(&mut self.waiting),
+        }
     }
 
     fn len(&self) -> usize {
         self.pending_jobs.len()
     }
+
+    fn max_batch_delay(&self) -> Duration {
+        Duration::from_millis(self.config.max_batch_delay_ms)
+    }
+
+    /// Change the batch size and delay, keeping the jobs already queued
+    fn set_limits(&mut self, max_batch_size: usize, max_batch_delay_ms: u64) {
+        self.config.max_batch_size = max_batch_size;
+        self.config.max_batch_delay_ms = max_batch_delay_ms;
+    }
+}
+
+/// Jobs taken from the batch queue for one flush
+struct PendingBatch {
+    jobs: Vec<Job>,
+    waiting: HashMap<String, oneshot::Sender<Flushed>>,
 }
 
 /// Shared application state
 #[derive(Clone)]
 struct AppState {
     faktory_pool: Pool<FaktoryManager>,
+    /// Pools pushes for individual tenants use instead of `faktory_pool`
+    tenant_pools: Arc<TenantPools>,
     batch_queue: Arc<Mutex<BatchQueue>>,
+    /// Settings at startup; the batch queue has the reloadable ones
     batch_config: BatchConfig,
+    /// Durable log of auto-batched jobs (`BATCH_WAL_PATH`)
+    batch_wal: Option<Arc<JobWal>>,
+    /// Trips when pushes to Faktory keep failing
+    breaker: Arc<CircuitBreaker>,
+    /// Latest queue depths and worker throughput
+    queue_sampler: Arc<QueueSampler>,
+    /// Holds back submissions to backlogged queues
+    backpressure: Arc<Backpressure>,
+    /// Jobs held while the circuit is open (`SPILL_PATH`)
+    spill: Option<Arc<JobWal>>,
+    result_store: Arc<dyn ResultStore>,
+    compute_config: ComputeConfig,
+    events_config: EventsConfig,
+    ws_config: WsConfig,
+    /// Results published by workers (`COMPLETION_EVENTS_URL`)
+    completions: Option<CompletionFeed>,
+    auth: Arc<Authenticator>,
+    admin_config: AdminConfig,
+    /// Record of accepted submissions (`AUDIT_SINK`)
+    audit: Option<Arc<AuditLog>>,
+    tenancy: TenancyConfig,
+    quota_config: Arc<RwLock<QuotaConfig>>,
+    queue_config: QueueConfig,
+    retry_limits: RetryLimits,
+    idempotency_config: IdempotencyConfig,
+    dedup: DedupConfig,
+    limits: LimitsConfig,
+    slow_requests: SlowRequestConfig,
+    /// Retries for pushes that hit a broken connection
+    push_retry: PushRetry,
+    /// Mirrors a sample of pushed jobs (`SHADOW_PERCENT`)
+    shadow: Option<Arc<Shadow>>,
+    /// Refuses submissions while switched on
+    maintenance: Arc<Maintenance>,
+    metrics: Arc<Metrics>,
+}
+
+/// Server-side maximums for client-requested retry options
+#[derive(Clone)]
+struct RetryLimits {
+    max_retries: u32,
+    max_backoff_seconds: u64,
+}
+
+/// Per-submission options that map onto Faktory job fields
+#[derive(Debug, Default, Clone, Deserialize, ToSchema)]
+struct JobOptions {
+    /// URL the worker POSTs a completion notification to when the job finishes
+    callback_url: Option<Url>,
+    /// When the job should run (defaults to immediately)
+    run_at: Option<DateTime<Utc>>,
+    /// Run the job this many seconds from now (alternative to `run_at`)
+    delay_seconds: Option<u64>,
+    /// Faktory priority from 1 (lowest) to 9 (highest)
+    priority: Option<u8>,
+    /// Target queue (must be on the allowlist; defaults per job type)
+    queue: Option<String>,
+    /// How many times to retry a failed job (Faktory's default is 25)
+    retries: Option<u32>,
+    /// Delay between retries, instead of Faktory's retry schedule
+    backoff: Option<Backoff>,
+    /// With auto-batching, `strong` waits until the job has been pushed to
+    /// Faktory before responding (defaults to `BATCH_ACK`)
+    ack: Option<AckMode>,
+    /// Authenticated caller, recorded on the job (never read from the body)
+    #[serde(skip)]
+    principal: Option<Principal>,
+    /// `Idempotency-Key` header value
+    #[serde(skip)]
+    idempotency_key: Option<String>,
+    /// Never deduplicate by `request_id`, for submissions that repeat a
+    /// payload on purpose
+    #[serde(skip)]
+    skip_dedup: bool,
+}
+
+/// Retry delay requested by the client
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema)]
+struct Backoff {
+    strategy: BackoffStrategy,
+    delay_seconds: u64,
+}
+
+/// Priority Faktory gives jobs that don't set one
+const DEFAULT_PRIORITY: u8 = 5;
+
+/// Retries Faktory gives jobs that don't set a count
+const DEFAULT_RETRIES: u32 = 25;
+
+impl JobOptions {
+    /// Validate the options and turn `delay_seconds` into an absolute
+    /// `run_at`, so every job in a request is scheduled for the same time
+    fn resolve(&mut self, state: &AppState) -> Result<()> {
+        if let Some(key) = &self.idempotency_key {
+            if key.len() > idempotency::MAX_KEY_LEN {
+                anyhow::bail!(
+                    "Idempotency-Key must be at most {} characters",
+                    idempotency::MAX_KEY_LEN
+                );
+            }
+        }
+        if let Some(queue) = &self.queue {
+            state.queue_config.check(queue)?;
+        }
+        let limits = &state.retry_limits;
+        if let Some(retries) = self.retries {
+            if retries > limits.max_retries {
+                anyhow::bail!(
+                    "retries must be at most {}, got {}",
+                    limits.max_retries,
+                    retries
+                );
+            }
+        }
+        if let Some(backoff) = &self.backoff {
+            if backoff.delay_seconds > limits.max_backoff_seconds {
+                anyhow::bail!(
+                    "backoff.delay_seconds must be at most {}, got {}",
+                    limits.max_backoff_seconds,
+                    backoff.delay_seconds
+                );
+            }
+        }
+        if let Some(priority) = self.priority {
+            if !(1..=9).contains(&priority) {
+                anyhow::bail!("priority must be between 1 and 9, got {}", priority);
+            }
+        }
+        if let Some(delay) = self.delay_seconds.take() {
+            if self.run_at.is_some() {
+                anyhow::bail!("Specify either run_at or delay_seconds, not both");
+            }
+            let run_at = i64::try_from(delay)
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|delay| Utc::now().checked_add_signed(delay))
+                .context("delay_seconds is out of range")?;
+            self.run_at = Some(run_at);
+        }
+        Ok(())
+    }
+
+    /// Priority the job will run with
+    fn effective_priority(&self) -> u8 {
+        self.priority.unwrap_or(DEFAULT_PRIORITY)
+    }
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct MathRequest {
     a: f64,
     b: f64,
     request_id: Option<String>,
+    #[serde(flatten)]
+    options: JobOptions,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct JobResponse {
     job_id: String,
     message: String,
+    /// Faktory was unavailable; the job is held locally and will be enqueued
+    /// when it recovers
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deferred: bool,
+    /// A job with the same `request_id` was submitted within
+    /// `DEDUP_WINDOW_SECS`; `job_id` is that job and nothing was enqueued
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deduplicated: bool,
+    /// When a delayed job is scheduled to run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_at: Option<DateTime<Utc>>,
+    priority: u8,
+    /// Jobs waiting in the job's queue when it was submitted (as of the last
+    /// sample of Faktory's queues)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    queue_depth: Option<u64>,
+    /// Rough seconds until a worker picks the job up, from the queue depth
+    /// and recent worker throughput
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_wait_seconds: Option<f64>,
 }
 
-#[derive(Debug, Serialize)]
+impl JobResponse {
+    /// Response for an accepted job. Jobs queued to run now include their
+    /// queue's depth and an expected wait; deferred and scheduled jobs don't.
+    fn new(state: &AppState, submitted: Submitted, message: String, options: &JobOptions) -> Self {
+        let estimate = if submitted.deferred || submitted.deduplicated || options.run_at.is_some() {
+            None
+        } else {
+            state.queue_sampler.estimate(&submitted.queue)
+        };
+        Self {
+            job_id: submitted.job_id,
+            message,
+            deferred: submitted.deferred,
+            deduplicated: submitted.deduplicated,
+            scheduled_at: options.run_at,
+            priority: options.effective_priority(),
+            queue_depth: estimate.map(|(depth, _)| depth),
+            estimated_wait_seconds: estimate.and_then(|(_, wait)| wait),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
 struct ErrorResponse {
     error: String,
 }
 
+/// 404 body for a job without a result. A job that's running says so, with
+/// the progress it last reported, if any.
+#[derive(Debug, Serialize, ToSchema)]
+struct PendingJobResponse {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<JobStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    progress: Option<JobProgress>,
+}
+
+/// 422 body listing every invalid field
+#[derive(Debug, Serialize, ToSchema)]
+struct ValidationErrorResponse {
+    error: String,
+    details: Vec<FieldError>,
+}
+
+/// A job of any type: the tagged `JobPayload` plus job options
+#[derive(Debug, Deserialize, ToSchema)]
+struct JobRequest {
+    #[serde(flatten)]
+    job: JobPayload,
+    #[serde(flatten)]
+    options: JobOptions,
+}
+
 /// Batch job request containing multiple operations
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, ToSchema)]
 struct BatchJobRequest {
-    jobs: Vec<JobPayload>,
+    /// Parsed one at a time, so a malformed job is reported in `results`
+    /// instead of failing the whole batch
+    #[schema(value_type = Vec<JobPayload>)]
+    jobs: Vec<serde_json::Value>,
+    /// Options applied to every job in the batch
+    #[serde(flatten)]
+    options: JobOptions,
 }
 
 /// Response for batch job submission
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, ToSchema)]
 struct BatchJobResponse {
+    /// Shared by the jobs of this request (their `batch_id` custom field)
+    batch_id: String,
     job_ids: Vec<String>,
     message: String,
     total_enqueued: usize,
+    /// Faktory was unavailable; the jobs are held locally and will be
+    /// enqueued when it recovers
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deferred: bool,
+    /// What happened to each job, in request order
+    results: Vec<BatchItem>,
+    /// When the delayed jobs are scheduled to run
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scheduled_at: Option<DateTime<Utc>>,
+    priority: u8,
 }
 
-/// Helper to enqueue a job to Faktory
-async fn enqueue_job(pool: Pool<FaktoryManager>, payload: JobPayload) -> Result<String> {
-    // Create job
-    let job_type = payload.job_type();
+/// Outcome of one job of a `/jobs/batch` request, by its position in `jobs`
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum BatchItem {
+    Accepted {
+        index: usize,
+        job_id: String,
+    },
+    /// The job was invalid; the rest of the batch went ahead
+    Rejected {
+        index: usize,
+        error: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        details: Vec<FieldError>,
+    },
+}
+
+/// Parse and validate one job of a batch. Errors name the job's index.
+fn parse_batch_item(
+    index: usize,
+    value: serde_json::Value,
+) -> Result<JobPayload, (String, Vec<FieldError>)> {
+    let payload: JobPayload =
+        serde_json::from_value(value).map_err(|e| (format!("Invalid job: {}", e), Vec::new()))?;
+    let details: Vec<FieldError> = payload
+        .validate()
+        .into_iter()
+        .map(|e| FieldError::new(format!("jobs[{}].args.{}", index, e.field), e.message))
+        .collect();
+    if !details.is_empty() {
+        return Err(("Validation failed".to_string(), details));
+    }
+    Ok(payload)
+}
+
+/// What happened to the jobs of a `/jobs/batch` request
+#[derive(Debug, Serialize, Deserialize)]
+struct BatchOutcome {
+    batch_id: String,
+    /// Every job of the batch, in request order
+    job_ids: Vec<String>,
+    #[serde(default)]
+    deferred: bool,
+}
+
+/// A job accepted by `enqueue_job` or `submit_job`
+struct Submitted {
+    job_id: String,
+    /// Queue the job was routed to
+    queue: String,
+    /// Held in the spill buffer until Faktory recovers
+    deferred: bool,
+    /// An earlier job with the same `request_id`; nothing was enqueued
+    deduplicated: bool,
+}
+
+/// Where jobs handed to `deliver` ended up
+enum Delivery<T> {
+    /// Pushed to Faktory
+    Enqueued(T),
+    /// Held in the spill buffer
+    Deferred,
+}
+
+/// Custom job field tying a job to its `/jobs/batch` request
+const BATCH_ID_FIELD: &str = "batch_id";
+
+/// Build the Faktory job for a payload
+fn build_job(state: &AppState, payload: &JobPayload, options: &JobOptions) -> Result<Job> {
+    state.maintenance.check()?;
     let args = payload.to_args()?;
+    let mut job = Job::new(payload.job_type(), vec![args]);
+    let queue = state
+        .queue_config
+        .route(payload.job_type(), options.queue.as_deref());
+    let principal = options.principal.as_ref();
+    let divert = state
+        .backpressure
+        .divert_queue()
+        .map(|queue| state.tenancy.queue(principal, &queue));
+    job.queue = state
+        .backpressure
+        .admit(state.tenancy.queue(principal, queue), divert)?;
+
+    if let Some(callback_url) = &options.callback_url {
+        job.custom
+            .insert("callback_url".to_string(), callback_url.as_str().into());
+    }
+    job.at = options.run_at;
+    job.priority = options.priority;
+    telemetry::inject_context(&mut job.custom);
+    telemetry::inject_request_id(&mut job.custom);
+    latency::note_job(payload.job_type());
+    match (options.backoff, options.retries) {
+        // The worker schedules retries itself; Faktory sends the final
+        // failure straight to the dead set
+        (Some(backoff), retries) => {
+            let policy = RetryPolicy {
+                max_retries: retries.unwrap_or(DEFAULT_RETRIES),
+                strategy: backoff.strategy,
+                delay_seconds: backoff.delay_seconds,
+            };
+            job.retry = Some(-1);
+            job.custom.insert(
+                RetryPolicy::FIELD.to_string(),
+                serde_json::to_value(policy)?,
+            );
+        }
+        (None, Some(retries)) => job.retry = Some(retries as isize),
+        (None, None) => {}
+    }
+    if let Some(principal) = &options.principal {
+        job.custom
+            .insert("submitted_by".to_string(), principal.subject.clone().into());
+        if let Some(tenant) = &principal.tenant {
+            job.custom
+                .insert("tenant".to_string(), tenant.clone().into());
+        }
+    }
+
+    Ok(job)
+}
 
-    let job = Job::new(job_type, vec![args]);
+/// Record delivered jobs (enqueued, or spilled to be enqueued later) in the
+/// result store's job history and the audit log. Failures are logged rather
+/// than failing a submission that already reached Faktory.
+async fn record_submissions(state: &AppState, jobs: &[Job]) {
+    audit::record(state, jobs).await;
+    for job in jobs {
+        let args = job.args().first().cloned().unwrap_or_default();
+        let tenant = audit::custom_str(job, "tenant");
+        if let Err(e) = state
+            .result_store
+            .record_submitted(job.id(), job.kind(), &args, tenant.as_deref())
+            .await
+        {
+            warn!(
+                "Failed to record submission of job {}: {:#}",
+                job.id().as_str(),
+                e
+            );
+        }
+    }
+}
+
+/// Helper to enqueue a job to Faktory
+#[instrument(skip_all, fields(job_type = payload.job_type()))]
+async fn enqueue_job(
+    state: &AppState,
+    payload: JobPayload,
+    options: &JobOptions,
+) -> Result<Submitted> {
+    // Create job
+    let job = build_job(state, &payload, options)?;
     let job_id = job.id().to_string();
+    let queue = job.queue.clone();
+    if let Some(original) = idempotency::claim(state, options, Scope::Job, &job_id).await {
+        return Ok(Submitted {
+            job_id: original,
+            queue,
+            deferred: false,
+            deduplicated: false,
+        });
+    }
+    if let Some(original) = dedup::claim(state, options, &payload, &job_id).await {
+        // A retry with the same key should find the original job too
+        idempotency::release(state, options, Scope::Job).await;
+        return Ok(Submitted {
+            job_id: original,
+            queue,
+            deferred: false,
+            deduplicated: true,
+        });
+    }
+    if let Err(e) = usage::charge(state, options.principal.as_ref(), 1).await {
+        idempotency::release(state, options, Scope::Job).await;
+        dedup::release(state, options, &payload).await;
+        return Err(e);
+    }
 
-    // Get a connection from the pool
-    let mut client = pool
-        .get()
-        .await
-        .context("Failed to get Faktory connection from pool")?;
+    let delivery = deliver(state, vec![job.clone()], |mut jobs| {
+        push_job(state, jobs.remove(0))
+    })
+    .await;
+    match delivery {
+        Ok(delivery) => {
+            record_submissions(state, std::slice::from_ref(&job)).await;
+            Ok(Submitted {
+                job_id,
+                queue,
+                deferred: matches!(delivery, Delivery::Deferred),
+                deduplicated: false,
+            })
+        }
+        Err(e) => {
+            usage::refund(state, options.principal.as_ref(), 1).await;
+            idempotency::release(state, options, Scope::Job).await;
+            dedup::release(state, options, &payload).await;
+            Err(e)
+        }
+    }
+}
 
-    // Push to Faktory
-    client.enqueue(job).await.context("Failed to enqueue job")?;
+/// Take a connection from the pool, recording how long it took and whether
+/// it timed out
+async fn faktory_client(
+    pool: &Pool<FaktoryManager>,
+    metrics: &Metrics,
+) -> Result<Object<FaktoryManager>> {
+    let started = Instant::now();
+    let client = pool.get().await;
+    metrics.observe_pool_wait(started.elapsed());
+    if let Err(PoolError::Timeout(stage)) = &client {
+        metrics.pool_timed_out(*stage);
+    }
+    client.context("Failed to get Faktory connection from pool")
+}
+
+/// Push a single job over a pooled connection
+async fn push_job(state: &AppState, job: Job) -> Result<()> {
+    let job_id = job.id().to_string();
+    let job_type = job.kind().to_string();
+
+    let pool = state
+        .tenant_pools
+        .pool(&state.faktory_pool, std::slice::from_ref(&job));
+
+    let started = Instant::now();
+    let enqueued: Result<()> = state
+        .push_retry
+        .run(
+            || async {
+                // Get a connection from the pool
+                let mut client = faktory_client(&pool, &state.metrics).await?;
+
+                // Push to Faktory
+                if let Err(e) = client.enqueue(job.clone()).await {
+                    push_retry::discard_if_broken(client, &e);
+                    return Err(anyhow::Error::new(e).context("Failed to enqueue job"));
+                }
+                Ok(())
+            },
+            || state.metrics.enqueue_retried(EnqueueMode::Single),
+        )
+        .await;
+    state
+        .metrics
+        .observe_enqueue(EnqueueMode::Single, started.elapsed());
+    if let Err(e) = enqueued {
+        state.metrics.enqueue_failed(EnqueueMode::Single);
+        return Err(e);
+    }
 
+    state.metrics.job_enqueued(&job_type);
+    if let Some(shadow) = &state.shadow {
+        shadow.mirror([&job]);
+    }
     info!("Enqueued job {} of type {}", job_id, job_type);
+    Ok(())
+}
 
-    Ok(job_id)
+/// Whether a failed push means Faktory is unreachable, rather than Faktory
+/// refusing the job
+fn is_outage(e: &anyhow::Error) -> bool {
+    if e.is::<batch_commit::BatchRejected>() {
+        return false;
+    }
+    !matches!(
+        e.downcast_ref::<faktory::Error>(),
+        Some(faktory::Error::Protocol(_) | faktory::Error::Serialization(_))
+    )
 }
 
-/// Helper to enqueue multiple jobs in a batch (much more efficient over network)
+/// Push jobs to Faktory through the circuit breaker. While the circuit is
+/// open, or if the push fails because Faktory is unreachable, the jobs are
+/// written to the spill buffer instead (when one is configured) and
+/// `spill_replayer` enqueues them once Faktory recovers.
+async fn deliver<T, Fut>(
+    state: &AppState,
+    jobs: Vec<Job>,
+    push: impl FnOnce(Vec<Job>) -> Fut,
+) -> Result<Delivery<T>>
+where
+    Fut: Future<Output = Result<T>>,
+{
+    let jobs = if state.breaker.allow() {
+        // Keep a copy to spill if the push fails
+        let fallback = state.spill.as_ref().map(|_| jobs.clone());
+        match push(jobs).await {
+            Ok(pushed) => {
+                state.breaker.record_success();
+                return Ok(Delivery::Enqueued(pushed));
+            }
+            Err(e) if !is_outage(&e) => {
+                state.breaker.record_success();
+                return Err(e);
+            }
+            Err(e) => {
+                state.breaker.record_failure();
+                match fallback {
+                    Some(jobs) => {
+                        warn!("Spilling {} jobs after failed push: {:#}", jobs.len(), e);
+                        jobs
+                    }
+                    None => return Err(e),
+                }
+            }
+        }
+    } else {
+        jobs
+    };
+
+    let Some(spill) = &state.spill else {
+        return Err(CircuitOpen.into());
+    };
+    for job in &jobs {
+        spill.append(job).await?;
+    }
+    state.metrics.jobs_spilled(jobs.len());
+    Ok(Delivery::Deferred)
+}
+
+/// Enqueue jobs as one batch: either all of them are enqueued (or deferred)
+/// or none are (see `batch_commit`)
+#[instrument(skip_all, fields(jobs = payloads.len()))]
 async fn enqueue_batch_jobs(
-    pool: Pool<FaktoryManager>,
+    state: &AppState,
     payloads: Vec<JobPayload>,
-) -> Result<Vec<String>> {
-    if payloads.is_empty() {
-        return Ok(vec![]);
-    }
+    options: &JobOptions,
+) -> Result<BatchOutcome> {
+    let enterprise = state.batch_config.enterprise;
+    let mut jobs = payloads
+        .iter()
+        .map(|payload| build_job(state, payload, options))
+        .collect::<Result<Vec<_>>>()?;
+    let job_count = jobs.len();
+    let mut outcome = BatchOutcome {
+        batch_id: uuid::Uuid::new_v4().to_string(),
+        job_ids: jobs.iter().map(|job| job.id().to_string()).collect(),
+        deferred: false,
+    };
 
-    // Get a single connection from the pool for all jobs
-    let mut client = pool
-        .get()
-        .await
-        .context("Failed to get Faktory connection from pool")?;
+    // Claimed before opening a Faktory batch, so a replay doesn't leave an
+    // empty one behind
+    let claim = serde_json::to_string(&outcome)?;
+    if let Some(original) = idempotency::claim(state, options, Scope::Batch, &claim).await {
+        return serde_json::from_str(&original).context("Invalid idempotency record");
+    }
+    if let Err(e) = usage::charge(state, options.principal.as_ref(), job_count).await {
+        idempotency::release(state, options, Scope::Batch).await;
+        return Err(e);
+    }
+    if enterprise {
+        match batch_commit::open(state, job_count).await {
+            Ok(bid) => outcome.batch_id = bid,
+            Err(e) => {
+                usage::refund(state, options.principal.as_ref(), job_count).await;
+                idempotency::release(state, options, Scope::Batch).await;
+                return Err(e);
+            }
+        }
+        idempotency::update(
+            state,
+            options,
+            Scope::Batch,
+            &serde_json::to_string(&outcome)?,
+        )
+        .await;
+    }
+    for job in &mut jobs {
+        job.custom
+            .insert(BATCH_ID_FIELD.to_string(), outcome.batch_id.clone().into());
+    }
 
-    let mut job_ids = Vec::with_capacity(payloads.len());
+    let bid = enterprise.then_some(outcome.batch_id.as_str());
+    let delivery = deliver(state, jobs.clone(), |jobs| {
+        batch_commit::push(state, jobs, bid)
+    })
+    .await;
+    match delivery {
+        Ok(Delivery::Enqueued(())) => {}
+        Ok(Delivery::Deferred) => outcome.deferred = true,
+        Err(e) => {
+            usage::refund(state, options.principal.as_ref(), job_count).await;
+            idempotency::release(state, options, Scope::Batch).await;
+            return Err(e);
+        }
+    }
+    record_submissions(state, &jobs).await;
+    Ok(outcome)
+}
 
-    // Create all jobs first
-    let mut jobs = Vec::with_capacity(payloads.len());
-    for payload in payloads {
-        let job_type = payload.job_type();
-        let args = payload.to_args()?;
-        let job = Job::new(job_type, vec![args]);
-        job_ids.push(job.id().to_string());
-        jobs.push(job);
+/// Push already-built jobs with one bulk command (`PUSHB`) over a single
+/// pooled connection. Returns the jobs Faktory rejected (job id -> reason);
+/// all others were enqueued.
+#[instrument(skip_all, fields(jobs = jobs.len()))]
+async fn enqueue_jobs(state: &AppState, jobs: Vec<Job>) -> Result<HashMap<String, String>> {
+    let metrics = &state.metrics;
+    if jobs.is_empty() {
+        return Ok(HashMap::new());
     }
 
-    // Enqueue all jobs using a single connection
-    for job in jobs {
-        client
-            .enqueue(job)
-            .await
-            .context("Failed to enqueue job in batch")?;
+    let job_types: Vec<(String, String)> = jobs
+        .iter()
+        .map(|job| (job.id().to_string(), job.kind().to_string()))
+        .collect();
+
+    let pool = state.tenant_pools.pool(&state.faktory_pool, &jobs);
+
+    let started = Instant::now();
+    let pushed: Result<HashMap<String, String>> = state
+        .push_retry
+        .run(
+            || async {
+                // Get a single connection from the pool for all jobs
+                let mut client = faktory_client(&pool, metrics).await?;
+
+                match client.enqueue_many(jobs.clone()).await {
+                    Ok((_, rejected)) => Ok(rejected.unwrap_or_default()),
+                    Err(e) => {
+                        push_retry::discard_if_broken(client, &e);
+                        Err(anyhow::Error::new(e).context("Failed to enqueue jobs in batch"))
+                    }
+                }
+            },
+            || metrics.enqueue_retried(EnqueueMode::Batch),
+        )
+        .await;
+    metrics.observe_enqueue(EnqueueMode::Batch, started.elapsed());
+    let rejected = match pushed {
+        Ok(rejected) => rejected,
+        Err(e) => {
+            metrics.enqueue_failed(EnqueueMode::Batch);
+            return Err(e);
+        }
+    };
+
+    for (job_id, job_type) in &job_types {
+        if !rejected.contains_key(job_id) {
+            metrics.job_enqueued(job_type);
+        }
+    }
+    if let Some(shadow) = &state.shadow {
+        shadow.mirror(
+            jobs.iter()
+                .filter(|job| !rejected.contains_key(job.id().as_str())),
+        );
+    }
+    if !rejected.is_empty() {
+        metrics.enqueue_failed(EnqueueMode::Batch);
+        warn!(
+            "Faktory rejected {} of {} jobs in batch",
+            rejected.len(),
+            job_types.len()
+        );
     }
 
-    info!("Enqueued batch of {} jobs", job_ids.len());
+    info!(
+        "Enqueued batch of {} jobs",
+        job_types.len() - rejected.len()
+    );
 
-    Ok(job_ids)
+    Ok(rejected)
 }
 
 /// Helper to enqueue a job with auto-batching support
 /// This collects jobs and flushes them when the batch is full
-async fn enqueue_job_with_batching(state: &AppState, payload: JobPayload) -> Result<String> {
-    // Create the job to get its ID
-    let job_type = payload.job_type();
-    let args = payload.to_args()?;
-    let job = Job::new(job_type, vec![args]);
+#[instrument(skip_all, fields(job_type = payload.job_type()))]
+async fn enqueue_job_with_batching(
+    state: &AppState,
+    payload: JobPayload,
+    options: &JobOptions,
+) -> Result<Submitted> {
+    // Create the job up front so the returned ID is the one Faktory will see
+    let job = build_job(state, &payload, options)?;
     let job_id = job.id().to_string();
+    let queue = job.queue.clone();
+    if let Some(original) = idempotency::claim(state, options, Scope::Job, &job_id).await {
+        return Ok(Submitted {
+            job_id: original,
+            queue,
+            deferred: false,
+            deduplicated: false,
+        });
+    }
+    if let Some(original) = dedup::claim(state, options, &payload, &job_id).await {
+        // A retry with the same key should find the original job too
+        idempotency::release(state, options, Scope::Job).await;
+        return Ok(Submitted {
+            job_id: original,
+            queue,
+            deferred: false,
+            deduplicated: true,
+        });
+    }
+    if let Err(e) = usage::charge(state, options.principal.as_ref(), 1).await {
+        idempotency::release(state, options, Scope::Job).await;
+        dedup::release(state, options, &payload).await;
+        return Err(e);
+    }
+    if let Some(wal) = &state.batch_wal {
+        if let Err(e) = wal.append(&job).await {
+            usage::refund(state, options.principal.as_ref(), 1).await;
+            idempotency::release(state, options, Scope::Job).await;
+            dedup::release(state, options, &payload).await;
+            return Err(e);
+        }
+    }
 
     // Add to batch queue
-    let should_flush = {
+    let ack_mode = options.ack.unwrap_or(state.batch_config.ack_mode);
+    let (batch, flushed) = {
         let mut queue = state.batch_queue.lock().await;
-        queue.add(payload);
-        queue.should_flush()
-    };
-
-    // If batch is full, flush it immediately
-    if should_flush {
-        let jobs_to_flush = {
-            let mut queue = state.batch_queue.lock().await;
-            queue.flush()
+        let mut flushed = match ack_mode {
+            AckMode::Strong => Some(queue.add_and_wait(job)),
+            AckMode::Fast => {
+                queue.add(job);
+                None
+            }
         };
+        // If batch is full, this request flushes it, and so waits to hear
+        // how its job fared even with a fast acknowledgement
+        let batch = if queue.should_flush() {
+            if flushed.is_none() {
+                flushed = Some(queue.wait(&job_id));
+            }
+            Some(queue.flush())
+        } else {
+            None
+        };
+        (batch, flushed)
+    };
 
-        info!(
-            "Auto-flushing batch of {} jobs (batch full)",
-            jobs_to_flush.len()
-        );
-        enqueue_batch_jobs(state.faktory_pool.clone(), jobs_to_flush).await?;
+    if let Some(batch) = batch {
+        // A failed push is reported to `flushed` below, which refunds it
+        if let Err(e) = flush_batch(state, batch, FlushTrigger::Full).await {
+            warn!("Failed to flush full batch: {:#}", e);
+        }
     }
 
-    Ok(job_id)
-}
+    let deferred = match flushed {
+        None => false,
+        Some(flushed) => match flushed.await {
+            Ok(Flushed::Enqueued) => false,
+            Ok(Flushed::Deferred) => true,
+            Ok(Flushed::Failed(error)) => {
+                usage::refund(state, options.principal.as_ref(), 1).await;
+                idempotency::release(state, options, Scope::Job).await;
+                dedup::release(state, options, &payload).await;
+                anyhow::bail!("Failed to enqueue job {}: {}", job_id, error);
+            }
+            Err(_) => anyhow::bail!("Job {} was dropped before its batch was flushed", job_id),
+        },
+    };
 
-/// POST /jobs/add - Add two numbers
-async fn add_handler(
-    State(state): State<Arc<AppState>>,
-    Json(req): Json<MathRequest>,
-) -> impl IntoResponse {
-    let payload = JobPayload::Add(MathArgs {
-        a: req.a,
-        b: req.b,
-        request_id: req.request_id,
-    });
+    Ok(Submitted {
+        job_id,
+        queue,
+        deferred,
+        deduplicated: false,
+    })
+}
 
-    let result = if state.batch_config.auto_batch_enabled {
-        enqueue_job_with_batching(&state, payload).await
-    } else {
-        enqueue_job(state.faktory_pool.clone(), payload).await
+/// Push jobs taken from the auto-batch queue, record the delivered ones,
+/// mark them flushed in the write-ahead log and tell requests waiting on a
+/// strong acknowledgement
+async fn flush_batch(state: &AppState, batch: PendingBatch, trigger: FlushTrigger) -> Result<()> {
+    let PendingBatch {
+        mut jobs,
+        mut waiting,
+    } = batch;
+    info!("Flushing batch of {} jobs ({:?})", jobs.len(), trigger);
+    state.metrics.batch_flushed(trigger, jobs.len());
+    let job_ids: Vec<String> = jobs.iter().map(|job| job.id().to_string()).collect();
+    let delivery = deliver(state, jobs.clone(), |jobs| enqueue_jobs(state, jobs)).await;
+    let mut notify = |job_id: &str, flushed: Flushed| {
+        if let Some(tx) = waiting.remove(job_id) {
+            // The request may have gone away
+            let _ = tx.send(flushed);
+        }
     };
-
-    match result {
-        Ok(job_id) => {
-            let response = JobResponse {
-                job_id,
-                message: format!("Job enqueued to add {} + {}", req.a, req.b),
-            };
-            (StatusCode::ACCEPTED, Json(response)).into_response()
+    match delivery {
+        Ok(Delivery::Enqueued(rejected)) => {
+            for (job_id, error) in &rejected {
+                warn!("Faktory rejected job {}: {}", job_id, error);
+            }
+            for job_id in &job_ids {
+                match rejected.get(job_id) {
+                    Some(error) => notify(job_id, Flushed::Failed(error.clone())),
+                    None => notify(job_id, Flushed::Enqueued),
+                }
+            }
+            jobs.retain(|job| !rejected.contains_key(job.id().as_str()));
+        }
+        Ok(Delivery::Deferred) => {
+            for job_id in &job_ids {
+                notify(job_id, Flushed::Deferred);
+            }
         }
         Err(e) => {
-            warn!("Failed to enqueue job: {:#}", e);
-            let response = ErrorResponse {
-                error: format!("Failed to enqueue job: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
+            for job_id in &job_ids {
+                notify(job_id, Flushed::Failed(format!("{:#}", e)));
+            }
+            return Err(e);
         }
     }
+    record_submissions(state, &jobs).await;
+    // Rejected jobs would be rejected again, and spilled jobs are now in the
+    // spill buffer, so they're all acked
+    if let Some(wal) = &state.batch_wal {
+        wal.ack(job_ids).await?;
+    }
+    Ok(())
 }
 
-/// POST /jobs/subtract - Subtract two numbers
-async fn subtract_handler(
-    State(state): State<Arc<AppState>>,
-    Json(req): Json<MathRequest>,
-) -> impl IntoResponse {
-    let payload = JobPayload::Subtract(MathArgs {
-        a: req.a,
-        b: req.b,
-        request_id: req.request_id,
-    });
+/// Enqueue a job through the auto-batcher when enabled, or directly otherwise
+async fn submit_job(
+    state: &AppState,
+    payload: JobPayload,
+    options: &JobOptions,
+) -> Result<Submitted> {
+    if state.batch_config.auto_batch_enabled {
+        enqueue_job_with_batching(state, payload, options).await
+    } else {
+        enqueue_job(state, payload, options).await
+    }
+}
 
-    let result = if state.batch_config.auto_batch_enabled {
-        enqueue_job_with_batching(&state, payload).await
+/// Message for an accepted math job, e.g. `add 1 + 2`
+fn accepted_message(deferred: bool, operation: String) -> String {
+    if deferred {
+        format!(
+            "Faktory is unavailable; job to {} will be enqueued when it recovers",
+            operation
+        )
     } else {
-        enqueue_job(state.faktory_pool.clone(), payload).await
+        format!("Job enqueued to {}", operation)
+    }
+}
+
+/// 400 response for a request with invalid options
+fn bad_request(e: anyhow::Error) -> Response {
+    let response = ErrorResponse {
+        error: format!("{:#}", e),
     };
+    (StatusCode::BAD_REQUEST, Json(response)).into_response()
+}
 
-    match result {
-        Ok(job_id) => {
-            let response = JobResponse {
-                job_id,
-                message: format!("Job enqueued to subtract {} - {}", req.a, req.b),
-            };
-            (StatusCode::ACCEPTED, Json(response)).into_response()
-        }
-        Err(e) => {
-            warn!("Failed to enqueue job: {:#}", e);
-            let response = ErrorResponse {
-                error: format!("Failed to enqueue job: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
-        }
-    }
+/// 422 response for a request that would only produce failing jobs
+fn validation_error(details: Vec<FieldError>) -> Response {
+    let response = ValidationErrorResponse {
+        error: "Validation failed".to_string(),
+        details,
+    };
+    (StatusCode::UNPROCESSABLE_ENTITY, Json(response)).into_response()
 }
 
-/// POST /jobs/multiply - Multiply two numbers
-async fn multiply_handler(
-    State(state): State<Arc<AppState>>,
-    Json(req): Json<MathRequest>,
-) -> impl IntoResponse {
-    let payload = JobPayload::Multiply(MathArgs {
-        a: req.a,
-        b: req.b,
-        request_id: req.request_id,
-    });
+/// Response for a failed submission: 429 if the caller is over quota or the
+/// job's queue is backlogged, 503 if the Faktory circuit is open, otherwise
+/// 500
+fn submission_error(message: &str, e: anyhow::Error) -> Response {
+    if let Some(exceeded) = e.downcast_ref::<QuotaExceeded>() {
+        let response = ErrorResponse {
+            error: exceeded.to_string(),
+        };
+        return (StatusCode::TOO_MANY_REQUESTS, Json(response)).into_response();
+    }
+    if let Some(backlogged) = e.downcast_ref::<Backlogged>() {
+        let response = ErrorResponse {
+            error: backlogged.to_string(),
+        };
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, backlogged.retry_after.to_string())],
+            Json(response),
+        )
+            .into_response();
+    }
+    if let Some(open) = e.downcast_ref::<CircuitOpen>() {
+        let response = ErrorResponse {
+            error: open.to_string(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response();
+    }
+    if let Some(maintenance) = e.downcast_ref::<UnderMaintenance>() {
+        let response = ErrorResponse {
+            error: maintenance.to_string(),
+        };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response();
+    }
 
-    let result = if state.batch_config.auto_batch_enabled {
-        enqueue_job_with_batching(&state, payload).await
-    } else {
-        enqueue_job(state.faktory_pool.clone(), payload).await
+    warn!("{}: {:#}", message, e);
+    let response = ErrorResponse {
+        error: format!("{}: {}", message, e),
     };
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
+}
 
-    match result {
-        Ok(job_id) => {
-            let response = JobResponse {
-                job_id,
-                message: format!("Job enqueued to multiply {} × {}", req.a, req.b),
-            };
+/// POST /jobs/{kind} - Submit a job of one type, with its arguments at the
+/// top level of the body. Mounted once per entry in `JobPayload::KINDS` by
+/// `typed_job_routes`; the OpenAPI document lists each route separately.
+#[utoipa::path(
+    post,
+    path = "/jobs/{kind}",
+    tag = "jobs",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Return the original job instead of enqueuing a duplicate")),
+    request_body = MathRequest,
+    responses(
+        (status = 202, description = "Job enqueued", body = JobResponse),
+        (status = 400, description = "Invalid job options", body = ErrorResponse),
+        (status = 422, description = "Invalid input", body = ValidationErrorResponse),
+        (status = 429, description = "Quota exceeded or queue backlogged (see `Retry-After`)", body = ErrorResponse),
+        (status = 500, description = "Failed to enqueue", body = ErrorResponse),
+        (status = 503, description = "Faktory is unavailable and no spill buffer is configured", body = ErrorResponse),
+    )
+)]
+async fn typed_job_handler(
+    state: Arc<AppState>,
+    kind: JobKind,
+    principal: Option<Principal>,
+    headers: HeaderMap,
+    body: serde_json::Value,
+) -> Response {
+    // The arguments and the job options share the body
+    let mut options: JobOptions = match serde_json::from_value(body.clone()) {
+        Ok(options) => options,
+        Err(e) => return validation_error(vec![FieldError::new("body", e.to_string())]),
+    };
+    let payload = match JobPayload::from_tagged(kind.tag(), body) {
+        Ok(payload) => payload,
+        Err(e) => return validation_error(vec![FieldError::new("body", e.to_string())]),
+    };
+    options.principal = principal;
+    options.idempotency_key = idempotency::key_from_headers(&headers);
+    if let Err(e) = options.resolve(&state) {
+        return bad_request(e);
+    }
+
+    let errors = payload.validate();
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+
+    let description = payload.describe();
+    match submit_job(&state, payload, &options).await {
+        Ok(submitted) => {
+            let message = accepted_message(submitted.deferred, description);
+            let response = JobResponse::new(&state, submitted, message, &options);
             (StatusCode::ACCEPTED, Json(response)).into_response()
         }
-        Err(e) => {
-            warn!("Failed to enqueue job: {:#}", e);
-            let response = ErrorResponse {
-                error: format!("Failed to enqueue job: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
-        }
+        Err(e) => submission_error("Failed to enqueue job", e),
     }
 }
 
-/// POST /jobs/divide - Divide two numbers
-async fn divide_handler(
+/// `POST /jobs/{name}` for every job type in the registry, each wrapped by
+/// `limit`
+fn typed_job_routes(
+    limit: impl Fn(&str, MethodRouter<Arc<AppState>>) -> MethodRouter<Arc<AppState>>,
+) -> Router<Arc<AppState>> {
+    JobPayload::KINDS
+        .iter()
+        .fold(Router::new(), |router, &kind| {
+            let path = format!("/jobs/{}", kind.name);
+            let route = post(
+                move |State(state): State<Arc<AppState>>,
+                      principal: Option<Extension<Principal>>,
+                      headers: HeaderMap,
+                      Json(body): Json<serde_json::Value>| {
+                    typed_job_handler(state, kind, principal.map(|Extension(p)| p), headers, body)
+                },
+            );
+            router.route(&path, limit(&path, route))
+        })
+}
+
+/// POST /jobs - Submit a job of any type
+#[utoipa::path(
+    post,
+    path = "/jobs",
+    tag = "jobs",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Return the original job instead of enqueuing a duplicate")),
+    request_body(content = JobRequest, example = json!({"type": "Add", "args": {"a": 1, "b": 2}, "priority": 7})),
+    responses(
+        (status = 202, description = "Job enqueued", body = JobResponse),
+        (status = 400, description = "Invalid job options", body = ErrorResponse),
+        (status = 422, description = "Unknown job type or invalid input", body = ValidationErrorResponse),
+        (status = 429, description = "Quota exceeded or queue backlogged (see `Retry-After`)", body = ErrorResponse),
+        (status = 500, description = "Failed to enqueue", body = ErrorResponse),
+        (status = 503, description = "Faktory is unavailable and no spill buffer is configured", body = ErrorResponse),
+    )
+)]
+async fn job_handler(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<MathRequest>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    Json(mut req): Json<JobRequest>,
 ) -> impl IntoResponse {
-    let payload = JobPayload::Divide(MathArgs {
-        a: req.a,
-        b: req.b,
-        request_id: req.request_id,
-    });
+    req.options.principal = principal.map(|Extension(p)| p);
+    req.options.idempotency_key = idempotency::key_from_headers(&headers);
+    if let Err(e) = req.options.resolve(&state) {
+        return bad_request(e);
+    }
 
-    let result = if state.batch_config.auto_batch_enabled {
-        enqueue_job_with_batching(&state, payload).await
-    } else {
-        enqueue_job(state.faktory_pool.clone(), payload).await
-    };
+    let errors: Vec<FieldError> = req
+        .job
+        .validate()
+        .into_iter()
+        .map(|e| FieldError::new(format!("args.{}", e.field), e.message))
+        .collect();
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
 
-    match result {
-        Ok(job_id) => {
-            let response = JobResponse {
-                job_id,
-                message: format!("Job enqueued to divide {} ÷ {}", req.a, req.b),
-            };
+    let job_type = req.job.job_type();
+    match submit_job(&state, req.job, &req.options).await {
+        Ok(submitted) => {
+            let message = accepted_message(submitted.deferred, format!("run {}", job_type));
+            let response = JobResponse::new(&state, submitted, message, &req.options);
             (StatusCode::ACCEPTED, Json(response)).into_response()
         }
-        Err(e) => {
-            warn!("Failed to enqueue job: {:#}", e);
-            let response = ErrorResponse {
-                error: format!("Failed to enqueue job: {}", e),
-            };
-            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
-        }
+        Err(e) => submission_error("Failed to enqueue job", e),
     }
 }
 
 /// POST /jobs/batch - Submit multiple jobs at once for optimal network performance
+#[utoipa::path(
+    post,
+    path = "/jobs/batch",
+    tag = "jobs",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Return the original job instead of enqueuing a duplicate")),
+    request_body = BatchJobRequest,
+    responses(
+        (status = 202, description = "Jobs enqueued", body = BatchJobResponse),
+        (status = 207, description = "Some jobs were invalid; the rest were enqueued (see `results`)", body = BatchJobResponse),
+        (status = 400, description = "Empty batch or invalid job options", body = ErrorResponse),
+        (status = 413, description = "Request body is larger than `REQUEST_MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 422, description = "Too many jobs, or none of them valid", body = ValidationErrorResponse),
+        (status = 429, description = "Quota exceeded or queue backlogged (see `Retry-After`)", body = ErrorResponse),
+        (status = 500, description = "Failed to enqueue, or Faktory refused a job; nothing was enqueued", body = ErrorResponse),
+        (status = 503, description = "Faktory is unavailable and no spill buffer is configured", body = ErrorResponse),
+    )
+)]
 async fn batch_handler(
     State(state): State<Arc<AppState>>,
-    Json(req): Json<BatchJobRequest>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    Json(mut req): Json<BatchJobRequest>,
 ) -> impl IntoResponse {
+    req.options.principal = principal.map(|Extension(p)| p);
+    req.options.idempotency_key = idempotency::key_from_headers(&headers);
+    if let Err(e) = req.options.resolve(&state) {
+        return bad_request(e);
+    }
     let job_count = req.jobs.len();
 
     if job_count == 0 {
@@ -368,20 +1456,143 @@ async fn batch_handler(
         };
         return (StatusCode::BAD_REQUEST, Json(response)).into_response();
     }
+    if job_count > state.batch_config.max_request_jobs {
+        return validation_error(vec![FieldError::new(
+            "jobs",
+            format!(
+                "At most {} jobs per batch, got {}",
+                state.batch_config.max_request_jobs, job_count
+            ),
+        )]);
+    }
+
+    // Invalid jobs are reported per item; the valid ones are still enqueued
+    let mut payloads = Vec::with_capacity(job_count);
+    let mut invalid = Vec::new();
+    for (index, value) in req.jobs.into_iter().enumerate() {
+        match parse_batch_item(index, value) {
+            Ok(payload) => payloads.push((index, payload)),
+            Err((error, details)) => invalid.push((index, error, details)),
+        }
+    }
+    if payloads.is_empty() {
+        let details = invalid
+            .into_iter()
+            .flat_map(|(index, error, details)| {
+                if details.is_empty() {
+                    vec![FieldError::new(format!("jobs[{}]", index), error)]
+                } else {
+                    details
+                }
+            })
+            .collect();
+        return validation_error(details);
+    }
 
-    match enqueue_batch_jobs(state.faktory_pool.clone(), req.jobs).await {
-        Ok(job_ids) => {
-            let response = BatchJobResponse {
-                total_enqueued: job_ids.len(),
-                job_ids,
-                message: format!("Successfully enqueued {} jobs in batch", job_count),
+    let (indices, payloads): (Vec<usize>, Vec<JobPayload>) = payloads.into_iter().unzip();
+    let outcome = match enqueue_batch_jobs(&state, payloads, &req.options).await {
+        Ok(outcome) => outcome,
+        Err(e) => return submission_error("Failed to enqueue batch jobs", e),
+    };
+
+    let mut results: Vec<BatchItem> = indices
+        .into_iter()
+        .zip(&outcome.job_ids)
+        .map(|(index, job_id)| BatchItem::Accepted {
+            index,
+            job_id: job_id.clone(),
+        })
+        .chain(
+            invalid
+                .into_iter()
+                .map(|(index, error, details)| BatchItem::Rejected {
+                    index,
+                    error,
+                    details,
+                }),
+        )
+        .collect();
+    results.sort_by_key(|item| match item {
+        BatchItem::Accepted { index, .. } | BatchItem::Rejected { index, .. } => *index,
+    });
+
+    let job_ids = outcome.job_ids;
+    let failed = job_count - job_ids.len();
+    let (status, message) = if failed > 0 {
+        (
+            StatusCode::MULTI_STATUS,
+            format!(
+                "Enqueued {} of {} jobs in batch; {} rejected",
+                job_ids.len(),
+                job_count,
+                failed
+            ),
+        )
+    } else if outcome.deferred {
+        (
+            StatusCode::ACCEPTED,
+            format!(
+                "Faktory is unavailable; {} jobs will be enqueued when it recovers",
+                job_count
+            ),
+        )
+    } else {
+        (
+            StatusCode::ACCEPTED,
+            format!("Successfully enqueued {} jobs in batch", job_count),
+        )
+    };
+    let response = BatchJobResponse {
+        batch_id: outcome.batch_id,
+        total_enqueued: job_ids.len(),
+        job_ids,
+        deferred: outcome.deferred,
+        message,
+        results,
+        scheduled_at: req.options.run_at,
+        priority: req.options.effective_priority(),
+    };
+    (status, Json(response)).into_response()
+}
+
+/// GET /jobs/{id} - Look up the result of a job
+#[utoipa::path(
+    get,
+    path = "/jobs/{id}",
+    tag = "jobs",
+    params(("id" = String, Path, description = "Job id")),
+    responses(
+        (status = 200, description = "The job finished", body = JobResult),
+        (status = 404, description = "Pending, unknown or expired; running jobs include their progress", body = PendingJobResponse),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+async fn job_status_handler(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    match state.result_store.get(&job_id).await {
+        Ok(Some(result)) => (StatusCode::OK, Json(result)).into_response(),
+        Ok(None) => {
+            // Failing to read these only loses detail from the 404
+            let status = state.result_store.status(&job_id).await.ok().flatten();
+            let progress = match status {
+                Some(JobStatus::Running) => {
+                    state.result_store.progress(&job_id).await.ok().flatten()
+                }
+                _ => None,
             };
-            (StatusCode::ACCEPTED, Json(response)).into_response()
+            let response = PendingJobResponse {
+                error: format!("No result for job {} (pending, unknown or expired)", job_id),
+                status,
+                progress,
+            };
+            (StatusCode::NOT_FOUND, Json(response)).into_response()
         }
         Err(e) => {
-            warn!("Failed to enqueue batch jobs: {:#}", e);
+            warn!("Failed to read job result: {:#}", e);
             let response = ErrorResponse {
-                error: format!("Failed to enqueue batch jobs: {}", e),
+                error: format!("Failed to read job result: {}", e),
             };
             (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
         }
@@ -389,6 +1600,15 @@ async fn batch_handler(
 }
 
 /// Health check endpoint
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    responses(
+        (status = 200, description = "The service is up", body = Object, example = json!({"status": "healthy", "service": "api-service"})),
+    ),
+    security(),
+)]
 async fn health_handler() -> impl IntoResponse {
     Json(serde_json::json!({
         "status": "healthy",
@@ -396,20 +1616,133 @@ async fn health_handler() -> impl IntoResponse {
     }))
 }
 
-/// Background task that periodically flushes the batch queue
-async fn batch_flusher(
-    pool: Pool<FaktoryManager>,
-    batch_queue: Arc<Mutex<BatchQueue>>,
-    flush_interval_ms: u64,
-) {
-    let interval = Duration::from_millis(flush_interval_ms);
+/// Build of this binary
+fn version_info() -> VersionInfo {
+    VersionInfo {
+        service: "api-service".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("GIT_SHA").to_string(),
+        built_at: env!("BUILD_TIMESTAMP").to_string(),
+        schema_version: job_types::SCHEMA_VERSION,
+    }
+}
+
+/// Version, commit and build time, with the job payload schema version the
+/// binary understands
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "health",
+    responses(
+        (status = 200, description = "Build details", body = VersionInfo),
+    ),
+    security(),
+)]
+async fn version_handler() -> Json<VersionInfo> {
+    Json(version_info())
+}
+
+/// How long the deep health check waits for Faktory
+const DEEP_HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Deep health check: round-trips to Faktory over a pooled connection and
+/// reports pool usage
+#[utoipa::path(
+    get,
+    path = "/health/deep",
+    tag = "health",
+    responses(
+        (status = 200, description = "Faktory is reachable", body = Object, example = json!({
+            "status": "healthy",
+            "service": "api-service",
+            "faktory": {"status": "ok", "latency_ms": 1},
+            "circuit": "closed",
+            "maintenance": false,
+            "pool": {"max_size": 50, "size": 3, "in_use": 1, "idle": 2, "waiting": 0}
+        })),
+        (status = 503, description = "Faktory is unreachable; `faktory.error` says why", body = Object),
+    ),
+    security(),
+)]
+async fn deep_health_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let started = Instant::now();
+    let ping = async {
+        let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+        client
+            .current_info()
+            .await
+            .context("Failed to query Faktory")?;
+        Ok(())
+    };
+    let ping: Result<()> = tokio::time::timeout(DEEP_HEALTH_TIMEOUT, ping)
+        .await
+        .unwrap_or_else(|_| {
+            Err(anyhow::anyhow!(
+                "Faktory did not respond within {}ms",
+                DEEP_HEALTH_TIMEOUT.as_millis()
+            ))
+        });
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    let (status, faktory) = match ping {
+        Ok(()) => (
+            StatusCode::OK,
+            serde_json::json!({"status": "ok", "latency_ms": latency_ms}),
+        ),
+        Err(e) => {
+            warn!("Deep health check failed: {:#}", e);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                serde_json::json!({"status": "error", "error": format!("{:#}", e)}),
+            )
+        }
+    };
+    let pool = state.faktory_pool.status();
+    let body = serde_json::json!({
+        "status": if status == StatusCode::OK { "healthy" } else { "unhealthy" },
+        "service": "api-service",
+        "faktory": faktory,
+        "circuit": state.breaker.state(),
+        "maintenance": state.maintenance.is_enabled(),
+        "pool": {
+            "max_size": pool.max_size,
+            "size": pool.size,
+            "in_use": pool.size.saturating_sub(pool.available),
+            "idle": pool.available,
+            "waiting": pool.waiting,
+        },
+    });
+    (status, Json(body))
+}
+
+/// gzip/zstd response compression for clients that accept it. Streamed
+/// responses (event streams and NDJSON acknowledgements) are sent
+/// uncompressed so each line goes out as soon as it's written.
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        DefaultPredicate::new().and(NotForContentType::const_new(ndjson::NDJSON_CONTENT_TYPE)),
+    )
+}
 
+/// Background task that periodically flushes the batch queue. When
+/// `shutdown` is notified it flushes whatever is left and returns.
+async fn batch_flusher(state: Arc<AppState>, shutdown: Arc<Notify>) {
     loop {
-        sleep(interval).await;
+        // Read each time round so a reload takes effect
+        let interval = state.batch_queue.lock().await.max_batch_delay();
+        let shutting_down = tokio::select! {
+            _ = sleep(interval) => false,
+            _ = shutdown.notified() => true,
+        };
+        let trigger = if shutting_down {
+            FlushTrigger::Shutdown
+        } else {
+            FlushTrigger::Timeout
+        };
 
         // Check if there are jobs to flush
-        let jobs_to_flush = {
-            let mut queue = batch_queue.lock().await;
+        let batch = {
+            let mut queue = state.batch_queue.lock().await;
             if queue.len() > 0 {
                 Some(queue.flush())
             } else {
@@ -418,56 +1751,172 @@ async fn batch_flusher(
         };
 
         // Flush jobs if any
-        if let Some(jobs) = jobs_to_flush {
-            info!("Batch flusher: flushing {} jobs after timeout", jobs.len());
-            if let Err(e) = enqueue_batch_jobs(pool.clone(), jobs).await {
+        if let Some(batch) = batch {
+            if let Err(e) = flush_batch(&state, batch, trigger).await {
                 warn!("Batch flusher: failed to flush jobs: {:#}", e);
             }
         }
+
+        if shutting_down {
+            info!("Batch flusher: drained");
+            return;
+        }
+    }
+}
+
+/// Background task that pushes spilled jobs to Faktory once the circuit
+/// lets pushes through again
+async fn spill_replayer(state: Arc<AppState>, spill: Arc<JobWal>, interval: Duration) {
+    loop {
+        sleep(interval).await;
+
+        // Drain the buffer a batch at a time while pushes keep succeeding
+        loop {
+            let batch_size = state.batch_queue.lock().await.config.max_batch_size;
+            let jobs = spill.pending(batch_size).await;
+            if jobs.is_empty() || !state.breaker.allow() {
+                break;
+            }
+            let job_ids: Vec<String> = jobs.iter().map(|job| job.id().to_string()).collect();
+            match enqueue_jobs(&state, jobs).await {
+                Ok(rejected) => {
+                    state.breaker.record_success();
+                    for (job_id, error) in &rejected {
+                        warn!("Faktory rejected spilled job {}: {}", job_id, error);
+                    }
+                    info!("Replayed {} spilled jobs", job_ids.len());
+                    if let Err(e) = spill.ack(job_ids).await {
+                        warn!("Spill replayer: failed to ack jobs: {:#}", e);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    if is_outage(&e) {
+                        state.breaker.record_failure();
+                    } else {
+                        state.breaker.record_success();
+                    }
+                    warn!("Spill replayer: failed to push jobs: {:#}", e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Resolves on SIGTERM or SIGINT
+async fn shutdown_signal() {
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to setup SIGTERM handler");
+    let mut sigint = signal(SignalKind::interrupt()).expect("Failed to setup SIGINT handler");
+
+    tokio::select! {
+        _ = sigterm.recv() => {
+            warn!("Received SIGTERM, initiating graceful shutdown...");
+        }
+        _ = sigint.recv() => {
+            warn!("Received SIGINT (Ctrl+C), initiating graceful shutdown...");
+        }
     }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
-
-    // Configuration
-    let faktory_url =
-        std::env::var("FAKTORY_URL").unwrap_or_else(|_| "tcp://localhost:7419".to_string());
-    let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:3000".to_string());
+    // Configuration from the environment and CONFIG_FILE
+    let config = Config::load()?;
+
+    // Initialize logging and tracing
+    let telemetry = telemetry::init(&config)?;
+
+    let faktory_config = FaktoryConfig::from_config(&config)?;
+    let bind_addr = config.string_or("BIND_ADDR", "0.0.0.0:3000");
+    let grpc_bind_addr: Option<SocketAddr> = config.parse("GRPC_BIND_ADDR");
+    let tls_config = TlsConfig::from_config(&config)?;
+    let store_config = StoreConfig::from_config(&config)?;
+    let completion_config = CompletionConfig::from_config(&config);
+    let audit_sink = AuditSink::from_config(&config, &store_config)?;
+    let auth_config = AuthConfig::from_config(&config)?;
+    let admin_config = AdminConfig::from_config(&config);
+    let tenancy = TenancyConfig::from_config(&config)?;
+    let quota_config = QuotaConfig::from_config(&config)?;
+    let queue_config = QueueConfig::from_config(&config)?;
+    let cors_config = CorsConfig::from_config(&config)?;
+    let concurrency_limits = ConcurrencyLimits::from_config(&config)?;
+    let slow_requests = SlowRequestConfig::from_config(&config);
+    let push_retry = PushRetry::from_config(&config);
+    let idempotency_ttl_secs = config.parse_or("IDEMPOTENCY_TTL_SECS", 86400);
+    let dedup = DedupConfig::from_config(&config);
+    let retry_limits = RetryLimits {
+        max_retries: config.parse_or("RETRY_MAX", DEFAULT_RETRIES),
+        max_backoff_seconds: config.parse_or("RETRY_MAX_BACKOFF_SECS", 3600),
+    };
 
     // Batch configuration
-    let max_batch_size = std::env::var("BATCH_MAX_SIZE")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(100);
-    let max_batch_delay_ms = std::env::var("BATCH_MAX_DELAY_MS")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(50);
-    let auto_batch_enabled = std::env::var("BATCH_AUTO_ENABLED")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(true);
-
-    info!("Faktory URL: {}", faktory_url);
-    info!("Binding to: {}", bind_addr);
-    info!(
-        "Batch config: max_size={}, max_delay={}ms, auto_batch={}",
-        max_batch_size, max_batch_delay_ms, auto_batch_enabled
+    let batch_config = BatchConfig::from_config(&config);
+    let batch_wal_path = config.string("BATCH_WAL_PATH");
+
+    // Request size limits
+    let limits = LimitsConfig {
+        max_body_bytes: config.parse_or("REQUEST_MAX_BODY_BYTES", 2 * 1024 * 1024),
+        max_stream_line_bytes: config.parse_or("STREAM_MAX_LINE_BYTES", 64 * 1024),
+    };
+
+    // Synchronous compute configuration
+    let compute_timeout_ms = config.parse_or("COMPUTE_TIMEOUT_MS", 5000);
+    let compute_poll_interval_ms = config.parse_or("COMPUTE_POLL_INTERVAL_MS", 20);
+
+    // Job event stream (SSE) configuration
+    let sse_poll_interval_ms = config.parse_or("SSE_POLL_INTERVAL_MS", 100);
+    let sse_max_duration_secs = config.parse_or("SSE_MAX_DURATION_SECS", 300);
+
+    // WebSocket channel configuration
+    let ws_poll_interval_ms = config.parse_or("WS_POLL_INTERVAL_MS", 100);
+    let ws_max_pending_jobs = config.parse_or("WS_MAX_PENDING_JOBS", 10_000);
+
+    // Recurring job scheduler configuration
+    let scheduler_interval_ms = config.parse_or("SCHEDULER_INTERVAL_MS", 1000);
+
+    // Circuit breaker around Faktory pushes
+    let breaker_config = BreakerConfig {
+        failure_threshold: config.parse_or("CIRCUIT_FAILURE_THRESHOLD", 5),
+        cooldown: Duration::from_secs(config.parse_or("CIRCUIT_COOLDOWN_SECS", 10)),
+    };
+    let spill_path = config.string("SPILL_PATH");
+    let backpressure_config = BackpressureConfig::from_config(&config);
+    let queue_sample_interval_ms = config.parse_or("QUEUE_SAMPLE_INTERVAL_MS", 1000);
+    let spill_replay_interval_ms = config.parse_or("SPILL_REPLAY_INTERVAL_MS", 1000);
+
+    // How long shutdown waits for in-flight requests
+    let shutdown_timeout_secs = config.parse_or("SHUTDOWN_TIMEOUT_SECS", 30);
+
+    // Faktory connection pool
+    let pool_config = PoolConfig::from_config(&config);
+    let shadow_config = ShadowConfig::from_config(&config)?;
+    let tenant_pools = TenantPools::from_config(
+        &config,
+        faktory_config.clone(),
+        &pool_config,
+        tenancy.enabled,
     );
 
+    config.validate()?;
+    config.log_effective();
+
     // Create Faktory connection pool
-    let manager = FaktoryManager {
-        faktory_url: faktory_url.clone(),
-    };
-    let faktory_pool = Pool::builder(manager)
-        .max_size(50) // Allow up to 50 concurrent connections
-        .build()
-        .context("Failed to create Faktory connection pool")?;
+    info!(
+        "Connecting to Faktory at {}{}",
+        faktory_config.url(),
+        if faktory_config.is_tls() {
+            " over TLS"
+        } else {
+            ""
+        }
+    );
+    let faktory_pool = FaktoryManager::pool(faktory_config, &pool_config)?;
 
-    info!("Created Faktory connection pool with max size 50");
+    info!(
+        "Created Faktory connection pool with max size {}",
+        pool_config.max_size
+    );
 
     // Test the pool by getting a connection
     info!("Testing Faktory connection pool...");
@@ -477,44 +1926,275 @@ async fn main() -> Result<()> {
         .context("Failed to get test connection from pool")?;
     info!("Successfully connected to Faktory");
 
-    // Create batch configuration and queue
-    let batch_config = BatchConfig {
-        max_batch_size,
-        max_batch_delay_ms,
-        auto_batch_enabled,
+    info!("Authentication mode: {:?}", auth_config.mode);
+    let auth = Arc::new(Authenticator::new(auth_config).await?);
+
+    info!("Connecting to {:?} result store", store_config.backend);
+    let result_store = result_store::connect(&store_config).await?;
+    let completions = CompletionFeed::start(&completion_config)?;
+    if let Some(completions) = &completions {
+        info!(
+            "Subscribed to completion events on {}",
+            completions.channel()
+        );
+    }
+
+    let audit = match audit_sink {
+        Some(sink) => {
+            let audit = AuditLog::open(sink, result_store.clone()).await?;
+            info!("Audit log: {}", audit.describe());
+            Some(Arc::new(audit))
+        }
+        None => None,
     };
+
+    // Create batch queue
     let batch_queue = Arc::new(Mutex::new(BatchQueue::new(batch_config.clone())));
 
-    // Start background batch flusher
-    let flusher_pool = faktory_pool.clone();
-    let flusher_queue = batch_queue.clone();
-    tokio::spawn(async move {
-        batch_flusher(flusher_pool, flusher_queue, max_batch_delay_ms).await;
-    });
-    info!("Started batch flusher background task");
+    // Queue auto-batched jobs that were accepted but not flushed before the
+    // last shutdown
+    let batch_wal = match batch_wal_path {
+        Some(path) => {
+            info!("Batch write-ahead log: {}", path);
+            let (wal, recovered) = JobWal::open(path).await?;
+            let mut queue = batch_queue.lock().await;
+            for job in recovered {
+                queue.add(job);
+            }
+            Some(Arc::new(wal))
+        }
+        None => None,
+    };
+
+    // Jobs spilled before the last shutdown stay in the log until the
+    // replayer pushes them
+    let spill = match spill_path {
+        Some(path) => {
+            info!("Spill buffer: {}", path);
+            let (spill, _) = JobWal::open(path).await?;
+            Some(Arc::new(spill))
+        }
+        None => None,
+    };
+
+    let metrics = Arc::new(Metrics::new()?);
+    let shadow = Shadow::new(shadow_config, &faktory_pool, &pool_config, metrics.clone())?;
+    if let Some(shadow) = &shadow {
+        info!("Mirroring {}", shadow.describe());
+    }
 
     // Create shared state
+    let queue_sampler = Arc::new(QueueSampler::new(Duration::from_millis(
+        queue_sample_interval_ms,
+    )));
     let state = Arc::new(AppState {
         faktory_pool,
+        tenant_pools: Arc::new(tenant_pools),
         batch_queue,
         batch_config,
+        batch_wal,
+        breaker: Arc::new(CircuitBreaker::new(breaker_config)),
+        queue_sampler: queue_sampler.clone(),
+        backpressure: Arc::new(Backpressure::new(backpressure_config, queue_sampler)),
+        spill: spill.clone(),
+        result_store,
+        compute_config: ComputeConfig {
+            timeout: Duration::from_millis(compute_timeout_ms),
+            poll_interval: Duration::from_millis(compute_poll_interval_ms),
+        },
+        events_config: EventsConfig {
+            poll_interval: Duration::from_millis(sse_poll_interval_ms),
+            max_duration: Duration::from_secs(sse_max_duration_secs),
+        },
+        ws_config: WsConfig {
+            poll_interval: Duration::from_millis(ws_poll_interval_ms),
+            max_pending_jobs: ws_max_pending_jobs,
+        },
+        completions,
+        auth,
+        admin_config,
+        audit,
+        tenancy,
+        quota_config: Arc::new(RwLock::new(quota_config)),
+        queue_config,
+        retry_limits,
+        idempotency_config: IdempotencyConfig {
+            ttl: Duration::from_secs(idempotency_ttl_secs),
+        },
+        dedup,
+        limits,
+        slow_requests,
+        push_retry,
+        shadow: shadow.map(Arc::new),
+        maintenance: Arc::new(Maintenance::default()),
+        metrics,
     });
 
-    // Build router
-    let app = Router::new()
-        .route("/health", axum::routing::get(health_handler))
-        .route("/jobs/add", post(add_handler))
-        .route("/jobs/subtract", post(subtract_handler))
-        .route("/jobs/multiply", post(multiply_handler))
-        .route("/jobs/divide", post(divide_handler))
-        .route("/jobs/batch", post(batch_handler))
-        .with_state(state);
+    // Start background batch flusher
+    let flusher_shutdown = Arc::new(Notify::new());
+    let flusher = tokio::spawn(batch_flusher(state.clone(), flusher_shutdown.clone()));
+    info!("Started batch flusher background task");
 
-    info!("Starting API service on {}", bind_addr);
+    // Start replaying spilled jobs
+    if let Some(spill) = spill {
+        tokio::spawn(spill_replayer(
+            state.clone(),
+            spill,
+            Duration::from_millis(spill_replay_interval_ms),
+        ));
+        info!("Started spill replayer background task");
+    }
 
-    // Start server
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
-    axum::serve(listener, app).await?;
+    // Reload runtime-tunable settings on SIGHUP
+    tokio::spawn(reload::watch_sighup(state.clone()));
+    info!("Started configuration reload handler (SIGHUP)");
+
+    // Start sampling queue depths for backpressure and wait estimates
+    tokio::spawn(sampler::run_sampler(state.clone()));
+    info!("Started queue sampler background task");
+
+    // Start recurring job scheduler
+    tokio::spawn(schedules::run_scheduler(
+        state.clone(),
+        Duration::from_millis(scheduler_interval_ms),
+    ));
+    info!("Started recurring job scheduler");
+
+    // Build router. Routes that submit jobs are subject to
+    // `CONCURRENCY_LIMIT(S)`.
+    let limit = |path: &str, route| concurrency_limits.limit(path, route, &state.metrics);
+    let mut app = Router::new()
+        .route(
+            "/jobs",
+            limit("/jobs", post(job_handler)).get(history::list_jobs_handler),
+        )
+        .route(
+            "/jobs/stream",
+            limit("/jobs/stream", post(ndjson::stream_handler)),
+        )
+        .route(
+            "/jobs/upload",
+            limit("/jobs/upload", post(upload::upload_handler)),
+        )
+        .merge(typed_job_routes(limit))
+        .merge(bulk::routes(limit))
+        .route("/jobs/batch", limit("/jobs/batch", post(batch_handler)))
+        .route("/jobs/validate", post(validate::validate_handler))
+        .route("/jobs/pending", get(history::list_pending_handler))
+        .route("/jobs/{id}", get(job_status_handler))
+        .route("/jobs/{id}/events", get(events::job_events_handler))
+        .route(
+            "/compute/{op}",
+            limit("/compute/{op}", post(compute::compute_handler)),
+        )
+        .route("/ws", get(ws::ws_handler))
+        .route(
+            "/graphql",
+            limit("/graphql", post(graphql::graphql_handler)),
+        )
+        .route("/graphql/ws", get(graphql::graphql_ws_handler))
+        .route("/usage", get(usage::usage_handler))
+        .route("/queues", get(queues::queue_stats_handler))
+        .route("/workers", get(workers::list_workers_handler))
+        .route(
+            "/schedules",
+            get(schedules::list_schedules_handler).post(schedules::create_schedule_handler),
+        )
+        .route(
+            "/schedules/{id}",
+            get(schedules::get_schedule_handler)
+                .put(schedules::update_schedule_handler)
+                .delete(schedules::delete_schedule_handler),
+        )
+        .merge(admin::routes(state.clone()))
+        .route_layer(middleware::from_fn_with_state(
+            state.clone(),
+            auth::require_auth,
+        ))
+        // Health checks, metrics and API docs stay unauthenticated
+        .route("/health", get(health_handler))
+        .route("/schema/jobs", get(schema::job_schemas_handler))
+        .route("/health/deep", get(deep_health_handler))
+        .route("/version", get(version_handler))
+        .route("/metrics", get(metrics::metrics_handler))
+        .route("/graphql", get(graphql::graphiql_handler))
+        .merge(openapi::docs())
+        .layer(Extension(graphql::schema(state.clone())))
+        .layer(DefaultBodyLimit::max(limits.max_body_bytes))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            limits::explain_body_limit,
+        ))
+        .layer(compression_layer())
+        .layer(RequestDecompressionLayer::new())
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            latency::observe_latency,
+        ))
+        .layer(middleware::from_fn(telemetry::trace_request));
+    if let Some(cors) = &cors_config {
+        info!("CORS enabled for {}", cors.describe_origins());
+        app = app.layer(cors.layer());
+    }
+    let app = app.with_state(state.clone());
+
+    let version = version_info();
+    info!(
+        "Starting API service {} ({}) on {}",
+        version.version, version.git_sha, bind_addr
+    );
+
+    // Start server. On SIGTERM/SIGINT it stops accepting connections and
+    // waits for in-flight requests (and their enqueues) to finish.
+    let grpc_shutdown = Arc::new(Notify::new());
+    let grpc_server = grpc_bind_addr
+        .map(|addr| grpc::start(state.clone(), addr, grpc_shutdown.clone()))
+        .transpose()?;
+    let listener = service_tls::bind(&bind_addr, tls_config).await?;
+    let (draining_tx, draining_rx) = oneshot::channel();
+    let server = axum::serve(listener, app).with_graceful_shutdown(async move {
+        shutdown_signal().await;
+        grpc_shutdown.notify_one();
+        let _ = draining_tx.send(());
+    });
+    // Long-lived connections (event streams, WebSockets) would otherwise
+    // hold up shutdown
+    let shutdown_timeout = Duration::from_secs(shutdown_timeout_secs);
+    let drain_deadline = async move {
+        if draining_rx.await.is_ok() {
+            sleep(shutdown_timeout).await;
+        } else {
+            std::future::pending::<()>().await;
+        }
+    };
+    tokio::select! {
+        result = server.into_future() => result?,
+        _ = drain_deadline => {
+            warn!(
+                "In-flight requests still running after {}s, shutting down anyway",
+                shutdown_timeout_secs
+            );
+        }
+    }
+
+    // gRPC calls may still be enqueueing
+    if let Some(grpc_server) = grpc_server {
+        match tokio::time::timeout(shutdown_timeout, grpc_server).await {
+            Ok(Ok(Ok(()))) => {}
+            Ok(Ok(Err(e))) => warn!("gRPC server failed: {:#}", e),
+            Ok(Err(e)) => warn!("gRPC server task failed: {}", e),
+            Err(_) => warn!("gRPC calls still running after {}s", shutdown_timeout_secs),
+        }
+    }
+
+    // Push jobs still waiting in the auto-batcher before exiting
+    info!("Draining batch queue...");
+    flusher_shutdown.notify_one();
+    if let Err(e) = flusher.await {
+        warn!("Batch flusher task failed: {}", e);
+    }
 
+    info!("API service stopped");
+    telemetry.shutdown();
     Ok(())
 }
@@ -0,0 +1,139 @@
+//! Maintenance mode.
+//!
+//! While it's on, new submissions on every API (REST, gRPC, GraphQL,
+//! WebSocket) are refused with 503 and the operator's message, and recurring
+//! schedules aren't fired, so queues can drain before Faktory goes down.
+//! Job status, results, health and the admin routes keep working. Admins
+//! switch it with `PUT /admin/maintenance`. The switch is held in memory, so
+//! with several replicas each one has to be told.
+
+use crate::{AppState, ErrorResponse};
+use axum::{extract::State, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Message used when the operator doesn't give one
+const DEFAULT_MESSAGE: &str = "The API is down for maintenance; try again later";
+
+/// Whether maintenance mode is on, and since when
+#[derive(Debug, Clone, Default, Serialize, ToSchema)]
+pub struct MaintenanceStatus {
+    enabled: bool,
+    /// Told to clients whose submissions are refused
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since: Option<DateTime<Utc>>,
+}
+
+/// Turn maintenance mode on or off
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct MaintenanceRequest {
+    enabled: bool,
+    /// Shown to refused clients; a generic message when omitted
+    message: Option<String>,
+}
+
+/// Returned (inside `anyhow::Error`) when a submission arrives during
+/// maintenance
+#[derive(Debug)]
+pub struct UnderMaintenance {
+    message: String,
+}
+
+impl fmt::Display for UnderMaintenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for UnderMaintenance {}
+
+/// The maintenance switch
+#[derive(Default)]
+pub struct Maintenance {
+    status: RwLock<MaintenanceStatus>,
+}
+
+impl Maintenance {
+    pub fn is_enabled(&self) -> bool {
+        self.status.read().unwrap().enabled
+    }
+
+    /// Fail if submissions are paused
+    pub fn check(&self) -> Result<(), UnderMaintenance> {
+        let status = self.status.read().unwrap();
+        if !status.enabled {
+            return Ok(());
+        }
+        Err(UnderMaintenance {
+            message: status
+                .message
+                .clone()
+                .unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+        })
+    }
+
+    pub fn status(&self) -> MaintenanceStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    fn set(&self, enabled: bool, message: Option<String>) -> MaintenanceStatus {
+        let mut status = self.status.write().unwrap();
+        *status = if enabled {
+            MaintenanceStatus {
+                enabled,
+                message,
+                // Keep the original start when only the message changes
+                since: status.since.filter(|_| status.enabled).or(Some(Utc::now())),
+            }
+        } else {
+            MaintenanceStatus::default()
+        };
+        status.clone()
+    }
+}
+
+/// GET /admin/maintenance - Whether submissions are paused
+#[utoipa::path(
+    get,
+    path = "/admin/maintenance",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Maintenance mode status", body = MaintenanceStatus),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn get_maintenance_handler(
+    State(state): State<Arc<AppState>>,
+) -> Json<MaintenanceStatus> {
+    Json(state.maintenance.status())
+}
+
+/// PUT /admin/maintenance - Pause or resume submissions
+#[utoipa::path(
+    put,
+    path = "/admin/maintenance",
+    tag = "admin",
+    request_body = MaintenanceRequest,
+    responses(
+        (status = 200, description = "Maintenance mode updated", body = MaintenanceStatus),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+    )
+)]
+pub async fn set_maintenance_handler(
+    State(state): State<Arc<AppState>>,
+    Json(req): Json<MaintenanceRequest>,
+) -> Json<MaintenanceStatus> {
+    let status = state.maintenance.set(req.enabled, req.message);
+    if status.enabled {
+        warn!("Maintenance mode on: refusing submissions");
+    } else {
+        info!("Maintenance mode off: accepting submissions");
+    }
+    Json(status)
+}
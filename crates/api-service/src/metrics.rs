@@ -0,0 +1,329 @@
+//! Prometheus metrics for the API, served at `/metrics`.
+
+use crate::AppState;
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use deadpool::managed::TimeoutType;
+use deadpool::Status;
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Encoder, Gauge, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Registry, TextEncoder,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Enqueue path a measurement was taken on
+#[derive(Debug, Clone, Copy)]
+pub enum EnqueueMode {
+    /// One job pushed on its own connection
+    Single,
+    /// Several jobs pushed over one connection
+    Batch,
+}
+
+impl EnqueueMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            EnqueueMode::Single => "single",
+            EnqueueMode::Batch => "batch",
+        }
+    }
+}
+
+/// Why the auto-batcher flushed
+#[derive(Debug, Clone, Copy)]
+pub enum FlushTrigger {
+    /// The batch reached `BATCH_MAX_SIZE`
+    Full,
+    /// `BATCH_MAX_DELAY_MS` elapsed
+    Timeout,
+    /// The service is shutting down
+    Shutdown,
+}
+
+impl FlushTrigger {
+    fn as_str(&self) -> &'static str {
+        match self {
+            FlushTrigger::Full => "full",
+            FlushTrigger::Timeout => "timeout",
+            FlushTrigger::Shutdown => "shutdown",
+        }
+    }
+}
+
+/// API-side metrics, registered in a private registry
+pub struct Metrics {
+    registry: Registry,
+    jobs_enqueued: IntCounterVec,
+    enqueue_errors: IntCounterVec,
+    enqueue_retries: IntCounterVec,
+    jobs_deduplicated: IntCounterVec,
+    jobs_spilled: IntCounter,
+    shadow_jobs: IntCounterVec,
+    requests_shed: IntCounterVec,
+    request_duration: HistogramVec,
+    enqueue_duration: HistogramVec,
+    batch_flush_size: HistogramVec,
+    pool_connections: IntGaugeVec,
+    pool_max_size: IntGauge,
+    pool_waiting: IntGauge,
+    pool_saturation: Gauge,
+    pool_wait: Histogram,
+    pool_timeouts: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("api".to_string()), None)
+            .context("Failed to create metrics registry")?;
+
+        let jobs_enqueued = IntCounterVec::new(
+            opts!("jobs_enqueued_total", "Jobs pushed to Faktory"),
+            &["job_type"],
+        )?;
+        let enqueue_errors = IntCounterVec::new(
+            opts!("enqueue_errors_total", "Failed pushes to Faktory"),
+            &["mode"],
+        )?;
+        let enqueue_retries = IntCounterVec::new(
+            opts!(
+                "enqueue_retries_total",
+                "Pushes to Faktory retried after a transient error"
+            ),
+            &["mode"],
+        )?;
+        let jobs_deduplicated = IntCounterVec::new(
+            opts!(
+                "jobs_deduplicated_total",
+                "Submissions answered with an earlier job sharing their request_id"
+            ),
+            &["job_type"],
+        )?;
+        let jobs_spilled = IntCounter::new(
+            "jobs_spilled_total",
+            "Jobs written to the spill buffer while Faktory was unavailable",
+        )?;
+        let shadow_jobs = IntCounterVec::new(
+            opts!(
+                "shadow_jobs_total",
+                "Copies of accepted jobs mirrored to the shadow queue, by outcome"
+            ),
+            &["outcome"],
+        )?;
+        let requests_shed = IntCounterVec::new(
+            opts!(
+                "requests_shed_total",
+                "Requests refused because their route was at its concurrency limit"
+            ),
+            &["route"],
+        )?;
+        let request_duration = HistogramVec::new(
+            histogram_opts!(
+                "request_duration_seconds",
+                "Time to handle HTTP requests, by method, route and status",
+                exponential_buckets(0.001, 2.0, 15)?
+            ),
+            &["method", "route", "status"],
+        )?;
+        let enqueue_duration = HistogramVec::new(
+            histogram_opts!(
+                "enqueue_duration_seconds",
+                "Time to push jobs to Faktory, including waiting for a connection"
+            ),
+            &["mode"],
+        )?;
+        let batch_flush_size = HistogramVec::new(
+            histogram_opts!(
+                "batch_flush_size",
+                "Jobs per auto-batcher flush",
+                exponential_buckets(1.0, 2.0, 11)?
+            ),
+            &["trigger"],
+        )?;
+        let pool_connections = IntGaugeVec::new(
+            opts!("faktory_pool_connections", "Faktory connections by state"),
+            &["state"],
+        )?;
+        let pool_max_size = IntGauge::new(
+            "faktory_pool_max_size",
+            "Maximum Faktory connections in the pool",
+        )?;
+        let pool_waiting = IntGauge::new(
+            "faktory_pool_waiting",
+            "Requests waiting for a Faktory connection",
+        )?;
+
+        let pool_saturation = Gauge::new(
+            "faktory_pool_saturation",
+            "Fraction of the maximum Faktory connections in use",
+        )?;
+        let pool_wait = Histogram::with_opts(histogram_opts!(
+            "faktory_pool_wait_seconds",
+            "Time to get a Faktory connection from the pool"
+        ))?;
+        let pool_timeouts = IntCounterVec::new(
+            opts!(
+                "faktory_pool_timeouts_total",
+                "Faktory connection requests that timed out, by stage"
+            ),
+            &["stage"],
+        )?;
+
+        registry.register(Box::new(jobs_enqueued.clone()))?;
+        registry.register(Box::new(enqueue_errors.clone()))?;
+        registry.register(Box::new(enqueue_retries.clone()))?;
+        registry.register(Box::new(jobs_deduplicated.clone()))?;
+        registry.register(Box::new(jobs_spilled.clone()))?;
+        registry.register(Box::new(shadow_jobs.clone()))?;
+        registry.register(Box::new(requests_shed.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(enqueue_duration.clone()))?;
+        registry.register(Box::new(batch_flush_size.clone()))?;
+        registry.register(Box::new(pool_connections.clone()))?;
+        registry.register(Box::new(pool_max_size.clone()))?;
+        registry.register(Box::new(pool_waiting.clone()))?;
+        registry.register(Box::new(pool_saturation.clone()))?;
+        registry.register(Box::new(pool_wait.clone()))?;
+        registry.register(Box::new(pool_timeouts.clone()))?;
+
+        Ok(Self {
+            registry,
+            jobs_enqueued,
+            enqueue_errors,
+            enqueue_retries,
+            jobs_deduplicated,
+            jobs_spilled,
+            shadow_jobs,
+            requests_shed,
+            request_duration,
+            enqueue_duration,
+            batch_flush_size,
+            pool_connections,
+            pool_max_size,
+            pool_waiting,
+            pool_saturation,
+            pool_wait,
+            pool_timeouts,
+        })
+    }
+
+    pub fn job_enqueued(&self, job_type: &str) {
+        self.jobs_enqueued.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn enqueue_failed(&self, mode: EnqueueMode) {
+        self.enqueue_errors
+            .with_label_values(&[mode.as_str()])
+            .inc();
+    }
+
+    pub fn enqueue_retried(&self, mode: EnqueueMode) {
+        self.enqueue_retries
+            .with_label_values(&[mode.as_str()])
+            .inc();
+    }
+
+    pub fn job_deduplicated(&self, job_type: &str) {
+        self.jobs_deduplicated.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn jobs_spilled(&self, jobs: usize) {
+        self.jobs_spilled.inc_by(jobs as u64);
+    }
+
+    pub fn jobs_shadowed(&self, jobs: usize, pushed: bool) {
+        let outcome = if pushed { "pushed" } else { "failed" };
+        self.shadow_jobs
+            .with_label_values(&[outcome])
+            .inc_by(jobs as u64);
+    }
+
+    pub fn request_shed(&self, route: &str) {
+        self.requests_shed.with_label_values(&[route]).inc();
+    }
+
+    pub fn observe_request(&self, method: &str, route: &str, status: u16, elapsed: Duration) {
+        self.request_duration
+            .with_label_values(&[method, route, &status.to_string()])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_enqueue(&self, mode: EnqueueMode, elapsed: Duration) {
+        self.enqueue_duration
+            .with_label_values(&[mode.as_str()])
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub fn observe_pool_wait(&self, elapsed: Duration) {
+        self.pool_wait.observe(elapsed.as_secs_f64());
+    }
+
+    pub fn pool_timed_out(&self, stage: TimeoutType) {
+        let stage = match stage {
+            TimeoutType::Wait => "wait",
+            TimeoutType::Create => "create",
+            TimeoutType::Recycle => "recycle",
+        };
+        self.pool_timeouts.with_label_values(&[stage]).inc();
+    }
+
+    pub fn batch_flushed(&self, trigger: FlushTrigger, jobs: usize) {
+        self.batch_flush_size
+            .with_label_values(&[trigger.as_str()])
+            .observe(jobs as f64);
+    }
+
+    /// Render all metrics in the Prometheus text format, sampling the
+    /// connection pool first
+    fn render(&self, pool: Status) -> Result<String> {
+        let in_use = pool.size.saturating_sub(pool.available);
+        self.pool_connections
+            .with_label_values(&["in_use"])
+            .set(in_use as i64);
+        self.pool_connections
+            .with_label_values(&["idle"])
+            .set(pool.available as i64);
+        self.pool_max_size.set(pool.max_size as i64);
+        self.pool_waiting.set(pool.waiting as i64);
+        if pool.max_size > 0 {
+            self.pool_saturation
+                .set(in_use as f64 / pool.max_size as f64);
+        }
+
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Failed to encode metrics")?;
+        String::from_utf8(buffer).context("Metrics are not valid UTF-8")
+    }
+}
+
+/// GET /metrics - Prometheus scrape endpoint
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "health",
+    responses(
+        (status = 200, description = "Metrics in the Prometheus text format", content_type = "text/plain", body = String),
+    ),
+    security()
+)]
+pub async fn metrics_handler(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.metrics.render(state.faktory_pool.status()) {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to render metrics: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
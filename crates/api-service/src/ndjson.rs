@@ -0,0 +1,238 @@
+//! Streaming job submission as newline-delimited JSON.
+//!
+//! `POST /jobs/stream` reads one job per line (the same shape as `POST /jobs`)
+//! and submits each as soon as its line arrives, through the auto-batcher
+//! when it's enabled. The response streams one acknowledgement per line,
+//! followed by a summary, so arbitrarily long job lists are never held in
+//! memory.
+
+use crate::auth::Principal;
+use crate::{submit_job, AppState, JobRequest};
+use axum::{body::Body, extract::State, http::header, response::IntoResponse, Extension};
+use futures_util::StreamExt;
+use job_types::FieldError;
+use serde::Serialize;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tracing::warn;
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// One line of the response
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum Ack {
+    Accepted {
+        line: usize,
+        job_id: String,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        deferred: bool,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        deduplicated: bool,
+    },
+    Rejected {
+        line: usize,
+        error: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        details: Vec<FieldError>,
+    },
+    /// Always the last line
+    Done { accepted: usize, rejected: usize },
+}
+
+impl Ack {
+    fn rejected(line: usize, error: impl Into<String>) -> Self {
+        Ack::Rejected {
+            line,
+            error: error.into(),
+            details: Vec::new(),
+        }
+    }
+
+    fn to_line(&self) -> String {
+        let mut line = serde_json::to_string(self).unwrap_or_default();
+        line.push('\n');
+        line
+    }
+}
+
+/// A line longer than `STREAM_MAX_LINE_BYTES`
+struct TooLong;
+
+/// Splits body chunks into lines, dropping the contents of overlong lines
+struct LineReader {
+    buffer: Vec<u8>,
+    too_long: bool,
+    max_line_bytes: usize,
+}
+
+impl LineReader {
+    fn new(max_line_bytes: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            too_long: false,
+            max_line_bytes,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Vec<Result<Vec<u8>, TooLong>> {
+        let mut lines = Vec::new();
+        let mut rest = chunk;
+        while let Some(end) = rest.iter().position(|&b| b == b'\n') {
+            self.extend(&rest[..end]);
+            lines.push(self.take());
+            rest = &rest[end + 1..];
+        }
+        self.extend(rest);
+        lines
+    }
+
+    /// The final line, if the body didn't end with a newline
+    fn finish(&mut self) -> Option<Result<Vec<u8>, TooLong>> {
+        (!self.buffer.is_empty() || self.too_long).then(|| self.take())
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        if self.too_long {
+            return;
+        }
+        if self.buffer.len() + bytes.len() > self.max_line_bytes {
+            self.too_long = true;
+            self.buffer = Vec::new();
+        } else {
+            self.buffer.extend_from_slice(bytes);
+        }
+    }
+
+    fn take(&mut self) -> Result<Vec<u8>, TooLong> {
+        if std::mem::take(&mut self.too_long) {
+            Err(TooLong)
+        } else {
+            Ok(std::mem::take(&mut self.buffer))
+        }
+    }
+}
+
+/// Submit the job on one line. Returns `None` for blank lines.
+async fn submit_line(
+    state: &AppState,
+    principal: &Option<Principal>,
+    line: usize,
+    contents: Result<Vec<u8>, TooLong>,
+) -> Option<Ack> {
+    let contents = match contents {
+        Ok(contents) => contents,
+        Err(TooLong) => {
+            return Some(Ack::rejected(
+                line,
+                format!(
+                    "Line is longer than {} bytes",
+                    state.limits.max_stream_line_bytes
+                ),
+            ))
+        }
+    };
+    if contents.iter().all(u8::is_ascii_whitespace) {
+        return None;
+    }
+
+    let mut req: JobRequest = match serde_json::from_slice(&contents) {
+        Ok(req) => req,
+        Err(e) => return Some(Ack::rejected(line, format!("Invalid job: {}", e))),
+    };
+    req.options.principal = principal.clone();
+    if let Err(e) = req.options.resolve(state) {
+        return Some(Ack::rejected(line, format!("{:#}", e)));
+    }
+    let details: Vec<FieldError> = req
+        .job
+        .validate()
+        .into_iter()
+        .map(|e| FieldError::new(format!("args.{}", e.field), e.message))
+        .collect();
+    if !details.is_empty() {
+        return Some(Ack::Rejected {
+            line,
+            error: "Validation failed".to_string(),
+            details,
+        });
+    }
+
+    Some(match submit_job(state, req.job, &req.options).await {
+        Ok(submitted) => Ack::Accepted {
+            line,
+            job_id: submitted.job_id,
+            deferred: submitted.deferred,
+            deduplicated: submitted.deduplicated,
+        },
+        Err(e) => {
+            warn!("Failed to enqueue job from line {}: {:#}", line, e);
+            Ack::rejected(line, format!("Failed to enqueue job: {}", e))
+        }
+    })
+}
+
+/// POST /jobs/stream - Submit newline-delimited jobs, acknowledged per line
+#[utoipa::path(
+    post,
+    path = "/jobs/stream",
+    tag = "jobs",
+    request_body(
+        content = String,
+        content_type = "application/x-ndjson",
+        description = "One job per line, each shaped like a `POST /jobs` body",
+        example = "{\"type\": \"Add\", \"args\": {\"a\": 1, \"b\": 2}}\n{\"type\": \"Divide\", \"args\": {\"a\": 1, \"b\": 0}}\n"
+    ),
+    responses(
+        (status = 200, description = "One acknowledgement per non-blank line (`accepted` or `rejected`), then a `done` summary", content_type = "application/x-ndjson", body = String,
+            example = "{\"status\":\"accepted\",\"line\":1,\"job_id\":\"...\"}\n{\"status\":\"rejected\",\"line\":2,\"error\":\"Validation failed\",\"details\":[{\"field\":\"args.b\",\"message\":\"Division by zero\"}]}\n{\"status\":\"done\",\"accepted\":1,\"rejected\":1}\n"),
+    )
+)]
+pub async fn stream_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    body: Body,
+) -> impl IntoResponse {
+    let principal = principal.map(|Extension(p)| p);
+
+    let acks = async_stream::stream! {
+        let mut chunks = body.into_data_stream();
+        let mut reader = LineReader::new(state.limits.max_stream_line_bytes);
+        let mut line = 0;
+        let (mut accepted, mut rejected) = (0, 0);
+
+        loop {
+            let (lines, finished) = match chunks.next().await {
+                Some(Ok(chunk)) => (reader.push(&chunk), false),
+                Some(Err(e)) => {
+                    rejected += 1;
+                    let ack = Ack::rejected(line + 1, format!("Failed to read request body: {}", e));
+                    yield Ok::<_, Infallible>(ack.to_line());
+                    break;
+                }
+                None => (reader.finish().into_iter().collect(), true),
+            };
+
+            for contents in lines {
+                line += 1;
+                if let Some(ack) = submit_line(&state, &principal, line, contents).await {
+                    match ack {
+                        Ack::Accepted { .. } => accepted += 1,
+                        _ => rejected += 1,
+                    }
+                    yield Ok(ack.to_line());
+                }
+            }
+            if finished {
+                break;
+            }
+        }
+
+        yield Ok(Ack::Done { accepted, rejected }.to_line());
+    };
+
+    (
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        Body::from_stream(acks),
+    )
+}
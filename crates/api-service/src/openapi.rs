@@ -0,0 +1,133 @@
+//! OpenAPI document for the API, served with Swagger UI at `/docs`.
+
+use crate::{
+    admin, audit, bulk, compute, dead, events, history, maintenance, metrics, queues, reload,
+    schedules, schema, usage, validate, workers, ws, AppState,
+};
+use axum::Router;
+use job_types::JobPayload;
+use std::sync::Arc;
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use utoipa_swagger_ui::SwaggerUi;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Work Factory API",
+        description = "Submit jobs to the Faktory-backed work queue and read their results."
+    ),
+    paths(
+        crate::job_handler,
+        history::list_jobs_handler,
+        history::list_pending_handler,
+        crate::ndjson::stream_handler,
+        crate::upload::upload_handler,
+        crate::typed_job_handler,
+        bulk::bulk_job_handler,
+        crate::batch_handler,
+        validate::validate_handler,
+        schema::job_schemas_handler,
+        crate::job_status_handler,
+        crate::health_handler,
+        crate::deep_health_handler,
+        crate::version_handler,
+        metrics::metrics_handler,
+        events::job_events_handler,
+        compute::compute_handler,
+        ws::ws_handler,
+        usage::usage_handler,
+        queues::queue_stats_handler,
+        dead::list_dead_handler,
+        dead::retry_dead_handler,
+        workers::list_workers_handler,
+        admin::purge_queue_handler,
+        admin::requeue_retries_handler,
+        admin::discard_dead_handler,
+        audit::audit_handler,
+        reload::reload_handler,
+        maintenance::get_maintenance_handler,
+        maintenance::set_maintenance_handler,
+        schedules::create_schedule_handler,
+        schedules::list_schedules_handler,
+        schedules::get_schedule_handler,
+        schedules::update_schedule_handler,
+        schedules::delete_schedule_handler,
+    ),
+    modifiers(&SecuritySchemes, &TypedJobPaths),
+    // Which scheme applies depends on AUTH_MODE
+    security(("api_key" = []), ("bearer" = []))
+)]
+struct ApiDoc;
+
+/// Registers the `AUTH_MODE=api_key` and `AUTH_MODE=jwt` credentials
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "api_key",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-API-Key"))),
+        );
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+/// Template paths of `typed_job_handler` and `bulk::bulk_job_handler`,
+/// each replaced by one path per job type, with the suffix of their
+/// operation IDs and summaries
+const TYPED_JOB_TEMPLATES: &[(&str, &str, &str)] = &[
+    ("/jobs/{kind}", "", ""),
+    (
+        "/jobs/{kind}/bulk",
+        "_bulk",
+        ", once per element of the operand arrays",
+    ),
+];
+
+/// Lists `POST /jobs/{name}` and `POST /jobs/{name}/bulk` for every job type
+/// in `JobPayload::KINDS`, matching the routes `typed_job_routes` and
+/// `bulk::routes` mount
+struct TypedJobPaths;
+
+impl Modify for TypedJobPaths {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        for (path, item) in std::mem::take(&mut openapi.paths.paths) {
+            let Some((template, id_suffix, summary_suffix)) = TYPED_JOB_TEMPLATES
+                .iter()
+                .find(|(template, ..)| *template == path)
+            else {
+                openapi.paths.paths.insert(path, item);
+                continue;
+            };
+            for kind in JobPayload::KINDS {
+                let mut item = item.clone();
+                if let Some(operation) = item.post.as_mut() {
+                    operation.operation_id = Some(format!("submit_{}{}", kind.name, id_suffix));
+                    operation.summary = Some(format!("{}{}", kind.description, summary_suffix));
+                    operation.description = None;
+                }
+                openapi
+                    .paths
+                    .paths
+                    .insert(template.replace("{kind}", kind.name), item);
+            }
+        }
+    }
+}
+
+/// Routes serving the document at `/openapi.json` and Swagger UI at `/docs`
+pub fn docs() -> Router<Arc<AppState>> {
+    SwaggerUi::new("/docs")
+        .url("/openapi.json", ApiDoc::openapi())
+        .into()
+}
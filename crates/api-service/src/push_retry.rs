@@ -0,0 +1,99 @@
+//! Retries for pushes to Faktory that fail on a transient error.
+//!
+//! A dropped or reset connection surfaces as an I/O or stream error from the
+//! Faktory client. Those pushes are retried on a fresh connection, up to
+//! `ENQUEUE_RETRY_ATTEMPTS` attempts in all, sleeping a random time up to an
+//! exponentially growing cap between attempts (full jitter) so replicas don't
+//! retry in lockstep. Protocol and serialization errors mean Faktory refused
+//! the push and fail straight away. Once the retries run out the error goes
+//! on to the circuit breaker as before.
+
+use crate::FaktoryManager;
+use anyhow::Result;
+use deadpool::managed::Object;
+use service_config::Config;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::warn;
+
+/// How transient push failures are retried
+#[derive(Debug, Clone)]
+pub struct PushRetry {
+    /// Attempts in all, including the first
+    pub max_attempts: u32,
+    /// Cap on the delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    /// Cap on any single delay
+    pub max_delay: Duration,
+}
+
+impl PushRetry {
+    /// Read `ENQUEUE_RETRY_ATTEMPTS` (default 3), `ENQUEUE_RETRY_BASE_MS`
+    /// (default 50) and `ENQUEUE_RETRY_MAX_MS` (default 1000)
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_attempts: config.parse_or("ENQUEUE_RETRY_ATTEMPTS", 3u32).max(1),
+            base_delay: Duration::from_millis(config.parse_or("ENQUEUE_RETRY_BASE_MS", 50)),
+            max_delay: Duration::from_millis(config.parse_or("ENQUEUE_RETRY_MAX_MS", 1000)),
+        }
+    }
+
+    /// Random delay before retry number `retry` (0 for the first)
+    fn backoff(&self, retry: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_delay);
+        cap.mul_f64(fastrand::f64())
+    }
+
+    /// Run `push` until it succeeds, fails permanently or runs out of
+    /// attempts. `on_retry` is called before each retry.
+    pub async fn run<T, Fut>(
+        &self,
+        mut push: impl FnMut() -> Fut,
+        mut on_retry: impl FnMut(),
+    ) -> Result<T>
+    where
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut retry = 0;
+        loop {
+            match push().await {
+                Err(e) if is_transient(&e) && retry + 1 < self.max_attempts => {
+                    let delay = self.backoff(retry);
+                    warn!(
+                        "Push to Faktory failed (attempt {} of {}), retrying in {}ms: {:#}",
+                        retry + 1,
+                        self.max_attempts,
+                        delay.as_millis(),
+                        e
+                    );
+                    on_retry();
+                    sleep(delay).await;
+                    retry += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// Whether a failed push may succeed if tried again: the connection broke,
+/// as opposed to Faktory refusing the command
+pub fn is_transient(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<faktory::Error>().is_some_and(is_broken)
+}
+
+fn is_broken(error: &faktory::Error) -> bool {
+    matches!(error, faktory::Error::IO(_) | faktory::Error::Stream(_))
+}
+
+/// Drop a connection from the pool if `error` means it's broken, so the
+/// next checkout opens a fresh one instead of reusing it
+pub fn discard_if_broken(client: Object<FaktoryManager>, error: &faktory::Error) {
+    if is_broken(error) {
+        drop(Object::take(client));
+    }
+}
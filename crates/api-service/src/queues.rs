@@ -0,0 +1,210 @@
+//! Queue routing (which Faktory queue each job is pushed to) and queue
+//! statistics.
+
+use crate::{faktory_client, AppState, ErrorResponse};
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use faktory::DataSnapshot;
+use job_types::split_tenant_queue;
+use serde::Serialize;
+use service_config::Config;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Faktory's default queue
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// Queue routing configuration
+#[derive(Debug, Clone)]
+pub struct QueueConfig {
+    /// Queues clients may request (per-type defaults are always allowed)
+    allowed: HashSet<String>,
+    /// Job type -> queue used when the request doesn't name one
+    defaults: HashMap<String, String>,
+}
+
+impl QueueConfig {
+    /// Read `QUEUE_ALLOWLIST` (comma-separated, default `default`) and
+    /// `QUEUE_DEFAULTS` (`job_type=queue` pairs, e.g. `math_divide=slow`)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let allowed: HashSet<String> = config
+            .string_or("QUEUE_ALLOWLIST", DEFAULT_QUEUE)
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+
+        let mut defaults = HashMap::new();
+        if let Some(value) = config.string("QUEUE_DEFAULTS") {
+            for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+                let Some((job_type, queue)) = entry.split_once('=') else {
+                    bail!(
+                        "Invalid QUEUE_DEFAULTS entry {:?} (expected job_type=queue)",
+                        entry
+                    );
+                };
+                defaults.insert(job_type.trim().to_string(), queue.trim().to_string());
+            }
+        }
+
+        Ok(Self { allowed, defaults })
+    }
+
+    /// Reject requested queues that aren't on the allowlist
+    pub fn check(&self, queue: &str) -> Result<()> {
+        if !self.allowed.contains(queue) {
+            let mut allowed: Vec<&str> = self.allowed.iter().map(String::as_str).collect();
+            allowed.sort_unstable();
+            bail!(
+                "Queue {:?} is not allowed (allowed: {})",
+                queue,
+                allowed.join(", ")
+            );
+        }
+        Ok(())
+    }
+
+    /// Queue for a job: the requested one, else the job type's default
+    pub fn route<'a>(&'a self, job_type: &str, requested: Option<&'a str>) -> &'a str {
+        requested
+            .or_else(|| self.defaults.get(job_type).map(String::as_str))
+            .unwrap_or(DEFAULT_QUEUE)
+    }
+}
+
+/// Jobs waiting in one queue
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QueueDepth {
+    name: String,
+    size: u64,
+}
+
+/// Jobs waiting in one tenant's queues
+#[derive(Debug, Serialize, ToSchema)]
+pub struct TenantDepth {
+    tenant: String,
+    size: u64,
+}
+
+/// Queue and job counts reported by Faktory's INFO command
+#[derive(Debug, Serialize, ToSchema)]
+pub struct QueueStats {
+    /// Every queue Faktory knows about, by name
+    queues: Vec<QueueDepth>,
+    /// Jobs waiting per tenant, when `TENANCY_ENABLED` is set
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tenants: Vec<TenantDepth>,
+    /// Jobs currently reserved by workers
+    busy: Option<u64>,
+    /// Failed jobs waiting to be retried
+    retries: Option<u64>,
+    /// Jobs waiting for their `run_at`
+    scheduled: Option<u64>,
+    /// Jobs that ran out of retries
+    dead: Option<u64>,
+    /// Worker processes with a recent heartbeat
+    workers: Option<u64>,
+    total_enqueued: u64,
+    total_processed: u64,
+    total_failures: u64,
+}
+
+impl From<DataSnapshot> for QueueStats {
+    fn from(data: DataSnapshot) -> Self {
+        // Faktory only reports busy jobs and the retry, scheduled and dead
+        // sets as task runner stats, which it may drop in a future version
+        #[allow(deprecated)]
+        let tasks = &data.tasks;
+        let task_size = |task: &str| tasks[task]["size"].as_u64();
+
+        let mut queues: Vec<QueueDepth> = data
+            .queues
+            .iter()
+            .map(|(name, &size)| QueueDepth {
+                name: name.clone(),
+                size,
+            })
+            .collect();
+        queues.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+        Self {
+            queues,
+            tenants: Vec::new(),
+            busy: task_size("Busy"),
+            retries: task_size("Retries"),
+            scheduled: task_size("Scheduled"),
+            dead: task_size("Dead"),
+            workers: task_size("Workers"),
+            total_enqueued: data.total_enqueued,
+            total_processed: data.total_processed,
+            total_failures: data.total_failures,
+        }
+    }
+}
+
+impl QueueStats {
+    /// Add up the depth of each tenant's `{tenant}.{queue}` queues
+    fn with_tenants(mut self) -> Self {
+        let mut tenants: HashMap<&str, u64> = HashMap::new();
+        for queue in &self.queues {
+            if let Some((tenant, _)) = split_tenant_queue(&queue.name) {
+                *tenants.entry(tenant).or_default() += queue.size;
+            }
+        }
+        let mut tenants: Vec<TenantDepth> = tenants
+            .into_iter()
+            .map(|(tenant, size)| TenantDepth {
+                tenant: tenant.to_string(),
+                size,
+            })
+            .collect();
+        tenants.sort_unstable_by(|a, b| a.tenant.cmp(&b.tenant));
+        self.tenants = tenants;
+        self
+    }
+}
+
+/// GET /queues - Depth of every Faktory queue, with busy and processed/failed
+/// totals
+#[utoipa::path(
+    get,
+    path = "/queues",
+    tag = "queues",
+    responses(
+        (status = 200, description = "Current queue statistics", body = QueueStats),
+        (status = 503, description = "Faktory is unreachable", body = ErrorResponse),
+    )
+)]
+pub async fn queue_stats_handler(State(state): State<Arc<AppState>>) -> Response {
+    let info = async {
+        let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+        client
+            .current_info()
+            .await
+            .context("Failed to query Faktory")
+    };
+    match info.await {
+        Ok(info) => {
+            let mut stats = QueueStats::from(info.data);
+            if state.tenancy.enabled {
+                stats = stats.with_tenants();
+            }
+            Json(stats).into_response()
+        }
+        Err(e) => {
+            warn!("Failed to read queue statistics: {:#}", e);
+            let response = ErrorResponse {
+                error: format!("{:#}", e),
+            };
+            (StatusCode::SERVICE_UNAVAILABLE, Json(response)).into_response()
+        }
+    }
+}
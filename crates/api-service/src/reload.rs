@@ -0,0 +1,173 @@
+//! Hot reload of runtime-tunable settings.
+//!
+//! On SIGHUP or `POST /admin/reload` the configuration is read again and
+//! these settings are swapped in without a restart:
+//!
+//! - batch size and delay (`BATCH_MAX_SIZE`, `BATCH_MAX_DELAY_MS`); jobs
+//!   already waiting in the batch queue stay queued
+//! - quotas (`QUOTA_DAILY_JOBS`, `QUOTA_MONTHLY_JOBS`, `QUOTA_OVERRIDES`)
+//! - backpressure thresholds (`BACKPRESSURE_MAX_DEPTH`,
+//!   `BACKPRESSURE_RETRY_AFTER_SECS`, `BACKPRESSURE_DIVERT_QUEUE`)
+//!
+//! Everything else still needs a restart. A running process can't see
+//! changes to its own environment, so settings meant to be reloaded belong
+//! in `CONFIG_FILE`. If any value fails to parse nothing is applied.
+
+use crate::backpressure::BackpressureConfig;
+use crate::usage::QuotaConfig;
+use crate::{AppState, BatchConfig, ErrorResponse};
+use anyhow::Result;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use service_config::Config;
+use std::fmt::Debug;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Settings a reload changed
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReloadResponse {
+    /// One entry per changed setting, e.g. `BATCH_MAX_SIZE: 100 -> 200`
+    changed: Vec<String>,
+}
+
+fn note_change<T: PartialEq + Debug>(changed: &mut Vec<String>, name: &str, old: &T, new: &T) {
+    if old != new {
+        changed.push(format!("{}: {:?} -> {:?}", name, old, new));
+    }
+}
+
+/// Read the configuration again and apply the reloadable settings,
+/// returning what changed
+pub async fn reload(state: &AppState) -> Result<Vec<String>> {
+    let config = Config::load()?;
+    let batch = BatchConfig::from_config(&config);
+    let quotas = QuotaConfig::from_config(&config)?;
+    let backpressure = BackpressureConfig::from_config(&config);
+    config.validate()?;
+
+    let mut changed = Vec::new();
+    {
+        let mut queue = state.batch_queue.lock().await;
+        note_change(
+            &mut changed,
+            "BATCH_MAX_SIZE",
+            &queue.config.max_batch_size,
+            &batch.max_batch_size,
+        );
+        note_change(
+            &mut changed,
+            "BATCH_MAX_DELAY_MS",
+            &queue.config.max_batch_delay_ms,
+            &batch.max_batch_delay_ms,
+        );
+        queue.set_limits(batch.max_batch_size, batch.max_batch_delay_ms);
+    }
+
+    {
+        let mut current = state.quota_config.write().unwrap();
+        note_change(
+            &mut changed,
+            "QUOTA_DAILY_JOBS",
+            &current.default.daily,
+            &quotas.default.daily,
+        );
+        note_change(
+            &mut changed,
+            "QUOTA_MONTHLY_JOBS",
+            &current.default.monthly,
+            &quotas.default.monthly,
+        );
+        if current.overrides != quotas.overrides {
+            changed.push(format!(
+                "QUOTA_OVERRIDES: {} callers -> {} callers",
+                current.overrides.len(),
+                quotas.overrides.len()
+            ));
+        }
+        *current = quotas;
+    }
+
+    let current = state.backpressure.config();
+    note_change(
+        &mut changed,
+        "BACKPRESSURE_MAX_DEPTH",
+        &current.max_depth,
+        &backpressure.max_depth,
+    );
+    note_change(
+        &mut changed,
+        "BACKPRESSURE_RETRY_AFTER_SECS",
+        &current.retry_after_secs,
+        &backpressure.retry_after_secs,
+    );
+    note_change(
+        &mut changed,
+        "BACKPRESSURE_DIVERT_QUEUE",
+        &current.divert_queue,
+        &backpressure.divert_queue,
+    );
+    state.backpressure.reconfigure(backpressure);
+
+    Ok(changed)
+}
+
+/// Reload and log the outcome
+async fn reload_and_log(state: &AppState, trigger: &str) -> Result<Vec<String>> {
+    match reload(state).await {
+        Ok(changed) if changed.is_empty() => {
+            info!("Configuration reloaded ({}): nothing changed", trigger);
+            Ok(changed)
+        }
+        Ok(changed) => {
+            info!(
+                "Configuration reloaded ({}): {}",
+                trigger,
+                changed.join(", ")
+            );
+            Ok(changed)
+        }
+        Err(e) => {
+            warn!("Configuration reload ({}) failed: {:#}", trigger, e);
+            Err(e)
+        }
+    }
+}
+
+/// Background task reloading the configuration on each SIGHUP
+pub async fn watch_sighup(state: Arc<AppState>) {
+    let mut sighup = signal(SignalKind::hangup()).expect("Failed to setup SIGHUP handler");
+    while sighup.recv().await.is_some() {
+        let _ = reload_and_log(&state, "SIGHUP").await;
+    }
+}
+
+/// POST /admin/reload - Re-read the configuration and apply runtime-tunable settings
+#[utoipa::path(
+    post,
+    path = "/admin/reload",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Settings reloaded", body = ReloadResponse),
+        (status = 400, description = "The new configuration is invalid; nothing was applied", body = ErrorResponse),
+        (status = 403, description = "Caller is not in `ADMIN_SUBJECTS`", body = ErrorResponse),
+    )
+)]
+pub async fn reload_handler(State(state): State<Arc<AppState>>) -> Response {
+    match reload_and_log(&state, "admin request").await {
+        Ok(changed) => Json(ReloadResponse { changed }).into_response(),
+        Err(e) => {
+            let response = ErrorResponse {
+                error: format!("{:#}", e),
+            };
+            (StatusCode::BAD_REQUEST, Json(response)).into_response()
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Periodic samples of Faktory's queue depths and job throughput.
+//!
+//! A background task reads Faktory's INFO every `QUEUE_SAMPLE_INTERVAL_MS`.
+//! The latest depths drive backpressure, and together with the rate at which
+//! workers have processed jobs over the last `THROUGHPUT_WINDOW` they give
+//! submitters an estimate of how long their job will wait.
+
+use crate::{faktory_client, AppState};
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// How far back processed-job counts are kept for the throughput estimate
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Samples {
+    /// Jobs waiting per queue, as of the latest sample
+    depths: HashMap<String, u64>,
+    /// (when, Faktory's total processed count) over `THROUGHPUT_WINDOW`
+    processed: VecDeque<(Instant, u64)>,
+}
+
+/// Latest queue depths and recent throughput
+pub struct QueueSampler {
+    interval: Duration,
+    samples: RwLock<Samples>,
+}
+
+impl QueueSampler {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            samples: RwLock::new(Samples::default()),
+        }
+    }
+
+    /// Jobs waiting in `queue` at the last sample; 0 for queues Faktory
+    /// doesn't list (it drops empty ones)
+    pub fn depth(&self, queue: &str) -> u64 {
+        let samples = self.samples.read().unwrap();
+        samples.depths.get(queue).copied().unwrap_or(0)
+    }
+
+    /// Jobs processed per second across all workers over the sampled
+    /// window, once there are two samples to compare
+    fn throughput(&self) -> Option<f64> {
+        let samples = self.samples.read().unwrap();
+        let (&(first_at, first), &(last_at, last)) =
+            (samples.processed.front()?, samples.processed.back()?);
+        let elapsed = last_at.duration_since(first_at).as_secs_f64();
+        (elapsed > 0.0).then(|| last.saturating_sub(first) as f64 / elapsed)
+    }
+
+    /// Depth of `queue` and how many seconds a job joining it now should
+    /// wait, assuming workers keep their recent pace. `None` before the first
+    /// sample; the wait is `None` until there's a throughput estimate, or
+    /// while nothing is being processed.
+    pub fn estimate(&self, queue: &str) -> Option<(u64, Option<f64>)> {
+        if self.samples.read().unwrap().processed.is_empty() {
+            return None;
+        }
+        let depth = self.depth(queue);
+        let wait = self
+            .throughput()
+            .filter(|&rate| rate > 0.0)
+            .map(|rate| (depth + 1) as f64 / rate);
+        Some((depth, wait))
+    }
+
+    fn record(&self, depths: HashMap<String, u64>, processed: u64) {
+        let now = Instant::now();
+        let mut samples = self.samples.write().unwrap();
+        samples.depths = depths;
+        samples.processed.push_back((now, processed));
+        while samples
+            .processed
+            .front()
+            .is_some_and(|&(at, _)| now.duration_since(at) > THROUGHPUT_WINDOW)
+        {
+            samples.processed.pop_front();
+        }
+    }
+
+    /// Forget everything, so stale samples aren't acted on
+    fn clear(&self) {
+        *self.samples.write().unwrap() = Samples::default();
+    }
+}
+
+/// Background task that keeps the samples current. If Faktory can't be
+/// reached they're dropped; the circuit breaker deals with the outage.
+pub async fn run_sampler(state: Arc<AppState>) {
+    let sampler = &state.queue_sampler;
+    let mut ticker = tokio::time::interval(sampler.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        match sample(&state).await {
+            Ok((depths, processed)) => sampler.record(depths, processed),
+            Err(e) => {
+                warn!("Failed to sample queue depths: {:#}", e);
+                sampler.clear();
+            }
+        }
+    }
+}
+
+async fn sample(state: &AppState) -> Result<(HashMap<String, u64>, u64)> {
+    let mut client = faktory_client(&state.faktory_pool, &state.metrics).await?;
+    let info = client
+        .current_info()
+        .await
+        .context("Failed to query Faktory")?;
+    Ok((
+        info.data.queues.into_iter().collect(),
+        info.data.total_processed,
+    ))
+}
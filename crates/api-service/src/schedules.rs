@@ -0,0 +1,417 @@
+//! Recurring jobs: CRUD endpoints for cron schedules and the background task
+//! that fires them.
+//!
+//! Schedules are persisted in the result store. Every API replica runs the
+//! scheduler; each run is claimed in the store first so it fires only once.
+//!
+//! A schedule belongs to the caller that created it: its runs are submitted
+//! as that caller, so they're charged to its quota and routed to its tenant's
+//! queues. With multi-tenancy enabled, callers other than admins only see and
+//! change their own tenant's schedules.
+
+use crate::auth::Principal;
+use crate::history::listed_tenant;
+use crate::{
+    enqueue_job, validation_error, AppState, ErrorResponse, JobOptions, ValidationErrorResponse,
+};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{Extension, Path, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use job_types::JobPayload;
+use result_store::Schedule;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+use utoipa::ToSchema;
+
+/// Parse a cron expression. Standard 5-field expressions are accepted as
+/// well as 6/7-field ones with seconds (and years).
+fn parse_cron(expr: &str) -> Result<cron::Schedule> {
+    let expr = expr.trim();
+    let expr = if expr.split_whitespace().count() == 5 {
+        format!("0 {}", expr)
+    } else {
+        expr.to_string()
+    };
+    cron::Schedule::from_str(&expr).with_context(|| format!("Invalid cron expression {:?}", expr))
+}
+
+/// The most recent time the schedule was due, if it's due now. Runs missed
+/// while no scheduler was running are collapsed into one.
+fn due_run(schedule: &Schedule, now: DateTime<Utc>) -> Result<Option<DateTime<Utc>>> {
+    let cron = parse_cron(&schedule.cron)?;
+    let since = schedule.last_run_at.unwrap_or(schedule.created_at);
+    Ok(cron.after(&since).take_while(|at| *at <= now).last())
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct ScheduleRequest {
+    name: Option<String>,
+    cron: String,
+    job: JobPayload,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ScheduleResponse {
+    #[serde(flatten)]
+    schedule: Schedule,
+    next_run_at: Option<DateTime<Utc>>,
+}
+
+impl ScheduleResponse {
+    fn new(schedule: Schedule) -> Self {
+        let next_run_at = parse_cron(&schedule.cron)
+            .ok()
+            .and_then(|cron| cron.upcoming(Utc).next());
+        Self {
+            schedule,
+            next_run_at,
+        }
+    }
+}
+
+fn error_response(status: StatusCode, error: String) -> Response {
+    (status, Json(ErrorResponse { error })).into_response()
+}
+
+fn store_error(message: &str, e: anyhow::Error) -> Response {
+    warn!("{}: {:#}", message, e);
+    error_response(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        format!("{}: {}", message, e),
+    )
+}
+
+/// The caller that owns `schedule`, for submitting its runs
+fn owner(schedule: &Schedule) -> Option<Principal> {
+    schedule.created_by.clone().map(|subject| Principal {
+        subject,
+        tenant: schedule.tenant.clone(),
+    })
+}
+
+/// Schedule `id`, if `principal` may see it
+async fn get_visible(
+    state: &AppState,
+    principal: Option<Principal>,
+    id: &str,
+) -> Result<Option<Schedule>> {
+    let tenant = listed_tenant(state, principal);
+    let schedule = state.result_store.get_schedule(id).await?;
+    Ok(schedule.filter(|s| tenant.as_ref().is_none_or(|tenant| &s.tenant == tenant)))
+}
+
+/// Validate a request and save it as schedule `id`, owned by `owner`
+async fn save(
+    state: &AppState,
+    id: String,
+    created_at: DateTime<Utc>,
+    owner: Option<Principal>,
+    req: ScheduleRequest,
+) -> Response {
+    if let Err(e) = parse_cron(&req.cron) {
+        return error_response(StatusCode::BAD_REQUEST, format!("{:#}", e));
+    }
+    let errors = req.job.validate();
+    if !errors.is_empty() {
+        return validation_error(errors);
+    }
+    let job = match serde_json::to_value(&req.job) {
+        Ok(job) => job,
+        Err(e) => return store_error("Failed to serialize job", e.into()),
+    };
+
+    let schedule = Schedule {
+        id,
+        name: req.name,
+        cron: req.cron,
+        job,
+        created_at,
+        last_run_at: None,
+        created_by: owner.as_ref().map(|p| p.subject.clone()),
+        tenant: owner.and_then(|p| p.tenant),
+    };
+    if let Err(e) = state.result_store.save_schedule(&schedule).await {
+        return store_error("Failed to save schedule", e);
+    }
+    info!("Saved schedule {} ({})", schedule.id, schedule.cron);
+
+    match state.result_store.get_schedule(&schedule.id).await {
+        Ok(Some(saved)) => (StatusCode::OK, Json(ScheduleResponse::new(saved))).into_response(),
+        Ok(None) => (StatusCode::OK, Json(ScheduleResponse::new(schedule))).into_response(),
+        Err(e) => store_error("Failed to read schedule", e),
+    }
+}
+
+/// POST /schedules - Create a recurring job
+#[utoipa::path(
+    post,
+    path = "/schedules",
+    tag = "schedules",
+    request_body = ScheduleRequest,
+    responses(
+        (status = 201, description = "Schedule created", body = ScheduleResponse),
+        (status = 400, description = "Invalid cron expression", body = ErrorResponse),
+        (status = 422, description = "Invalid job", body = ValidationErrorResponse),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+pub async fn create_schedule_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Json(req): Json<ScheduleRequest>,
+) -> impl IntoResponse {
+    let id = uuid::Uuid::new_v4().to_string();
+    let owner = principal.map(|Extension(p)| p);
+    let mut response = save(&state, id, Utc::now(), owner, req).await;
+    if response.status() == StatusCode::OK {
+        *response.status_mut() = StatusCode::CREATED;
+    }
+    response
+}
+
+/// GET /schedules - List recurring jobs
+#[utoipa::path(
+    get,
+    path = "/schedules",
+    tag = "schedules",
+    responses(
+        (status = 200, description = "The caller's schedules (all of them for admins or without multi-tenancy), oldest first", body = [ScheduleResponse]),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+pub async fn list_schedules_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+) -> impl IntoResponse {
+    let tenant = listed_tenant(&state, principal.map(|Extension(p)| p));
+    match state.result_store.list_schedules().await {
+        Ok(schedules) => {
+            let schedules: Vec<ScheduleResponse> = schedules
+                .into_iter()
+                .filter(|s| tenant.as_ref().is_none_or(|tenant| &s.tenant == tenant))
+                .map(ScheduleResponse::new)
+                .collect();
+            (StatusCode::OK, Json(schedules)).into_response()
+        }
+        Err(e) => store_error("Failed to list schedules", e),
+    }
+}
+
+/// GET /schedules/{id} - Look up a recurring job
+#[utoipa::path(
+    get,
+    path = "/schedules/{id}",
+    tag = "schedules",
+    params(("id" = String, Path, description = "Schedule id")),
+    responses(
+        (status = 200, description = "The schedule", body = ScheduleResponse),
+        (status = 404, description = "No such schedule", body = ErrorResponse),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+pub async fn get_schedule_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match get_visible(&state, principal.map(|Extension(p)| p), &id).await {
+        Ok(Some(schedule)) => {
+            (StatusCode::OK, Json(ScheduleResponse::new(schedule))).into_response()
+        }
+        Ok(None) => error_response(StatusCode::NOT_FOUND, format!("No schedule {}", id)),
+        Err(e) => store_error("Failed to read schedule", e),
+    }
+}
+
+/// PUT /schedules/{id} - Replace a recurring job's definition
+#[utoipa::path(
+    put,
+    path = "/schedules/{id}",
+    tag = "schedules",
+    params(("id" = String, Path, description = "Schedule id")),
+    request_body = ScheduleRequest,
+    responses(
+        (status = 200, description = "Schedule updated", body = ScheduleResponse),
+        (status = 400, description = "Invalid cron expression", body = ErrorResponse),
+        (status = 404, description = "No such schedule", body = ErrorResponse),
+        (status = 422, description = "Invalid job", body = ValidationErrorResponse),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+pub async fn update_schedule_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+    Json(req): Json<ScheduleRequest>,
+) -> impl IntoResponse {
+    match get_visible(&state, principal.map(|Extension(p)| p), &id).await {
+        // The schedule keeps its owner, even when an admin edits it
+        Ok(Some(existing)) => save(&state, id, existing.created_at, owner(&existing), req).await,
+        Ok(None) => error_response(StatusCode::NOT_FOUND, format!("No schedule {}", id)),
+        Err(e) => store_error("Failed to read schedule", e),
+    }
+}
+
+/// DELETE /schedules/{id} - Remove a recurring job
+#[utoipa::path(
+    delete,
+    path = "/schedules/{id}",
+    tag = "schedules",
+    params(("id" = String, Path, description = "Schedule id")),
+    responses(
+        (status = 204, description = "Schedule deleted"),
+        (status = 404, description = "No such schedule", body = ErrorResponse),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+pub async fn delete_schedule_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    match get_visible(&state, principal.map(|Extension(p)| p), &id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, format!("No schedule {}", id)),
+        Err(e) => return store_error("Failed to read schedule", e),
+    }
+    match state.result_store.delete_schedule(&id).await {
+        Ok(true) => {
+            info!("Deleted schedule {}", id);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        Ok(false) => error_response(StatusCode::NOT_FOUND, format!("No schedule {}", id)),
+        Err(e) => store_error("Failed to delete schedule", e),
+    }
+}
+
+/// Background task that enqueues jobs for due schedules
+pub async fn run_scheduler(state: Arc<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticker.tick().await;
+        // Runs missed during maintenance fire, collapsed into one, once it ends
+        if state.maintenance.is_enabled() {
+            continue;
+        }
+
+        let schedules = match state.result_store.list_schedules().await {
+            Ok(schedules) => schedules,
+            Err(e) => {
+                warn!("Scheduler: failed to list schedules: {:#}", e);
+                continue;
+            }
+        };
+
+        let now = Utc::now();
+        for schedule in schedules {
+            if let Err(e) = fire_if_due(&state, &schedule, now).await {
+                warn!("Scheduler: schedule {} failed: {:#}", schedule.id, e);
+            }
+        }
+    }
+}
+
+async fn fire_if_due(state: &AppState, schedule: &Schedule, now: DateTime<Utc>) -> Result<()> {
+    let Some(run_at) = due_run(schedule, now)? else {
+        return Ok(());
+    };
+    if !state
+        .result_store
+        .claim_schedule_run(&schedule.id, run_at)
+        .await?
+    {
+        // Another replica got this run
+        return Ok(());
+    }
+
+    let payload: JobPayload =
+        serde_json::from_value(schedule.job.clone()).context("Invalid stored job payload")?;
+    // Every run repeats the schedule's payload, request_id and all, and is
+    // submitted as the schedule's owner
+    let options = JobOptions {
+        skip_dedup: true,
+        principal: owner(schedule),
+        ..JobOptions::default()
+    };
+    let submitted = enqueue_job(state, payload, &options).await?;
+    info!(
+        "Scheduler: {} job {} for schedule {} (due {})",
+        if submitted.deferred {
+            "deferred"
+        } else {
+            "enqueued"
+        },
+        submitted.job_id,
+        schedule.id,
+        run_at
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(hour: u32, minute: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2025, 11, 12, hour, minute, 0).unwrap()
+    }
+
+    fn schedule(cron: &str, last_run_at: Option<DateTime<Utc>>) -> Schedule {
+        Schedule {
+            id: "sched-1".to_string(),
+            name: None,
+            cron: cron.to_string(),
+            job: serde_json::json!({"type": "Add", "args": {"a": 1.0, "b": 2.0}}),
+            created_at: at(9, 0),
+            last_run_at,
+            created_by: None,
+            tenant: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        // Five fields run on the minute
+        let every_15 = parse_cron(" */15 * * * * ").unwrap();
+        assert_eq!(every_15.after(&at(9, 1)).next(), Some(at(9, 15)));
+
+        // Six and seven fields have seconds (and years)
+        let with_seconds = parse_cron("30 0 10 * * *").unwrap();
+        assert_eq!(
+            with_seconds.after(&at(9, 0)).next(),
+            Some(at(10, 0) + chrono::Duration::seconds(30))
+        );
+        assert!(parse_cron("0 0 10 * * * 2025").is_ok());
+
+        for invalid in ["", "* * *", "61 * * * *", "not a cron"] {
+            assert!(parse_cron(invalid).is_err(), "{:?} parsed", invalid);
+        }
+    }
+
+    #[test]
+    fn test_due_run() {
+        let hourly = schedule("0 * * * *", None);
+        assert_eq!(due_run(&hourly, at(9, 59)).unwrap(), None);
+        assert_eq!(due_run(&hourly, at(10, 0)).unwrap(), Some(at(10, 0)));
+
+        // Runs missed in the meantime collapse into the latest
+        let behind = schedule("0 * * * *", Some(at(10, 0)));
+        assert_eq!(due_run(&behind, at(13, 30)).unwrap(), Some(at(13, 0)));
+        let caught_up = schedule("0 * * * *", Some(at(13, 0)));
+        assert_eq!(due_run(&caught_up, at(13, 30)).unwrap(), None);
+
+        assert!(due_run(&schedule("bogus", None), at(10, 0)).is_err());
+    }
+}
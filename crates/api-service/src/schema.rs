@@ -0,0 +1,71 @@
+//! Schema discovery for job types.
+//!
+//! `GET /schema/jobs` lists every job type in `JobPayload::KINDS` with the
+//! JSON Schema of its arguments and an example submission, so producers can
+//! find out what a job takes without reading the OpenAPI document. Like the
+//! docs, it needs no authentication.
+
+use axum::Json;
+use job_types::{JobKind, JobPayload};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Every submittable job type
+#[derive(Debug, Serialize, ToSchema)]
+pub struct JobSchemasResponse {
+    jobs: Vec<JobSchema>,
+}
+
+/// How to submit one job type
+#[derive(Debug, Serialize, ToSchema)]
+struct JobSchema {
+    /// Route segment, e.g. `divide`
+    name: &'static str,
+    /// `type` of the payload in `POST /jobs` and `/jobs/batch`, e.g. `Divide`
+    tag: &'static str,
+    /// Faktory job type the worker runs, e.g. `math_divide`
+    job_type: &'static str,
+    description: &'static str,
+    /// Endpoint taking the arguments as the whole body
+    endpoint: String,
+    /// JSON Schema of the arguments
+    #[schema(value_type = Object)]
+    args_schema: serde_json::Value,
+    /// Example body for `POST /jobs`; its `args` alone is a body for
+    /// `endpoint`
+    #[schema(value_type = Object)]
+    example: serde_json::Value,
+}
+
+impl JobSchema {
+    fn new(kind: &JobKind) -> Option<Self> {
+        let example = kind.example()?;
+        Some(Self {
+            name: kind.name,
+            tag: kind.tag(),
+            job_type: kind.job_type,
+            description: kind.description,
+            endpoint: format!("/jobs/{}", kind.name),
+            args_schema: serde_json::to_value(kind.args_schema()?).ok()?,
+            example: serde_json::to_value(&example).ok()?,
+        })
+    }
+}
+
+/// GET /schema/jobs - JSON Schemas and examples for every job type
+#[utoipa::path(
+    get,
+    path = "/schema/jobs",
+    tag = "jobs",
+    responses(
+        (status = 200, description = "Every job type with its argument schema and an example", body = JobSchemasResponse),
+    ),
+    security(),
+)]
+pub async fn job_schemas_handler() -> Json<JobSchemasResponse> {
+    let jobs = JobPayload::KINDS
+        .iter()
+        .filter_map(JobSchema::new)
+        .collect();
+    Json(JobSchemasResponse { jobs })
+}
@@ -0,0 +1,161 @@
+//! Shadow traffic: copies of a sample of accepted jobs for testing new
+//! worker builds against live traffic.
+//!
+//! With `SHADOW_PERCENT` set, that share of the jobs pushed to Faktory is
+//! also pushed, in the background, to `SHADOW_QUEUE` on the Faktory server at
+//! `SHADOW_FAKTORY_URL` (the main server when unset). Copies get their own
+//! job id, point back at the original in `shadow_of`, and lose their
+//! `callback_url`, so production results and callbacks are untouched.
+//! Mirroring never delays or fails a submission: copies that can't be pushed
+//! are counted and dropped.
+
+use crate::metrics::Metrics;
+use crate::{FaktoryManager, PoolConfig};
+use anyhow::{bail, Result};
+use deadpool::managed::Pool;
+use faktory::Job;
+use service_config::Config;
+use service_tls::faktory::FaktoryConfig;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+/// Custom field naming the job a shadow copy was made from
+pub const SHADOW_OF_FIELD: &str = "shadow_of";
+
+/// Connections the shadow pool may hold; mirroring is best-effort, so it
+/// doesn't get many
+const SHADOW_POOL_SIZE: usize = 4;
+
+/// Where and how much traffic to mirror
+#[derive(Clone)]
+pub struct ShadowConfig {
+    /// Share of jobs mirrored, from 0 to 100
+    percent: f64,
+    /// Queue the copies go to; the original's queue when unset
+    queue: Option<String>,
+    /// Server the copies go to; the main server when unset
+    faktory: Option<FaktoryConfig>,
+}
+
+impl ShadowConfig {
+    /// Read `SHADOW_PERCENT` (default 0, off), `SHADOW_QUEUE` and
+    /// `SHADOW_FAKTORY_URL` (with `SHADOW_FAKTORY_PASSWORD` and
+    /// `SHADOW_FAKTORY_TLS_CA_PATH`)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let percent: f64 = config.parse_or("SHADOW_PERCENT", 0.0);
+        if !(0.0..=100.0).contains(&percent) {
+            bail!("SHADOW_PERCENT must be between 0 and 100, got {}", percent);
+        }
+        let queue = config.string("SHADOW_QUEUE");
+        let faktory = FaktoryConfig::from_config_prefixed(config, "SHADOW_FAKTORY")?;
+        if percent > 0.0 && queue.is_none() && faktory.is_none() {
+            // Copies would land next to the originals and run twice
+            bail!("SHADOW_PERCENT needs SHADOW_QUEUE or SHADOW_FAKTORY_URL");
+        }
+        Ok(Self {
+            percent,
+            queue,
+            faktory,
+        })
+    }
+}
+
+/// Mirrors a sample of pushed jobs
+pub struct Shadow {
+    config: ShadowConfig,
+    pool: Pool<FaktoryManager>,
+    metrics: Arc<Metrics>,
+}
+
+impl Shadow {
+    /// `None` when mirroring is off. Copies go over `main` unless
+    /// `SHADOW_FAKTORY_URL` names another server.
+    pub fn new(
+        config: ShadowConfig,
+        main: &Pool<FaktoryManager>,
+        pool_config: &PoolConfig,
+        metrics: Arc<Metrics>,
+    ) -> Result<Option<Self>> {
+        if config.percent <= 0.0 {
+            return Ok(None);
+        }
+        let pool = match &config.faktory {
+            Some(faktory) => FaktoryManager::pool(
+                faktory.clone(),
+                &PoolConfig {
+                    max_size: SHADOW_POOL_SIZE,
+                    ..pool_config.clone()
+                },
+            )?,
+            None => main.clone(),
+        };
+        Ok(Some(Self {
+            config,
+            pool,
+            metrics,
+        }))
+    }
+
+    /// Where copies go, for logging
+    pub fn describe(&self) -> String {
+        format!(
+            "{}% of jobs to {} on {}",
+            self.config.percent,
+            self.config.queue.as_deref().unwrap_or("their own queues"),
+            self.config
+                .faktory
+                .as_ref()
+                .map_or("the main Faktory server", |f| f.url())
+        )
+    }
+
+    /// Push copies of a sample of `jobs` in the background
+    pub fn mirror<'a>(&self, jobs: impl IntoIterator<Item = &'a Job>) {
+        let copies: Vec<Job> = jobs
+            .into_iter()
+            .filter(|_| fastrand::f64() * 100.0 < self.config.percent)
+            .map(|job| self.copy(job))
+            .collect();
+        if copies.is_empty() {
+            return;
+        }
+
+        let pool = self.pool.clone();
+        let metrics = self.metrics.clone();
+        tokio::spawn(async move {
+            let count = copies.len();
+            let pushed = async {
+                let mut client = pool.get().await?;
+                client.enqueue_many(copies).await?;
+                anyhow::Ok(())
+            };
+            match pushed.await {
+                Ok(()) => {
+                    debug!("Mirrored {} jobs to the shadow queue", count);
+                    metrics.jobs_shadowed(count, true);
+                }
+                Err(e) => {
+                    warn!("Failed to mirror {} jobs: {:#}", count, e);
+                    metrics.jobs_shadowed(count, false);
+                }
+            }
+        });
+    }
+
+    fn copy(&self, job: &Job) -> Job {
+        let mut copy = Job::new(job.kind(), job.args().to_vec());
+        copy.queue = self
+            .config
+            .queue
+            .clone()
+            .unwrap_or_else(|| job.queue.clone());
+        copy.priority = job.priority;
+        copy.at = job.at;
+        copy.retry = job.retry;
+        copy.custom = job.custom.clone();
+        copy.custom.remove("callback_url");
+        copy.custom
+            .insert(SHADOW_OF_FIELD.to_string(), job.id().to_string().into());
+        copy
+    }
+}
@@ -0,0 +1,121 @@
+//! Per-route concurrency limits with load shedding.
+//!
+//! Each limited route admits at most its cap of in-flight requests; once
+//! they're all taken, further requests are refused with 503 straight away
+//! instead of queueing for a Faktory connection and dragging everyone's
+//! latency up with them.
+
+use crate::metrics::Metrics;
+use crate::ErrorResponse;
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    error_handling::HandleErrorLayer,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    routing::MethodRouter,
+    BoxError, Json,
+};
+use service_config::Config;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::{error::Overloaded, LoadShedLayer};
+use tower::ServiceBuilder;
+use tracing::warn;
+
+/// In-flight request caps, per route path
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyLimits {
+    /// Applies to limited routes without an override; unlimited when `None`
+    default: Option<usize>,
+    /// Route path (as registered, e.g. `/compute/{op}`) -> cap
+    overrides: HashMap<String, usize>,
+}
+
+impl ConcurrencyLimits {
+    /// Read `CONCURRENCY_LIMIT` and `CONCURRENCY_LIMITS` (`path=limit` pairs
+    /// separated by commas, e.g. `/jobs=200,/jobs/batch=20`)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let default = config.parse("CONCURRENCY_LIMIT");
+        let overrides = match config.string("CONCURRENCY_LIMITS") {
+            Some(v) => parse_overrides(&v)?,
+            None => HashMap::new(),
+        };
+        Ok(Self { default, overrides })
+    }
+
+    fn limit_for(&self, path: &str) -> Option<usize> {
+        self.overrides.get(path).copied().or(self.default)
+    }
+
+    /// Wrap the handlers for `path` in its concurrency limit, shedding
+    /// requests over it with 503
+    pub fn limit<S>(
+        &self,
+        path: &str,
+        route: MethodRouter<S>,
+        metrics: &Arc<Metrics>,
+    ) -> MethodRouter<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let Some(max) = self.limit_for(path) else {
+            return route;
+        };
+        let path = path.to_string();
+        let metrics = metrics.clone();
+        route.layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(move |e: BoxError| {
+                    let response = overloaded(&path, max, e, &metrics);
+                    async move { response }
+                }))
+                .layer(LoadShedLayer::new())
+                // Shared by every method on the route
+                .layer(GlobalConcurrencyLimitLayer::new(max)),
+        )
+    }
+}
+
+fn parse_overrides(value: &str) -> Result<HashMap<String, usize>> {
+    let mut overrides = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (path, limit) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid CONCURRENCY_LIMITS entry {:?}", entry))?;
+        let limit: usize = limit
+            .trim()
+            .parse()
+            .with_context(|| format!("Invalid concurrency limit {:?}", limit))?;
+        if limit == 0 {
+            return Err(anyhow!("Concurrency limit for {} must be at least 1", path));
+        }
+        overrides.insert(path.trim().to_string(), limit);
+    }
+    Ok(overrides)
+}
+
+fn overloaded(path: &str, max: usize, e: BoxError, metrics: &Metrics) -> Response {
+    if !e.is::<Overloaded>() {
+        // The limit and shedding layers don't fail any other way
+        warn!("Request to {} failed: {}", path, e);
+        let response = ErrorResponse {
+            error: e.to_string(),
+        };
+        return (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response();
+    }
+
+    metrics.request_shed(path);
+    let response = ErrorResponse {
+        error: format!(
+            "Too many requests in flight for {} (limit {}); try again shortly",
+            path, max
+        ),
+    };
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        [(header::RETRY_AFTER, "1")],
+        Json(response),
+    )
+        .into_response()
+}
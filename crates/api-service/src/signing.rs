@@ -0,0 +1,276 @@
+//! HMAC request signing, for producers that can't use TLS client auth.
+//!
+//! With `AUTH_MODE=hmac`, each request carries its client id, a Unix
+//! timestamp, a unique nonce and an HMAC-SHA256 signature made with the
+//! client's secret from `HMAC_SECRETS`. The signature covers
+//!
+//! ```text
+//! {timestamp}\n{nonce}\n{METHOD}\n{path and query}\n{body}
+//! ```
+//!
+//! hex encoded. Requests whose timestamp is more than `HMAC_MAX_SKEW_SECS`
+//! off, or whose nonce was already used by the same client within that
+//! window, are refused, so a captured request can't be replayed.
+
+use crate::auth::Principal;
+use anyhow::{anyhow, bail, Result};
+use axum::http::{HeaderMap, Method};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const CLIENT_HEADER: &str = "x-client-id";
+const TIMESTAMP_HEADER: &str = "x-signature-timestamp";
+const NONCE_HEADER: &str = "x-signature-nonce";
+const SIGNATURE_HEADER: &str = "x-signature";
+
+/// Longest nonce accepted, to bound the cache
+const MAX_NONCE_LEN: usize = 128;
+
+/// Expired nonces are swept every this many signed requests
+const SWEEP_INTERVAL: usize = 1024;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Parse `client:secret` pairs separated by commas
+pub fn parse_secrets(value: &str) -> Result<HashMap<String, String>> {
+    let mut secrets = HashMap::new();
+    for (i, entry) in value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .enumerate()
+    {
+        // Don't echo entries back: a malformed one may be a bare secret
+        let (client, secret) = entry
+            .split_once(':')
+            .ok_or_else(|| anyhow!("HMAC_SECRETS entry {} is not in client:secret form", i + 1))?;
+        if secrets
+            .insert(client.to_string(), secret.to_string())
+            .is_some()
+        {
+            bail!("Duplicate client in HMAC_SECRETS: {}", client);
+        }
+    }
+    Ok(secrets)
+}
+
+/// Checks request signatures and remembers recent nonces
+pub struct HmacVerifier {
+    /// Client id -> secret
+    secrets: HashMap<String, String>,
+    max_skew: Duration,
+    /// (client, nonce) -> when it was first seen
+    nonces: Mutex<HashMap<(String, String), Instant>>,
+    /// Nonces claimed so far, to pace sweeps
+    claims: AtomicUsize,
+}
+
+impl HmacVerifier {
+    pub fn new(secrets: HashMap<String, String>, max_skew: Duration) -> Self {
+        Self {
+            secrets,
+            max_skew,
+            nonces: Mutex::new(HashMap::new()),
+            claims: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn clients(&self) -> usize {
+        self.secrets.len()
+    }
+
+    /// Verify a request's signature, freshness and nonce
+    pub fn verify(
+        &self,
+        method: &Method,
+        path_and_query: &str,
+        headers: &HeaderMap,
+        body: &[u8],
+    ) -> Result<Principal> {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .ok_or_else(|| anyhow!("Missing {} header", name))
+        };
+        let client = header(CLIENT_HEADER)?;
+        let timestamp = header(TIMESTAMP_HEADER)?;
+        let nonce = header(NONCE_HEADER)?;
+        let signature = header(SIGNATURE_HEADER)?;
+
+        let secret = self
+            .secrets
+            .get(client)
+            .ok_or_else(|| anyhow!("Unknown client {}", client))?;
+        let signed_at: u64 = timestamp
+            .parse()
+            .map_err(|_| anyhow!("Invalid signature timestamp"))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if now.abs_diff(signed_at) > self.max_skew.as_secs() {
+            bail!("Signature timestamp is outside the allowed window");
+        }
+        if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+            bail!("Signature nonce must be 1 to {} characters", MAX_NONCE_LEN);
+        }
+
+        let signature = hex::decode(signature).map_err(|_| anyhow!("Malformed signature"))?;
+        let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+            .map_err(|_| anyhow!("Unusable secret for client {}", client))?;
+        for part in [timestamp, nonce, method.as_str(), path_and_query] {
+            mac.update(part.as_bytes());
+            mac.update(b"\n");
+        }
+        mac.update(body);
+        mac.verify_slice(&signature)
+            .map_err(|_| anyhow!("Invalid signature"))?;
+
+        // Only signed requests reach the cache, so it can't be filled with
+        // junk by anyone without a secret
+        self.claim_nonce(client, nonce)?;
+        Ok(Principal {
+            subject: client.to_string(),
+            tenant: None,
+        })
+    }
+
+    /// Record a nonce, failing if the client used it within the window
+    fn claim_nonce(&self, client: &str, nonce: &str) -> Result<()> {
+        let now = Instant::now();
+        // A nonce older than both skew windows can't pass the timestamp check
+        let window = self.max_skew * 2;
+        let mut nonces = self.nonces.lock().unwrap();
+
+        let claims = self.claims.fetch_add(1, Ordering::Relaxed) + 1;
+        if claims.is_multiple_of(SWEEP_INTERVAL) {
+            nonces.retain(|_, seen_at| now.duration_since(*seen_at) < window);
+        }
+
+        let key = (client.to_string(), nonce.to_string());
+        match nonces.get(&key) {
+            Some(seen_at) if now.duration_since(*seen_at) < window => {
+                bail!("Signature nonce was already used")
+            }
+            _ => {
+                nonces.insert(key, now);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const BODY: &[u8] = br#"{"a":1,"b":2}"#;
+
+    fn verifier() -> HmacVerifier {
+        let secrets = parse_secrets("producer:s3cret").unwrap();
+        HmacVerifier::new(secrets, Duration::from_secs(300))
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// Headers of a request signed by `producer` at `timestamp`
+    fn signed(timestamp: u64, nonce: &str, body: &[u8]) -> HeaderMap {
+        let timestamp = timestamp.to_string();
+        let mut mac = HmacSha256::new_from_slice(b"s3cret").unwrap();
+        for part in [timestamp.as_str(), nonce, "POST", "/jobs/add"] {
+            mac.update(part.as_bytes());
+            mac.update(b"\n");
+        }
+        mac.update(body);
+        let signature = hex::encode(mac.finalize().into_bytes());
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            (CLIENT_HEADER, "producer"),
+            (TIMESTAMP_HEADER, timestamp.as_str()),
+            (NONCE_HEADER, nonce),
+            (SIGNATURE_HEADER, signature.as_str()),
+        ] {
+            headers.insert(name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn verify(verifier: &HmacVerifier, headers: &HeaderMap, body: &[u8]) -> Result<Principal> {
+        verifier.verify(&Method::POST, "/jobs/add", headers, body)
+    }
+
+    #[test]
+    fn test_parse_secrets() {
+        let secrets = parse_secrets(" producer:s3cret, billing:a:b ,").unwrap();
+        assert_eq!(secrets["producer"], "s3cret");
+        assert_eq!(secrets["billing"], "a:b");
+
+        let err = parse_secrets("producer:one,bare-secret").unwrap_err();
+        assert!(!err.to_string().contains("bare-secret"));
+        assert!(parse_secrets("producer:one,producer:two").is_err());
+    }
+
+    #[test]
+    fn test_valid_signature() {
+        let verifier = verifier();
+        let principal = verify(&verifier, &signed(now(), "n-1", BODY), BODY).unwrap();
+        assert_eq!(principal.subject, "producer");
+        assert!(principal.tenant.is_none());
+    }
+
+    #[test]
+    fn test_tampered_body() {
+        let verifier = verifier();
+        let headers = signed(now(), "n-1", BODY);
+        let err = verify(&verifier, &headers, br#"{"a":1,"b":3}"#).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid signature");
+
+        // A refused request doesn't use up its nonce
+        assert!(verify(&verifier, &headers, BODY).is_ok());
+    }
+
+    #[test]
+    fn test_stale_timestamp() {
+        let verifier = verifier();
+        for timestamp in [now() - 301, now() + 301] {
+            let err = verify(&verifier, &signed(timestamp, "n-1", BODY), BODY).unwrap_err();
+            assert!(err.to_string().contains("outside the allowed window"));
+        }
+        assert!(verify(&verifier, &signed(now() - 290, "n-1", BODY), BODY).is_ok());
+    }
+
+    #[test]
+    fn test_replayed_nonce() {
+        let verifier = verifier();
+        let headers = signed(now(), "n-1", BODY);
+        assert!(verify(&verifier, &headers, BODY).is_ok());
+        let err = verify(&verifier, &headers, BODY).unwrap_err();
+        assert_eq!(err.to_string(), "Signature nonce was already used");
+
+        assert!(verify(&verifier, &signed(now(), "n-2", BODY), BODY).is_ok());
+    }
+
+    #[test]
+    fn test_unknown_client_and_missing_headers() {
+        let verifier = verifier();
+        let mut headers = signed(now(), "n-1", BODY);
+        headers.insert(CLIENT_HEADER, HeaderValue::from_static("intruder"));
+        assert!(verify(&verifier, &headers, BODY).is_err());
+
+        headers.remove(SIGNATURE_HEADER);
+        let err = verify(&verifier, &headers, BODY).unwrap_err();
+        assert_eq!(err.to_string(), "Missing x-signature header");
+    }
+}
@@ -0,0 +1,175 @@
+//! Logging and OpenTelemetry tracing.
+//!
+//! Logs are plain text, or one JSON object per line with `LOG_FORMAT=json`.
+//! Every request gets an ID (the caller's `X-Request-ID`, or a new UUID)
+//! that's attached to its log lines, echoed in the response and recorded on
+//! the jobs it enqueues, so worker logs for those jobs carry it too.
+//!
+//! When `OTEL_EXPORTER_OTLP_ENDPOINT` is set, spans are exported over OTLP
+//! (HTTP/protobuf). Incoming W3C `traceparent` headers are honored, and each
+//! job carries the trace context of the request that enqueued it so workers
+//! can continue the same trace.
+
+use anyhow::{bail, Context, Result};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::{HeaderMap, HeaderName, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use job_types::{REQUEST_ID_FIELD, TRACE_CONTEXT_FIELD};
+use opentelemetry::global;
+use opentelemetry::propagation::Extractor;
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use service_config::Config;
+use std::collections::HashMap;
+use tracing::{info_span, warn, Instrument, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+
+/// Header carrying the request's correlation ID, in both directions
+const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Longest caller-supplied request ID that's kept; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// ID of the request being handled, read when building jobs
+    static REQUEST_ID: String;
+}
+
+/// Handle to the tracer provider, flushed on shutdown
+pub struct Telemetry {
+    provider: Option<SdkTracerProvider>,
+}
+
+/// Install the global tracing subscriber, exporting spans if configured
+pub fn init(config: &Config) -> Result<Telemetry> {
+    let provider = if let Some(endpoint) = config.string("OTEL_EXPORTER_OTLP_ENDPOINT") {
+        // Like the OTLP environment variable, the setting is the collector's
+        // base URL
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .context("Failed to create OTLP span exporter")?;
+        let service_name = config.string_or("OTEL_SERVICE_NAME", "api-service");
+        let provider = SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_resource(Resource::builder().with_service_name(service_name).build())
+            .build();
+
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(provider.clone());
+        Some(provider)
+    } else {
+        None
+    };
+
+    let otel_layer = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("api-service")));
+    let fmt_layer = match config.string_or("LOG_FORMAT", "text").as_str() {
+        "text" => tracing_subscriber::fmt::layer().boxed(),
+        "json" => tracing_subscriber::fmt::layer().json().boxed(),
+        other => bail!("Invalid LOG_FORMAT {:?} (expected text or json)", other),
+    };
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(fmt_layer)
+        .with(otel_layer)
+        .init();
+
+    Ok(Telemetry { provider })
+}
+
+impl Telemetry {
+    /// Export any spans still buffered
+    pub fn shutdown(self) {
+        if let Some(provider) = self.provider {
+            if let Err(e) = provider.shutdown() {
+                warn!("Failed to shut down tracer provider: {}", e);
+            }
+        }
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// The caller's request ID if it's usable, otherwise a new one
+fn request_id(headers: &HeaderMap) -> String {
+    headers
+        .get(&REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::trim)
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map_or_else(|| uuid::Uuid::new_v4().to_string(), String::from)
+}
+
+/// Middleware wrapping each request in a span tagged with its request ID,
+/// continuing the caller's trace if it sent one
+pub async fn trace_request(request: Request, next: Next) -> Response {
+    let request_id = request_id(request.headers());
+    let route = request.extensions().get::<MatchedPath>().map_or_else(
+        || request.uri().path().to_string(),
+        |p| p.as_str().to_string(),
+    );
+    let span = info_span!(
+        "request",
+        otel.name = %format!("{} {}", request.method(), route),
+        http.request.method = %request.method(),
+        http.route = %route,
+        http.response.status_code = tracing::field::Empty,
+        request_id = %request_id,
+    );
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+    let _ = span.set_parent(parent);
+
+    let mut response = REQUEST_ID
+        .scope(request_id.clone(), next.run(request))
+        .instrument(span.clone())
+        .await;
+    span.record("http.response.status_code", response.status().as_u16());
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+/// Record the current request's ID in a job's custom fields
+pub fn inject_request_id(custom: &mut HashMap<String, serde_json::Value>) {
+    if let Ok(request_id) = REQUEST_ID.try_with(String::clone) {
+        custom.insert(REQUEST_ID_FIELD.to_string(), request_id.into());
+    }
+}
+
+/// Record the current trace context in a job's custom fields
+pub fn inject_context(custom: &mut HashMap<String, serde_json::Value>) {
+    let mut carrier: HashMap<String, String> = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    if !carrier.is_empty() {
+        custom.insert(
+            TRACE_CONTEXT_FIELD.to_string(),
+            carrier.into_iter().collect(),
+        );
+    }
+}
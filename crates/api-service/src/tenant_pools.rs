@@ -0,0 +1,95 @@
+//! Per-tenant Faktory connection pools.
+//!
+//! With `TENANCY_ENABLED` and `TENANT_POOL_MAX_SIZE` set, pushes of a
+//! tenant's jobs take connections from that tenant's own pool of at most
+//! `TENANT_POOL_MAX_SIZE` connections instead of the shared pool. A burst
+//! from one tenant then waits on (and times out against) its own pool while
+//! the others keep getting connections. Jobs without a tenant, auto-batch
+//! flushes mixing several tenants, and everything that isn't a push (queue
+//! stats, admin, health checks) use the shared pool.
+//!
+//! Pools are created when a tenant first submits and kept for the life of
+//! the process. Tenants beyond `TENANT_POOL_MAX_TENANTS` share the shared
+//! pool, so tenant names sent by callers can't open connections without
+//! bound.
+
+use crate::{FaktoryManager, PoolConfig};
+use deadpool::managed::Pool;
+use faktory::Job;
+use job_types::split_tenant_queue;
+use service_config::Config;
+use service_tls::faktory::FaktoryConfig;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{info, warn};
+
+/// Connection pools of individual tenants
+pub struct TenantPools {
+    faktory: FaktoryConfig,
+    /// Sizing of each tenant's pool; `None` when tenants share the pool
+    config: Option<PoolConfig>,
+    max_tenants: usize,
+    pools: Mutex<HashMap<String, Pool<FaktoryManager>>>,
+}
+
+impl TenantPools {
+    /// Read `TENANT_POOL_MAX_SIZE` (default 0, off) and
+    /// `TENANT_POOL_MAX_TENANTS` (default 100). Tenant pools use the shared
+    /// pool's timeouts, and are only used with tenancy `enabled`.
+    pub fn from_config(
+        config: &Config,
+        faktory: FaktoryConfig,
+        shared: &PoolConfig,
+        enabled: bool,
+    ) -> Self {
+        let max_size: usize = config.parse_or("TENANT_POOL_MAX_SIZE", 0);
+        Self {
+            faktory,
+            config: (enabled && max_size > 0).then(|| PoolConfig {
+                max_size,
+                ..shared.clone()
+            }),
+            max_tenants: config.parse_or("TENANT_POOL_MAX_TENANTS", 100),
+            pools: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Pool to push `jobs` with: their tenant's, if they all belong to one
+    /// tenant, otherwise `shared`
+    pub fn pool(&self, shared: &Pool<FaktoryManager>, jobs: &[Job]) -> Pool<FaktoryManager> {
+        let Some(config) = &self.config else {
+            return shared.clone();
+        };
+        let mut tenants = jobs
+            .iter()
+            .map(|job| split_tenant_queue(&job.queue).map(|(tenant, _)| tenant));
+        let Some(Some(tenant)) = tenants.next() else {
+            return shared.clone();
+        };
+        if !tenants.all(|other| other == Some(tenant)) {
+            return shared.clone();
+        }
+
+        let mut pools = self.pools.lock().unwrap();
+        if let Some(pool) = pools.get(tenant) {
+            return pool.clone();
+        }
+        if pools.len() >= self.max_tenants {
+            return shared.clone();
+        }
+        match FaktoryManager::pool(self.faktory.clone(), config) {
+            Ok(pool) => {
+                info!(
+                    "Created Faktory connection pool for tenant {} with max size {}",
+                    tenant, config.max_size
+                );
+                pools.insert(tenant.to_string(), pool.clone());
+                pool
+            }
+            Err(e) => {
+                warn!("Using the shared pool for tenant {}: {:#}", tenant, e);
+                shared.clone()
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Tenant isolation through namespaced queues.
+//!
+//! With `TENANCY_ENABLED`, jobs submitted for a tenant go to
+//! `{tenant}.{queue}` instead of `{queue}`, so workers can give each tenant
+//! its own queues (`WORKER_TENANTS`) and one tenant's backlog doesn't hold
+//! up everyone else's work. The tenant comes from the token's tenant claim,
+//! or for callers whose credentials don't carry one (API keys, HMAC
+//! clients), from `TENANT_SUBJECTS`. The `TENANT_HEADER` header may only
+//! repeat that tenant, so a caller can't pick another tenant's queues. Jobs
+//! without a tenant keep using the shared queues.
+
+use crate::auth::Principal;
+use anyhow::{anyhow, bail, Context, Result};
+use axum::http::{HeaderMap, HeaderName};
+use job_types::{is_valid_tenant, tenant_queue, MAX_TENANT_LEN};
+use service_config::Config;
+use std::collections::HashMap;
+
+/// Tenant routing configuration
+#[derive(Debug, Clone)]
+pub struct TenancyConfig {
+    pub enabled: bool,
+    /// Header naming the caller's tenant, checked against its credentials
+    header: HeaderName,
+    /// Tenant of each caller whose credentials don't carry a tenant claim
+    subjects: HashMap<String, String>,
+}
+
+impl TenancyConfig {
+    /// Read `TENANCY_ENABLED` (default false), `TENANT_HEADER` (default
+    /// `X-Tenant-ID`) and `TENANT_SUBJECTS` (`subject=tenant` pairs separated
+    /// by commas, e.g. `billing-svc=acme,reports=globex`)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let header = config.string_or("TENANT_HEADER", "X-Tenant-ID");
+        let subjects = match config.string("TENANT_SUBJECTS") {
+            Some(v) => parse_subjects(&v)?,
+            None => HashMap::new(),
+        };
+        Ok(Self {
+            enabled: config.parse_or("TENANCY_ENABLED", false),
+            header: HeaderName::from_bytes(header.as_bytes())
+                .with_context(|| format!("Invalid TENANT_HEADER {:?}", header))?,
+            subjects,
+        })
+    }
+
+    /// Fill in the caller's tenant from `TENANT_SUBJECTS` when its
+    /// credentials don't name one, and check it's usable in queue names. The
+    /// tenant header must match the caller's tenant; a caller without one
+    /// can't send it at all.
+    pub fn apply(&self, principal: &mut Principal, headers: &HeaderMap) -> Result<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        if principal.tenant.is_none() {
+            principal.tenant = self.subjects.get(&principal.subject).cloned();
+        }
+        if let Some(header) = headers.get(&self.header) {
+            let header = header
+                .to_str()
+                .with_context(|| format!("Invalid {} header", self.header))?;
+            match &principal.tenant {
+                Some(tenant) if tenant != header => bail!(
+                    "{} header {:?} doesn't match the caller's tenant",
+                    self.header,
+                    header
+                ),
+                Some(_) => {}
+                None => bail!(
+                    "{} header {:?} given, but {:?} isn't assigned a tenant",
+                    self.header,
+                    header,
+                    principal.subject
+                ),
+            }
+        }
+        if let Some(tenant) = &principal.tenant {
+            if !is_valid_tenant(tenant) {
+                bail!(
+                    "Invalid tenant {:?} (expected up to {} letters, digits, '-' or '_')",
+                    tenant,
+                    MAX_TENANT_LEN
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Queue a job routed to `queue` goes to for `principal`
+    pub fn queue(&self, principal: Option<&Principal>, queue: &str) -> String {
+        match principal.and_then(|p| p.tenant.as_deref()) {
+            Some(tenant) if self.enabled => tenant_queue(tenant, queue),
+            _ => queue.to_string(),
+        }
+    }
+}
+
+fn parse_subjects(value: &str) -> Result<HashMap<String, String>> {
+    let mut subjects = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (subject, tenant) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid TENANT_SUBJECTS entry {:?}", entry))?;
+        subjects.insert(subject.trim().to_string(), tenant.trim().to_string());
+    }
+    Ok(subjects)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> TenancyConfig {
+        TenancyConfig {
+            enabled: true,
+            header: HeaderName::from_static("x-tenant-id"),
+            subjects: parse_subjects("billing-svc=acme").unwrap(),
+        }
+    }
+
+    fn principal(subject: &str, tenant: Option<&str>) -> Principal {
+        Principal {
+            subject: subject.to_string(),
+            tenant: tenant.map(str::to_string),
+        }
+    }
+
+    fn header(tenant: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant-id", tenant.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_header_cannot_impersonate_tenant() {
+        let tenancy = config();
+
+        // An API key without an assigned tenant can't claim one
+        let mut caller = principal("reports", None);
+        assert!(tenancy.apply(&mut caller, &header("acme")).is_err());
+        assert_eq!(caller.tenant, None);
+
+        // An assigned caller can't switch to another tenant
+        let mut caller = principal("billing-svc", None);
+        assert!(tenancy.apply(&mut caller, &header("globex")).is_err());
+
+        // Nor can a token with a tenant claim
+        let mut caller = principal("alice", Some("acme"));
+        assert!(tenancy.apply(&mut caller, &header("globex")).is_err());
+    }
+
+    #[test]
+    fn test_assigned_tenant() {
+        let tenancy = config();
+
+        let mut caller = principal("billing-svc", None);
+        tenancy.apply(&mut caller, &HeaderMap::new()).unwrap();
+        assert_eq!(caller.tenant.as_deref(), Some("acme"));
+
+        let mut caller = principal("billing-svc", None);
+        tenancy.apply(&mut caller, &header("acme")).unwrap();
+        assert_eq!(caller.tenant.as_deref(), Some("acme"));
+
+        let mut caller = principal("reports", None);
+        tenancy.apply(&mut caller, &HeaderMap::new()).unwrap();
+        assert_eq!(caller.tenant, None);
+    }
+}
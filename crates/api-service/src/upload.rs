@@ -0,0 +1,349 @@
+//! Bulk job submission from a CSV file.
+//!
+//! `POST /jobs/upload` takes a CSV with a header row naming the `op`, `a`,
+//! `b` and (optionally) `request_id` columns, in any order. Valid rows are
+//! enqueued together as one batch; invalid rows are reported by line number
+//! without failing the rest of the file.
+
+use crate::auth::Principal;
+use crate::idempotency;
+use crate::{
+    bad_request, enqueue_batch_jobs, submission_error, validation_error, AppState, ErrorResponse,
+    JobOptions, ValidationErrorResponse,
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use csv::StringRecord;
+use job_types::{FieldError, JobPayload, MathArgs};
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// Summary of a CSV upload
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UploadResponse {
+    /// Shared by the enqueued jobs (their `batch_id` custom field)
+    batch_id: String,
+    message: String,
+    /// Data rows in the file, excluding the header
+    total_rows: usize,
+    total_enqueued: usize,
+    /// Faktory was unavailable; the jobs are held locally and will be
+    /// enqueued when it recovers
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    deferred: bool,
+    accepted: Vec<AcceptedRow>,
+    rejected: Vec<RejectedRow>,
+}
+
+/// A row that was enqueued
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AcceptedRow {
+    /// Line number in the file (the header is line 1)
+    line: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    job_id: String,
+}
+
+/// A row that was invalid
+#[derive(Debug, Serialize, ToSchema)]
+pub struct RejectedRow {
+    /// Line number in the file (the header is line 1)
+    line: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<String>,
+    error: String,
+}
+
+/// Positions of the known columns in the header row
+#[derive(Debug)]
+struct Columns {
+    op: usize,
+    a: usize,
+    b: usize,
+    request_id: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &StringRecord) -> Result<Self, String> {
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+        };
+        match (find("op"), find("a"), find("b")) {
+            (Some(op), Some(a), Some(b)) => Ok(Self {
+                op,
+                a,
+                b,
+                request_id: find("request_id"),
+            }),
+            _ => Err(format!(
+                "CSV header must name the op, a and b columns (and optionally request_id), got {:?}",
+                headers.iter().collect::<Vec<_>>().join(",")
+            )),
+        }
+    }
+
+    fn request_id(&self, record: &StringRecord) -> Option<String> {
+        self.request_id
+            .and_then(|i| record.get(i))
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+    }
+
+    /// Build and validate the job for one row
+    fn payload(&self, record: &StringRecord) -> Result<JobPayload, String> {
+        let field = |i: usize| record.get(i).unwrap_or_default();
+        let op = field(self.op);
+        let kind = JobPayload::kind(&op.to_ascii_lowercase()).ok_or_else(|| {
+            let names: Vec<_> = JobPayload::KINDS.iter().map(|kind| kind.name).collect();
+            format!(
+                "op: unknown operation {:?} (expected {})",
+                op,
+                names.join(", ")
+            )
+        })?;
+        let number = |name: &str, i: usize| {
+            let value = field(i);
+            value
+                .parse::<f64>()
+                .ok()
+                .filter(|n| n.is_finite())
+                .ok_or_else(|| format!("{}: {:?} is not a finite number", name, value))
+        };
+        let args = MathArgs {
+            a: number("a", self.a)?,
+            b: number("b", self.b)?,
+            request_id: self.request_id(record),
+        };
+        let payload = serde_json::to_value(args)
+            .and_then(|args| JobPayload::from_tagged(kind.tag(), args))
+            .map_err(|e| e.to_string())?;
+
+        let errors = payload.validate();
+        if !errors.is_empty() {
+            let errors: Vec<_> = errors
+                .iter()
+                .map(|e| format!("{}: {}", e.field, e.message))
+                .collect();
+            return Err(errors.join("; "));
+        }
+        Ok(payload)
+    }
+}
+
+/// POST /jobs/upload - Enqueue the rows of a CSV file as one batch
+#[utoipa::path(
+    post,
+    path = "/jobs/upload",
+    tag = "jobs",
+    params(("Idempotency-Key" = Option<String>, Header, description = "Return the original jobs instead of enqueuing duplicates")),
+    request_body(
+        content = String,
+        content_type = "text/csv",
+        description = "A header row naming the `op`, `a`, `b` and optional `request_id` columns, then one job per row",
+        example = "op,a,b,request_id\nadd,1,2,row-1\ndivide,10,4,row-2\n"
+    ),
+    responses(
+        (status = 202, description = "Every row was enqueued", body = UploadResponse),
+        (status = 207, description = "Some rows were rejected (see `rejected`); the rest were enqueued", body = UploadResponse),
+        (status = 400, description = "Empty file or missing columns", body = ErrorResponse),
+        (status = 413, description = "File is larger than `REQUEST_MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 422, description = "Too many rows, or no valid rows (`details` names each line)", body = ValidationErrorResponse),
+        (status = 429, description = "Quota exceeded or queue backlogged (see `Retry-After`)", body = ErrorResponse),
+        (status = 500, description = "Failed to enqueue", body = ErrorResponse),
+        (status = 503, description = "Faktory is unavailable and no spill buffer is configured", body = ErrorResponse),
+    )
+)]
+pub async fn upload_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let mut options = JobOptions {
+        principal: principal.map(|Extension(p)| p),
+        idempotency_key: idempotency::key_from_headers(&headers),
+        ..JobOptions::default()
+    };
+    if let Err(e) = options.resolve(&state) {
+        return bad_request(e);
+    }
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(body.as_ref());
+    let columns = match reader.headers() {
+        Ok(headers) if !headers.is_empty() => Columns::from_headers(headers),
+        Ok(_) => Err("CSV file is empty".to_string()),
+        Err(e) => Err(format!("Invalid CSV header: {}", e)),
+    };
+    let columns = match columns {
+        Ok(columns) => columns,
+        Err(error) => {
+            return (StatusCode::BAD_REQUEST, Json(ErrorResponse { error })).into_response()
+        }
+    };
+
+    let max_rows = state.batch_config.max_request_jobs;
+    let mut total_rows = 0;
+    let mut valid = Vec::new();
+    let mut rejected = Vec::new();
+    for record in reader.records() {
+        total_rows += 1;
+        if total_rows > max_rows {
+            return validation_error(vec![FieldError::new(
+                "rows",
+                format!("At most {} rows per upload", max_rows),
+            )]);
+        }
+        match record {
+            Ok(record) => {
+                let line = record.position().map_or(0, |p| p.line());
+                let request_id = columns.request_id(&record);
+                match columns.payload(&record) {
+                    Ok(payload) => valid.push((line, request_id, payload)),
+                    Err(error) => rejected.push(RejectedRow {
+                        line,
+                        request_id,
+                        error,
+                    }),
+                }
+            }
+            Err(e) => rejected.push(RejectedRow {
+                line: e.position().map_or(0, |p| p.line()),
+                request_id: None,
+                error: format!("Invalid CSV row: {}", e),
+            }),
+        }
+    }
+    if total_rows == 0 {
+        let response = ErrorResponse {
+            error: "CSV file must contain at least one row".to_string(),
+        };
+        return (StatusCode::BAD_REQUEST, Json(response)).into_response();
+    }
+    if valid.is_empty() {
+        return validation_error(
+            rejected
+                .into_iter()
+                .map(|row| FieldError::new(format!("line {}", row.line), row.error))
+                .collect(),
+        );
+    }
+
+    let (rows, payloads): (Vec<_>, Vec<_>) = valid
+        .into_iter()
+        .map(|(line, request_id, payload)| ((line, request_id), payload))
+        .unzip();
+    let outcome = match enqueue_batch_jobs(&state, payloads, &options).await {
+        Ok(outcome) => outcome,
+        Err(e) => return submission_error("Failed to enqueue uploaded jobs", e),
+    };
+
+    let accepted: Vec<AcceptedRow> = rows
+        .into_iter()
+        .zip(outcome.job_ids)
+        .map(|((line, request_id), job_id)| AcceptedRow {
+            line,
+            request_id,
+            job_id,
+        })
+        .collect();
+
+    let message = if outcome.deferred {
+        format!(
+            "Faktory is unavailable; {} jobs will be enqueued when it recovers",
+            accepted.len()
+        )
+    } else {
+        format!("Enqueued {} of {} rows", accepted.len(), total_rows)
+    };
+    let status = if rejected.is_empty() {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+    let response = UploadResponse {
+        batch_id: outcome.batch_id,
+        message,
+        total_rows,
+        total_enqueued: accepted.len(),
+        deferred: outcome.deferred,
+        accepted,
+        rejected,
+    };
+    (status, Json(response)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(fields: &[&str]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+
+    #[test]
+    fn test_columns_from_headers() {
+        let columns = Columns::from_headers(&record(&["B", "request_id", "Op", "a"])).unwrap();
+        assert_eq!((columns.op, columns.a, columns.b), (2, 3, 0));
+        assert_eq!(columns.request_id, Some(1));
+
+        let columns = Columns::from_headers(&record(&["op", "a", "b"])).unwrap();
+        assert_eq!(columns.request_id, None);
+
+        let err = Columns::from_headers(&record(&["op", "a", "c"])).unwrap_err();
+        assert!(err.contains("\"op,a,c\""));
+    }
+
+    #[test]
+    fn test_row_payload() {
+        let columns = Columns::from_headers(&record(&["op", "a", "b", "request_id"])).unwrap();
+
+        let payload = columns
+            .payload(&record(&["Divide", "10", "4", "row-1"]))
+            .unwrap();
+        assert_eq!(payload.job_type(), "math_divide");
+        match payload {
+            JobPayload::Divide(args) => {
+                assert_eq!((args.a, args.b), (10.0, 4.0));
+                assert_eq!(args.request_id.as_deref(), Some("row-1"));
+            }
+            other => panic!("unexpected payload {:?}", other),
+        }
+
+        // An empty request_id is no request_id
+        let payload = columns.payload(&record(&["add", "1", "2", ""])).unwrap();
+        assert!(matches!(payload, JobPayload::Add(args) if args.request_id.is_none()));
+    }
+
+    #[test]
+    fn test_invalid_rows() {
+        let columns = Columns::from_headers(&record(&["op", "a", "b"])).unwrap();
+        let error = |fields: &[&str]| columns.payload(&record(fields)).unwrap_err();
+
+        assert!(error(&["modulo", "1", "2"]).starts_with("op: unknown operation \"modulo\""));
+        assert_eq!(
+            error(&["add", "one", "2"]),
+            "a: \"one\" is not a finite number"
+        );
+        assert_eq!(
+            error(&["add", "1", "inf"]),
+            "b: \"inf\" is not a finite number"
+        );
+        // A short row is missing its operands
+        assert_eq!(error(&["add", "1"]), "b: \"\" is not a finite number");
+        assert_eq!(error(&["divide", "1", "0"]), "b: Division by zero");
+        // Batches can't be built from one row's operands
+        assert!(columns.payload(&record(&["math-batch", "1", "2"])).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Per-caller usage accounting and quotas.
+//!
+//! Every job submitted by an authenticated caller is counted against the
+//! caller's daily and monthly counters in the result store. Submissions that
+//! would take a caller over its quota are rejected with 429.
+
+use crate::auth::Principal;
+use crate::{AppState, ErrorResponse};
+use anyhow::{anyhow, Context, Result};
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Extension, Json};
+use result_store::Usage;
+use serde::Serialize;
+use service_config::Config;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+use tracing::warn;
+use utoipa::ToSchema;
+
+/// Job limits for one caller. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quota {
+    pub daily: Option<u64>,
+    pub monthly: Option<u64>,
+}
+
+/// Quotas applied to callers, read from the environment
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QuotaConfig {
+    /// Applies to callers without an override
+    pub default: Quota,
+    /// Caller name -> quota
+    pub overrides: HashMap<String, Quota>,
+}
+
+impl QuotaConfig {
+    /// Read `QUOTA_DAILY_JOBS`, `QUOTA_MONTHLY_JOBS` and `QUOTA_OVERRIDES`
+    /// (`name=daily/monthly` pairs separated by commas; leave a side empty for
+    /// no limit, e.g. `team-a=1000/20000,team-b=/500`)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let default = Quota {
+            daily: config.parse("QUOTA_DAILY_JOBS"),
+            monthly: config.parse("QUOTA_MONTHLY_JOBS"),
+        };
+        let overrides = match config.string("QUOTA_OVERRIDES") {
+            Some(v) => parse_overrides(&v)?,
+            None => HashMap::new(),
+        };
+        Ok(Self { default, overrides })
+    }
+
+    fn quota_for(&self, key: &str) -> Quota {
+        self.overrides.get(key).copied().unwrap_or(self.default)
+    }
+}
+
+fn parse_overrides(value: &str) -> Result<HashMap<String, Quota>> {
+    let parse_limit = |limit: &str| -> Result<Option<u64>> {
+        let limit = limit.trim();
+        if limit.is_empty() {
+            return Ok(None);
+        }
+        limit
+            .parse()
+            .map(Some)
+            .with_context(|| format!("Invalid quota limit {:?}", limit))
+    };
+
+    let mut overrides = HashMap::new();
+    for entry in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let (name, limits) = entry
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid QUOTA_OVERRIDES entry {:?}", entry))?;
+        let (daily, monthly) = limits.split_once('/').unwrap_or((limits, ""));
+        overrides.insert(
+            name.trim().to_string(),
+            Quota {
+                daily: parse_limit(daily)?,
+                monthly: parse_limit(monthly)?,
+            },
+        );
+    }
+    Ok(overrides)
+}
+
+/// Returned (inside `anyhow::Error`) when a submission is over quota
+#[derive(Debug)]
+pub struct QuotaExceeded {
+    period: &'static str,
+    limit: u64,
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} quota of {} jobs exceeded", self.period, self.limit)
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+/// Count `jobs` submissions against the caller's usage, rejecting them with
+/// [`QuotaExceeded`] if they don't fit in its quota. Anonymous submissions
+/// aren't counted. If the result store is unavailable the jobs are let
+/// through rather than failing submissions.
+pub async fn charge(state: &AppState, principal: Option<&Principal>, jobs: usize) -> Result<()> {
+    let Some(principal) = principal else {
+        return Ok(());
+    };
+    let key = &principal.subject;
+
+    let usage = match state.result_store.add_usage(key, jobs as i64).await {
+        Ok(usage) => usage,
+        Err(e) => {
+            warn!("Failed to record usage for {}: {:#}", key, e);
+            return Ok(());
+        }
+    };
+
+    let quota = state.quota_config.read().unwrap().quota_for(key);
+    let exceeded = match (quota.daily, quota.monthly) {
+        (Some(limit), _) if usage.daily_jobs > limit => Some(QuotaExceeded {
+            period: "Daily",
+            limit,
+        }),
+        (_, Some(limit)) if usage.monthly_jobs > limit => Some(QuotaExceeded {
+            period: "Monthly",
+            limit,
+        }),
+        _ => None,
+    };
+
+    if let Some(exceeded) = exceeded {
+        refund(state, Some(principal), jobs).await;
+        return Err(exceeded.into());
+    }
+    Ok(())
+}
+
+/// Undo a [`charge`] for jobs that were not enqueued after all
+pub async fn refund(state: &AppState, principal: Option<&Principal>, jobs: usize) {
+    let Some(principal) = principal else {
+        return;
+    };
+    if let Err(e) = state
+        .result_store
+        .add_usage(&principal.subject, -(jobs as i64))
+        .await
+    {
+        warn!("Failed to refund usage for {}: {:#}", principal.subject, e);
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UsageResponse {
+    #[serde(flatten)]
+    usage: Usage,
+    daily_limit: Option<u64>,
+    monthly_limit: Option<u64>,
+}
+
+/// GET /usage - The caller's job counts for the current day and month
+#[utoipa::path(
+    get,
+    path = "/usage",
+    tag = "usage",
+    responses(
+        (status = 200, description = "Usage and quota", body = UsageResponse),
+        (status = 400, description = "Authentication is disabled", body = ErrorResponse),
+        (status = 500, description = "Result store error", body = ErrorResponse),
+    )
+)]
+pub async fn usage_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+) -> impl IntoResponse {
+    let Some(Extension(principal)) = principal else {
+        let response = ErrorResponse {
+            error: "Usage is tracked per caller; enable AUTH_MODE to use it".to_string(),
+        };
+        return (StatusCode::BAD_REQUEST, Json(response)).into_response();
+    };
+
+    match state.result_store.usage(&principal.subject).await {
+        Ok(usage) => {
+            let quota = state
+                .quota_config
+                .read()
+                .unwrap()
+                .quota_for(&principal.subject);
+            let response = UsageResponse {
+                usage,
+                daily_limit: quota.daily,
+                monthly_limit: quota.monthly,
+            };
+            (StatusCode::OK, Json(response)).into_response()
+        }
+        Err(e) => {
+            warn!("Failed to read usage: {:#}", e);
+            let response = ErrorResponse {
+                error: format!("Failed to read usage: {}", e),
+            };
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(response)).into_response()
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! Dry-run validation of job submissions.
+//!
+//! `POST /jobs/validate` takes the same body as `POST /jobs/batch` and runs
+//! the same parsing, validation and routing, but instead of enqueuing it
+//! reports the Faktory job each entry would become. Nothing is pushed, no
+//! quota is charged and nothing is audited, so CI can check generated job
+//! files before they're submitted for real. Backpressure and maintenance mode
+//! still apply, so jobs for a backlogged queue, or any job during
+//! maintenance, are reported as invalid.
+
+use crate::auth::Principal;
+use crate::{
+    bad_request, build_job, parse_batch_item, validation_error, AppState, BatchJobRequest,
+    ErrorResponse, ValidationErrorResponse,
+};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use job_types::FieldError;
+use serde::Serialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// What a dry run found
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ValidateResponse {
+    /// Whether every job would be accepted
+    valid: bool,
+    total: usize,
+    invalid: usize,
+    /// One entry per requested job, in request order
+    jobs: Vec<ValidatedJob>,
+}
+
+/// One job of a dry run, by its position in `jobs`
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+enum ValidatedJob {
+    /// The Faktory job that would be enqueued
+    Valid {
+        index: usize,
+        job_type: String,
+        queue: String,
+        priority: u8,
+        args: serde_json::Value,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scheduled_at: Option<DateTime<Utc>>,
+    },
+    Invalid {
+        index: usize,
+        error: String,
+        #[serde(skip_serializing_if = "Vec::is_empty")]
+        details: Vec<FieldError>,
+    },
+}
+
+/// POST /jobs/validate - Check jobs without enqueuing them
+#[utoipa::path(
+    post,
+    path = "/jobs/validate",
+    tag = "jobs",
+    request_body = BatchJobRequest,
+    responses(
+        (status = 200, description = "Each job with what would be enqueued, or why it would be rejected", body = ValidateResponse),
+        (status = 400, description = "Empty batch or invalid job options", body = ErrorResponse),
+        (status = 413, description = "Request body is larger than `REQUEST_MAX_BODY_BYTES`", body = ErrorResponse),
+        (status = 422, description = "More jobs than `BATCH_REQUEST_MAX_JOBS`", body = ValidationErrorResponse),
+    )
+)]
+pub async fn validate_handler(
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+    Json(mut req): Json<BatchJobRequest>,
+) -> Response {
+    req.options.principal = principal.map(|Extension(p)| p);
+    if let Err(e) = req.options.resolve(&state) {
+        return bad_request(e);
+    }
+    let total = req.jobs.len();
+    if total == 0 {
+        let response = ErrorResponse {
+            error: "Batch request must contain at least one job".to_string(),
+        };
+        return (StatusCode::BAD_REQUEST, Json(response)).into_response();
+    }
+    if total > state.batch_config.max_request_jobs {
+        return validation_error(vec![FieldError::new(
+            "jobs",
+            format!(
+                "At most {} jobs per batch, got {}",
+                state.batch_config.max_request_jobs, total
+            ),
+        )]);
+    }
+
+    let jobs: Vec<ValidatedJob> = req
+        .jobs
+        .into_iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let payload = match parse_batch_item(index, value) {
+                Ok(payload) => payload,
+                Err((error, details)) => {
+                    return ValidatedJob::Invalid {
+                        index,
+                        error,
+                        details,
+                    }
+                }
+            };
+            match build_job(&state, &payload, &req.options) {
+                Ok(job) => ValidatedJob::Valid {
+                    index,
+                    job_type: job.kind().to_string(),
+                    queue: job.queue.clone(),
+                    priority: req.options.effective_priority(),
+                    args: job.args().first().cloned().unwrap_or_default(),
+                    scheduled_at: req.options.run_at,
+                },
+                Err(e) => ValidatedJob::Invalid {
+                    index,
+                    error: format!("{:#}", e),
+                    details: Vec::new(),
+                },
+            }
+        })
+        .collect();
+
+    let invalid = jobs
+        .iter()
+        .filter(|job| matches!(job, ValidatedJob::Invalid { .. }))
+        .count();
+    let response = ValidateResponse {
+        valid: invalid == 0,
+        total,
+        invalid,
+        jobs,
+    };
+    Json(response).into_response()
+}
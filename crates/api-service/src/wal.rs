@@ -0,0 +1,363 @@
+//! Write-ahead logs for jobs accepted before they reach Faktory.
+//!
+//! Used for the auto-batch queue and for the spill buffer that holds jobs
+//! while the Faktory circuit is open. Each job is appended to a local log and
+//! fsynced before it's acknowledged (202); once it reaches Faktory an ack is
+//! appended. On startup every job without an ack is recovered, so a crash
+//! before the push doesn't lose jobs (jobs from a push that failed part-way
+//! may be enqueued twice).
+//!
+//! The log is one JSON record per line and is compacted once it has grown
+//! past `COMPACT_AFTER` records.
+
+use anyhow::{Context, Result};
+use faktory::Job;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+/// Records written since the last compaction that trigger a rewrite
+const COMPACT_AFTER: usize = 10_000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Record {
+    /// A job accepted but not yet in Faktory
+    Add { job: Box<Job> },
+    /// Jobs that reached Faktory
+    Ack { jids: Vec<String> },
+}
+
+struct Log {
+    file: File,
+    /// Jobs without an ack, as written to the log, in the order they were
+    /// accepted
+    pending: Pending,
+    /// Records appended since the file was last rewritten
+    records: usize,
+}
+
+/// Unacknowledged log records, kept in acceptance order so jobs are replayed
+/// and re-logged in the order they came in
+#[derive(Default)]
+struct Pending {
+    /// Record lines by sequence number
+    lines: BTreeMap<u64, String>,
+    /// Sequence number of each job's record
+    seqs: HashMap<String, u64>,
+    next_seq: u64,
+}
+
+impl Pending {
+    /// Add a job's record after the others; a job logged again moves to the
+    /// back with its latest record
+    fn insert(&mut self, jid: String, line: String) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if let Some(old) = self.seqs.insert(jid, seq) {
+            self.lines.remove(&old);
+        }
+        self.lines.insert(seq, line);
+    }
+
+    fn remove(&mut self, jid: &str) {
+        if let Some(seq) = self.seqs.remove(jid) {
+            self.lines.remove(&seq);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Record lines, oldest first
+    fn lines(&self) -> impl Iterator<Item = &String> {
+        self.lines.values()
+    }
+}
+
+/// Append-only log of jobs waiting to be pushed to Faktory
+pub struct JobWal {
+    path: PathBuf,
+    log: Mutex<Log>,
+}
+
+impl JobWal {
+    /// Open (or create) the log at `path`, returning it along with the jobs
+    /// that were accepted but never pushed
+    pub async fn open(path: impl Into<PathBuf>) -> Result<(Self, Vec<Job>)> {
+        let path = path.into();
+        let contents = match fs::read_to_string(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+
+        // Replay the log, keeping jobs in the order they were accepted
+        let mut pending = Pending::default();
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Record>(line) {
+                Ok(Record::Add { job }) => pending.insert(job.id().to_string(), line.to_string()),
+                Ok(Record::Ack { jids }) => {
+                    for jid in &jids {
+                        pending.remove(jid);
+                    }
+                }
+                // A torn final write from a crash; earlier records are intact
+                Err(e) => warn!(
+                    "Skipping unreadable job log record at {}:{}: {}",
+                    path.display(),
+                    number + 1,
+                    e
+                ),
+            }
+        }
+        let jobs = pending
+            .lines()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(Record::Add { job }) => Some(*job),
+                _ => None,
+            })
+            .collect::<Vec<Job>>();
+
+        let file = rewrite(&path, pending.lines()).await?;
+        if !jobs.is_empty() {
+            info!(
+                "Recovered {} unpushed jobs from {}",
+                jobs.len(),
+                path.display()
+            );
+        }
+
+        let wal = Self {
+            path,
+            log: Mutex::new(Log {
+                file,
+                pending,
+                records: 0,
+            }),
+        };
+        Ok((wal, jobs))
+    }
+
+    /// Durably record a job before it's acknowledged
+    pub async fn append(&self, job: &Job) -> Result<()> {
+        let line = serde_json::to_string(&Record::Add {
+            job: Box::new(job.clone()),
+        })?;
+        let mut log = self.log.lock().await;
+        write_record(&mut log.file, &line).await?;
+        log.pending.insert(job.id().to_string(), line);
+        log.records += 1;
+        Ok(())
+    }
+
+    /// Up to `limit` jobs that haven't reached Faktory yet, oldest first
+    pub async fn pending(&self, limit: usize) -> Vec<Job> {
+        let log = self.log.lock().await;
+        log.pending
+            .lines()
+            .take(limit)
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(Record::Add { job }) => Some(*job),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Record that `jids` reached Faktory
+    pub async fn ack(&self, jids: Vec<String>) -> Result<()> {
+        if jids.is_empty() {
+            return Ok(());
+        }
+        let mut log = self.log.lock().await;
+        for jid in &jids {
+            log.pending.remove(jid);
+        }
+
+        if log.pending.is_empty() || log.records >= COMPACT_AFTER {
+            log.file = rewrite(&self.path, log.pending.lines()).await?;
+            log.records = 0;
+        } else {
+            let line = serde_json::to_string(&Record::Ack { jids })?;
+            write_record(&mut log.file, &line).await?;
+            log.records += 1;
+        }
+        Ok(())
+    }
+}
+
+async fn write_record(file: &mut File, line: &str) -> Result<()> {
+    file.write_all(format!("{}\n", line).as_bytes())
+        .await
+        .context("Failed to write job log")?;
+    file.sync_data().await.context("Failed to sync job log")?;
+    Ok(())
+}
+
+/// Make a rename in `path`'s directory durable, so a crash can't bring back
+/// the old log with records that were already acknowledged
+#[cfg(unix)]
+async fn sync_dir(path: &Path) -> Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let dir = File::open(dir)
+        .await
+        .with_context(|| format!("Failed to open {}", dir.display()))?;
+    dir.sync_all()
+        .await
+        .context("Failed to sync job log directory")
+}
+
+/// Windows can't open a directory as a file to sync it
+#[cfg(not(unix))]
+async fn sync_dir(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Atomically replace the log with `lines` and reopen it for appending
+async fn rewrite<'a>(path: &Path, lines: impl Iterator<Item = &'a String>) -> Result<File> {
+    let tmp = path.with_extension("tmp");
+    let mut contents = String::new();
+    for line in lines {
+        contents.push_str(line);
+        contents.push('\n');
+    }
+
+    let mut file = File::create(&tmp)
+        .await
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.write_all(contents.as_bytes()).await?;
+    file.sync_all().await?;
+    fs::rename(&tmp, path)
+        .await
+        .with_context(|| format!("Failed to replace {}", path.display()))?;
+    sync_dir(path).await?;
+
+    OpenOptions::new()
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("api-wal-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.join("jobs.log")
+    }
+
+    fn jobs(count: usize) -> Vec<Job> {
+        (0..count)
+            .map(|i| Job::new("math_add", vec![serde_json::json!({"a": i, "b": 1})]))
+            .collect()
+    }
+
+    fn ids(jobs: &[Job]) -> Vec<String> {
+        jobs.iter().map(|job| job.id().to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_append_and_replay_in_order() {
+        let path = log_path("replay");
+        let jobs = jobs(50);
+        {
+            let (wal, recovered) = JobWal::open(&path).await.unwrap();
+            assert!(recovered.is_empty());
+            for job in &jobs {
+                wal.append(job).await.unwrap();
+            }
+            assert_eq!(ids(&wal.pending(10).await), ids(&jobs[..10]));
+            assert_eq!(ids(&wal.pending(100).await), ids(&jobs));
+        }
+
+        let (_, recovered) = JobWal::open(&path).await.unwrap();
+        assert_eq!(ids(&recovered), ids(&jobs));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ack_and_compaction() {
+        let path = log_path("ack");
+        let jobs = jobs(5);
+        let (wal, _) = JobWal::open(&path).await.unwrap();
+        for job in &jobs {
+            wal.append(job).await.unwrap();
+        }
+
+        wal.ack(vec![ids(&jobs)[1].clone(), ids(&jobs)[3].clone()])
+            .await
+            .unwrap();
+        let remaining = [&jobs[0], &jobs[2], &jobs[4]].map(|job| job.id().to_string());
+        assert_eq!(ids(&wal.pending(10).await), remaining);
+        // Acks are appended until everything is acknowledged
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 6);
+
+        wal.ack(remaining.to_vec()).await.unwrap();
+        assert!(wal.pending(10).await.is_empty());
+        // Then the log is compacted away
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        // Compaction keeps the unacknowledged jobs, in order
+        for job in &jobs {
+            wal.append(job).await.unwrap();
+        }
+        wal.log.lock().await.records = COMPACT_AFTER;
+        wal.ack(vec![ids(&jobs)[0].clone()]).await.unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 4);
+        let (_, recovered) = JobWal::open(&path).await.unwrap();
+        assert_eq!(ids(&recovered), ids(&jobs[1..]));
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reopen_after_crash() {
+        let path = log_path("crash");
+        let jobs = jobs(3);
+        {
+            let (wal, _) = JobWal::open(&path).await.unwrap();
+            for job in &jobs {
+                wal.append(job).await.unwrap();
+            }
+            wal.ack(vec![ids(&jobs)[0].clone()]).await.unwrap();
+        }
+        // The process died halfway through writing a record, and before it
+        // could clean up a compaction
+        let mut contents = std::fs::read_to_string(&path).unwrap();
+        contents.push_str(r#"{"op":"add","job":{"jid":"#);
+        std::fs::write(&path, contents).unwrap();
+        std::fs::write(path.with_extension("tmp"), "garbage\n").unwrap();
+
+        let (wal, recovered) = JobWal::open(&path).await.unwrap();
+        assert_eq!(ids(&recovered), ids(&jobs[1..]));
+        // Recovery rewrites the log without the torn record
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 2);
+
+        // A job logged again after recovery moves to the back
+        wal.append(&jobs[1]).await.unwrap();
+        assert_eq!(
+            ids(&wal.pending(10).await),
+            [ids(&jobs)[2].clone(), ids(&jobs)[1].clone()]
+        );
+
+        std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
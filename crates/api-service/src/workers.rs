@@ -0,0 +1,44 @@
+//! The live worker fleet.
+//!
+//! Every worker records a heartbeat in the result store at a fixed interval
+//! with its labels, queues, concurrency, jobs in flight and throughput.
+//! `GET /workers` lists the workers whose latest heartbeat hasn't expired, so
+//! it shows who's up and what they're doing; a worker that stops drops off
+//! the list once its heartbeat expires.
+
+use crate::{AppState, ErrorResponse};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use result_store::WorkerHeartbeat;
+use std::sync::Arc;
+use tracing::warn;
+
+/// GET /workers - Live workers and their latest heartbeat, by worker id
+#[utoipa::path(
+    get,
+    path = "/workers",
+    tag = "workers",
+    responses(
+        (status = 200, description = "Workers that sent a heartbeat recently", body = Vec<WorkerHeartbeat>),
+        (status = 500, description = "The result store couldn't be read", body = ErrorResponse),
+    )
+)]
+pub async fn list_workers_handler(State(state): State<Arc<AppState>>) -> Response {
+    match state.result_store.list_workers().await {
+        Ok(workers) => Json(workers).into_response(),
+        Err(e) => {
+            warn!("Failed to list workers: {:#}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to list workers: {}", e),
+                }),
+            )
+                .into_response()
+        }
+    }
+}
@@ -0,0 +1,277 @@
+//! WebSocket channel for submitting jobs and receiving their results.
+//!
+//! Clients send `submit` messages and get an `accepted` (or `error`) reply
+//! for each one, followed by a `completed` message carrying the job result
+//! once the worker finishes it. Pending jobs are polled from the result
+//! store; with completion events on (`COMPLETION_EVENTS_URL`), results the
+//! worker publishes are sent right away.
+
+use crate::auth::Principal;
+use crate::{submit_job, AppState, JobOptions};
+use axum::{
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        State,
+    },
+    response::IntoResponse,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use job_types::JobPayload;
+use result_store::JobResult;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// Configuration for WebSocket connections
+#[derive(Clone)]
+pub struct WsConfig {
+    /// How often to check the result store for finished jobs
+    pub poll_interval: Duration,
+    /// Maximum unfinished jobs tracked per connection
+    pub max_pending_jobs: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Submit a job. `ref` is echoed back so clients can match replies.
+    Submit {
+        #[serde(rename = "ref")]
+        client_ref: Option<String>,
+        job: JobPayload,
+        #[serde(flatten)]
+        options: JobOptions,
+    },
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage {
+    Accepted {
+        #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+        client_ref: Option<String>,
+        job_id: String,
+        /// Faktory is unavailable; the job will be enqueued when it recovers
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        deferred: bool,
+        /// An earlier job shares the `request_id`; nothing was enqueued
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        deduplicated: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        scheduled_at: Option<DateTime<Utc>>,
+        priority: u8,
+    },
+    Completed {
+        #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+        client_ref: Option<String>,
+        result: Box<JobResult>,
+    },
+    Error {
+        #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+        client_ref: Option<String>,
+        error: String,
+    },
+}
+
+/// GET /ws - Upgrade to a job submission/completion channel
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "jobs",
+    responses(
+        (status = 101, description = "Switched to the WebSocket protocol"),
+    )
+)]
+pub async fn ws_handler(
+    ws: WebSocketUpgrade,
+    State(state): State<Arc<AppState>>,
+    principal: Option<Extension<Principal>>,
+) -> impl IntoResponse {
+    // Jobs submitted over the socket belong to whoever opened it
+    let principal = principal.map(|Extension(p)| p);
+    ws.max_message_size(state.limits.max_body_bytes)
+        .on_upgrade(move |socket| handle_socket(socket, state, principal))
+}
+
+async fn handle_socket(mut socket: WebSocket, state: Arc<AppState>, principal: Option<Principal>) {
+    // Unfinished jobs submitted on this connection: job id -> client ref
+    let mut pending: HashMap<String, Option<String>> = HashMap::new();
+
+    let mut ticker = tokio::time::interval(state.ws_config.poll_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut completions = state.completions.as_ref().map(|feed| feed.subscribe());
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => {
+                        warn!("WebSocket receive error: {:#}", e);
+                        break;
+                    }
+                };
+
+                let reply = handle_message(&state, principal.as_ref(), &mut pending, text.as_str()).await;
+                if send(&mut socket, &reply).await.is_err() {
+                    break;
+                }
+            }
+            Some(event) = next_completion(completions.as_mut()), if !pending.is_empty() => {
+                let result = match event {
+                    Ok(result) => result,
+                    // Missed events are caught by the next poll
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => {
+                        completions = None;
+                        continue;
+                    }
+                };
+                // A failure the job will be retried after isn't its result
+                if !result.is_final() {
+                    continue;
+                }
+                let Some(client_ref) = pending.remove(&result.job_id) else {
+                    continue;
+                };
+                let message = ServerMessage::Completed {
+                    client_ref,
+                    result: Box::new(JobResult::clone(&result)),
+                };
+                if send(&mut socket, &message).await.is_err() {
+                    break;
+                }
+            }
+            _ = ticker.tick(), if !pending.is_empty() => {
+                for message in collect_finished(&state, &mut pending).await {
+                    if send(&mut socket, &message).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn handle_message(
+    state: &AppState,
+    principal: Option<&Principal>,
+    pending: &mut HashMap<String, Option<String>>,
+    text: &str,
+) -> ServerMessage {
+    let message: ClientMessage = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(e) => {
+            return ServerMessage::Error {
+                client_ref: None,
+                error: format!("Invalid message: {}", e),
+            }
+        }
+    };
+
+    match message {
+        ClientMessage::Submit {
+            client_ref,
+            job,
+            mut options,
+        } => {
+            options.principal = principal.cloned();
+            if let Err(e) = options.resolve(state) {
+                return ServerMessage::Error {
+                    client_ref,
+                    error: format!("{:#}", e),
+                };
+            }
+            let errors = job.validate();
+            if !errors.is_empty() {
+                let details: Vec<String> = errors
+                    .iter()
+                    .map(|e| format!("{}: {}", e.field, e.message))
+                    .collect();
+                return ServerMessage::Error {
+                    client_ref,
+                    error: format!("Invalid job: {}", details.join("; ")),
+                };
+            }
+            if pending.len() >= state.ws_config.max_pending_jobs {
+                return ServerMessage::Error {
+                    client_ref,
+                    error: format!(
+                        "Too many unfinished jobs on this connection (max {})",
+                        state.ws_config.max_pending_jobs
+                    ),
+                };
+            }
+
+            match submit_job(state, job, &options).await {
+                Ok(submitted) => {
+                    pending.insert(submitted.job_id.clone(), client_ref.clone());
+                    ServerMessage::Accepted {
+                        client_ref,
+                        job_id: submitted.job_id,
+                        deferred: submitted.deferred,
+                        deduplicated: submitted.deduplicated,
+                        scheduled_at: options.run_at,
+                        priority: options.effective_priority(),
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to enqueue job: {:#}", e);
+                    ServerMessage::Error {
+                        client_ref,
+                        error: format!("Failed to enqueue job: {}", e),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Look up all pending jobs and remove the ones that have finished for good
+async fn collect_finished(
+    state: &AppState,
+    pending: &mut HashMap<String, Option<String>>,
+) -> Vec<ServerMessage> {
+    let lookups = pending.keys().map(|job_id| state.result_store.get(job_id));
+    let results = join_all(lookups).await;
+
+    let mut finished = Vec::new();
+    for result in results {
+        match result {
+            Ok(Some(result)) if result.is_final() => finished.push(result),
+            Ok(_) => {}
+            Err(e) => warn!("Failed to read job result: {:#}", e),
+        }
+    }
+
+    finished
+        .into_iter()
+        .map(|result| ServerMessage::Completed {
+            client_ref: pending.remove(&result.job_id).flatten(),
+            result: Box::new(result),
+        })
+        .collect()
+}
+
+/// The next completion event, or never without a feed
+async fn next_completion(
+    completions: Option<&mut broadcast::Receiver<Arc<JobResult>>>,
+) -> Option<Result<Arc<JobResult>, RecvError>> {
+    match completions {
+        Some(completions) => Some(completions.recv().await),
+        None => std::future::pending().await,
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).unwrap_or_default();
+    socket.send(Message::Text(text.into())).await
+}
@@ -0,0 +1,268 @@
+//! Recent submissions, from the API's `GET /jobs` listing of finished jobs
+//! and its `GET /jobs/pending` listing of those still queued or running.
+//!
+//! `/history` shows a page of them, newest first, filtered by status and job
+//! type; queued and running jobs head the first page. Pages are keyed by the
+//! API's `next_page` token, so jobs finishing while someone pages through
+//! don't shift what they see.
+
+use crate::{display_value, ApiError, AppState};
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse},
+};
+use job_types::JobPayload;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::warn;
+
+/// Jobs shown per page
+const PAGE_SIZE: usize = 20;
+
+/// Longest operands or result shown before it's cut short
+const MAX_CELL_CHARS: usize = 80;
+
+/// Statuses of jobs that haven't finished
+const PENDING_STATUSES: [&str; 2] = ["enqueued", "running"];
+
+/// Filters and position in the listing, from the query string
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct HistoryParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    job_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    page: Option<String>,
+}
+
+impl HistoryParams {
+    /// The same filters at `page` (the first without one), as a link
+    fn link(&self, page: Option<String>) -> String {
+        let params = HistoryParams {
+            status: self.status.clone(),
+            job_type: self.job_type.clone(),
+            page,
+        };
+        match serde_urlencoded::to_string(&params) {
+            Ok(query) if !query.is_empty() => format!("/history?{}", query),
+            _ => "/history".to_string(),
+        }
+    }
+
+    /// Whether the status filter only matches jobs that haven't finished
+    fn pending_only(&self) -> bool {
+        self.status
+            .as_deref()
+            .is_some_and(|status| PENDING_STATUSES.contains(&status))
+    }
+
+    /// Whether the status filter matches jobs that have finished
+    fn finished_only(&self) -> bool {
+        self.status.is_some() && !self.pending_only()
+    }
+}
+
+/// A page of the API's `GET /jobs`
+#[derive(Debug, Deserialize)]
+struct ApiJobPage {
+    jobs: Vec<ApiJobResult>,
+    next_page: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiJobResult {
+    job_id: String,
+    job_type: String,
+    status: String,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    finished_at: String,
+    duration_ms: Option<u64>,
+    args: Option<serde_json::Value>,
+}
+
+/// A job from the API's `GET /jobs/pending`
+#[derive(Debug, Deserialize)]
+struct ApiPendingJob {
+    job_id: String,
+    job_type: String,
+    status: String,
+    args: Option<serde_json::Value>,
+}
+
+/// A job as listed
+struct Row {
+    job_id: String,
+    job_type: String,
+    operands: String,
+    status: String,
+    /// The result, or the error of a failed job
+    outcome: String,
+    duration: String,
+    finished_at: String,
+}
+
+impl From<ApiJobResult> for Row {
+    fn from(job: ApiJobResult) -> Self {
+        let operands = operands(&job.args);
+        let outcome = match (&job.result, job.error) {
+            (Some(result), _) => display_value(result),
+            (None, Some(error)) => error,
+            (None, None) => String::new(),
+        };
+        Self {
+            job_id: job.job_id,
+            job_type: job.job_type,
+            operands: shorten(operands),
+            status: job.status,
+            outcome: shorten(outcome),
+            duration: job
+                .duration_ms
+                .map_or_else(String::new, |ms| format!("{} ms", ms)),
+            finished_at: job.finished_at,
+        }
+    }
+}
+
+impl From<ApiPendingJob> for Row {
+    fn from(job: ApiPendingJob) -> Self {
+        Self {
+            job_id: job.job_id,
+            job_type: job.job_type,
+            operands: shorten(operands(&job.args)),
+            status: job.status,
+            outcome: String::new(),
+            duration: String::new(),
+            finished_at: String::new(),
+        }
+    }
+}
+
+/// A job's arguments as listed: `name = value` for each field
+fn operands(args: &Option<serde_json::Value>) -> String {
+    match args {
+        Some(serde_json::Value::Object(fields)) => fields
+            .iter()
+            .map(|(name, value)| format!("{} = {}", name, display_value(value)))
+            .collect::<Vec<_>>()
+            .join(", "),
+        Some(args) => display_value(args),
+        None => String::new(),
+    }
+}
+
+/// `text` cut to [`MAX_CELL_CHARS`]
+fn shorten(text: String) -> String {
+    match text.char_indices().nth(MAX_CELL_CHARS) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+#[derive(Template)]
+#[template(path = "history.html")]
+struct HistoryTemplate {
+    status: String,
+    job_type: String,
+    job_types: Vec<&'static str>,
+    rows: Vec<Row>,
+    /// Set when not on the first page
+    first_link: Option<String>,
+    next_link: Option<String>,
+    error: Option<String>,
+}
+
+impl IntoResponse for HistoryTemplate {
+    fn into_response(self) -> axum::response::Response {
+        match self.render() {
+            Ok(html) => Html(html).into_response(),
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Template error: {}", err),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// `GET {path}` on the API with the filters in `params`, parsed as `T`
+async fn fetch<T: DeserializeOwned>(
+    client: &reqwest::Client,
+    state: &AppState,
+    path: &str,
+    params: &HistoryParams,
+) -> Result<T, String> {
+    let endpoint = format!("{}{}", state.api_url, path);
+
+    let resp = client
+        .get(&endpoint)
+        .query(params)
+        .query(&[("limit", PAGE_SIZE)])
+        .send()
+        .await
+        .map_err(|e| format!("Failed to connect to API: {}", e))?;
+    if !resp.status().is_success() {
+        let status = resp.status();
+        return Err(match resp.json::<ApiError>().await {
+            Ok(err) => err.error,
+            Err(_) => format!("API request failed with status: {}", status),
+        });
+    }
+    resp.json::<T>()
+        .await
+        .map_err(|e| format!("Failed to parse response: {}", e))
+}
+
+/// Jobs on the page `params` asks for, and the next page's token: on the
+/// first page, queued and running jobs before finished ones
+async fn fetch_page(
+    state: &AppState,
+    params: &HistoryParams,
+) -> Result<(Vec<Row>, Option<String>), String> {
+    let client = reqwest::Client::new();
+    let mut rows = Vec::new();
+    if params.page.is_none() && !params.finished_only() {
+        let pending: Vec<ApiPendingJob> = fetch(&client, state, "/jobs/pending", params).await?;
+        rows.extend(pending.into_iter().map(Row::from));
+    }
+    if params.pending_only() {
+        return Ok((rows, None));
+    }
+    let page: ApiJobPage = fetch(&client, state, "/jobs", params).await?;
+    rows.extend(page.jobs.into_iter().map(Row::from));
+    Ok((rows, page.next_page))
+}
+
+/// GET /history - Recent jobs, filtered by `status` and `type`
+pub async fn history(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<HistoryParams>,
+) -> impl IntoResponse {
+    // An empty select or box means no filter
+    let params = HistoryParams {
+        status: params.status.filter(|s| !s.is_empty()),
+        job_type: params.job_type.filter(|t| !t.is_empty()),
+        page: params.page.filter(|p| !p.is_empty()),
+    };
+
+    let (rows, next_page, error) = match fetch_page(&state, &params).await {
+        Ok((rows, next_page)) => (rows, next_page, None),
+        Err(error) => {
+            warn!("Failed to list jobs: {}", error);
+            (Vec::new(), None, Some(error))
+        }
+    };
+
+    HistoryTemplate {
+        status: params.status.clone().unwrap_or_default(),
+        job_type: params.job_type.clone().unwrap_or_default(),
+        job_types: JobPayload::KINDS.iter().map(|kind| kind.job_type).collect(),
+        rows,
+        first_link: params.page.is_some().then(|| params.link(None)),
+        next_link: next_page.map(|page| params.link(Some(page))),
+        error,
+    }
+}
@@ -0,0 +1,147 @@
+//! Live job updates for the browser.
+//!
+//! `GET /jobs/{id}/events` relays the API's event stream for a job as
+//! `update` events carrying the rendered status fragment, then a `done` event
+//! once the job completes, or fails with no retries left, so the page stops
+//! listening. The browser can't reach the API itself, and this way it gets
+//! HTML to swap in rather than JSON. A failure Faktory will retry is shown
+//! as it arrives, and the relay carries on with the next attempt. When the
+//! API's stream can't be opened, or ends before the job is done, the last
+//! update is the polling fragment instead, so the page falls back to polling
+//! `GET /jobs/{id}/status`.
+
+use crate::{job_path, ApiJobStatus, ApiProgress, AppState, JobStatusTemplate};
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures_util::Stream;
+use std::convert::Infallible;
+use std::sync::Arc;
+use tracing::warn;
+
+/// An event read from the API's stream
+struct ApiEvent {
+    event: String,
+    data: String,
+}
+
+/// Splits a `text/event-stream` body into events as it arrives
+#[derive(Default)]
+struct EventParser {
+    buffer: Vec<u8>,
+}
+
+impl EventParser {
+    /// Add `chunk` of the body, returning the events it completes
+    fn push(&mut self, chunk: &[u8]) -> Vec<ApiEvent> {
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let block = String::from_utf8_lossy(&block);
+            let mut event = ApiEvent {
+                event: "message".to_string(),
+                data: String::new(),
+            };
+            for line in block.lines() {
+                let (field, value) = line.split_once(':').unwrap_or((line, ""));
+                let value = value.strip_prefix(' ').unwrap_or(value);
+                match field {
+                    "event" => event.event = value.to_string(),
+                    "data" => {
+                        if !event.data.is_empty() {
+                            event.data.push('\n');
+                        }
+                        event.data.push_str(value);
+                    }
+                    // Comments (keep-alives), ids and retry hints
+                    _ => {}
+                }
+            }
+            if !event.data.is_empty() {
+                events.push(event);
+            }
+        }
+        events
+    }
+}
+
+/// `template` rendered as an `update` event
+fn update(template: JobStatusTemplate) -> Event {
+    let html = template
+        .render()
+        .unwrap_or_else(|err| format!("Template error: {}", err));
+    Event::default().event("update").data(html)
+}
+
+/// GET /jobs/{id}/events - The job's status fragment each time it changes
+pub async fn job_events(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let stream = async_stream::stream! {
+        let endpoint = format!("{}/jobs/{}/events", state.api_url, job_path(&job_id));
+        // The job as far as the stream has told, and whether its last attempt
+        // has finished (with a failure that will be retried)
+        let mut job = ApiJobStatus::default();
+        let mut finished = false;
+
+        let client = reqwest::Client::new();
+        match client.get(&endpoint).send().await {
+            Ok(mut resp) if resp.status().is_success() => {
+                let mut parser = EventParser::default();
+                loop {
+                    let chunk = match resp.chunk().await {
+                        Ok(Some(chunk)) => chunk,
+                        Ok(None) => break,
+                        Err(e) => {
+                            warn!("Event stream of job {} failed: {}", job_id, e);
+                            break;
+                        }
+                    };
+                    for event in parser.push(&chunk) {
+                        match event.event.as_str() {
+                            // Each transition; the last carries the full result
+                            "status" => {
+                                let Ok(status) = serde_json::from_str::<ApiJobStatus>(&event.data) else {
+                                    continue;
+                                };
+                                if matches!(status.status.as_deref(), Some("completed" | "failed")) {
+                                    if JobStatusTemplate::done(true, &status) {
+                                        yield Ok(update(JobStatusTemplate::new(job_id.clone(), true, status)));
+                                        yield Ok(Event::default().event("done").data(job_id.as_str()));
+                                        return;
+                                    }
+                                    job = status;
+                                    finished = true;
+                                } else {
+                                    job.status = status.status;
+                                    finished = false;
+                                }
+                            }
+                            "progress" => {
+                                if let Ok(progress) = serde_json::from_str::<ApiProgress>(&event.data) {
+                                    job.progress = Some(progress);
+                                }
+                            }
+                            // The API gave up waiting (`timeout`); polling takes over
+                            _ => continue,
+                        }
+                        let mut template = JobStatusTemplate::new(job_id.clone(), finished, job.clone());
+                        template.poll = false;
+                        yield Ok(update(template));
+                    }
+                }
+            }
+            Ok(resp) => warn!("API refused event stream of job {}: {}", job_id, resp.status()),
+            Err(e) => warn!("Failed to connect to API for events of job {}: {}", job_id, e),
+        }
+
+        yield Ok(update(JobStatusTemplate::new(job_id.clone(), finished, job)));
+        yield Ok(Event::default().event("done").data(job_id.as_str()));
+    };
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
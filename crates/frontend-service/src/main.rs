@@ -1,15 +1,30 @@
 use anyhow::Result;
 use askama::Template;
 use axum::{
-    extract::Form,
+    extract::{Form, Path, State},
     http::StatusCode,
     response::{Html, IntoResponse},
     routing::{get, post},
     Router,
 };
+use percent_encoding::{utf8_percent_encode, AsciiSet, PercentEncode, NON_ALPHANUMERIC};
 use serde::Deserialize;
+use service_config::Config;
+use service_tls::TlsConfig;
+use std::sync::Arc;
+use tower_http::compression::CompressionLayer;
+use tower_http::decompression::RequestDecompressionLayer;
 use tracing::info;
 
+mod history;
+mod live;
+
+/// Shared state for the handlers
+struct AppState {
+    /// Base URL of the API service
+    api_url: String,
+}
+
 #[derive(Template)]
 #[template(path = "index.html")]
 struct IndexTemplate;
@@ -66,6 +81,145 @@ impl IntoResponse for ErrorTemplate {
     }
 }
 
+#[derive(Template)]
+#[template(path = "job.html")]
+struct JobTemplate {
+    job_id: String,
+}
+
+impl IntoResponse for JobTemplate {
+    fn into_response(self) -> axum::response::Response {
+        match self.render() {
+            Ok(html) => Html(html).into_response(),
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Template error: {}", err),
+            )
+                .into_response(),
+        }
+    }
+}
+
+/// A stage of a job's life on the job page
+struct Step {
+    label: &'static str,
+    /// `reached`, `failed` or empty when not reached yet
+    class: &'static str,
+    /// When the job reached it, if known
+    at: Option<String>,
+}
+
+impl Step {
+    fn new(label: &'static str, reached: bool, at: Option<String>) -> Self {
+        Self {
+            label,
+            class: if reached { "reached" } else { "" },
+            at,
+        }
+    }
+}
+
+/// A job's status, result or error: the live part of its page and of a
+/// submission's result
+#[derive(Template)]
+#[template(path = "job_status.html")]
+struct JobStatusTemplate {
+    job_id: String,
+    steps: Vec<Step>,
+    progress: Option<String>,
+    result: Option<String>,
+    error: Option<String>,
+    detail: Option<String>,
+    /// Whether the fragment polls for the next update itself, until the job
+    /// is done (see [`JobStatusTemplate::done`]); not when updates are pushed
+    /// (see [`live`])
+    poll: bool,
+}
+
+impl JobStatusTemplate {
+    /// `job` is the API's result when `finished`, otherwise its status so far
+    fn new(job_id: String, finished: bool, job: ApiJobStatus) -> Self {
+        let done = Self::done(finished, &job);
+        let status = job.status.as_deref();
+        let started = matches!(status, Some("running" | "completed" | "failed"));
+        let failed = finished && status == Some("failed");
+        let last = if failed {
+            Step {
+                label: "Failed",
+                class: "failed",
+                at: job.finished_at,
+            }
+        } else {
+            Step::new("Completed", finished, job.finished_at)
+        };
+        let steps = vec![
+            Step::new("Enqueued", status.is_some(), None),
+            Step::new("Running", started, job.started_at),
+            last,
+        ];
+
+        let detail = if finished {
+            let mut parts = Vec::new();
+            if let Some(attempt) = job.attempt {
+                parts.push(format!("Attempt {}", attempt));
+            }
+            if let Some(duration_ms) = job.duration_ms {
+                parts.push(format!("ran for {} ms", duration_ms));
+            }
+            if failed && job.retrying {
+                parts.push("it will be retried".to_string());
+            } else if failed {
+                parts.push("no retries left".to_string());
+            }
+            (!parts.is_empty()).then(|| parts.join(", "))
+        } else if status.is_none() {
+            Some("Waiting for the job to be recorded (or its result has expired)".to_string())
+        } else {
+            None
+        };
+
+        Self {
+            job_id,
+            steps,
+            progress: job.progress.map(|progress| match progress.message {
+                Some(message) => format!("{}%: {}", progress.percent, message),
+                None => format!("{}%", progress.percent),
+            }),
+            result: job
+                .result
+                .filter(|_| finished && !failed)
+                .map(|value| display_value(&value)),
+            error: job.error.filter(|_| failed),
+            detail,
+            poll: !done,
+        }
+    }
+
+    /// Whether the job won't change again: it completed, or failed with no
+    /// retries left
+    fn done(finished: bool, job: &ApiJobStatus) -> bool {
+        finished
+            && match job.status.as_deref() {
+                Some("completed") => true,
+                Some("failed") => !job.retrying,
+                _ => false,
+            }
+    }
+}
+
+impl IntoResponse for JobStatusTemplate {
+    fn into_response(self) -> axum::response::Response {
+        match self.render() {
+            Ok(html) => Html(html).into_response(),
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Template error: {}", err),
+            )
+                .into_response(),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 struct MathForm {
     a: f64,
@@ -83,31 +237,83 @@ struct ApiError {
     error: String,
 }
 
+/// The API's `GET /jobs/{id}`: the result of a finished job, or (with a 404)
+/// the status of one that hasn't finished
+#[derive(Debug, Clone, Default, Deserialize)]
+struct ApiJobStatus {
+    status: Option<String>,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    started_at: Option<String>,
+    finished_at: Option<String>,
+    duration_ms: Option<u64>,
+    attempt: Option<u32>,
+    /// Whether a failed job will run again
+    #[serde(default)]
+    retrying: bool,
+    progress: Option<ApiProgress>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ApiProgress {
+    percent: u8,
+    message: Option<String>,
+}
+
+/// Characters escaped in a path segment: all but the unreserved ones
+const PATH_SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// `job_id` as a segment of an API path
+fn job_path(job_id: &str) -> PercentEncode<'_> {
+    utf8_percent_encode(job_id, PATH_SEGMENT)
+}
+
+/// A JSON value as shown to users: strings without their quotes
+fn display_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
 async fn index() -> impl IntoResponse {
     IndexTemplate
 }
 
-async fn submit_add(Form(form): Form<MathForm>) -> impl IntoResponse {
-    submit_job("add", form).await
+async fn submit_add(
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<MathForm>,
+) -> impl IntoResponse {
+    submit_job(&state, "add", form).await
 }
 
-async fn submit_subtract(Form(form): Form<MathForm>) -> impl IntoResponse {
-    submit_job("subtract", form).await
+async fn submit_subtract(
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<MathForm>,
+) -> impl IntoResponse {
+    submit_job(&state, "subtract", form).await
 }
 
-async fn submit_multiply(Form(form): Form<MathForm>) -> impl IntoResponse {
-    submit_job("multiply", form).await
+async fn submit_multiply(
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<MathForm>,
+) -> impl IntoResponse {
+    submit_job(&state, "multiply", form).await
 }
 
-async fn submit_divide(Form(form): Form<MathForm>) -> impl IntoResponse {
-    submit_job("divide", form).await
+async fn submit_divide(
+    State(state): State<Arc<AppState>>,
+    Form(form): Form<MathForm>,
+) -> impl IntoResponse {
+    submit_job(&state, "divide", form).await
 }
 
-async fn submit_job(operation: &str, form: MathForm) -> impl IntoResponse {
-    let api_url =
-        std::env::var("API_SERVICE_URL").unwrap_or_else(|_| "http://api-service:3000".to_string());
-
-    let endpoint = format!("{}/jobs/{}", api_url, operation);
+async fn submit_job(state: &AppState, operation: &str, form: MathForm) -> impl IntoResponse {
+    let endpoint = format!("{}/jobs/{}", state.api_url, operation);
 
     info!("Submitting {} job: {} and {}", operation, form.a, form.b);
 
@@ -151,6 +357,45 @@ async fn submit_job(operation: &str, form: MathForm) -> impl IntoResponse {
     }
 }
 
+async fn job_page(Path(job_id): Path<String>) -> impl IntoResponse {
+    JobTemplate { job_id }
+}
+
+async fn job_status(
+    State(state): State<Arc<AppState>>,
+    Path(job_id): Path<String>,
+) -> impl IntoResponse {
+    let endpoint = format!("{}/jobs/{}", state.api_url, job_path(&job_id));
+
+    let client = reqwest::Client::new();
+    let resp = match client.get(&endpoint).send().await {
+        Ok(resp) => resp,
+        Err(e) => {
+            return ErrorTemplate {
+                error: format!("Failed to connect to API: {}", e),
+            }
+            .into_response()
+        }
+    };
+    // A job that hasn't finished is a 404 with its status so far
+    let finished = resp.status().is_success();
+    if !finished && resp.status() != reqwest::StatusCode::NOT_FOUND {
+        let status = resp.status();
+        let error_msg = match resp.json::<ApiError>().await {
+            Ok(err) => err.error,
+            Err(_) => format!("API request failed with status: {}", status),
+        };
+        return ErrorTemplate { error: error_msg }.into_response();
+    }
+    match resp.json::<ApiJobStatus>().await {
+        Ok(job) => JobStatusTemplate::new(job_id, finished, job).into_response(),
+        Err(e) => ErrorTemplate {
+            error: format!("Failed to parse response: {}", e),
+        }
+        .into_response(),
+    }
+}
+
 async fn health() -> impl IntoResponse {
     (
         StatusCode::OK,
@@ -165,19 +410,31 @@ async fn health() -> impl IntoResponse {
 async fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
 
-    let bind_addr = std::env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:8000".to_string());
+    let config = Config::load()?;
+    let bind_addr = config.string_or("BIND_ADDR", "0.0.0.0:8000");
+    let api_url = config.string_or("API_SERVICE_URL", "http://api-service:3000");
+    let tls_config = TlsConfig::from_config(&config)?;
+    config.validate()?;
+    config.log_effective();
 
     info!("Starting frontend service on {}", bind_addr);
 
     let app = Router::new()
         .route("/", get(index))
         .route("/health", get(health))
+        .route("/jobs/{id}", get(job_page))
+        .route("/jobs/{id}/status", get(job_status))
+        .route("/jobs/{id}/events", get(live::job_events))
+        .route("/history", get(history::history))
         .route("/submit/add", post(submit_add))
         .route("/submit/subtract", post(submit_subtract))
         .route("/submit/multiply", post(submit_multiply))
-        .route("/submit/divide", post(submit_divide));
+        .route("/submit/divide", post(submit_divide))
+        .layer(CompressionLayer::new())
+        .layer(RequestDecompressionLayer::new())
+        .with_state(Arc::new(AppState { api_url }));
 
-    let listener = tokio::net::TcpListener::bind(&bind_addr).await?;
+    let listener = service_tls::bind(&bind_addr, tls_config).await?;
     axum::serve(listener, app).await?;
 
     Ok(())
@@ -1,9 +1,83 @@
 use serde::{Deserialize, Serialize};
 use anyhow::{Context, Result};
+use std::time::Duration;
+
+/// Custom job field carrying the W3C trace context (`traceparent`,
+/// `tracestate`) of the request that enqueued the job
+pub const TRACE_CONTEXT_FIELD: &str = "trace_context";
+
+/// IDs in a W3C `traceparent` value, `{version}-{trace_id}-{parent_id}-{flags}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceParent {
+    /// 32 lowercase hex digits
+    pub trace_id: String,
+    /// Span the job was enqueued from; 16 lowercase hex digits
+    pub parent_id: String,
+}
+
+impl TraceParent {
+    /// Parse a `traceparent` value, or `None` if it's malformed or its IDs
+    /// are all zeros
+    pub fn parse(value: &str) -> Option<Self> {
+        let is_id = |id: &str, len: usize| {
+            id.len() == len
+                && id.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+                && id.bytes().any(|b| b != b'0')
+        };
+        let mut parts = value.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version.len() != 2 || version == "ff" || flags.len() != 2 {
+            return None;
+        }
+        // Version 00 has exactly four fields; later versions may add more
+        if version == "00" && parts.next().is_some() {
+            return None;
+        }
+        if !is_id(trace_id, 32) || !is_id(parent_id, 16) {
+            return None;
+        }
+        Some(Self {
+            trace_id: trace_id.to_string(),
+            parent_id: parent_id.to_string(),
+        })
+    }
+}
+
+/// Custom job field carrying the ID of the API request that enqueued the
+/// job, so its logs can be correlated with the request's
+pub const REQUEST_ID_FIELD: &str = "request_id";
+
+/// Version of the job payload formats. Bump it whenever a payload changes in
+/// a way an older producer or worker can't handle, so a mixed-version fleet
+/// can be checked for compatibility during a rollout.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// Build details a service reports at `GET /version`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct VersionInfo {
+    /// e.g. `api-service`
+    pub service: String,
+    /// Crate version
+    pub version: String,
+    /// Commit the binary was built from, or `unknown`
+    pub git_sha: String,
+    /// When the binary was built (RFC 3339)
+    pub built_at: String,
+    /// `SCHEMA_VERSION` the binary was compiled against
+    pub schema_version: u32,
+}
 
 /// All supported job types in the system.
-/// Add new job types here to make them available to both producers and consumers.
+/// Add new job types here to make them available to both producers and consumers,
+/// to `JobPayload::KINDS` to give them an API endpoint and have workers
+/// register them (the worker refuses to start until it has a handler for
+/// every kind), to `JobType`, whose matches in `JobKind::example`,
+/// `JobKind::args_schema` and `JobKind::with_math_args` then say how to list
+/// the type in schema discovery and whether it takes `MathArgs`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 #[serde(tag = "type", content = "args")]
 pub enum JobPayload {
     /// Add two numbers together
@@ -14,9 +88,90 @@ pub enum JobPayload {
     Multiply(MathArgs),
     /// Divide two numbers
     Divide(MathArgs),
+    /// Many operations in one job
+    MathBatch(MathBatchArgs),
+}
+
+/// The `JobPayload` variants, for matching on a job type without its
+/// arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum JobType {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    MathBatch,
+}
+
+impl JobType {
+    /// The payload's `type` tag, e.g. `Add`
+    pub fn tag(self) -> &'static str {
+        match self {
+            JobType::Add => "Add",
+            JobType::Subtract => "Subtract",
+            JobType::Multiply => "Multiply",
+            JobType::Divide => "Divide",
+            JobType::MathBatch => "MathBatch",
+        }
+    }
+}
+
+/// A job type submittable on its own endpoint, `POST /jobs/{name}`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobKind {
+    /// Route segment, e.g. `add`
+    pub name: &'static str,
+    /// The payload variant the kind submits
+    pub variant: JobType,
+    /// Faktory job type workers register a handler for, e.g. `math_add`
+    pub job_type: &'static str,
+    /// What the job does, for API docs
+    pub description: &'static str,
 }
 
 impl JobPayload {
+    /// Every job type. Listing a variant here exposes it as `POST /jobs/{name}`
+    /// with its arguments at the top level of the request body.
+    pub const KINDS: &'static [JobKind] = &[
+        JobKind { name: "add", variant: JobType::Add, job_type: "math_add", description: "Add two numbers" },
+        JobKind { name: "subtract", variant: JobType::Subtract, job_type: "math_subtract", description: "Subtract two numbers" },
+        JobKind { name: "multiply", variant: JobType::Multiply, job_type: "math_multiply", description: "Multiply two numbers" },
+        JobKind { name: "divide", variant: JobType::Divide, job_type: "math_divide", description: "Divide two numbers" },
+        JobKind { name: "math-batch", variant: JobType::MathBatch, job_type: "math_batch", description: "Run many math operations in one job" },
+    ];
+
+    /// Look up a job type by its route segment
+    pub fn kind(name: &str) -> Option<JobKind> {
+        Self::KINDS.iter().copied().find(|kind| kind.name == name)
+    }
+
+    /// Build a payload from its `type` tag and arguments
+    pub fn from_tagged(tag: &str, args: serde_json::Value) -> serde_json::Result<Self> {
+        serde_json::from_value(serde_json::json!({ "type": tag, "args": args }))
+    }
+
+    /// Short description of the operation, e.g. `add 1 + 2`
+    pub fn describe(&self) -> String {
+        match self {
+            JobPayload::Add(args) => format!("add {} + {}", args.a, args.b),
+            JobPayload::Subtract(args) => format!("subtract {} - {}", args.a, args.b),
+            JobPayload::Multiply(args) => format!("multiply {} × {}", args.a, args.b),
+            JobPayload::Divide(args) => format!("divide {} ÷ {}", args.a, args.b),
+            JobPayload::MathBatch(args) => format!("batch of {} operations", args.operations.len()),
+        }
+    }
+
+    /// The caller's `request_id` for the job, if it set one
+    pub fn request_id(&self) -> Option<&str> {
+        match self {
+            JobPayload::Add(args)
+            | JobPayload::Subtract(args)
+            | JobPayload::Multiply(args)
+            | JobPayload::Divide(args) => args.request_id.as_deref(),
+            JobPayload::MathBatch(args) => args.request_id.as_deref(),
+        }
+    }
+
     /// Get the job type string for Faktory
     pub fn job_type(&self) -> &'static str {
         match self {
@@ -24,6 +179,7 @@ impl JobPayload {
             JobPayload::Subtract(_) => "math_subtract",
             JobPayload::Multiply(_) => "math_multiply",
             JobPayload::Divide(_) => "math_divide",
+            JobPayload::MathBatch(_) => "math_batch",
         }
     }
 
@@ -34,10 +190,29 @@ impl JobPayload {
             JobPayload::Subtract(args) => serde_json::to_value(args)?,
             JobPayload::Multiply(args) => serde_json::to_value(args)?,
             JobPayload::Divide(args) => serde_json::to_value(args)?,
+            JobPayload::MathBatch(args) => serde_json::to_value(args)?,
         };
         Ok(args)
     }
 
+    /// Check the arguments for values the job can't succeed with. Field
+    /// names are relative to the job's `args`.
+    pub fn validate(&self) -> Vec<FieldError> {
+        match self {
+            JobPayload::Add(args) | JobPayload::Subtract(args) | JobPayload::Multiply(args) => {
+                args.validate()
+            }
+            JobPayload::Divide(args) => {
+                let mut errors = args.validate();
+                if args.b == 0.0 {
+                    errors.push(FieldError::new("b", "Division by zero"));
+                }
+                errors
+            }
+            JobPayload::MathBatch(args) => args.validate(),
+        }
+    }
+
     /// Parse job payload from job type and JSON args
     pub fn from_job_type(job_type: &str, args: serde_json::Value) -> Result<Self> {
         let payload = match job_type {
@@ -61,13 +236,70 @@ impl JobPayload {
                     .context("Failed to parse Divide job args")?;
                 JobPayload::Divide(args)
             }
+            "math_batch" => {
+                let args: MathBatchArgs = serde_json::from_value(args)
+                    .context("Failed to parse MathBatch job args")?;
+                JobPayload::MathBatch(args)
+            }
             _ => anyhow::bail!("Unknown job type: {}", job_type),
         };
         Ok(payload)
     }
 }
 
+impl JobKind {
+    /// The payload's `type` tag, e.g. `Add`
+    pub fn tag(&self) -> &'static str {
+        self.variant.tag()
+    }
+
+    /// A valid payload of this type, for docs and schema discovery
+    pub fn example(&self) -> Option<JobPayload> {
+        match self.variant {
+            JobType::MathBatch => Some(JobPayload::MathBatch(MathBatchArgs {
+                operations: vec![
+                    MathOperation { op: MathOp::Add, a: 6.0, b: 3.0 },
+                    MathOperation { op: MathOp::Divide, a: 6.0, b: 3.0 },
+                ],
+                request_id: Some("example-1".to_string()),
+            })),
+            JobType::Add | JobType::Subtract | JobType::Multiply | JobType::Divide => {
+                self.with_math_args(MathArgs {
+                    a: 6.0,
+                    b: 3.0,
+                    request_id: Some("example-1".to_string()),
+                })
+            }
+        }
+    }
+
+    /// A payload of this type with `args`, if the type takes `MathArgs`
+    pub fn with_math_args(&self, args: MathArgs) -> Option<JobPayload> {
+        let payload = match self.variant {
+            JobType::Add => JobPayload::Add(args),
+            JobType::Subtract => JobPayload::Subtract(args),
+            JobType::Multiply => JobPayload::Multiply(args),
+            JobType::Divide => JobPayload::Divide(args),
+            JobType::MathBatch => return None,
+        };
+        Some(payload)
+    }
+
+    /// JSON Schema of the job's `args`
+    #[cfg(feature = "openapi")]
+    pub fn args_schema(&self) -> Option<utoipa::openapi::RefOr<utoipa::openapi::schema::Schema>> {
+        use utoipa::PartialSchema;
+        match self.variant {
+            JobType::Add | JobType::Subtract | JobType::Multiply | JobType::Divide => {
+                Some(MathArgs::schema())
+            }
+            JobType::MathBatch => Some(MathBatchArgs::schema()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
 pub struct MathArgs {
     pub a: f64,
     pub b: f64,
@@ -75,6 +307,245 @@ pub struct MathArgs {
     pub request_id: Option<String>,
 }
 
+impl MathArgs {
+    fn validate(&self) -> Vec<FieldError> {
+        [("a", self.a), ("b", self.b)]
+            .into_iter()
+            .filter(|(_, value)| !value.is_finite())
+            .map(|(field, _)| FieldError::new(field, "Must be a finite number"))
+            .collect()
+    }
+}
+
+/// Most operations one `MathBatch` job may carry
+pub const MAX_BATCH_OPERATIONS: usize = 10_000;
+
+/// A math operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum MathOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+/// One operation of a `MathBatch` job
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MathOperation {
+    pub op: MathOp,
+    pub a: f64,
+    pub b: f64,
+}
+
+/// Many math operations run by one job, whose result is the array of their
+/// results in the same order. Saves the per-job overhead, which dwarfs a
+/// single floating-point operation.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MathBatchArgs {
+    pub operations: Vec<MathOperation>,
+    /// Optional identifier for tracking the batch
+    pub request_id: Option<String>,
+}
+
+impl MathBatchArgs {
+    fn validate(&self) -> Vec<FieldError> {
+        if self.operations.is_empty() {
+            return vec![FieldError::new("operations", "Must not be empty")];
+        }
+        if self.operations.len() > MAX_BATCH_OPERATIONS {
+            return vec![FieldError::new(
+                "operations",
+                format!("At most {} operations per batch, got {}", MAX_BATCH_OPERATIONS, self.operations.len()),
+            )];
+        }
+        let mut errors = Vec::new();
+        for (index, operation) in self.operations.iter().enumerate() {
+            for (field, value) in [("a", operation.a), ("b", operation.b)] {
+                if !value.is_finite() {
+                    errors.push(FieldError::new(format!("operations[{}].{}", index, field), "Must be a finite number"));
+                }
+            }
+            if operation.op == MathOp::Divide && operation.b == 0.0 {
+                errors.push(FieldError::new(format!("operations[{}].b", index), "Division by zero"));
+            }
+        }
+        errors
+    }
+}
+
+/// One operand of a bulk submission: a single value shared by every job, or
+/// one value per job
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(untagged)]
+pub enum Operand {
+    Scalar(f64),
+    Vector(Vec<f64>),
+}
+
+impl Operand {
+    fn get(&self, index: usize) -> f64 {
+        match self {
+            Operand::Scalar(value) => *value,
+            Operand::Vector(values) => values[index],
+        }
+    }
+}
+
+/// The arguments of many math jobs as parallel arrays, expanded into one
+/// `MathArgs` per element
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct BulkMathArgs {
+    pub a: Operand,
+    pub b: Operand,
+    /// One per job, if given
+    pub request_ids: Option<Vec<String>>,
+}
+
+impl BulkMathArgs {
+    /// How many jobs the arrays describe. At least one of `a` and `b` must
+    /// be an array, and every array must have the same, non-zero, length.
+    pub fn job_count(&self) -> Result<usize, FieldError> {
+        let lengths = [
+            ("a", self.a_len()),
+            ("b", self.b_len()),
+            ("request_ids", self.request_ids.as_ref().map(Vec::len)),
+        ];
+        let mut count = None;
+        for (field, len) in lengths {
+            let Some(len) = len else { continue };
+            match count {
+                None if len == 0 => return Err(FieldError::new(field, "Must not be empty")),
+                None => count = Some((field, len)),
+                Some((first, expected)) if len != expected => {
+                    return Err(FieldError::new(
+                        field,
+                        format!("Has {} elements but {} has {}", len, first, expected),
+                    ))
+                }
+                Some(_) => {}
+            }
+        }
+        match (&self.a, &self.b, count) {
+            (Operand::Scalar(_), Operand::Scalar(_), _) | (_, _, None) => Err(FieldError::new(
+                "a",
+                "At least one of a and b must be an array",
+            )),
+            (_, _, Some((_, count))) => Ok(count),
+        }
+    }
+
+    fn a_len(&self) -> Option<usize> {
+        match &self.a {
+            Operand::Scalar(_) => None,
+            Operand::Vector(values) => Some(values.len()),
+        }
+    }
+
+    fn b_len(&self) -> Option<usize> {
+        match &self.b {
+            Operand::Scalar(_) => None,
+            Operand::Vector(values) => Some(values.len()),
+        }
+    }
+
+    /// One `MathArgs` per job, in array order
+    pub fn expand(self) -> Result<Vec<MathArgs>, FieldError> {
+        let count = self.job_count()?;
+        let mut request_ids = self.request_ids.map(Vec::into_iter);
+        Ok((0..count)
+            .map(|index| MathArgs {
+                a: self.a.get(index),
+                b: self.b.get(index),
+                request_id: request_ids.as_mut().and_then(Iterator::next),
+            })
+            .collect())
+    }
+}
+
+/// A problem with one field of a job submission
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// How the delay between retries grows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum BackoffStrategy {
+    /// Wait the same delay before every retry
+    Fixed,
+    /// Double the delay after every retry
+    Exponential,
+}
+
+/// Client-requested retry schedule, carried in the job's custom fields.
+/// Jobs with a policy are retried by the worker rather than by Faktory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub strategy: BackoffStrategy,
+    /// Delay before the first retry
+    pub delay_seconds: u64,
+}
+
+impl RetryPolicy {
+    /// Custom job field holding the policy
+    pub const FIELD: &'static str = "retry_policy";
+
+    /// Delay before retry number `retry` (starting at 0), capped at `max`
+    pub fn delay(&self, retry: u32, max: Duration) -> Duration {
+        let base = Duration::from_secs(self.delay_seconds);
+        let delay = match self.strategy {
+            BackoffStrategy::Fixed => base,
+            BackoffStrategy::Exponential => base.saturating_mul(2u32.saturating_pow(retry)),
+        };
+        delay.min(max)
+    }
+}
+
+/// Separates the tenant from the queue in tenant queue names
+pub const TENANT_QUEUE_SEPARATOR: char = '.';
+
+/// Longest tenant id accepted
+pub const MAX_TENANT_LEN: usize = 64;
+
+/// Whether `tenant` can be used in queue names: 1 to 64 ASCII letters,
+/// digits, `-` or `_`
+pub fn is_valid_tenant(tenant: &str) -> bool {
+    !tenant.is_empty()
+        && tenant.len() <= MAX_TENANT_LEN
+        && tenant.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Faktory queue holding a tenant's jobs for `queue`, e.g. `acme.default`
+pub fn tenant_queue(tenant: &str, queue: &str) -> String {
+    format!("{}{}{}", tenant, TENANT_QUEUE_SEPARATOR, queue)
+}
+
+/// Split a tenant queue name into its tenant and queue
+pub fn split_tenant_queue(name: &str) -> Option<(&str, &str)> {
+    name.split_once(TENANT_QUEUE_SEPARATOR)
+        .filter(|(tenant, queue)| is_valid_tenant(tenant) && !queue.is_empty())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -87,6 +558,7 @@ mod tests {
             request_id: Some("test-123".to_string()),
         });
 
+        assert_eq!(payload.request_id(), Some("test-123"));
         let job_type = payload.job_type();
         let args = payload.to_args().unwrap();
 
@@ -101,4 +573,147 @@ mod tests {
             _ => panic!("Wrong job type parsed"),
         }
     }
+
+    #[test]
+    fn test_kinds() {
+        let args = serde_json::json!({"a": 6.0, "b": 3.0, "priority": 5});
+        for kind in JobPayload::KINDS {
+            let mut kind_args = kind.example().unwrap().to_args().unwrap();
+            kind_args["priority"] = 5.into();
+            let payload = JobPayload::from_tagged(kind.tag(), kind_args).unwrap();
+            assert_eq!(JobPayload::kind(kind.name), Some(*kind));
+            assert_eq!(payload.job_type(), kind.job_type);
+        }
+        assert_eq!(JobPayload::kind("modulo"), None);
+        assert!(JobPayload::from_tagged("Modulo", args).is_err());
+    }
+
+    #[test]
+    fn test_examples() {
+        for kind in JobPayload::KINDS {
+            let example = kind.example().unwrap();
+            assert_eq!(example.job_type(), kind.job_type);
+            assert!(example.validate().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_bulk_math_args() {
+        let bulk: BulkMathArgs = serde_json::from_value(
+            serde_json::json!({"a": [1.0, 2.0, 3.0], "b": 10.0, "request_ids": ["x", "y", "z"]}),
+        )
+        .unwrap();
+        let args = bulk.expand().unwrap();
+        let pairs: Vec<(f64, f64)> = args.iter().map(|args| (args.a, args.b)).collect();
+        assert_eq!(pairs, vec![(1.0, 10.0), (2.0, 10.0), (3.0, 10.0)]);
+        assert_eq!(args[2].request_id.as_deref(), Some("z"));
+
+        let bulk = |a, b| BulkMathArgs {
+            a,
+            b,
+            request_ids: None,
+        };
+        assert_eq!(
+            bulk(Operand::Vector(vec![1.0, 2.0]), Operand::Vector(vec![3.0])).job_count(),
+            Err(FieldError::new("b", "Has 1 elements but a has 2"))
+        );
+        assert_eq!(
+            bulk(Operand::Scalar(1.0), Operand::Vector(Vec::new()))
+                .job_count()
+                .unwrap_err()
+                .field,
+            "b"
+        );
+        assert!(bulk(Operand::Scalar(1.0), Operand::Scalar(2.0)).job_count().is_err());
+
+        let kind = JobPayload::kind("divide").unwrap();
+        let payload = kind.with_math_args(args[0].clone()).unwrap();
+        assert_eq!(payload.job_type(), "math_divide");
+    }
+
+    #[test]
+    fn test_math_batch() {
+        let args = serde_json::json!({
+            "operations": [
+                {"op": "add", "a": 1.0, "b": 2.0},
+                {"op": "divide", "a": 1.0, "b": 0.0},
+            ],
+        });
+        let payload = JobPayload::from_job_type("math_batch", args).unwrap();
+        assert_eq!(payload.describe(), "batch of 2 operations");
+        assert_eq!(
+            payload.validate(),
+            vec![FieldError::new("operations[1].b", "Division by zero")]
+        );
+
+        let empty = JobPayload::from_tagged("MathBatch", serde_json::json!({"operations": []})).unwrap();
+        assert_eq!(empty.validate()[0].field, "operations");
+        assert!(JobPayload::from_job_type("math_batch", serde_json::json!({"operations": [{"op": "modulo", "a": 1.0, "b": 2.0}]})).is_err());
+    }
+
+    #[test]
+    fn test_validate() {
+        let args = |a, b| MathArgs {
+            a,
+            b,
+            request_id: None,
+        };
+
+        assert!(JobPayload::Add(args(1.0, 2.0)).validate().is_empty());
+        assert!(JobPayload::Subtract(args(1.0, 0.0)).validate().is_empty());
+        assert_eq!(
+            JobPayload::Divide(args(1.0, 0.0)).validate(),
+            vec![FieldError::new("b", "Division by zero")]
+        );
+
+        let errors = JobPayload::Multiply(args(f64::NAN, f64::INFINITY)).validate();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn test_retry_policy_delay() {
+        let max = Duration::from_secs(60);
+        let policy = RetryPolicy {
+            max_retries: 10,
+            strategy: BackoffStrategy::Exponential,
+            delay_seconds: 5,
+        };
+        assert_eq!(policy.delay(0, max), Duration::from_secs(5));
+        assert_eq!(policy.delay(2, max), Duration::from_secs(20));
+        assert_eq!(policy.delay(40, max), max);
+
+        let fixed = RetryPolicy {
+            strategy: BackoffStrategy::Fixed,
+            ..policy
+        };
+        assert_eq!(fixed.delay(3, max), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_trace_parent() {
+        let parsed =
+            TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").unwrap();
+        assert_eq!(parsed.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parsed.parent_id, "00f067aa0ba902b7");
+
+        assert!(TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01").is_none());
+        assert!(TraceParent::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01").is_none());
+        assert!(TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7").is_none());
+        assert!(TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x").is_none());
+        assert!(TraceParent::parse("ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").is_none());
+    }
+
+    #[test]
+    fn test_tenant_queue() {
+        assert!(is_valid_tenant("acme-corp_1"));
+        assert!(!is_valid_tenant(""));
+        assert!(!is_valid_tenant("acme.corp"));
+        assert!(!is_valid_tenant(&"a".repeat(MAX_TENANT_LEN + 1)));
+
+        let queue = tenant_queue("acme", "default");
+        assert_eq!(queue, "acme.default");
+        assert_eq!(split_tenant_queue(&queue), Some(("acme", "default")));
+        assert_eq!(split_tenant_queue("default"), None);
+    }
 }
@@ -0,0 +1,15 @@
+-- One row per job, updated as the job moves through its lifecycle.
+CREATE TABLE IF NOT EXISTS job_history (
+    job_id       TEXT PRIMARY KEY,
+    job_type     TEXT NOT NULL,
+    status       TEXT NOT NULL,
+    args         JSONB,
+    result       JSONB,
+    error        TEXT,
+    submitted_at TIMESTAMPTZ,
+    started_at   TIMESTAMPTZ,
+    finished_at  TIMESTAMPTZ
+);
+
+CREATE INDEX IF NOT EXISTS job_history_submitted_at_idx ON job_history (submitted_at DESC);
+CREATE INDEX IF NOT EXISTS job_history_status_idx ON job_history (status);
@@ -0,0 +1,4 @@
+-- Results past their expiry are hidden from lookups but the row is kept for auditing.
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS job_history_finished_at_idx ON job_history (finished_at DESC);
@@ -0,0 +1,7 @@
+-- Jobs enqueued per caller, bucketed by UTC day (YYYY-MM-DD) and month (YYYY-MM)
+CREATE TABLE IF NOT EXISTS job_usage (
+    api_key TEXT NOT NULL,
+    period TEXT NOT NULL,
+    jobs BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key, period)
+);
@@ -0,0 +1,10 @@
+-- Recurring job definitions, fired by the API's scheduler.
+CREATE TABLE IF NOT EXISTS job_schedules (
+    id          TEXT PRIMARY KEY,
+    name        TEXT,
+    cron        TEXT NOT NULL,
+    job         JSONB NOT NULL,
+    created_at  TIMESTAMPTZ NOT NULL,
+    -- Due time of the most recently claimed run
+    last_run_at TIMESTAMPTZ
+);
@@ -0,0 +1,7 @@
+-- Idempotency-Key header values and the job ids they were first used for.
+-- Expired rows are reclaimed when the same key is used again.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key        TEXT PRIMARY KEY,
+    value      TEXT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,12 @@
+-- Jobs that failed their last attempt, kept for inspection and retry.
+CREATE TABLE IF NOT EXISTS dead_jobs (
+    job_id    TEXT PRIMARY KEY,
+    job_type  TEXT NOT NULL,
+    queue     TEXT NOT NULL,
+    job       JSONB NOT NULL,
+    error     TEXT NOT NULL,
+    attempts  INTEGER NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS dead_jobs_failed_at_idx ON dead_jobs (failed_at DESC);
@@ -0,0 +1,27 @@
+-- Append-only log of accepted submissions: who enqueued what, and when.
+CREATE TABLE IF NOT EXISTS submission_audit (
+    id           BIGSERIAL PRIMARY KEY,
+    job_id       TEXT NOT NULL,
+    job_type     TEXT NOT NULL,
+    payload      JSONB NOT NULL,
+    submitted_by TEXT,
+    tenant       TEXT,
+    request_id   TEXT,
+    submitted_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS submission_audit_submitted_at_idx ON submission_audit (submitted_at DESC);
+CREATE INDEX IF NOT EXISTS submission_audit_submitted_by_idx ON submission_audit (submitted_by, submitted_at DESC);
+CREATE INDEX IF NOT EXISTS submission_audit_job_id_idx ON submission_audit (job_id);
+
+-- Rows can't be changed or removed once written
+CREATE OR REPLACE FUNCTION submission_audit_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'submission_audit is append-only';
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS submission_audit_append_only ON submission_audit;
+CREATE TRIGGER submission_audit_append_only
+    BEFORE UPDATE OR DELETE ON submission_audit
+    FOR EACH ROW EXECUTE FUNCTION submission_audit_append_only();
@@ -0,0 +1,5 @@
+-- Lets support look a job up by the request that submitted it.
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS request_id TEXT;
+
+CREATE INDEX IF NOT EXISTS job_history_request_id_idx ON job_history (request_id);
+CREATE INDEX IF NOT EXISTS job_history_listing_idx ON job_history (finished_at DESC, job_id DESC);
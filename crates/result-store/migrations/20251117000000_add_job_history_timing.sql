@@ -0,0 +1,3 @@
+-- How long the attempt that finished the job took, and which attempt it was.
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS duration_ms BIGINT;
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS attempt INTEGER;
@@ -0,0 +1,2 @@
+-- Latest progress reported by a running job.
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS progress JSONB;
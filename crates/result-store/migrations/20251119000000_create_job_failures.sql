@@ -0,0 +1,12 @@
+-- Every failed attempt of a job, kept for the dead-letter record's error
+-- history.
+CREATE TABLE IF NOT EXISTS job_failures (
+    job_id    TEXT NOT NULL,
+    attempt   INTEGER NOT NULL,
+    error     TEXT NOT NULL,
+    failed_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS job_failures_job_id_idx ON job_failures (job_id, failed_at DESC);
+
+ALTER TABLE dead_jobs ADD COLUMN IF NOT EXISTS errors JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,7 @@
+-- Failures of each payload (by hash) within a window, for poison-pill
+-- detection.
+CREATE TABLE IF NOT EXISTS payload_failures (
+    hash       TEXT PRIMARY KEY,
+    failures   BIGINT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
@@ -0,0 +1,7 @@
+-- The latest heartbeat of each worker, for listing the live fleet. Rows past
+-- their expiry belong to workers that stopped.
+CREATE TABLE IF NOT EXISTS worker_heartbeats (
+    worker_id  TEXT PRIMARY KEY,
+    heartbeat  JSONB NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
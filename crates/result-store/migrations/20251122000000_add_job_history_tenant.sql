@@ -0,0 +1,2 @@
+-- Lets listings be limited to the tenant whose queue a job ran from.
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS tenant TEXT;
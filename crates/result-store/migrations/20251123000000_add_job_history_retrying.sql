@@ -0,0 +1,2 @@
+-- Tells a failure the job will be retried after from its final one.
+ALTER TABLE job_history ADD COLUMN IF NOT EXISTS retrying BOOLEAN NOT NULL DEFAULT false;
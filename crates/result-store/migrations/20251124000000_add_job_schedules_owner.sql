@@ -0,0 +1,4 @@
+-- Who created each schedule, so its runs are charged and routed like their
+-- jobs and other tenants can't see or change it.
+ALTER TABLE job_schedules ADD COLUMN IF NOT EXISTS created_by TEXT;
+ALTER TABLE job_schedules ADD COLUMN IF NOT EXISTS tenant TEXT;
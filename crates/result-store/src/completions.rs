@@ -0,0 +1,167 @@
+//! Completion events over Redis Pub/Sub.
+//!
+//! With `COMPLETION_EVENTS_URL` set, workers publish every result they store
+//! to `COMPLETION_EVENTS_CHANNEL` as the result's JSON, so the API's event
+//! streams and any external subscriber hear about finished jobs as soon as
+//! they're written instead of polling for them. Events are best effort:
+//! nothing is replayed to subscribers that were disconnected, so readers
+//! that can't miss a result still check the result store.
+
+use crate::JobResult;
+use anyhow::{Context, Result};
+use futures_util::StreamExt;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use service_config::Config;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::warn;
+
+/// Events buffered per subscriber before the slowest start missing them
+const FEED_CAPACITY: usize = 1024;
+
+/// Delay before resubscribing after the connection is lost
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+/// Where completion events are published
+#[derive(Debug, Clone)]
+pub struct CompletionConfig {
+    url: Option<String>,
+    channel: String,
+}
+
+impl CompletionConfig {
+    /// Read `COMPLETION_EVENTS_URL` (a Redis URL; unset disables events) and
+    /// `COMPLETION_EVENTS_CHANNEL` (default `job_completions`)
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            url: config.string("COMPLETION_EVENTS_URL"),
+            channel: config.string_or("COMPLETION_EVENTS_CHANNEL", "job_completions"),
+        }
+    }
+
+    fn client(&self) -> Result<Option<redis::Client>> {
+        self.url
+            .as_deref()
+            .map(|url| redis::Client::open(url).context("Invalid COMPLETION_EVENTS_URL"))
+            .transpose()
+    }
+}
+
+/// Publishes stored results
+#[derive(Clone)]
+pub struct CompletionPublisher {
+    conn: ConnectionManager,
+    channel: String,
+}
+
+impl CompletionPublisher {
+    /// Connect, unless events are disabled
+    pub async fn connect(config: &CompletionConfig) -> Result<Option<Self>> {
+        let Some(client) = config.client()? else {
+            return Ok(None);
+        };
+        let conn = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis for completion events")?;
+        Ok(Some(Self {
+            conn,
+            channel: config.channel.clone(),
+        }))
+    }
+
+    /// The channel events go to, for logging
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    pub async fn publish(&self, result: &JobResult) -> Result<()> {
+        let event = serde_json::to_string(result).context("Failed to serialize job result")?;
+        let mut conn = self.conn.clone();
+        conn.publish::<_, _, ()>(&self.channel, event)
+            .await
+            .context("Failed to publish completion event")?;
+        Ok(())
+    }
+}
+
+/// Completion events received from the channel, fanned out to any number of
+/// in-process listeners
+#[derive(Clone)]
+pub struct CompletionFeed {
+    sender: broadcast::Sender<Arc<JobResult>>,
+    channel: String,
+}
+
+impl CompletionFeed {
+    /// Subscribe in the background, unless events are disabled. Lost
+    /// connections are resubscribed; events published meanwhile are missed.
+    pub fn start(config: &CompletionConfig) -> Result<Option<Self>> {
+        let Some(client) = config.client()? else {
+            return Ok(None);
+        };
+        let (sender, _) = broadcast::channel(FEED_CAPACITY);
+        tokio::spawn(forward(client, config.channel.clone(), sender.clone()));
+        Ok(Some(Self {
+            sender,
+            channel: config.channel.clone(),
+        }))
+    }
+
+    /// The channel events come from, for logging
+    pub fn channel(&self) -> &str {
+        &self.channel
+    }
+
+    /// Events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<JobResult>> {
+        self.sender.subscribe()
+    }
+}
+
+async fn forward(
+    client: redis::Client,
+    channel: String,
+    sender: broadcast::Sender<Arc<JobResult>>,
+) {
+    loop {
+        if let Err(e) = forward_until_disconnected(&client, &channel, &sender).await {
+            warn!("Completion event subscription failed: {:#}", e);
+        }
+        tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+    }
+}
+
+async fn forward_until_disconnected(
+    client: &redis::Client,
+    channel: &str,
+    sender: &broadcast::Sender<Arc<JobResult>>,
+) -> Result<()> {
+    let mut pubsub = client
+        .get_async_pubsub()
+        .await
+        .context("Failed to connect to Redis for completion events")?;
+    pubsub
+        .subscribe(channel)
+        .await
+        .context("Failed to subscribe to completion events")?;
+
+    let mut messages = pubsub.on_message();
+    while let Some(message) = messages.next().await {
+        let result = message
+            .get_payload::<String>()
+            .context("Invalid completion event")
+            .and_then(|payload| {
+                serde_json::from_str::<JobResult>(&payload).context("Invalid completion event")
+            });
+        match result {
+            // No listeners is fine
+            Ok(result) => {
+                let _ = sender.send(Arc::new(result));
+            }
+            Err(e) => warn!("Skipping completion event: {:#}", e),
+        }
+    }
+    anyhow::bail!("Connection closed")
+}
@@ -0,0 +1,382 @@
+use crate::{
+    DeadJob, JobFailure, JobPage, JobProgress, JobQuery, JobResult, JobStatus, ResultStore,
+    Schedule, Usage, UsagePeriods, WorkerHeartbeat, FAILURE_HISTORY_TTL, MAX_FAILURES_KEPT,
+};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Expired entries are swept every this many writes
+const SWEEP_INTERVAL: usize = 1024;
+
+struct Entry {
+    result: JobResult,
+    expires_at: Instant,
+}
+
+/// In-process result store for development and tests
+pub struct MemoryResultStore {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Jobs that have started but not finished
+    running: Mutex<HashMap<String, Instant>>,
+    /// Latest progress of jobs that reported any, and when it was reported
+    progress: Mutex<HashMap<String, (JobProgress, Instant)>>,
+    /// Failed attempts of jobs, and when the last one was recorded
+    failures: Mutex<HashMap<String, (Vec<JobFailure>, Instant)>>,
+    /// Payload hash -> (failures, end of the window)
+    payload_failures: Mutex<HashMap<String, (u64, Instant)>>,
+    /// Usage counters keyed by (caller, period)
+    usage: Mutex<HashMap<(String, String), i64>>,
+    schedules: Mutex<HashMap<String, Schedule>>,
+    /// Idempotency key -> (value, expiry)
+    idempotency_keys: Mutex<HashMap<String, (String, Instant)>>,
+    dead_jobs: Mutex<HashMap<String, DeadJob>>,
+    /// Worker id -> (latest heartbeat, expiry)
+    heartbeats: Mutex<HashMap<String, (WorkerHeartbeat, Instant)>>,
+}
+
+impl MemoryResultStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+            running: Mutex::new(HashMap::new()),
+            progress: Mutex::new(HashMap::new()),
+            failures: Mutex::new(HashMap::new()),
+            payload_failures: Mutex::new(HashMap::new()),
+            usage: Mutex::new(HashMap::new()),
+            schedules: Mutex::new(HashMap::new()),
+            idempotency_keys: Mutex::new(HashMap::new()),
+            dead_jobs: Mutex::new(HashMap::new()),
+            heartbeats: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[async_trait]
+impl ResultStore for MemoryResultStore {
+    async fn set(&self, result: &JobResult) -> Result<()> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len().is_multiple_of(SWEEP_INTERVAL) {
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+
+        entries.insert(
+            result.job_id.clone(),
+            Entry {
+                result: result.clone(),
+                expires_at: now + self.ttl,
+            },
+        );
+        drop(entries);
+
+        self.running.lock().unwrap().remove(&result.job_id);
+        Ok(())
+    }
+
+    async fn get(&self, job_id: &str) -> Result<Option<JobResult>> {
+        let entries = self.entries.lock().unwrap();
+        Ok(entries
+            .get(job_id)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .map(|entry| entry.result.clone()))
+    }
+
+    async fn list(&self, limit: usize) -> Result<Vec<JobResult>> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+
+        let mut results: Vec<JobResult> = entries
+            .values()
+            .filter(|entry| entry.expires_at > now)
+            .map(|entry| entry.result.clone())
+            .collect();
+        results.sort_by_key(|result| std::cmp::Reverse(result.finished_at));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    async fn list_jobs(&self, query: &JobQuery) -> Result<JobPage> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+
+        let mut results: Vec<JobResult> = entries
+            .values()
+            .filter(|entry| entry.expires_at > now && query.matches(&entry.result))
+            .map(|entry| entry.result.clone())
+            .collect();
+        results.sort_by_key(|result| std::cmp::Reverse(result.cursor()));
+        results.truncate(query.limit + 1);
+        Ok(JobPage::from_results(results, query.limit))
+    }
+
+    async fn expire(&self, job_id: &str, ttl: Duration) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(job_id) {
+            entry.expires_at = Instant::now() + ttl;
+        }
+        Ok(())
+    }
+
+    async fn record_started(&self, job_id: &str, _job_type: &str) -> Result<()> {
+        let now = Instant::now();
+        let mut running = self.running.lock().unwrap();
+
+        if running.len().is_multiple_of(SWEEP_INTERVAL) {
+            let ttl = self.ttl;
+            running.retain(|_, started_at| now.duration_since(*started_at) < ttl);
+        }
+
+        running.insert(job_id.to_string(), now);
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<Option<JobStatus>> {
+        if let Some(result) = self.get(job_id).await? {
+            return Ok(Some(result.status));
+        }
+        let running = self.running.lock().unwrap();
+        Ok(running.contains_key(job_id).then_some(JobStatus::Running))
+    }
+
+    async fn set_progress(&self, job_id: &str, progress: &JobProgress) -> Result<()> {
+        let now = Instant::now();
+        let mut reported = self.progress.lock().unwrap();
+
+        if reported.len().is_multiple_of(SWEEP_INTERVAL) {
+            let ttl = self.ttl;
+            reported.retain(|_, (_, at)| now.duration_since(*at) < ttl);
+        }
+
+        reported.insert(job_id.to_string(), (progress.clone(), now));
+        Ok(())
+    }
+
+    async fn progress(&self, job_id: &str) -> Result<Option<JobProgress>> {
+        let reported = self.progress.lock().unwrap();
+        Ok(reported.get(job_id).map(|(progress, _)| progress.clone()))
+    }
+
+    async fn record_failure(&self, job_id: &str, failure: &JobFailure) -> Result<()> {
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+
+        if failures.len().is_multiple_of(SWEEP_INTERVAL) {
+            failures.retain(|_, (_, at)| now.duration_since(*at) < FAILURE_HISTORY_TTL);
+        }
+
+        let (history, at) = failures
+            .entry(job_id.to_string())
+            .or_insert_with(|| (Vec::new(), now));
+        history.push(failure.clone());
+        if history.len() > MAX_FAILURES_KEPT {
+            history.drain(..history.len() - MAX_FAILURES_KEPT);
+        }
+        *at = now;
+        Ok(())
+    }
+
+    async fn failures(&self, job_id: &str) -> Result<Vec<JobFailure>> {
+        let failures = self.failures.lock().unwrap();
+        Ok(failures
+            .get(job_id)
+            .map(|(history, _)| history.clone())
+            .unwrap_or_default())
+    }
+
+    async fn add_payload_failure(&self, hash: &str, window: Duration) -> Result<u64> {
+        let now = Instant::now();
+        let mut counts = self.payload_failures.lock().unwrap();
+
+        if counts.len().is_multiple_of(SWEEP_INTERVAL) {
+            counts.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+
+        let (failures, expires_at) = counts.entry(hash.to_string()).or_insert((0, now + window));
+        if *expires_at <= now {
+            *failures = 0;
+            *expires_at = now + window;
+        }
+        *failures += 1;
+        Ok(*failures)
+    }
+
+    async fn payload_failures(&self, hash: &str) -> Result<u64> {
+        let counts = self.payload_failures.lock().unwrap();
+        Ok(match counts.get(hash) {
+            Some((failures, expires_at)) if *expires_at > Instant::now() => *failures,
+            _ => 0,
+        })
+    }
+
+    async fn add_usage(&self, key: &str, jobs: i64) -> Result<Usage> {
+        let periods = UsagePeriods::current();
+        let mut usage = self.usage.lock().unwrap();
+
+        // Counters for past days and months are no longer needed
+        usage.retain(|(_, period), _| *period == periods.day || *period == periods.month);
+
+        let mut add = |period: &str| {
+            let count = usage
+                .entry((key.to_string(), period.to_string()))
+                .or_default();
+            *count += jobs;
+            *count
+        };
+        let daily_jobs = add(&periods.day);
+        let monthly_jobs = add(&periods.month);
+        Ok(Usage::new(key, periods, daily_jobs, monthly_jobs))
+    }
+
+    async fn usage(&self, key: &str) -> Result<Usage> {
+        let periods = UsagePeriods::current();
+        let usage = self.usage.lock().unwrap();
+
+        let count = |period: &str| {
+            usage
+                .get(&(key.to_string(), period.to_string()))
+                .copied()
+                .unwrap_or(0)
+        };
+        let (daily_jobs, monthly_jobs) = (count(&periods.day), count(&periods.month));
+        Ok(Usage::new(key, periods, daily_jobs, monthly_jobs))
+    }
+
+    async fn save_schedule(&self, schedule: &Schedule) -> Result<()> {
+        let mut schedules = self.schedules.lock().unwrap();
+        let last_run_at = schedules.get(&schedule.id).and_then(|s| s.last_run_at);
+        schedules.insert(
+            schedule.id.clone(),
+            Schedule {
+                last_run_at,
+                ..schedule.clone()
+            },
+        );
+        Ok(())
+    }
+
+    async fn get_schedule(&self, id: &str) -> Result<Option<Schedule>> {
+        Ok(self.schedules.lock().unwrap().get(id).cloned())
+    }
+
+    async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let mut schedules: Vec<Schedule> =
+            self.schedules.lock().unwrap().values().cloned().collect();
+        schedules.sort_by_key(|schedule| schedule.created_at);
+        Ok(schedules)
+    }
+
+    async fn delete_schedule(&self, id: &str) -> Result<bool> {
+        Ok(self.schedules.lock().unwrap().remove(id).is_some())
+    }
+
+    async fn claim_schedule_run(&self, id: &str, run_at: DateTime<Utc>) -> Result<bool> {
+        let mut schedules = self.schedules.lock().unwrap();
+        match schedules.get_mut(id) {
+            Some(schedule) if schedule.last_run_at.is_none_or(|last| last < run_at) => {
+                schedule.last_run_at = Some(run_at);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<Option<String>> {
+        let now = Instant::now();
+        let mut keys = self.idempotency_keys.lock().unwrap();
+
+        if keys.len().is_multiple_of(SWEEP_INTERVAL) {
+            keys.retain(|_, (_, expires_at)| *expires_at > now);
+        }
+
+        match keys.get(key) {
+            Some((existing, expires_at)) if *expires_at > now => Ok(Some(existing.clone())),
+            _ => {
+                keys.insert(key.to_string(), (value.to_string(), now + ttl));
+                Ok(None)
+            }
+        }
+    }
+
+    async fn update_idempotency_key(&self, key: &str, value: &str) -> Result<()> {
+        let mut keys = self.idempotency_keys.lock().unwrap();
+        if let Some((existing, expires_at)) = keys.get_mut(key) {
+            if *expires_at > Instant::now() {
+                *existing = value.to_string();
+            }
+        }
+        Ok(())
+    }
+
+    async fn extend_idempotency_key(&self, key: &str, ttl: Duration) -> Result<()> {
+        let mut keys = self.idempotency_keys.lock().unwrap();
+        if let Some((_, expires_at)) = keys.get_mut(key) {
+            let now = Instant::now();
+            if *expires_at > now {
+                *expires_at = now + ttl;
+            }
+        }
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        self.idempotency_keys.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn save_dead_job(&self, job: &DeadJob) -> Result<()> {
+        self.dead_jobs
+            .lock()
+            .unwrap()
+            .insert(job.job_id.clone(), job.clone());
+        Ok(())
+    }
+
+    async fn get_dead_job(&self, job_id: &str) -> Result<Option<DeadJob>> {
+        Ok(self.dead_jobs.lock().unwrap().get(job_id).cloned())
+    }
+
+    async fn list_dead_jobs(&self, limit: usize) -> Result<Vec<DeadJob>> {
+        let mut jobs: Vec<DeadJob> = self.dead_jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by_key(|job| std::cmp::Reverse(job.failed_at));
+        jobs.truncate(limit);
+        Ok(jobs)
+    }
+
+    async fn delete_dead_job(&self, job_id: &str) -> Result<bool> {
+        Ok(self.dead_jobs.lock().unwrap().remove(job_id).is_some())
+    }
+
+    async fn save_heartbeat(&self, heartbeat: &WorkerHeartbeat, ttl: Duration) -> Result<()> {
+        let now = Instant::now();
+        let mut heartbeats = self.heartbeats.lock().unwrap();
+        heartbeats.retain(|_, (_, expires_at)| *expires_at > now);
+        heartbeats.insert(heartbeat.worker_id.clone(), (heartbeat.clone(), now + ttl));
+        Ok(())
+    }
+
+    async fn list_workers(&self) -> Result<Vec<WorkerHeartbeat>> {
+        let now = Instant::now();
+        let mut workers: Vec<WorkerHeartbeat> = self
+            .heartbeats
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(heartbeat, _)| heartbeat.clone())
+            .collect();
+        workers.sort_by(|a, b| a.worker_id.cmp(&b.worker_id));
+        Ok(workers)
+    }
+}
@@ -0,0 +1,850 @@
+use crate::{
+    AuditQuery, AuditRecord, DeadJob, JobFailure, JobPage, JobProgress, JobQuery, JobRecord,
+    JobResult, JobStatus, ResultStore, Schedule, Usage, UsagePeriods, WorkerHeartbeat,
+    MAX_FAILURES_KEPT,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::QueryBuilder;
+use std::time::Duration;
+
+/// Result store backed by Postgres. Unlike the Redis store, rows are never
+/// expired automatically: each job keeps a durable `job_history` row covering
+/// its whole lifecycle (submitted, started, finished). Results are only hidden
+/// from lookups once [`ResultStore::expire`] is called for them.
+#[derive(Clone)]
+pub struct PostgresResultStore {
+    pool: PgPool,
+}
+
+/// Raw `job_history` row (without the internal `expires_at` column)
+#[derive(sqlx::FromRow)]
+struct JobHistoryRow {
+    job_id: String,
+    job_type: String,
+    status: String,
+    args: Option<serde_json::Value>,
+    result: Option<serde_json::Value>,
+    error: Option<String>,
+    submitted_at: Option<DateTime<Utc>>,
+    started_at: Option<DateTime<Utc>>,
+    finished_at: Option<DateTime<Utc>>,
+    request_id: Option<String>,
+    duration_ms: Option<i64>,
+    attempt: Option<i32>,
+    tenant: Option<String>,
+    retrying: bool,
+}
+
+impl JobHistoryRow {
+    /// Convert a finished row into a result. Returns `None` for jobs that
+    /// haven't finished yet.
+    fn into_result(self) -> Result<Option<JobResult>> {
+        let Some(finished_at) = self.finished_at else {
+            return Ok(None);
+        };
+        Ok(Some(JobResult {
+            job_id: self.job_id,
+            job_type: self.job_type,
+            status: self.status.parse()?,
+            result: self.result,
+            error: self.error,
+            finished_at,
+            request_id: self.request_id,
+            // Rows finished before timing was recorded still have the
+            // worker's start time from `record_started`
+            started_at: self.started_at,
+            duration_ms: self.duration_ms.map(|ms| ms as u64),
+            attempt: self.attempt.map(|attempt| attempt as u32),
+            args: self.args,
+            tenant: self.tenant,
+            retrying: self.retrying,
+        }))
+    }
+}
+
+impl TryFrom<JobHistoryRow> for JobRecord {
+    type Error = anyhow::Error;
+
+    fn try_from(row: JobHistoryRow) -> Result<Self> {
+        Ok(JobRecord {
+            job_id: row.job_id,
+            job_type: row.job_type,
+            status: row.status.parse()?,
+            args: row.args,
+            result: row.result,
+            error: row.error,
+            submitted_at: row.submitted_at,
+            started_at: row.started_at,
+            finished_at: row.finished_at,
+            request_id: row.request_id,
+            tenant: row.tenant,
+        })
+    }
+}
+
+impl PostgresResultStore {
+    /// Connect to Postgres with a pool of up to `max_connections` connections
+    pub async fn connect(database_url: &str, max_connections: u32) -> Result<Self> {
+        let pool = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .connect(database_url)
+            .await
+            .context("Failed to connect to Postgres")?;
+        Ok(Self { pool })
+    }
+
+    /// Apply any pending schema migrations (embedded from `migrations/`)
+    pub async fn migrate(&self) -> Result<()> {
+        sqlx::migrate!("./migrations")
+            .run(&self.pool)
+            .await
+            .context("Failed to run result store migrations")?;
+        Ok(())
+    }
+
+    /// Fetch the full lifecycle row for a job
+    pub async fn record(&self, job_id: &str) -> Result<Option<JobRecord>> {
+        let row: Option<JobHistoryRow> = sqlx::query_as(
+            "SELECT job_id, job_type, status, args, result, error,
+                    submitted_at, started_at, finished_at, request_id, duration_ms, attempt, tenant,
+                    retrying
+             FROM job_history
+             WHERE job_id = $1",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read job history from Postgres")?;
+
+        row.map(JobRecord::try_from).transpose()
+    }
+}
+
+#[async_trait]
+impl ResultStore for PostgresResultStore {
+    async fn record_submitted(
+        &self,
+        job_id: &str,
+        job_type: &str,
+        args: &serde_json::Value,
+        tenant: Option<&str>,
+    ) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO job_history (job_id, job_type, status, args, submitted_at, tenant)
+             VALUES ($1, $2, $3, $4, now(), $5)
+             ON CONFLICT (job_id) DO UPDATE
+             SET args = EXCLUDED.args,
+                 submitted_at = EXCLUDED.submitted_at,
+                 tenant = COALESCE(job_history.tenant, EXCLUDED.tenant)",
+        )
+        .bind(job_id)
+        .bind(job_type)
+        .bind(JobStatus::Enqueued.as_str())
+        .bind(args)
+        .bind(tenant)
+        .execute(&self.pool)
+        .await
+        .context("Failed to record job submission")?;
+        Ok(())
+    }
+
+    async fn record_started(&self, job_id: &str, job_type: &str) -> Result<()> {
+        // Don't move a job that already finished back to running
+        sqlx::query(
+            "INSERT INTO job_history (job_id, job_type, status, started_at)
+             VALUES ($1, $2, $3, now())
+             ON CONFLICT (job_id) DO UPDATE
+             SET status = EXCLUDED.status, started_at = EXCLUDED.started_at
+             WHERE job_history.finished_at IS NULL",
+        )
+        .bind(job_id)
+        .bind(job_type)
+        .bind(JobStatus::Running.as_str())
+        .execute(&self.pool)
+        .await
+        .context("Failed to record job start")?;
+        Ok(())
+    }
+
+    async fn set(&self, result: &JobResult) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO job_history
+                 (job_id, job_type, status, result, error, finished_at, request_id,
+                  started_at, duration_ms, attempt, args, tenant, retrying)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
+             ON CONFLICT (job_id) DO UPDATE
+             SET status = EXCLUDED.status,
+                 result = EXCLUDED.result,
+                 error = EXCLUDED.error,
+                 finished_at = EXCLUDED.finished_at,
+                 request_id = EXCLUDED.request_id,
+                 started_at = COALESCE(EXCLUDED.started_at, job_history.started_at),
+                 duration_ms = EXCLUDED.duration_ms,
+                 attempt = EXCLUDED.attempt,
+                 args = COALESCE(EXCLUDED.args, job_history.args),
+                 tenant = EXCLUDED.tenant,
+                 retrying = EXCLUDED.retrying",
+        )
+        .bind(&result.job_id)
+        .bind(&result.job_type)
+        .bind(result.status.as_str())
+        .bind(&result.result)
+        .bind(&result.error)
+        .bind(result.finished_at)
+        .bind(&result.request_id)
+        .bind(result.started_at)
+        .bind(result.duration_ms.map(|ms| ms as i64))
+        .bind(result.attempt.map(|attempt| attempt as i32))
+        .bind(&result.args)
+        .bind(&result.tenant)
+        .bind(result.retrying)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write job result to Postgres")?;
+        Ok(())
+    }
+
+    async fn get(&self, job_id: &str) -> Result<Option<JobResult>> {
+        let row: Option<JobHistoryRow> = sqlx::query_as(
+            "SELECT job_id, job_type, status, args, result, error,
+                    submitted_at, started_at, finished_at, request_id, duration_ms, attempt, tenant,
+                    retrying
+             FROM job_history
+             WHERE job_id = $1 AND (expires_at IS NULL OR expires_at > now())",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read job result from Postgres")?;
+
+        Ok(row.map(JobHistoryRow::into_result).transpose()?.flatten())
+    }
+
+    async fn list(&self, limit: usize) -> Result<Vec<JobResult>> {
+        let rows: Vec<JobHistoryRow> = sqlx::query_as(
+            "SELECT job_id, job_type, status, args, result, error,
+                    submitted_at, started_at, finished_at, request_id, duration_ms, attempt, tenant,
+                    retrying
+             FROM job_history
+             WHERE finished_at IS NOT NULL AND (expires_at IS NULL OR expires_at > now())
+             ORDER BY finished_at DESC
+             LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list job results from Postgres")?;
+
+        let mut results = Vec::with_capacity(rows.len());
+        for row in rows {
+            results.extend(row.into_result()?);
+        }
+        Ok(results)
+    }
+
+    async fn list_jobs(&self, query: &JobQuery) -> Result<JobPage> {
+        let (after_finished_at, after_job_id) = match &query.after {
+            Some(after) => (Some(after.finished_at), Some(after.job_id.as_str())),
+            None => (None, None),
+        };
+        let rows: Vec<JobHistoryRow> = sqlx::query_as(
+            "SELECT job_id, job_type, status, args, result, error,
+                    submitted_at, started_at, finished_at, request_id, duration_ms, attempt, tenant,
+                    retrying
+             FROM job_history
+             WHERE finished_at IS NOT NULL AND (expires_at IS NULL OR expires_at > now())
+               AND ($1::text IS NULL OR status = $1)
+               AND ($2::text IS NULL OR job_type = $2)
+               AND ($3::text IS NULL OR request_id = $3)
+               AND ($4::timestamptz IS NULL OR (finished_at, job_id) < ($4, $5))
+               AND (NOT $7 OR tenant IS NOT DISTINCT FROM $8)
+             ORDER BY finished_at DESC, job_id DESC
+             LIMIT $6",
+        )
+        .bind(query.status.map(|status| status.as_str()))
+        .bind(&query.job_type)
+        .bind(&query.request_id)
+        .bind(after_finished_at)
+        .bind(after_job_id)
+        .bind(query.limit as i64 + 1)
+        .bind(query.tenant.is_some())
+        .bind(query.tenant.clone().flatten())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list job results from Postgres")?;
+
+        let mut results = Vec::with_capacity(rows.len());
+        for row in rows {
+            results.extend(row.into_result()?);
+        }
+        Ok(JobPage::from_results(results, query.limit))
+    }
+
+    async fn list_pending(&self, query: &JobQuery) -> Result<Vec<JobRecord>> {
+        let rows: Vec<JobHistoryRow> = sqlx::query_as(
+            "SELECT job_id, job_type, status, args, result, error,
+                    submitted_at, started_at, finished_at, request_id, duration_ms, attempt, tenant,
+                    retrying
+             FROM job_history
+             WHERE finished_at IS NULL AND submitted_at IS NOT NULL
+               AND ($1::text IS NULL OR status = $1)
+               AND ($2::text IS NULL OR job_type = $2)
+               AND (NOT $4 OR tenant IS NOT DISTINCT FROM $5)
+             ORDER BY submitted_at DESC, job_id DESC
+             LIMIT $3",
+        )
+        .bind(query.status.map(|status| status.as_str()))
+        .bind(&query.job_type)
+        .bind(query.limit as i64)
+        .bind(query.tenant.is_some())
+        .bind(query.tenant.clone().flatten())
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list pending jobs from Postgres")?;
+
+        rows.into_iter().map(JobRecord::try_from).collect()
+    }
+
+    async fn expire(&self, job_id: &str, ttl: Duration) -> Result<()> {
+        let expires_at =
+            Utc::now() + chrono::Duration::from_std(ttl).context("Expiry duration out of range")?;
+        sqlx::query("UPDATE job_history SET expires_at = $2 WHERE job_id = $1")
+            .bind(job_id)
+            .bind(expires_at)
+            .execute(&self.pool)
+            .await
+            .context("Failed to set job result expiry in Postgres")?;
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<Option<JobStatus>> {
+        let status: Option<String> =
+            sqlx::query_scalar("SELECT status FROM job_history WHERE job_id = $1")
+                .bind(job_id)
+                .fetch_optional(&self.pool)
+                .await
+                .context("Failed to read job status from Postgres")?;
+        status.map(|s| s.parse()).transpose()
+    }
+
+    async fn set_progress(&self, job_id: &str, progress: &JobProgress) -> Result<()> {
+        let progress =
+            serde_json::to_value(progress).context("Failed to serialize job progress")?;
+        // Late updates don't reach a job that already finished
+        sqlx::query(
+            "UPDATE job_history SET progress = $2
+             WHERE job_id = $1 AND finished_at IS NULL",
+        )
+        .bind(job_id)
+        .bind(progress)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write job progress to Postgres")?;
+        Ok(())
+    }
+
+    async fn progress(&self, job_id: &str) -> Result<Option<JobProgress>> {
+        let progress: Option<serde_json::Value> =
+            sqlx::query_scalar("SELECT progress FROM job_history WHERE job_id = $1")
+                .bind(job_id)
+                .fetch_optional(&self.pool)
+                .await
+                .context("Failed to read job progress from Postgres")?
+                .flatten();
+        progress
+            .map(serde_json::from_value)
+            .transpose()
+            .context("Failed to deserialize job progress")
+    }
+
+    async fn record_failure(&self, job_id: &str, failure: &JobFailure) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO job_failures (job_id, attempt, error, failed_at)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(job_id)
+        .bind(failure.attempt as i32)
+        .bind(&failure.error)
+        .bind(failure.failed_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write job failure to Postgres")?;
+        Ok(())
+    }
+
+    async fn failures(&self, job_id: &str) -> Result<Vec<JobFailure>> {
+        let rows: Vec<JobFailureRow> = sqlx::query_as(
+            "SELECT attempt, error, failed_at FROM job_failures
+             WHERE job_id = $1 ORDER BY failed_at DESC LIMIT $2",
+        )
+        .bind(job_id)
+        .bind(MAX_FAILURES_KEPT as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to read job failures from Postgres")?;
+        Ok(rows.into_iter().rev().map(JobFailure::from).collect())
+    }
+
+    async fn add_payload_failure(&self, hash: &str, window: Duration) -> Result<u64> {
+        let expires_at =
+            Utc::now() + chrono::Duration::from_std(window).context("Window out of range")?;
+        // An expired window starts over
+        let failures: i64 = sqlx::query_scalar(
+            "INSERT INTO payload_failures (hash, failures, expires_at)
+             VALUES ($1, 1, $2)
+             ON CONFLICT (hash) DO UPDATE
+             SET failures = CASE WHEN payload_failures.expires_at <= now() THEN 1
+                                 ELSE payload_failures.failures + 1 END,
+                 expires_at = CASE WHEN payload_failures.expires_at <= now()
+                                   THEN EXCLUDED.expires_at
+                                   ELSE payload_failures.expires_at END
+             RETURNING failures",
+        )
+        .bind(hash)
+        .bind(expires_at)
+        .fetch_one(&self.pool)
+        .await
+        .context("Failed to count payload failure in Postgres")?;
+        Ok(failures.max(0) as u64)
+    }
+
+    async fn payload_failures(&self, hash: &str) -> Result<u64> {
+        let failures: Option<i64> = sqlx::query_scalar(
+            "SELECT failures FROM payload_failures WHERE hash = $1 AND expires_at > now()",
+        )
+        .bind(hash)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read payload failures from Postgres")?;
+        Ok(failures.unwrap_or(0).max(0) as u64)
+    }
+
+    async fn add_usage(&self, key: &str, jobs: i64) -> Result<Usage> {
+        let periods = UsagePeriods::current();
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "INSERT INTO job_usage (api_key, period, jobs)
+             VALUES ($1, $2, $4), ($1, $3, $4)
+             ON CONFLICT (api_key, period) DO UPDATE
+             SET jobs = job_usage.jobs + EXCLUDED.jobs
+             RETURNING period, jobs",
+        )
+        .bind(key)
+        .bind(&periods.day)
+        .bind(&periods.month)
+        .bind(jobs)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to update usage counters in Postgres")?;
+
+        Ok(usage_from_rows(key, periods, rows))
+    }
+
+    async fn usage(&self, key: &str) -> Result<Usage> {
+        let periods = UsagePeriods::current();
+        let rows: Vec<(String, i64)> = sqlx::query_as(
+            "SELECT period, jobs FROM job_usage
+             WHERE api_key = $1 AND period IN ($2, $3)",
+        )
+        .bind(key)
+        .bind(&periods.day)
+        .bind(&periods.month)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to read usage counters from Postgres")?;
+
+        Ok(usage_from_rows(key, periods, rows))
+    }
+
+    async fn save_schedule(&self, schedule: &Schedule) -> Result<()> {
+        sqlx::query(
+            "INSERT INTO job_schedules (id, name, cron, job, created_at, created_by, tenant)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             ON CONFLICT (id) DO UPDATE
+             SET name = EXCLUDED.name, cron = EXCLUDED.cron, job = EXCLUDED.job",
+        )
+        .bind(&schedule.id)
+        .bind(&schedule.name)
+        .bind(&schedule.cron)
+        .bind(&schedule.job)
+        .bind(schedule.created_at)
+        .bind(&schedule.created_by)
+        .bind(&schedule.tenant)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write schedule to Postgres")?;
+        Ok(())
+    }
+
+    async fn get_schedule(&self, id: &str) -> Result<Option<Schedule>> {
+        let row: Option<ScheduleRow> = sqlx::query_as(
+            "SELECT id, name, cron, job, created_at, last_run_at, created_by, tenant
+             FROM job_schedules WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read schedule from Postgres")?;
+        Ok(row.map(Schedule::from))
+    }
+
+    async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let rows: Vec<ScheduleRow> = sqlx::query_as(
+            "SELECT id, name, cron, job, created_at, last_run_at, created_by, tenant
+             FROM job_schedules ORDER BY created_at",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list schedules from Postgres")?;
+        Ok(rows.into_iter().map(Schedule::from).collect())
+    }
+
+    async fn delete_schedule(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM job_schedules WHERE id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete schedule from Postgres")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn claim_schedule_run(&self, id: &str, run_at: DateTime<Utc>) -> Result<bool> {
+        let result = sqlx::query(
+            "UPDATE job_schedules SET last_run_at = $2
+             WHERE id = $1 AND (last_run_at IS NULL OR last_run_at < $2)",
+        )
+        .bind(id)
+        .bind(run_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to claim schedule run in Postgres")?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<Option<String>> {
+        let expires_at =
+            Utc::now() + chrono::Duration::from_std(ttl).context("Expiry duration out of range")?;
+        // Takes the key if it's free or its previous claim has expired
+        let claimed = sqlx::query(
+            "INSERT INTO idempotency_keys (key, value, expires_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (key) DO UPDATE
+             SET value = EXCLUDED.value, expires_at = EXCLUDED.expires_at
+             WHERE idempotency_keys.expires_at <= now()",
+        )
+        .bind(key)
+        .bind(value)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to claim idempotency key in Postgres")?;
+        if claimed.rows_affected() == 1 {
+            return Ok(None);
+        }
+
+        sqlx::query_scalar("SELECT value FROM idempotency_keys WHERE key = $1")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await
+            .context("Failed to read idempotency key from Postgres")
+    }
+
+    async fn update_idempotency_key(&self, key: &str, value: &str) -> Result<()> {
+        sqlx::query(
+            "UPDATE idempotency_keys SET value = $2
+             WHERE key = $1 AND expires_at > now()",
+        )
+        .bind(key)
+        .bind(value)
+        .execute(&self.pool)
+        .await
+        .context("Failed to update idempotency key in Postgres")?;
+        Ok(())
+    }
+
+    async fn extend_idempotency_key(&self, key: &str, ttl: Duration) -> Result<()> {
+        let expires_at =
+            Utc::now() + chrono::Duration::from_std(ttl).context("Expiry duration out of range")?;
+        sqlx::query(
+            "UPDATE idempotency_keys SET expires_at = $2
+             WHERE key = $1 AND expires_at > now()",
+        )
+        .bind(key)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to extend idempotency key in Postgres")?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        sqlx::query("DELETE FROM idempotency_keys WHERE key = $1")
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .context("Failed to release idempotency key in Postgres")?;
+        Ok(())
+    }
+
+    async fn save_dead_job(&self, job: &DeadJob) -> Result<()> {
+        let errors =
+            serde_json::to_value(&job.errors).context("Failed to serialize job failures")?;
+        sqlx::query(
+            "INSERT INTO dead_jobs
+                 (job_id, job_type, queue, job, error, errors, attempts, failed_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (job_id) DO UPDATE
+             SET job_type = EXCLUDED.job_type, queue = EXCLUDED.queue, job = EXCLUDED.job,
+                 error = EXCLUDED.error, errors = EXCLUDED.errors,
+                 attempts = EXCLUDED.attempts, failed_at = EXCLUDED.failed_at",
+        )
+        .bind(&job.job_id)
+        .bind(&job.job_type)
+        .bind(&job.queue)
+        .bind(&job.job)
+        .bind(&job.error)
+        .bind(errors)
+        .bind(job.attempts as i32)
+        .bind(job.failed_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write dead job to Postgres")?;
+        Ok(())
+    }
+
+    async fn get_dead_job(&self, job_id: &str) -> Result<Option<DeadJob>> {
+        let row: Option<DeadJobRow> = sqlx::query_as(
+            "SELECT job_id, job_type, queue, job, error, errors, attempts, failed_at
+             FROM dead_jobs WHERE job_id = $1",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.pool)
+        .await
+        .context("Failed to read dead job from Postgres")?;
+        Ok(row.map(DeadJob::from))
+    }
+
+    async fn list_dead_jobs(&self, limit: usize) -> Result<Vec<DeadJob>> {
+        let rows: Vec<DeadJobRow> = sqlx::query_as(
+            "SELECT job_id, job_type, queue, job, error, errors, attempts, failed_at
+             FROM dead_jobs ORDER BY failed_at DESC LIMIT $1",
+        )
+        .bind(limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list dead jobs from Postgres")?;
+        Ok(rows.into_iter().map(DeadJob::from).collect())
+    }
+
+    async fn delete_dead_job(&self, job_id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM dead_jobs WHERE job_id = $1")
+            .bind(job_id)
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete dead job from Postgres")?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn save_heartbeat(&self, heartbeat: &WorkerHeartbeat, ttl: Duration) -> Result<()> {
+        let value = serde_json::to_value(heartbeat).context("Failed to serialize heartbeat")?;
+        let expires_at =
+            Utc::now() + chrono::Duration::from_std(ttl).context("Expiry duration out of range")?;
+        sqlx::query(
+            "INSERT INTO worker_heartbeats (worker_id, heartbeat, expires_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (worker_id) DO UPDATE
+             SET heartbeat = EXCLUDED.heartbeat, expires_at = EXCLUDED.expires_at",
+        )
+        .bind(&heartbeat.worker_id)
+        .bind(value)
+        .bind(expires_at)
+        .execute(&self.pool)
+        .await
+        .context("Failed to write heartbeat to Postgres")?;
+
+        // Workers that stopped don't come back under the same id
+        sqlx::query("DELETE FROM worker_heartbeats WHERE expires_at <= now()")
+            .execute(&self.pool)
+            .await
+            .context("Failed to delete expired heartbeats from Postgres")?;
+        Ok(())
+    }
+
+    async fn list_workers(&self) -> Result<Vec<WorkerHeartbeat>> {
+        let values: Vec<serde_json::Value> = sqlx::query_scalar(
+            "SELECT heartbeat FROM worker_heartbeats
+             WHERE expires_at > now() ORDER BY worker_id",
+        )
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to list workers from Postgres")?;
+        values
+            .into_iter()
+            .map(|v| serde_json::from_value(v).context("Failed to parse stored heartbeat"))
+            .collect()
+    }
+
+    async fn append_audit(&self, records: &[AuditRecord]) -> Result<()> {
+        // Keep each statement well under Postgres' bind parameter limit
+        for chunk in records.chunks(AUDIT_INSERT_CHUNK) {
+            let mut query = QueryBuilder::new(
+                "INSERT INTO submission_audit
+                 (job_id, job_type, payload, submitted_by, tenant, request_id, submitted_at) ",
+            );
+            query.push_values(chunk, |mut row, record| {
+                row.push_bind(&record.job_id)
+                    .push_bind(&record.job_type)
+                    .push_bind(&record.payload)
+                    .push_bind(&record.submitted_by)
+                    .push_bind(&record.tenant)
+                    .push_bind(&record.request_id)
+                    .push_bind(record.submitted_at);
+            });
+            query
+                .build()
+                .execute(&self.pool)
+                .await
+                .context("Failed to write audit records to Postgres")?;
+        }
+        Ok(())
+    }
+
+    async fn query_audit(&self, query: &AuditQuery) -> Result<Vec<AuditRecord>> {
+        let rows: Vec<AuditRow> = sqlx::query_as(
+            "SELECT job_id, job_type, payload, submitted_by, tenant, request_id, submitted_at
+             FROM submission_audit
+             WHERE ($1::text IS NULL OR submitted_by = $1)
+               AND ($2::text IS NULL OR job_id = $2)
+               AND ($3::timestamptz IS NULL OR submitted_at >= $3)
+               AND ($4::timestamptz IS NULL OR submitted_at < $4)
+             ORDER BY submitted_at DESC, id DESC LIMIT $5",
+        )
+        .bind(&query.submitted_by)
+        .bind(&query.job_id)
+        .bind(query.since)
+        .bind(query.until)
+        .bind(query.limit as i64)
+        .fetch_all(&self.pool)
+        .await
+        .context("Failed to read audit records from Postgres")?;
+        Ok(rows.into_iter().map(AuditRecord::from).collect())
+    }
+}
+
+/// Audit records written per `INSERT` (seven parameters each)
+const AUDIT_INSERT_CHUNK: usize = 1000;
+
+#[derive(sqlx::FromRow)]
+struct AuditRow {
+    job_id: String,
+    job_type: String,
+    payload: serde_json::Value,
+    submitted_by: Option<String>,
+    tenant: Option<String>,
+    request_id: Option<String>,
+    submitted_at: DateTime<Utc>,
+}
+
+impl From<AuditRow> for AuditRecord {
+    fn from(row: AuditRow) -> Self {
+        AuditRecord {
+            job_id: row.job_id,
+            job_type: row.job_type,
+            payload: row.payload,
+            submitted_by: row.submitted_by,
+            tenant: row.tenant,
+            request_id: row.request_id,
+            submitted_at: row.submitted_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct DeadJobRow {
+    job_id: String,
+    job_type: String,
+    queue: String,
+    job: serde_json::Value,
+    error: String,
+    errors: serde_json::Value,
+    attempts: i32,
+    failed_at: DateTime<Utc>,
+}
+
+impl From<DeadJobRow> for DeadJob {
+    fn from(row: DeadJobRow) -> Self {
+        DeadJob {
+            job_id: row.job_id,
+            job_type: row.job_type,
+            queue: row.queue,
+            job: row.job,
+            error: row.error,
+            // Written by us, so only unreadable if the schema moved on
+            errors: serde_json::from_value(row.errors).unwrap_or_default(),
+            attempts: row.attempts.max(0) as u32,
+            failed_at: row.failed_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct JobFailureRow {
+    attempt: i32,
+    error: String,
+    failed_at: DateTime<Utc>,
+}
+
+impl From<JobFailureRow> for JobFailure {
+    fn from(row: JobFailureRow) -> Self {
+        JobFailure {
+            attempt: row.attempt.max(0) as u32,
+            error: row.error,
+            failed_at: row.failed_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ScheduleRow {
+    id: String,
+    name: Option<String>,
+    cron: String,
+    job: serde_json::Value,
+    created_at: DateTime<Utc>,
+    last_run_at: Option<DateTime<Utc>>,
+    created_by: Option<String>,
+    tenant: Option<String>,
+}
+
+impl From<ScheduleRow> for Schedule {
+    fn from(row: ScheduleRow) -> Self {
+        Schedule {
+            id: row.id,
+            name: row.name,
+            cron: row.cron,
+            job: row.job,
+            created_at: row.created_at,
+            last_run_at: row.last_run_at,
+            created_by: row.created_by,
+            tenant: row.tenant,
+        }
+    }
+}
+
+/// Build usage from `(period, jobs)` rows
+fn usage_from_rows(key: &str, periods: UsagePeriods, rows: Vec<(String, i64)>) -> Usage {
+    let jobs_in = |period: &str| {
+        rows.iter()
+            .find(|(p, _)| p == period)
+            .map_or(0, |(_, jobs)| *jobs)
+    };
+    let (daily_jobs, monthly_jobs) = (jobs_in(&periods.day), jobs_in(&periods.month));
+    Usage::new(key, periods, daily_jobs, monthly_jobs)
+}
@@ -0,0 +1,88 @@
+//! Progress reporting for long-running jobs.
+
+use crate::{JobProgress, ResultStore};
+use anyhow::{Context, Result};
+use std::sync::{Arc, Mutex};
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Writes a job's progress to the result store as its handler reports it.
+///
+/// `report` doesn't block, so it can be called from computations running on
+/// blocking threads. Updates are written in the background, one at a time;
+/// if a handler reports faster than the store keeps up, only the latest is
+/// written. Nothing is written, and no task started, for jobs that never
+/// report.
+pub struct ProgressReporter {
+    store: Arc<dyn ResultStore>,
+    job_id: String,
+    runtime: Handle,
+    writer: Mutex<Option<Writer>>,
+}
+
+struct Writer {
+    latest: watch::Sender<JobProgress>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl ProgressReporter {
+    /// Reporter for `job_id`. Must be called within a Tokio runtime, which
+    /// the updates are written on.
+    pub fn new(store: Arc<dyn ResultStore>, job_id: impl Into<String>) -> Self {
+        Self {
+            store,
+            job_id: job_id.into(),
+            runtime: Handle::current(),
+            writer: Mutex::new(None),
+        }
+    }
+
+    /// Report `percent` done (capped at 100) and what the job is doing
+    pub fn report(&self, percent: u8, message: Option<String>) {
+        let progress = JobProgress::new(percent, message);
+        let mut writer = self.writer.lock().unwrap();
+        match writer.as_ref() {
+            Some(writer) => {
+                writer.latest.send_replace(progress);
+            }
+            None => {
+                let (latest, updates) = watch::channel(progress);
+                let task = self.runtime.spawn(write_updates(
+                    self.store.clone(),
+                    self.job_id.clone(),
+                    updates,
+                ));
+                *writer = Some(Writer { latest, task });
+            }
+        }
+    }
+
+    /// Wait until the last update is written. Returns the last error writing
+    /// one, if any failed.
+    pub async fn finish(self) -> Result<()> {
+        let Some(Writer { latest, task }) = self.writer.into_inner().unwrap() else {
+            return Ok(());
+        };
+        drop(latest);
+        task.await.context("Progress writer failed")?
+    }
+}
+
+async fn write_updates(
+    store: Arc<dyn ResultStore>,
+    job_id: String,
+    mut updates: watch::Receiver<JobProgress>,
+) -> Result<()> {
+    let mut result = Ok(());
+    // The value the channel was created with counts as unseen
+    updates.mark_changed();
+    // Ends once the reporter is finished and its last update written
+    while updates.changed().await.is_ok() {
+        let progress = updates.borrow_and_update().clone();
+        if let Err(e) = store.set_progress(&job_id, &progress).await {
+            result = Err(e);
+        }
+    }
+    result
+}
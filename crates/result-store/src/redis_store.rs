@@ -0,0 +1,641 @@
+use crate::{
+    DeadJob, JobFailure, JobPage, JobProgress, JobQuery, JobResult, JobStatus, ResultStore,
+    Schedule, Usage, UsagePeriods, WorkerHeartbeat, FAILURE_HISTORY_TTL, MAX_FAILURES_KEPT,
+};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use redis::aio::ConnectionManager;
+use redis::{AsyncCommands, ExistenceCheck, SetExpiry, SetOptions};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Prefix for result keys so they don't collide with other data in Redis
+const KEY_PREFIX: &str = "job_result:";
+
+/// Prefix for in-progress status keys, removed once the result is written
+const STATUS_KEY_PREFIX: &str = "job_status:";
+
+/// Prefix for the latest progress reported by a job
+const PROGRESS_KEY_PREFIX: &str = "job_progress:";
+
+/// Prefix for lists of a job's failed attempts (JSON)
+const FAILURES_KEY_PREFIX: &str = "job_failures:";
+
+/// Prefix for failure counts of payloads, suffixed with the payload hash
+const PAYLOAD_FAILURES_KEY_PREFIX: &str = "payload_failures:";
+
+/// Sorted set of job ids scored by finish time (ms), used for listing
+const INDEX_KEY: &str = "job_results";
+
+/// Prefix for sorted sets of a request's job ids scored by finish time (ms),
+/// suffixed with the request id, so `request_id` lookups skip the full index
+const REQUEST_INDEX_KEY_PREFIX: &str = "job_results_by_request:";
+
+/// Index entries read per round trip when listing with filters
+const LIST_SCAN_CHUNK: isize = 500;
+
+/// Prefix for usage counters, suffixed with the caller and period
+const USAGE_KEY_PREFIX: &str = "usage:";
+
+/// Daily counters are kept a little over a month, monthly ones a little over
+/// a year, so recent history stays around for billing
+const DAILY_USAGE_TTL_SECS: i64 = 35 * 24 * 3600;
+const MONTHLY_USAGE_TTL_SECS: i64 = 400 * 24 * 3600;
+
+/// Hash of schedule id -> schedule definition (JSON)
+const SCHEDULES_KEY: &str = "schedules";
+
+/// Hash of schedule id -> due time (ms) of its last claimed run. Kept apart
+/// from the definitions so claims don't race with updates.
+const SCHEDULE_RUNS_KEY: &str = "schedule_runs";
+
+/// Prefix for idempotency keys
+const IDEMPOTENCY_KEY_PREFIX: &str = "idempotency:";
+
+/// Hash of job id -> dead job (JSON)
+const DEAD_JOBS_KEY: &str = "dead_jobs";
+
+/// Sorted set of dead job ids scored by failure time (ms), used for listing
+const DEAD_INDEX_KEY: &str = "dead_jobs_index";
+
+/// Prefix for a worker's latest heartbeat (JSON), which expires with it
+const HEARTBEAT_KEY_PREFIX: &str = "worker_heartbeat:";
+
+/// Sorted set of worker ids scored by heartbeat expiry (ms), used for listing
+const WORKERS_INDEX_KEY: &str = "workers_index";
+
+/// Claims a run only if it's later than the last claimed one
+const CLAIM_RUN_SCRIPT: &str = r"
+local last = redis.call('HGET', KEYS[1], ARGV[1])
+if last and tonumber(last) >= tonumber(ARGV[2]) then
+    return 0
+end
+redis.call('HSET', KEYS[1], ARGV[1], ARGV[2])
+return 1
+";
+
+/// Result store backed by Redis. Results expire after the configured TTL.
+#[derive(Clone)]
+pub struct RedisResultStore {
+    conn: ConnectionManager,
+    ttl: Duration,
+}
+
+impl RedisResultStore {
+    /// Connect to Redis. The connection manager reconnects automatically if the
+    /// connection drops, so a single instance can be cloned and shared.
+    pub async fn connect(redis_url: &str, ttl: Duration) -> Result<Self> {
+        let client = redis::Client::open(redis_url).context("Invalid Redis URL")?;
+        let conn = ConnectionManager::new(client)
+            .await
+            .context("Failed to connect to Redis")?;
+        Ok(Self { conn, ttl })
+    }
+}
+
+#[async_trait]
+impl ResultStore for RedisResultStore {
+    async fn set(&self, result: &JobResult) -> Result<()> {
+        let value = serde_json::to_string(result).context("Failed to serialize job result")?;
+        let finished_ms = result.finished_at.timestamp_millis();
+        // Index entries older than the TTL point at keys that have expired
+        let cutoff_ms = finished_ms - self.ttl.as_millis() as i64;
+
+        let mut conn = self.conn.clone();
+        let mut pipe = redis::pipe();
+        pipe.set_ex(key(&result.job_id), value, self.ttl.as_secs())
+            .ignore()
+            .zadd(INDEX_KEY, &result.job_id, finished_ms)
+            .ignore()
+            .zrembyscore(INDEX_KEY, "-inf", cutoff_ms)
+            .ignore()
+            .del(status_key(&result.job_id))
+            .ignore();
+        if let Some(request_id) = &result.request_id {
+            let request_index = request_index_key(request_id);
+            pipe.zadd(&request_index, &result.job_id, finished_ms)
+                .ignore()
+                .zrembyscore(&request_index, "-inf", cutoff_ms)
+                .ignore()
+                .expire(&request_index, self.ttl.as_secs() as i64)
+                .ignore();
+        }
+        pipe.query_async::<()>(&mut conn)
+            .await
+            .context("Failed to write job result to Redis")?;
+        Ok(())
+    }
+
+    async fn get(&self, job_id: &str) -> Result<Option<JobResult>> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn
+            .get(key(job_id))
+            .await
+            .context("Failed to read job result from Redis")?;
+
+        value
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("Failed to parse stored job result")
+    }
+
+    async fn list(&self, limit: usize) -> Result<Vec<JobResult>> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut conn = self.conn.clone();
+        let job_ids: Vec<String> = conn
+            .zrevrange(INDEX_KEY, 0, limit as isize - 1)
+            .await
+            .context("Failed to read job result index from Redis")?;
+        if job_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys: Vec<String> = job_ids.iter().map(|id| key(id)).collect();
+        let values: Vec<Option<String>> = conn
+            .mget(keys)
+            .await
+            .context("Failed to read job results from Redis")?;
+
+        // Results expired individually (see `expire`) may still be indexed
+        values
+            .into_iter()
+            .flatten()
+            .map(|v| serde_json::from_str(&v).context("Failed to parse stored job result"))
+            .collect()
+    }
+
+    async fn list_jobs(&self, query: &JobQuery) -> Result<JobPage> {
+        // The index only knows finish times, so filters are applied to the
+        // results as they're read, a chunk at a time. A request's jobs have
+        // an index of their own.
+        let index = match &query.request_id {
+            Some(request_id) => request_index_key(request_id),
+            None => INDEX_KEY.to_string(),
+        };
+        let max_score = match &query.after {
+            Some(after) => after.finished_at.timestamp_millis().to_string(),
+            None => "+inf".to_string(),
+        };
+        let mut conn = self.conn.clone();
+        let mut results = Vec::new();
+        let mut offset = 0;
+        while results.len() <= query.limit {
+            let job_ids: Vec<String> = conn
+                .zrevrangebyscore_limit(&index, &max_score, "-inf", offset, LIST_SCAN_CHUNK)
+                .await
+                .context("Failed to read job result index from Redis")?;
+            if job_ids.is_empty() {
+                break;
+            }
+            offset += job_ids.len() as isize;
+
+            let keys: Vec<String> = job_ids.iter().map(|id| key(id)).collect();
+            let values: Vec<Option<String>> = conn
+                .mget(keys)
+                .await
+                .context("Failed to read job results from Redis")?;
+            for value in values.into_iter().flatten() {
+                let result: JobResult =
+                    serde_json::from_str(&value).context("Failed to parse stored job result")?;
+                if query.matches(&result) {
+                    results.push(result);
+                }
+            }
+        }
+
+        // Index scores are in ms, so order within the same ms by the full key
+        results.sort_by_key(|result| std::cmp::Reverse(result.cursor()));
+        results.truncate(query.limit + 1);
+        Ok(JobPage::from_results(results, query.limit))
+    }
+
+    async fn expire(&self, job_id: &str, ttl: Duration) -> Result<()> {
+        let mut conn = self.conn.clone();
+        conn.expire::<_, ()>(key(job_id), ttl.as_secs() as i64)
+            .await
+            .context("Failed to set job result expiry in Redis")?;
+        Ok(())
+    }
+
+    async fn record_started(&self, job_id: &str, _job_type: &str) -> Result<()> {
+        let mut conn = self.conn.clone();
+        conn.set_ex::<_, _, ()>(
+            status_key(job_id),
+            JobStatus::Running.as_str(),
+            self.ttl.as_secs(),
+        )
+        .await
+        .context("Failed to write job status to Redis")?;
+        Ok(())
+    }
+
+    async fn status(&self, job_id: &str) -> Result<Option<JobStatus>> {
+        if let Some(result) = self.get(job_id).await? {
+            return Ok(Some(result.status));
+        }
+
+        let mut conn = self.conn.clone();
+        let status: Option<String> = conn
+            .get(status_key(job_id))
+            .await
+            .context("Failed to read job status from Redis")?;
+        status.map(|s| s.parse()).transpose()
+    }
+
+    async fn set_progress(&self, job_id: &str, progress: &JobProgress) -> Result<()> {
+        let value = serde_json::to_string(progress).context("Failed to serialize job progress")?;
+        let mut conn = self.conn.clone();
+        conn.set_ex::<_, _, ()>(progress_key(job_id), value, self.ttl.as_secs())
+            .await
+            .context("Failed to write job progress to Redis")?;
+        Ok(())
+    }
+
+    async fn progress(&self, job_id: &str) -> Result<Option<JobProgress>> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn
+            .get(progress_key(job_id))
+            .await
+            .context("Failed to read job progress from Redis")?;
+        value
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("Failed to deserialize job progress")
+    }
+
+    async fn record_failure(&self, job_id: &str, failure: &JobFailure) -> Result<()> {
+        let value = serde_json::to_string(failure).context("Failed to serialize job failure")?;
+        let key = failures_key(job_id);
+        let mut conn = self.conn.clone();
+        redis::pipe()
+            .rpush(&key, value)
+            .ignore()
+            .ltrim(&key, -(MAX_FAILURES_KEPT as isize), -1)
+            .ignore()
+            .expire(&key, FAILURE_HISTORY_TTL.as_secs() as i64)
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await
+            .context("Failed to write job failure to Redis")?;
+        Ok(())
+    }
+
+    async fn failures(&self, job_id: &str) -> Result<Vec<JobFailure>> {
+        let mut conn = self.conn.clone();
+        let values: Vec<String> = conn
+            .lrange(failures_key(job_id), 0, -1)
+            .await
+            .context("Failed to read job failures from Redis")?;
+        values
+            .iter()
+            .map(|v| serde_json::from_str(v).context("Failed to deserialize job failure"))
+            .collect()
+    }
+
+    async fn add_payload_failure(&self, hash: &str, window: Duration) -> Result<u64> {
+        let key = payload_failures_key(hash);
+        let mut conn = self.conn.clone();
+        // The window is set by the first failure only
+        let (failures,): (u64,) = redis::pipe()
+            .set_options(
+                &key,
+                0,
+                SetOptions::default()
+                    .conditional_set(ExistenceCheck::NX)
+                    .with_expiration(SetExpiry::PX(window.as_millis().max(1) as u64)),
+            )
+            .ignore()
+            .incr(&key, 1)
+            .query_async(&mut conn)
+            .await
+            .context("Failed to count payload failure in Redis")?;
+        Ok(failures)
+    }
+
+    async fn payload_failures(&self, hash: &str) -> Result<u64> {
+        let mut conn = self.conn.clone();
+        let failures: Option<u64> = conn
+            .get(payload_failures_key(hash))
+            .await
+            .context("Failed to read payload failures from Redis")?;
+        Ok(failures.unwrap_or(0))
+    }
+
+    async fn add_usage(&self, key: &str, jobs: i64) -> Result<Usage> {
+        let periods = UsagePeriods::current();
+        let day_key = usage_key(key, &periods.day);
+        let month_key = usage_key(key, &periods.month);
+
+        let mut conn = self.conn.clone();
+        let (daily_jobs, monthly_jobs): (i64, i64) = redis::pipe()
+            .incr(&day_key, jobs)
+            .expire(&day_key, DAILY_USAGE_TTL_SECS)
+            .ignore()
+            .incr(&month_key, jobs)
+            .expire(&month_key, MONTHLY_USAGE_TTL_SECS)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .context("Failed to update usage counters in Redis")?;
+        Ok(Usage::new(key, periods, daily_jobs, monthly_jobs))
+    }
+
+    async fn usage(&self, key: &str) -> Result<Usage> {
+        let periods = UsagePeriods::current();
+
+        let mut conn = self.conn.clone();
+        let (daily_jobs, monthly_jobs): (Option<i64>, Option<i64>) = conn
+            .mget(&[usage_key(key, &periods.day), usage_key(key, &periods.month)])
+            .await
+            .context("Failed to read usage counters from Redis")?;
+        Ok(Usage::new(
+            key,
+            periods,
+            daily_jobs.unwrap_or(0),
+            monthly_jobs.unwrap_or(0),
+        ))
+    }
+
+    async fn save_schedule(&self, schedule: &Schedule) -> Result<()> {
+        let value = serde_json::to_string(&Schedule {
+            last_run_at: None,
+            ..schedule.clone()
+        })
+        .context("Failed to serialize schedule")?;
+
+        let mut conn = self.conn.clone();
+        conn.hset::<_, _, _, ()>(SCHEDULES_KEY, &schedule.id, value)
+            .await
+            .context("Failed to write schedule to Redis")?;
+        Ok(())
+    }
+
+    async fn get_schedule(&self, id: &str) -> Result<Option<Schedule>> {
+        let mut conn = self.conn.clone();
+        let (value, last_run_ms): (Option<String>, Option<i64>) = redis::pipe()
+            .hget(SCHEDULES_KEY, id)
+            .hget(SCHEDULE_RUNS_KEY, id)
+            .query_async(&mut conn)
+            .await
+            .context("Failed to read schedule from Redis")?;
+
+        value.map(|v| parse_schedule(&v, last_run_ms)).transpose()
+    }
+
+    async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let mut conn = self.conn.clone();
+        let (values, runs): (HashMap<String, String>, HashMap<String, i64>) = redis::pipe()
+            .hgetall(SCHEDULES_KEY)
+            .hgetall(SCHEDULE_RUNS_KEY)
+            .query_async(&mut conn)
+            .await
+            .context("Failed to read schedules from Redis")?;
+
+        let mut schedules = values
+            .iter()
+            .map(|(id, value)| parse_schedule(value, runs.get(id).copied()))
+            .collect::<Result<Vec<_>>>()?;
+        schedules.sort_by_key(|schedule| schedule.created_at);
+        Ok(schedules)
+    }
+
+    async fn delete_schedule(&self, id: &str) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        let (deleted,): (u64,) = redis::pipe()
+            .hdel(SCHEDULES_KEY, id)
+            .hdel(SCHEDULE_RUNS_KEY, id)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .context("Failed to delete schedule from Redis")?;
+        Ok(deleted > 0)
+    }
+
+    async fn claim_schedule_run(&self, id: &str, run_at: DateTime<Utc>) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        let claimed: i64 = redis::Script::new(CLAIM_RUN_SCRIPT)
+            .key(SCHEDULE_RUNS_KEY)
+            .arg(id)
+            .arg(run_at.timestamp_millis())
+            .invoke_async(&mut conn)
+            .await
+            .context("Failed to claim schedule run in Redis")?;
+        Ok(claimed == 1)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        key: &str,
+        value: &str,
+        ttl: Duration,
+    ) -> Result<Option<String>> {
+        let key = idempotency_key(key);
+        let mut conn = self.conn.clone();
+        let claimed: Option<String> = redis::cmd("SET")
+            .arg(&key)
+            .arg(value)
+            .arg("NX")
+            .arg("EX")
+            .arg(ttl.as_secs().max(1))
+            .query_async(&mut conn)
+            .await
+            .context("Failed to claim idempotency key in Redis")?;
+        if claimed.is_some() {
+            return Ok(None);
+        }
+
+        conn.get(&key)
+            .await
+            .context("Failed to read idempotency key from Redis")
+    }
+
+    async fn update_idempotency_key(&self, key: &str, value: &str) -> Result<()> {
+        let mut conn = self.conn.clone();
+        redis::cmd("SET")
+            .arg(idempotency_key(key))
+            .arg(value)
+            .arg("XX")
+            .arg("KEEPTTL")
+            .query_async::<Option<String>>(&mut conn)
+            .await
+            .context("Failed to update idempotency key in Redis")?;
+        Ok(())
+    }
+
+    async fn extend_idempotency_key(&self, key: &str, ttl: Duration) -> Result<()> {
+        let mut conn = self.conn.clone();
+        conn.expire::<_, ()>(idempotency_key(key), ttl.as_secs() as i64)
+            .await
+            .context("Failed to extend idempotency key in Redis")?;
+        Ok(())
+    }
+
+    async fn release_idempotency_key(&self, key: &str) -> Result<()> {
+        let mut conn = self.conn.clone();
+        conn.del::<_, ()>(idempotency_key(key))
+            .await
+            .context("Failed to release idempotency key in Redis")?;
+        Ok(())
+    }
+
+    async fn save_dead_job(&self, job: &DeadJob) -> Result<()> {
+        let value = serde_json::to_string(job).context("Failed to serialize dead job")?;
+        let mut conn = self.conn.clone();
+        redis::pipe()
+            .hset(DEAD_JOBS_KEY, &job.job_id, value)
+            .ignore()
+            .zadd(
+                DEAD_INDEX_KEY,
+                &job.job_id,
+                job.failed_at.timestamp_millis(),
+            )
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await
+            .context("Failed to write dead job to Redis")?;
+        Ok(())
+    }
+
+    async fn get_dead_job(&self, job_id: &str) -> Result<Option<DeadJob>> {
+        let mut conn = self.conn.clone();
+        let value: Option<String> = conn
+            .hget(DEAD_JOBS_KEY, job_id)
+            .await
+            .context("Failed to read dead job from Redis")?;
+        value
+            .map(|v| serde_json::from_str(&v))
+            .transpose()
+            .context("Failed to parse stored dead job")
+    }
+
+    async fn list_dead_jobs(&self, limit: usize) -> Result<Vec<DeadJob>> {
+        if limit == 0 {
+            return Ok(vec![]);
+        }
+
+        let mut conn = self.conn.clone();
+        let job_ids: Vec<String> = conn
+            .zrevrange(DEAD_INDEX_KEY, 0, limit as isize - 1)
+            .await
+            .context("Failed to read dead job index from Redis")?;
+        if job_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let values: Vec<Option<String>> = redis::cmd("HMGET")
+            .arg(DEAD_JOBS_KEY)
+            .arg(&job_ids)
+            .query_async(&mut conn)
+            .await
+            .context("Failed to read dead jobs from Redis")?;
+        values
+            .into_iter()
+            .flatten()
+            .map(|v| serde_json::from_str(&v).context("Failed to parse stored dead job"))
+            .collect()
+    }
+
+    async fn delete_dead_job(&self, job_id: &str) -> Result<bool> {
+        let mut conn = self.conn.clone();
+        let (removed,): (u64,) = redis::pipe()
+            .hdel(DEAD_JOBS_KEY, job_id)
+            .zrem(DEAD_INDEX_KEY, job_id)
+            .ignore()
+            .query_async(&mut conn)
+            .await
+            .context("Failed to delete dead job from Redis")?;
+        Ok(removed > 0)
+    }
+
+    async fn save_heartbeat(&self, heartbeat: &WorkerHeartbeat, ttl: Duration) -> Result<()> {
+        let value = serde_json::to_string(heartbeat).context("Failed to serialize heartbeat")?;
+        let ttl = ttl.max(Duration::from_secs(1));
+        let expires_at = Utc::now().timestamp_millis() + ttl.as_millis() as i64;
+        let mut conn = self.conn.clone();
+        redis::pipe()
+            .set_ex(heartbeat_key(&heartbeat.worker_id), value, ttl.as_secs())
+            .ignore()
+            .zadd(WORKERS_INDEX_KEY, &heartbeat.worker_id, expires_at)
+            .ignore()
+            .query_async::<()>(&mut conn)
+            .await
+            .context("Failed to write heartbeat to Redis")?;
+        Ok(())
+    }
+
+    async fn list_workers(&self) -> Result<Vec<WorkerHeartbeat>> {
+        let mut conn = self.conn.clone();
+        // Forget workers whose last heartbeat has expired
+        let (worker_ids,): (Vec<String>,) = redis::pipe()
+            .zrembyscore(WORKERS_INDEX_KEY, "-inf", Utc::now().timestamp_millis())
+            .ignore()
+            .zrange(WORKERS_INDEX_KEY, 0, -1)
+            .query_async(&mut conn)
+            .await
+            .context("Failed to read worker index from Redis")?;
+        if worker_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let keys: Vec<String> = worker_ids.iter().map(|id| heartbeat_key(id)).collect();
+        let values: Vec<Option<String>> = redis::cmd("MGET")
+            .arg(&keys)
+            .query_async(&mut conn)
+            .await
+            .context("Failed to read heartbeats from Redis")?;
+        let mut workers = values
+            .into_iter()
+            .flatten()
+            .map(|v| serde_json::from_str(&v).context("Failed to parse stored heartbeat"))
+            .collect::<Result<Vec<WorkerHeartbeat>>>()?;
+        workers.sort_by(|a, b| a.worker_id.cmp(&b.worker_id));
+        Ok(workers)
+    }
+}
+
+fn key(job_id: &str) -> String {
+    format!("{}{}", KEY_PREFIX, job_id)
+}
+
+fn request_index_key(request_id: &str) -> String {
+    format!("{}{}", REQUEST_INDEX_KEY_PREFIX, request_id)
+}
+
+fn status_key(job_id: &str) -> String {
+    format!("{}{}", STATUS_KEY_PREFIX, job_id)
+}
+
+fn progress_key(job_id: &str) -> String {
+    format!("{}{}", PROGRESS_KEY_PREFIX, job_id)
+}
+
+fn failures_key(job_id: &str) -> String {
+    format!("{}{}", FAILURES_KEY_PREFIX, job_id)
+}
+
+fn payload_failures_key(hash: &str) -> String {
+    format!("{}{}", PAYLOAD_FAILURES_KEY_PREFIX, hash)
+}
+
+fn usage_key(key: &str, period: &str) -> String {
+    format!("{}{}:{}", USAGE_KEY_PREFIX, key, period)
+}
+
+fn idempotency_key(key: &str) -> String {
+    format!("{}{}", IDEMPOTENCY_KEY_PREFIX, key)
+}
+
+fn heartbeat_key(worker_id: &str) -> String {
+    format!("{}{}", HEARTBEAT_KEY_PREFIX, worker_id)
+}
+
+fn parse_schedule(value: &str, last_run_ms: Option<i64>) -> Result<Schedule> {
+    let mut schedule: Schedule =
+        serde_json::from_str(value).context("Failed to parse stored schedule")?;
+    schedule.last_run_at = last_run_ms.and_then(DateTime::from_timestamp_millis);
+    Ok(schedule)
+}
@@ -0,0 +1,279 @@
+//! Connections to the Faktory server, with optional password and TLS.
+//!
+//! `FAKTORY_URL` is `tcp://host:port`, or `tcp+tls://host:port` for a server
+//! behind TLS. The server password comes from `FAKTORY_PASSWORD`, or from the
+//! URL itself (`tcp://:password@host:port`). TLS connections trust the PEM CA
+//! certificates in `FAKTORY_TLS_CA_PATH`, which `tcp+tls://` requires since
+//! no system roots are bundled.
+//!
+//! faktory's TLS stream takes a client config from the rustls it was built
+//! against (0.22), not the one the listeners in this crate use, so the config
+//! here comes from `faktory_rustls`.
+
+use anyhow::{anyhow, bail, Context, Result};
+use faktory::rustls::TlsStream;
+use faktory::{Client, Worker, WorkerBuilder};
+use faktory_rustls::pki_types::pem::PemObject;
+use faktory_rustls::pki_types::CertificateDer;
+use faktory_rustls::{ClientConfig, RootCertStore};
+use service_config::Config;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::BufStream;
+use url::Url;
+
+const DEFAULT_URL: &str = "tcp://localhost:7419";
+
+/// How to reach the Faktory server
+#[derive(Clone)]
+pub struct FaktoryConfig {
+    /// Server URL, without the password
+    url: Url,
+    password: Option<String>,
+    /// Client settings for `tcp+tls://`
+    tls: Option<Arc<ClientConfig>>,
+}
+
+impl FaktoryConfig {
+    /// Read `FAKTORY_URL` (default `tcp://localhost:7419`), `FAKTORY_PASSWORD`
+    /// and `FAKTORY_TLS_CA_PATH`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let url = config.string_or("FAKTORY_URL", DEFAULT_URL);
+        Self::from_url(config, "FAKTORY", &url)
+    }
+
+    /// Read a second server's settings from `{prefix}_URL`,
+    /// `{prefix}_PASSWORD` and `{prefix}_TLS_CA_PATH`, if `{prefix}_URL` is
+    /// set
+    pub fn from_config_prefixed(config: &Config, prefix: &str) -> Result<Option<Self>> {
+        config
+            .string(&format!("{}_URL", prefix))
+            .map(|url| Self::from_url(config, prefix, &url))
+            .transpose()
+    }
+
+    fn from_url(config: &Config, prefix: &str, url: &str) -> Result<Self> {
+        // The URL may carry the password, so it isn't echoed in errors
+        let mut url = Url::parse(url).map_err(|e| anyhow!("Invalid {}_URL: {}", prefix, e))?;
+        let password = config
+            .secret(&format!("{}_PASSWORD", prefix))
+            .or_else(|| url.password().map(String::from));
+        url.set_password(None)
+            .map_err(|_| anyhow!("{}_URL must name a host", prefix))?;
+
+        let tls = match url.scheme() {
+            "tcp" => None,
+            "tcp+tls" => {
+                let ca_path = config
+                    .string(&format!("{}_TLS_CA_PATH", prefix))
+                    .with_context(|| {
+                        format!(
+                            "{0}_TLS_CA_PATH is required for a tcp+tls:// {0}_URL",
+                            prefix
+                        )
+                    })?;
+                Some(client_config(Path::new(&ca_path))?)
+            }
+            scheme => bail!(
+                "{}_URL must start with tcp:// or tcp+tls://, not {}://",
+                prefix,
+                scheme
+            ),
+        };
+
+        Ok(Self { url, password, tls })
+    }
+
+    /// Server URL without the password, for logging
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Open a producer connection
+    pub async fn client(&self) -> Result<Client, faktory::Error> {
+        match &self.tls {
+            None => Client::connect_to(self.url_with_password().as_str()).await,
+            Some(tls) => {
+                let stream = self.tls_stream(tls).await?;
+                Client::connect_with(stream, self.password.clone()).await
+            }
+        }
+    }
+
+    /// Connect a worker built by `builder`
+    pub async fn worker<E: 'static>(
+        &self,
+        builder: WorkerBuilder<E>,
+    ) -> Result<Worker<E>, faktory::Error> {
+        match &self.tls {
+            None => builder.connect_to(self.url_with_password().as_str()).await,
+            Some(tls) => {
+                let stream = self.tls_stream(tls).await?;
+                builder.connect_with(stream, self.password.clone()).await
+            }
+        }
+    }
+
+    fn url_with_password(&self) -> Url {
+        let mut url = self.url.clone();
+        if let Some(password) = &self.password {
+            // Can't fail: `from_config` checked the URL has a host
+            let _ = url.set_password(Some(password));
+        }
+        url
+    }
+
+    async fn tls_stream(
+        &self,
+        tls: &ClientConfig,
+    ) -> Result<BufStream<TlsStream<tokio::net::TcpStream>>, faktory::Error> {
+        // faktory only parses tcp:// URLs; TLS is decided by the stream
+        let mut url = self.url.clone();
+        // Can't fail: both schemes are non-special
+        let _ = url.set_scheme("tcp");
+        let stream = TlsStream::with_client_config(tls.clone(), Some(url.as_str())).await?;
+        Ok(BufStream::new(stream))
+    }
+}
+
+/// TLS client settings trusting only the CA certificates in `ca_path`
+fn client_config(ca_path: &Path) -> Result<Arc<ClientConfig>> {
+    let certs = CertificateDer::pem_file_iter(ca_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read CA certificates from {}", ca_path.display()))?;
+    let mut roots = RootCertStore::empty();
+    for cert in certs {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA certificate in {}", ca_path.display()))?;
+    }
+    if roots.is_empty() {
+        bail!("No CA certificates found in {}", ca_path.display());
+    }
+
+    let config = ClientConfig::builder_with_provider(Arc::new(
+        faktory_rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    Ok(Arc::new(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn config(dir: &Path, contents: &str) -> Config {
+        let path = dir.join("faktory.toml");
+        std::fs::write(&path, contents).unwrap();
+        Config::from_file(path).unwrap()
+    }
+
+    #[test]
+    fn test_password_and_tls() {
+        let dir = std::env::temp_dir().join(format!("service-tls-faktory-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let faktory = FaktoryConfig::from_config(&config(
+            &dir,
+            "FAKTORY_URL = \"tcp://:hunter2@faktory:7419\"\n",
+        ))
+        .unwrap();
+        assert_eq!(faktory.url(), "tcp://faktory:7419");
+        assert_eq!(faktory.password.as_deref(), Some("hunter2"));
+        assert_eq!(
+            faktory.url_with_password().as_str(),
+            "tcp://:hunter2@faktory:7419"
+        );
+        assert!(!faktory.is_tls());
+
+        let error =
+            FaktoryConfig::from_config(&config(&dir, "FAKTORY_URL = \"tcp+tls://faktory:7419\"\n"))
+                .err()
+                .unwrap();
+        assert!(error.to_string().contains("FAKTORY_TLS_CA_PATH"));
+
+        let cert = rcgen::generate_simple_self_signed(vec!["faktory".to_string()]).unwrap();
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, cert.cert.pem()).unwrap();
+        let faktory = FaktoryConfig::from_config(&config(
+            &dir,
+            &format!(
+                "FAKTORY_URL = \"tcp+tls://faktory:7419\"\nFAKTORY_PASSWORD = \"s3cret\"\nFAKTORY_TLS_CA_PATH = {:?}\n",
+                ca_path.display().to_string()
+            ),
+        ))
+        .unwrap();
+        assert!(faktory.is_tls());
+        assert_eq!(faktory.password.as_deref(), Some("s3cret"));
+
+        let shadow = config(&dir, "SHADOW_FAKTORY_URL = \"tcp://:pw@shadow:7419\"\n");
+        assert!(
+            FaktoryConfig::from_config_prefixed(&shadow, "OTHER_FAKTORY")
+                .unwrap()
+                .is_none()
+        );
+        let faktory = FaktoryConfig::from_config_prefixed(&shadow, "SHADOW_FAKTORY")
+            .unwrap()
+            .unwrap();
+        assert_eq!(faktory.url(), "tcp://shadow:7419");
+        assert_eq!(faktory.password.as_deref(), Some("pw"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake() {
+        let dir = std::env::temp_dir().join(format!(
+            "service-tls-faktory-handshake-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+        let server = crate::TlsConfig {
+            cert_path: cert_path.clone(),
+            key_path,
+            reload_interval: None,
+        };
+        let mut listener = crate::bind("127.0.0.1:0", Some(server)).await.unwrap();
+        let addr = axum::serve::Listener::local_addr(&listener).unwrap();
+
+        let faktory = FaktoryConfig::from_config(&config(
+            &dir,
+            &format!(
+                "FAKTORY_URL = \"tcp+tls://localhost:{}\"\nFAKTORY_TLS_CA_PATH = {:?}\n",
+                addr.port(),
+                cert_path.display().to_string()
+            ),
+        ))
+        .unwrap();
+        let client = tokio::spawn(async move {
+            let tls = faktory.tls.clone().unwrap();
+            let mut stream = faktory.tls_stream(&tls).await.unwrap();
+            stream.write_all(b"ping").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let (io, _) = axum::serve::Listener::accept(&mut listener).await;
+        let tokio_util::either::Either::Right(mut stream) = io else {
+            panic!("Expected a TLS stream");
+        };
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        client.await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Plain TCP or TLS listeners for the HTTP services.
+//!
+//! With `TLS_CERT_PATH` and `TLS_KEY_PATH` set, `bind` terminates TLS itself
+//! (rustls), so simple deployments don't need a reverse proxy. Handshakes run
+//! in the background so a slow client can't hold up other connections. When
+//! `TLS_RELOAD_INTERVAL_SECS` is set, the certificate and key files are
+//! checked that often and reloaded when they change, which picks up renewals
+//! without a restart.
+//!
+//! The [`faktory`] module makes client connections to the Faktory server,
+//! over TLS when its URL asks for it.
+
+pub mod faktory;
+
+use anyhow::{bail, Context, Result};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use service_config::Config;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::either::Either;
+use tracing::{info, warn};
+
+/// How long a client gets to finish the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Completed handshakes waiting for the server to pick them up
+const ACCEPT_BACKLOG: usize = 128;
+
+/// TLS certificate settings
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: PathBuf,
+    /// How often to check the files for a renewed certificate
+    pub reload_interval: Option<Duration>,
+}
+
+impl TlsConfig {
+    /// Read `TLS_CERT_PATH`, `TLS_KEY_PATH` and `TLS_RELOAD_INTERVAL_SECS`
+    /// (0, the default, disables reloading). Returns `None` when neither path
+    /// is set.
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let cert_path = config.string("TLS_CERT_PATH");
+        let key_path = config.string("TLS_KEY_PATH");
+        let reload_secs: u64 = config.parse_or("TLS_RELOAD_INTERVAL_SECS", 0);
+        match (cert_path, key_path) {
+            (None, None) => Ok(None),
+            (Some(cert_path), Some(key_path)) => Ok(Some(Self {
+                cert_path: cert_path.into(),
+                key_path: key_path.into(),
+                reload_interval: (reload_secs > 0).then(|| Duration::from_secs(reload_secs)),
+            })),
+            _ => bail!("TLS_CERT_PATH and TLS_KEY_PATH must be set together"),
+        }
+    }
+
+    fn load(&self) -> Result<Arc<ServerConfig>> {
+        let certs = CertificateDer::pem_file_iter(&self.cert_path)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .with_context(|| {
+                format!(
+                    "Failed to read certificates from {}",
+                    self.cert_path.display()
+                )
+            })?;
+        if certs.is_empty() {
+            bail!("No certificates found in {}", self.cert_path.display());
+        }
+        let key = PrivateKeyDer::from_pem_file(&self.key_path).with_context(|| {
+            format!(
+                "Failed to read private key from {}",
+                self.key_path.display()
+            )
+        })?;
+
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_single_cert(certs, key)
+                .context("Certificate and private key don't match")?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Ok(Arc::new(config))
+    }
+
+    /// When the certificate or key file last changed
+    fn modified(&self) -> Option<SystemTime> {
+        let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        modified(&self.cert_path).max(modified(&self.key_path))
+    }
+}
+
+/// A bound listener for `axum::serve`, with or without TLS
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TlsListener),
+}
+
+/// Bind `addr`, terminating TLS when `tls` is set
+pub async fn bind(addr: &str, tls: Option<TlsConfig>) -> Result<Listener> {
+    let tcp = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind {}", addr))?;
+    Ok(match tls {
+        Some(tls) => Listener::Tls(TlsListener::new(tcp, tls)?),
+        None => Listener::Tcp(tcp),
+    })
+}
+
+impl axum::serve::Listener for Listener {
+    type Io = Either<TcpStream, TlsStream<TcpStream>>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self {
+            Listener::Tcp(tcp) => {
+                let (stream, addr) = axum::serve::Listener::accept(tcp).await;
+                (Either::Left(stream), addr)
+            }
+            Listener::Tls(tls) => {
+                let (stream, addr) = tls.accept().await;
+                (Either::Right(stream), addr)
+            }
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        match self {
+            Listener::Tcp(tcp) => tcp.local_addr(),
+            Listener::Tls(tls) => Ok(tls.local_addr),
+        }
+    }
+}
+
+/// Accepts TCP connections and hands out the ones that complete a TLS
+/// handshake
+pub struct TlsListener {
+    handshaken: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    /// Start accepting on `tcp`. Fails if the certificate can't be loaded.
+    pub fn new(tcp: TcpListener, config: TlsConfig) -> Result<Self> {
+        let local_addr = tcp.local_addr()?;
+        let server_config = Arc::new(RwLock::new(config.load()?));
+        info!(
+            "TLS enabled with certificate {}",
+            config.cert_path.display()
+        );
+
+        let (tx, handshaken) = mpsc::channel(ACCEPT_BACKLOG);
+        if let Some(interval) = config.reload_interval {
+            tokio::spawn(reload(
+                config.clone(),
+                server_config.clone(),
+                interval,
+                tx.clone(),
+            ));
+        }
+        tokio::spawn(accept_loop(tcp, server_config, tx));
+
+        Ok(Self {
+            handshaken,
+            local_addr,
+        })
+    }
+
+    async fn accept(&mut self) -> (TlsStream<TcpStream>, SocketAddr) {
+        match self.handshaken.recv().await {
+            Some(accepted) => accepted,
+            // The accept loop only stops once this listener is dropped
+            None => std::future::pending().await,
+        }
+    }
+}
+
+async fn accept_loop(
+    tcp: TcpListener,
+    server_config: Arc<RwLock<Arc<ServerConfig>>>,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    while !tx.is_closed() {
+        let (stream, addr) = match tcp.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // Usually running out of file descriptors; back off like
+                // axum's own listener does
+                warn!("Failed to accept connection: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let acceptor = TlsAcceptor::from(server_config.read().unwrap().clone());
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => {
+                    let _ = tx.send((stream, addr)).await;
+                }
+                Ok(Err(e)) => warn!("TLS handshake with {} failed: {}", addr, e),
+                Err(_) => warn!("TLS handshake with {} timed out", addr),
+            }
+        });
+    }
+}
+
+/// Swap in the certificate whenever its files change
+async fn reload(
+    config: TlsConfig,
+    server_config: Arc<RwLock<Arc<ServerConfig>>>,
+    interval: Duration,
+    tx: mpsc::Sender<(TlsStream<TcpStream>, SocketAddr)>,
+) {
+    let mut loaded_at = config.modified();
+    while !tx.is_closed() {
+        tokio::time::sleep(interval).await;
+        let modified = config.modified();
+        if modified == loaded_at {
+            continue;
+        }
+        match config.load() {
+            Ok(reloaded) => {
+                *server_config.write().unwrap() = reloaded;
+                loaded_at = modified;
+                info!("Reloaded TLS certificate {}", config.cert_path.display());
+            }
+            // Likely caught mid-renewal; keep serving the old certificate
+            // and try again next time
+            Err(e) => warn!("Failed to reload TLS certificate: {:#}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, RootCertStore};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn write_cert(dir: &Path) -> (TlsConfig, CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+        let config = TlsConfig {
+            cert_path,
+            key_path,
+            reload_interval: None,
+        };
+        (config, cert.cert.der().clone())
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = Config::default();
+        assert!(TlsConfig::from_config(&config).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshake() {
+        let dir = std::env::temp_dir().join(format!("service-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (config, cert) = write_cert(&dir);
+
+        let mut listener = bind("127.0.0.1:0", Some(config)).await.unwrap();
+        let addr = axum::serve::Listener::local_addr(&listener).unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client_config =
+            ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+
+        let client = tokio::spawn(async move {
+            let tcp = TcpStream::connect(addr).await.unwrap();
+            let name = ServerName::try_from("localhost").unwrap();
+            let mut stream = connector.connect(name, tcp).await.unwrap();
+            stream.write_all(b"ping").await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let (io, _) = axum::serve::Listener::accept(&mut listener).await;
+        let Either::Right(mut stream) = io else {
+            panic!("Expected a TLS stream");
+        };
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        client.await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,41 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::process::Command;
+
+fn main() {
+    build_info();
+}
+
+/// Embed the commit and build time reported by `GET /version`. `GIT_SHA`
+/// overrides the commit for builds without a checkout (e.g. Docker), and
+/// `SOURCE_DATE_EPOCH` the build time for reproducible builds.
+fn build_info() {
+    let git_sha = std::env::var("GIT_SHA")
+        .ok()
+        .filter(|sha| !sha.is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "HEAD"])
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|secs| secs.parse().ok())
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .unwrap_or_else(Utc::now);
+
+    println!("cargo:rustc-env=GIT_SHA={}", git_sha);
+    println!(
+        "cargo:rustc-env=BUILD_TIMESTAMP={}",
+        built_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+    );
+    println!("cargo:rerun-if-env-changed=GIT_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    // Commits and checkouts append to the HEAD log
+    println!("cargo:rerun-if-changed=../../.git/logs/HEAD");
+}
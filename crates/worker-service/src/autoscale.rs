@@ -0,0 +1,268 @@
+//! Concurrency autoscaling.
+//!
+//! With `WORKER_AUTOSCALE_MIN` set, the worker fetches with
+//! `WORKER_AUTOSCALE_MAX` slots but only runs as many jobs at once as its
+//! current target; the rest wait in the worker for a turn. Every
+//! `WORKER_AUTOSCALE_INTERVAL_SECS` the target is adjusted from the depth of
+//! the worker's queues and the average job latency since the last sample:
+//!
+//! - it doubles while every slot is busy and jobs are waiting, so a backlog
+//!   drains at full speed;
+//! - it drops by a quarter while the queues are empty and less than half the
+//!   slots were used, so an idle worker doesn't hold connections it has no
+//!   use for;
+//! - it drops by a quarter when latency climbs past twice its recent low,
+//!   since more concurrency against a saturated dependency only makes each
+//!   job slower.
+//!
+//! `worker_concurrency_target` reports the current target.
+
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use faktory::Job;
+use service_config::Config;
+use service_tls::faktory::FaktoryConfig;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// Latency above this multiple of the baseline counts as a slowdown
+const SLOWDOWN_FACTOR: f64 = 2.0;
+
+/// How far the baseline moves towards a higher latency each sample, so a
+/// slower mix of jobs becomes the new normal instead of a permanent slowdown
+const BASELINE_DRIFT: f64 = 0.1;
+
+/// Bounds and sampling interval for the concurrency target
+#[derive(Debug, Clone)]
+pub struct AutoscaleConfig {
+    min: usize,
+    max: usize,
+    interval: Duration,
+}
+
+impl AutoscaleConfig {
+    /// Read `WORKER_AUTOSCALE_MIN` (unset disables autoscaling),
+    /// `WORKER_AUTOSCALE_MAX` (default `concurrency`, i.e.
+    /// `WORKER_CONCURRENCY`) and `WORKER_AUTOSCALE_INTERVAL_SECS` (default 10)
+    pub fn from_config(config: &Config, concurrency: usize) -> Result<Option<Self>> {
+        let min = config.parse::<usize>("WORKER_AUTOSCALE_MIN");
+        let max = config.parse_or("WORKER_AUTOSCALE_MAX", concurrency);
+        let interval_secs = config.parse_or("WORKER_AUTOSCALE_INTERVAL_SECS", 10u64);
+        let Some(min) = min else {
+            return Ok(None);
+        };
+        if min == 0 {
+            bail!("WORKER_AUTOSCALE_MIN must be above 0");
+        }
+        if max < min {
+            bail!(
+                "WORKER_AUTOSCALE_MAX ({}) must be at least WORKER_AUTOSCALE_MIN ({})",
+                max,
+                min
+            );
+        }
+        if interval_secs == 0 {
+            bail!("WORKER_AUTOSCALE_INTERVAL_SECS must be above 0");
+        }
+        Ok(Some(Self {
+            min,
+            max,
+            interval: Duration::from_secs(interval_secs),
+        }))
+    }
+
+    /// Slots to fetch with
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+/// The target and what it takes to get there
+struct Scale {
+    target: usize,
+    /// Permits still to be taken out of circulation after scaling down; they
+    /// come back as the jobs holding them finish
+    debt: usize,
+    /// Recent low of the average job latency, in milliseconds
+    baseline_ms: Option<f64>,
+}
+
+/// Limits running jobs to a target it adjusts over time
+pub struct Autoscaler {
+    config: AutoscaleConfig,
+    permits: Semaphore,
+    scale: Mutex<Scale>,
+    metrics: Arc<Metrics>,
+    /// Jobs waiting for a permit
+    waiting: AtomicUsize,
+    running: AtomicUsize,
+    /// Most jobs running at once since the last sample
+    peak: AtomicUsize,
+    /// Jobs finished and the time they took since the last sample
+    completed: AtomicU64,
+    busy_ms: AtomicU64,
+}
+
+impl Autoscaler {
+    /// Start at the minimum; a backlog is reached in a few doublings
+    pub fn new(config: AutoscaleConfig, metrics: Arc<Metrics>) -> Self {
+        metrics.concurrency_target(config.min);
+        Self {
+            permits: Semaphore::new(config.min),
+            scale: Mutex::new(Scale {
+                target: config.min,
+                debt: 0,
+                baseline_ms: None,
+            }),
+            config,
+            metrics,
+            waiting: AtomicUsize::new(0),
+            running: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            busy_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// `min..=max (starting at target)`, for logging
+    pub fn describe(&self) -> String {
+        format!(
+            "{}..={} (starting at {})",
+            self.config.min,
+            self.config.max,
+            self.scale.lock().unwrap().target
+        )
+    }
+
+    /// Adjust the target every interval from the depth of `queues`. Samples
+    /// Faktory can't answer are skipped.
+    pub async fn run(self: Arc<Self>, faktory: FaktoryConfig, queues: Vec<String>) {
+        let mut ticker = tokio::time::interval(self.config.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        // The first tick completes immediately, before there's anything to
+        // sample
+        ticker.tick().await;
+
+        loop {
+            ticker.tick().await;
+            match queue_depth(&faktory, &queues).await {
+                Ok(depth) => self.rescale(depth),
+                Err(e) => warn!("Failed to sample queue depth for autoscaling: {:#}", e),
+            }
+        }
+    }
+
+    fn rescale(&self, depth: u64) {
+        let completed = self.completed.swap(0, Ordering::Relaxed);
+        let busy_ms = self.busy_ms.swap(0, Ordering::Relaxed);
+        let peak = self
+            .peak
+            .swap(self.running.load(Ordering::Relaxed), Ordering::Relaxed);
+        let waiting = self.waiting.load(Ordering::Relaxed);
+        let latency_ms = (completed > 0).then(|| busy_ms as f64 / completed as f64);
+
+        let mut scale = self.scale.lock().unwrap();
+        let slowed = match (latency_ms, scale.baseline_ms) {
+            (Some(latency), Some(baseline)) => latency > baseline * SLOWDOWN_FACTOR,
+            _ => false,
+        };
+        if let Some(latency) = latency_ms {
+            scale.baseline_ms = Some(match scale.baseline_ms {
+                Some(baseline) => latency.min(baseline + (latency - baseline) * BASELINE_DRIFT),
+                None => latency,
+            });
+        }
+
+        let target = scale.target;
+        let next = if slowed {
+            target - target / 4
+        } else if peak >= target && (waiting > 0 || depth > 0) {
+            target * 2
+        } else if depth == 0 && peak < target / 2 {
+            target - target / 4
+        } else {
+            target
+        };
+        let next = next.clamp(self.config.min, self.config.max);
+
+        if next > target {
+            let grow = next - target;
+            let repaid = grow.min(scale.debt);
+            scale.debt -= repaid;
+            self.permits.add_permits(grow - repaid);
+        } else {
+            scale.debt += target - next;
+        }
+        // Permits of running jobs are collected over later samples
+        scale.debt -= self.permits.forget_permits(scale.debt);
+        scale.target = next;
+
+        if next != target {
+            info!(
+                queue_depth = depth,
+                latency_ms = latency_ms.map(|ms| ms.round() as u64),
+                "Scaling concurrency from {} to {}",
+                target,
+                next
+            );
+            self.metrics.concurrency_target(next);
+        }
+    }
+}
+
+/// Jobs waiting in `queues`
+async fn queue_depth(faktory: &FaktoryConfig, queues: &[String]) -> Result<u64> {
+    let mut client = faktory.client().await?;
+    let info = client
+        .current_info()
+        .await
+        .context("Failed to query Faktory")?;
+    Ok(queues
+        .iter()
+        .filter_map(|queue| info.data.queues.get(queue))
+        .sum())
+}
+
+/// Undoes a counter increment when dropped, however the job ends
+struct Counted<'a>(&'a AtomicUsize);
+
+impl<'a> Counted<'a> {
+    fn enter(counter: &'a AtomicUsize) -> Self {
+        counter.fetch_add(1, Ordering::Relaxed);
+        Self(counter)
+    }
+}
+
+impl Drop for Counted<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Middleware for Autoscaler {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let _permit = {
+            let _waiting = Counted::enter(&self.waiting);
+            // The semaphore is never closed
+            self.permits.acquire().await.map_err(io::Error::other)?
+        };
+        let _running = Counted::enter(&self.running);
+        self.peak
+            .fetch_max(self.running.load(Ordering::Relaxed), Ordering::Relaxed);
+
+        let started = Instant::now();
+        let result = next.run(job).await;
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.busy_ms
+            .fetch_add(started.elapsed().as_millis() as u64, Ordering::Relaxed);
+        result
+    }
+}
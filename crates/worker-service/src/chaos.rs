@@ -0,0 +1,91 @@
+//! Fault injection, for testing.
+//!
+//! With `WORKER_CHAOS_ENABLED` set, jobs handled through the job registry,
+//! WASM plugins and commands are randomly sabotaged after running:
+//!
+//! - `WORKER_CHAOS_DELAY_PERCENT` of them are held for up to
+//!   `WORKER_CHAOS_DELAY_MAX_MS` (default 1000), which can trip timeouts;
+//! - `WORKER_CHAOS_PANIC_PERCENT` of them panic;
+//! - `WORKER_CHAOS_FAILURE_PERCENT` of them fail with a retryable error
+//!   instead of their result.
+//!
+//! Injected faults go through the same paths as real ones (results, retries,
+//! dead letters, webhooks), so those can be checked under realistic failure
+//! rates. Never enable it in production.
+
+use anyhow::{bail, Result};
+use service_config::Config;
+use std::io;
+use std::time::Duration;
+use tracing::warn;
+
+/// Faults to inject, each as a percentage of jobs
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    failure_percent: f64,
+    panic_percent: f64,
+    delay_percent: f64,
+    delay_max: Duration,
+}
+
+impl Chaos {
+    /// Read the `WORKER_CHAOS_*` settings; `None` unless
+    /// `WORKER_CHAOS_ENABLED` is set
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let enabled = config.parse_or("WORKER_CHAOS_ENABLED", false);
+        let failure_percent = config.parse_or("WORKER_CHAOS_FAILURE_PERCENT", 0.0);
+        let panic_percent = config.parse_or("WORKER_CHAOS_PANIC_PERCENT", 0.0);
+        let delay_percent = config.parse_or("WORKER_CHAOS_DELAY_PERCENT", 0.0);
+        let delay_max_ms = config.parse_or("WORKER_CHAOS_DELAY_MAX_MS", 1000u64);
+        if !enabled {
+            return Ok(None);
+        }
+        for (key, percent) in [
+            ("WORKER_CHAOS_FAILURE_PERCENT", failure_percent),
+            ("WORKER_CHAOS_PANIC_PERCENT", panic_percent),
+            ("WORKER_CHAOS_DELAY_PERCENT", delay_percent),
+        ] {
+            if !(0.0..=100.0).contains(&percent) {
+                bail!("{} must be between 0 and 100, got {}", key, percent);
+            }
+        }
+        Ok(Some(Self {
+            failure_percent,
+            panic_percent,
+            delay_percent,
+            delay_max: Duration::from_millis(delay_max_ms),
+        }))
+    }
+
+    /// The injected fault rates, for logging
+    pub fn describe(&self) -> String {
+        format!(
+            "{}% failures, {}% panics, {}% delays of up to {}ms",
+            self.failure_percent,
+            self.panic_percent,
+            self.delay_percent,
+            self.delay_max.as_millis()
+        )
+    }
+
+    /// Maybe delay, panic, or replace `result` with a failure
+    pub async fn inject<T>(&self, result: io::Result<T>) -> io::Result<T> {
+        if roll(self.delay_percent) {
+            let delay = self.delay_max.mul_f64(fastrand::f64());
+            warn!("Chaos: delaying job by {}ms", delay.as_millis());
+            tokio::time::sleep(delay).await;
+        }
+        if roll(self.panic_percent) {
+            panic!("Chaos: injected panic");
+        }
+        if roll(self.failure_percent) {
+            warn!("Chaos: failing job");
+            return Err(io::Error::other("Chaos: injected failure"));
+        }
+        result
+    }
+}
+
+fn roll(percent: f64) -> bool {
+    fastrand::f64() * 100.0 < percent
+}
@@ -0,0 +1,67 @@
+//! Per-job-type concurrency limits.
+//!
+//! `WORKER_CONCURRENCY` bounds how many jobs a worker runs at once across all
+//! types. `WORKER_TYPE_CONCURRENCY` additionally caps individual types, e.g.
+//! `webhook_delivery=20` so slow endpoints can't tie up every slot while math
+//! jobs keep the rest. A job over its type's limit waits for a permit in the
+//! worker, still holding the slot it was fetched into.
+
+use crate::middleware::{Middleware, Next};
+use crate::per_type_setting;
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use faktory::Job;
+use service_config::Config;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Permits of the job types with a limit
+#[derive(Default)]
+pub struct ConcurrencyLimits {
+    limits: HashMap<String, Arc<Semaphore>>,
+}
+
+impl ConcurrencyLimits {
+    /// Read `WORKER_TYPE_CONCURRENCY` (`job_type=limit` pairs, e.g.
+    /// `math_divide=10,webhook_delivery=20`; other types are only bounded by
+    /// `WORKER_CONCURRENCY`)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let limits: HashMap<String, usize> = per_type_setting(config, "WORKER_TYPE_CONCURRENCY")?;
+        if let Some(job_type) = limits
+            .iter()
+            .find_map(|(job_type, &limit)| (limit == 0).then_some(job_type))
+        {
+            bail!("WORKER_TYPE_CONCURRENCY for {} must be above 0", job_type);
+        }
+        let limits = limits
+            .into_iter()
+            .map(|(job_type, limit)| (job_type, Arc::new(Semaphore::new(limit))))
+            .collect();
+        Ok(Self { limits })
+    }
+
+    /// The limits as `job_type=limit`, for logging
+    pub fn describe(&self) -> Vec<String> {
+        let mut limits: Vec<String> = self
+            .limits
+            .iter()
+            .map(|(job_type, permits)| format!("{}={}", job_type, permits.available_permits()))
+            .collect();
+        limits.sort_unstable();
+        limits
+    }
+}
+
+#[async_trait]
+impl Middleware for ConcurrencyLimits {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let Some(permits) = self.limits.get(job.kind()) else {
+            return next.run(job).await;
+        };
+        // The semaphore is never closed
+        let _permit = permits.acquire().await.map_err(io::Error::other)?;
+        next.run(job).await
+    }
+}
@@ -0,0 +1,66 @@
+//! What a handler knows about the job it's running, beyond its arguments.
+
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::retry;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use faktory::Job;
+use job_types::REQUEST_ID_FIELD;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Built when a job is fetched and passed down the chain to its handler
+#[derive(Debug, Clone)]
+pub struct JobContext {
+    pub jid: String,
+    pub job_type: String,
+    /// Which attempt this is, counting from 1
+    pub attempt: u32,
+    /// When the job was last pushed to its queue, if Faktory says
+    pub enqueued_at: Option<DateTime<Utc>>,
+    pub fetched_at: DateTime<Utc>,
+    /// The job's custom fields (request ID, trace context, callback URL...)
+    pub metadata: HashMap<String, serde_json::Value>,
+}
+
+impl JobContext {
+    pub fn new(job: &Job) -> Self {
+        Self {
+            jid: job.id().to_string(),
+            job_type: job.kind().to_string(),
+            attempt: retry::attempts(job),
+            enqueued_at: job.enqueued_at,
+            fetched_at: Utc::now(),
+            metadata: job.custom.clone(),
+        }
+    }
+
+    /// How long the job waited in its queue before being fetched
+    pub fn fetch_latency(&self) -> Option<Duration> {
+        // Clocks can disagree; a job can't have waited less than nothing
+        self.enqueued_at
+            .map(|enqueued_at| (self.fetched_at - enqueued_at).to_std().unwrap_or_default())
+    }
+
+    /// ID of the API request that enqueued the job, if it was tagged with one
+    pub fn request_id(&self) -> Option<&str> {
+        self.metadata.get(REQUEST_ID_FIELD).and_then(|v| v.as_str())
+    }
+}
+
+/// Records how long each job waited in its queue in
+/// `worker_job_queue_wait_seconds`
+pub struct QueueWait(pub Arc<Metrics>);
+
+#[async_trait]
+impl Middleware for QueueWait {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        if let Some(latency) = next.context().fetch_latency() {
+            self.0.job_queue_wait(job.kind(), latency);
+        }
+        next.run(job).await
+    }
+}
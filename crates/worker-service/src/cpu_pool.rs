@@ -0,0 +1,127 @@
+//! Dedicated threads for CPU-bound job types.
+//!
+//! Handlers run on the async runtime that also drives every in-flight
+//! fetch, result write and webhook, so a computation that holds its thread
+//! for long stalls them all. Job types listed in `WORKER_CPU_JOB_TYPES`
+//! compute on blocking threads instead, at most `WORKER_CPU_THREADS` at a
+//! time; the rest wait their turn. `worker_cpu_pool_queue_depth` and
+//! `worker_cpu_pool_busy_threads` show how far behind the pool is.
+
+use crate::metrics::Metrics;
+use service_config::Config;
+use std::collections::HashSet;
+use std::io;
+use std::panic;
+use std::sync::Arc;
+use std::thread;
+use tokio::sync::Semaphore;
+
+/// Which job types use the pool, and how many threads it has
+#[derive(Debug, Clone)]
+pub struct CpuPoolConfig {
+    job_types: HashSet<String>,
+    threads: usize,
+}
+
+impl CpuPoolConfig {
+    /// Read `WORKER_CPU_JOB_TYPES` (comma-separated, default none) and
+    /// `WORKER_CPU_THREADS` (default: the number of CPUs)
+    pub fn from_config(config: &Config) -> Self {
+        let job_types = config
+            .string("WORKER_CPU_JOB_TYPES")
+            .map(|v| {
+                v.split(',')
+                    .map(str::trim)
+                    .filter(|s| !s.is_empty())
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        Self {
+            job_types,
+            threads: config.parse_or("WORKER_CPU_THREADS", cpus).max(1),
+        }
+    }
+}
+
+/// Runs the computations of CPU-bound job types on blocking threads
+pub struct CpuPool {
+    job_types: HashSet<String>,
+    threads: Arc<Semaphore>,
+    metrics: Arc<Metrics>,
+}
+
+impl CpuPool {
+    pub fn new(config: CpuPoolConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            job_types: config.job_types,
+            threads: Arc::new(Semaphore::new(config.threads)),
+            metrics,
+        }
+    }
+
+    /// Job types using the pool, for logging
+    pub fn job_types(&self) -> Vec<&str> {
+        let mut job_types: Vec<&str> = self.job_types.iter().map(String::as_str).collect();
+        job_types.sort_unstable();
+        job_types
+    }
+
+    /// Compute `f` for a job of `job_type`: on a pool thread if the type is
+    /// CPU-bound, otherwise in place. Panics in `f` resume in the caller.
+    pub async fn run<T, F>(&self, job_type: &str, f: F) -> io::Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        if !self.job_types.contains(job_type) {
+            return Ok(f());
+        }
+
+        let permit = {
+            let _queued = Gauge::enter(&self.metrics, Metrics::cpu_pool_queued);
+            // The semaphore is never closed
+            self.threads
+                .clone()
+                .acquire_owned()
+                .await
+                .map_err(io::Error::other)?
+        };
+        let metrics = self.metrics.clone();
+        // The thread keeps its permit until the computation ends, even if the
+        // job gives up waiting for it (e.g. on a timeout)
+        let computed = tokio::task::spawn_blocking(move || {
+            let _busy = (permit, Gauge::enter(&metrics, Metrics::cpu_pool_busy));
+            f()
+        })
+        .await;
+        match computed {
+            Ok(value) => Ok(value),
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(io::Error::other(e)),
+        }
+    }
+}
+
+/// Adds one to a pool gauge until dropped
+struct Gauge {
+    metrics: Arc<Metrics>,
+    update: fn(&Metrics, i64),
+}
+
+impl Gauge {
+    fn enter(metrics: &Arc<Metrics>, update: fn(&Metrics, i64)) -> Self {
+        update(metrics, 1);
+        Self {
+            metrics: metrics.clone(),
+            update,
+        }
+    }
+}
+
+impl Drop for Gauge {
+    fn drop(&mut self) {
+        (self.update)(&self.metrics, -1);
+    }
+}
@@ -0,0 +1,114 @@
+//! Dead-letter routing for jobs that run out of attempts.
+//!
+//! Every failed attempt is added to the job's error history in the result
+//! store. Once the job is given up on, its payload and that history are kept
+//! as a [`DeadJob`] for `GET /dead`, and, with `WORKER_DEAD_QUEUE` set, a
+//! copy is pushed to that queue. Nothing fetches from it unless told to, so
+//! jobs wait there for inspection or for a worker started with
+//! `WORKER_QUEUES` pointing at it to reprocess them, with a full set of
+//! retries.
+
+use crate::producer::Producer;
+use crate::retry;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use faktory::Job;
+use result_store::{DeadJob, JobFailure, ResultStore};
+use serde::Serialize;
+use std::sync::Arc;
+use tracing::warn;
+
+/// Custom field on jobs in the dead queue describing how they died
+const DEAD_LETTER_FIELD: &str = "dead_letter";
+
+/// What a job in the dead queue carries about its failures
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    /// Queue the job was fetched from when it died
+    queue: &'a str,
+    attempts: u32,
+    errors: &'a [JobFailure],
+}
+
+/// Records failures and routes jobs that are out of attempts
+pub struct DeadLetters {
+    result_store: Arc<dyn ResultStore>,
+    producer: Arc<Producer>,
+    queue: Option<String>,
+}
+
+impl DeadLetters {
+    /// `queue` is the dead queue, or `None` to keep dead jobs in the result
+    /// store only
+    pub fn new(
+        result_store: Arc<dyn ResultStore>,
+        producer: Arc<Producer>,
+        queue: Option<String>,
+    ) -> Self {
+        Self {
+            result_store,
+            producer,
+            queue,
+        }
+    }
+
+    /// The dead queue, if any, for logging
+    pub fn queue(&self) -> Option<&str> {
+        self.queue.as_deref()
+    }
+
+    /// Add a failed attempt to the job's error history
+    pub async fn record_failure(&self, job: &Job, error: &str) -> Result<()> {
+        let failure = JobFailure {
+            attempt: retry::attempts(job),
+            error: error.to_string(),
+            failed_at: Utc::now(),
+        };
+        self.result_store.record_failure(job.id(), &failure).await
+    }
+
+    /// Keep a job that failed its last attempt with its error history, and
+    /// push it to the dead queue
+    pub async fn bury(&self, job: &Job, error: &str) -> Result<()> {
+        // Without its history the job is still worth keeping
+        let errors = self
+            .result_store
+            .failures(job.id())
+            .await
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Failed to read error history of job {}: {:#}",
+                    job.id().as_str(),
+                    e
+                );
+                Vec::new()
+            });
+        let dead = retry::dead_job(job, error, errors)?;
+        self.result_store.save_dead_job(&dead).await?;
+
+        if let Some(queue) = &self.queue {
+            self.producer
+                .enqueue(dead_letter(&dead, queue)?)
+                .await
+                .with_context(|| format!("Failed to push job to dead queue {}", queue))?;
+        }
+        Ok(())
+    }
+}
+
+/// The dead job as pushed to `queue`
+fn dead_letter(dead: &DeadJob, queue: &str) -> Result<Job> {
+    let mut job: Job =
+        serde_json::from_value(dead.job.clone()).context("Failed to parse dead job")?;
+    job.queue = queue.to_string();
+    let letter = DeadLetter {
+        queue: &dead.queue,
+        attempts: dead.attempts,
+        errors: &dead.errors,
+    };
+    job.custom.insert(
+        DEAD_LETTER_FIELD.to_string(),
+        serde_json::to_value(letter).context("Failed to serialize dead letter")?,
+    );
+    Ok(job)
+}
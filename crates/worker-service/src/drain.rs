@@ -0,0 +1,135 @@
+//! Draining in-flight jobs on shutdown.
+//!
+//! On `SIGTERM`/`SIGINT` (see [`crate::shutdown`]) the worker stops starting
+//! jobs and waits for the ones it's running, up to
+//! `WORKER_SHUTDOWN_TIMEOUT_SECS`. Shutdown goes ahead as soon as the last one
+//! finishes; any still running when time's up are abandoned: logged, then
+//! FAILed to Faktory so they're retried elsewhere instead of waiting out their
+//! reservation. Jobs fetched while draining are failed straight away without
+//! running, for the same reason. Faktory doesn't retry jobs with a backoff
+//! policy (see [`crate::retry`]), so those are pushed again per their policy
+//! instead: a refused one is then acknowledged, and an abandoned one is left
+//! for Faktory to move to its dead set.
+
+use crate::middleware::{Middleware, Next};
+use crate::producer::Producer;
+use crate::retry;
+use async_trait::async_trait;
+use faktory::Job;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Tracks the jobs in flight and holds new ones back once draining
+pub struct Drain {
+    /// Jobs being run, by id
+    running: watch::Sender<BTreeMap<String, Job>>,
+    draining: AtomicBool,
+    /// Pushes backoff jobs again
+    producer: Arc<Producer>,
+}
+
+impl Drain {
+    pub fn new(producer: Arc<Producer>) -> Self {
+        Self {
+            running: watch::Sender::new(BTreeMap::new()),
+            draining: AtomicBool::new(false),
+            producer,
+        }
+    }
+
+    /// Stop starting jobs and wait up to `timeout` for the running ones.
+    /// Returns the number abandoned.
+    pub async fn drain(&self, timeout: Duration) -> usize {
+        self.draining.store(true, Ordering::Relaxed);
+        let running = self.running.borrow().len();
+        if running > 0 {
+            info!(
+                "Waiting up to {}s for {} jobs to finish",
+                timeout.as_secs(),
+                running
+            );
+        }
+
+        let mut receiver = self.running.subscribe();
+        // The sender lives as long as `self`, so waiting can't fail
+        let drained = tokio::time::timeout(timeout, receiver.wait_for(BTreeMap::is_empty))
+            .await
+            .is_ok();
+        if drained {
+            return 0;
+        }
+        let abandoned: Vec<Job> = self.running.borrow().values().cloned().collect();
+        let jobs: Vec<String> = abandoned
+            .iter()
+            .map(|job| format!("{} ({})", job.id().as_str(), job.kind()))
+            .collect();
+        warn!(
+            "Abandoning {} jobs still running after {}s: {}",
+            jobs.len(),
+            timeout.as_secs(),
+            jobs.join(", ")
+        );
+        for job in &abandoned {
+            self.retry(job).await;
+        }
+        abandoned.len()
+    }
+
+    /// Push a job with a backoff policy again, returning whether it was
+    pub async fn retry(&self, job: &Job) -> bool {
+        let Some(policy) = retry::policy(job) else {
+            return false;
+        };
+        match retry::schedule_retry(&self.producer, job, policy).await {
+            Ok(scheduled) => scheduled,
+            Err(e) => {
+                warn!(
+                    "Failed to schedule retry of job {}: {:#}",
+                    job.id().as_str(),
+                    e
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Removes a job from the running ones however it ends
+struct Running<'a> {
+    drain: &'a Drain,
+    id: &'a str,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.drain.running.send_modify(|running| {
+            running.remove(self.id);
+        });
+    }
+}
+
+#[async_trait]
+impl Middleware for Drain {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        if self.draining.load(Ordering::Relaxed) {
+            // Ack a backoff job once it's queued again
+            if self.retry(job).await {
+                return Ok(());
+            }
+            return Err(io::Error::other("Worker shutting down; job not started"));
+        }
+        self.running.send_modify(|running| {
+            running.insert(job.id().to_string(), job.clone());
+        });
+        let _running = Running {
+            drain: self,
+            id: job.id().as_ref(),
+        };
+        next.run(job).await
+    }
+}
@@ -0,0 +1,86 @@
+//! Dry runs.
+//!
+//! With `WORKER_DRY_RUN` set, the worker fetches jobs and checks their
+//! arguments as usual but runs nothing: it logs what each job would do (or
+//! why it would fail) and hands the job back, so new job types and handlers
+//! can be tried against real queues without storing results, calling
+//! webhooks or running commands. Payload quarantine is off too, since it
+//! records dead jobs.
+//!
+//! Faktory can't give a fetched job back, so the worker pushes an identical
+//! copy (same id) to the same queue a few seconds later and acknowledges the
+//! original. The delay keeps a dry-run worker from fetching the same jobs in
+//! a tight loop and the copy from being picked up before the original is
+//! acknowledged.
+
+use crate::context::JobContext;
+use crate::middleware::Handler;
+use crate::producer::Producer;
+use async_trait::async_trait;
+use chrono::Utc;
+use faktory::Job;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// How long after a dry run its job is back in the queue
+const REQUEUE_DELAY: Duration = Duration::from_secs(5);
+
+/// Whether handlers run or only say what they'd do
+#[derive(Clone)]
+pub struct DryRun {
+    /// Pushes jobs back; `None` when jobs run for real
+    producer: Option<Arc<Producer>>,
+}
+
+impl DryRun {
+    pub fn new(enabled: bool, producer: Arc<Producer>) -> Self {
+        Self {
+            producer: enabled.then_some(producer),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.producer.is_some()
+    }
+
+    /// `handler`, dry-run if enabled
+    pub fn wrap<H: Handler>(&self, handler: H) -> DryRunHandler<H> {
+        DryRunHandler {
+            handler,
+            producer: self.producer.clone(),
+        }
+    }
+}
+
+/// A handler that runs jobs or, in a dry run, logs and requeues them
+pub struct DryRunHandler<H> {
+    handler: H,
+    producer: Option<Arc<Producer>>,
+}
+
+#[async_trait]
+impl<H: Handler> Handler for DryRunHandler<H> {
+    async fn run(&self, job: &Job, context: &JobContext) -> io::Result<()> {
+        let Some(producer) = &self.producer else {
+            return self.handler.run(job, context).await;
+        };
+        match self.handler.plan(job) {
+            Ok(plan) => info!("Dry run: would {}", plan),
+            Err(e) => warn!("Dry run: job would fail: {}", e),
+        }
+
+        let mut copy = job.clone();
+        copy.at = Some(Utc::now() + REQUEUE_DELAY);
+        // If the copy can't be pushed, failing the original keeps the job
+        producer
+            .enqueue(copy)
+            .await
+            .map_err(|e| io::Error::other(format!("Failed to requeue dry-run job: {:#}", e)))
+    }
+
+    fn plan(&self, job: &Job) -> io::Result<String> {
+        self.handler.plan(job)
+    }
+}
@@ -0,0 +1,62 @@
+//! Guard against running a job twice.
+//!
+//! Faktory delivers jobs at least once: a job whose worker stopped before
+//! acknowledging it (e.g. abandoned on shutdown, or acknowledged over a
+//! connection that then dropped) is handed out again once its reservation
+//! expires, even if it had finished. Before running a job, the worker checks
+//! the result store for a `completed` result under its id and, if there is
+//! one, acknowledges the job without running it again;
+//! `worker_jobs_duplicate_total` counts these. Resubmissions with the same
+//! `Idempotency-Key` already share the original job's id, so they're caught
+//! too. `failed` results don't count, since the job may be retrying.
+//!
+//! On by default; `WORKER_DUPLICATE_GUARD=false` saves the lookup per job.
+
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use async_trait::async_trait;
+use faktory::Job;
+use result_store::{JobStatus, ResultStore};
+use std::io;
+use std::sync::Arc;
+use tracing::{info, warn};
+
+/// Acknowledges jobs that already completed instead of running them
+pub struct DuplicateGuard {
+    result_store: Arc<dyn ResultStore>,
+    metrics: Arc<Metrics>,
+}
+
+impl DuplicateGuard {
+    pub fn new(result_store: Arc<dyn ResultStore>, metrics: Arc<Metrics>) -> Self {
+        Self {
+            result_store,
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for DuplicateGuard {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        // A store outage shouldn't stop jobs from running
+        match self.result_store.get(job.id()).await {
+            Ok(Some(result)) if result.status == JobStatus::Completed => {
+                info!(
+                    "Job {} already completed at {}, skipping it",
+                    job.id().as_str(),
+                    result.finished_at
+                );
+                self.metrics.job_duplicate(job.kind());
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to look up result of job {}: {:#}",
+                job.id().as_str(),
+                e
+            ),
+        }
+        next.run(job).await
+    }
+}
@@ -0,0 +1,239 @@
+//! Liveness of the worker, reported at `GET /health` on `WORKER_HTTP_ADDR`.
+//!
+//! A worker whose Faktory connection died keeps running but fetches nothing,
+//! which from outside looks just like an idle one. The health check tells
+//! them apart: it's unhealthy (503) while any of the worker's instances has
+//! stopped fetching or Faktory doesn't answer a probe, and it reports the
+//! jobs in flight and when the last one was fetched so a worker stuck on its
+//! jobs can be spotted too. It also names the worker's labels and the job types it handles, to
+//! tell instances apart during a rollout. The same details go into the
+//! worker's heartbeats (see [`crate::heartbeat`]).
+
+use crate::labels::WorkerLabels;
+use crate::middleware::{Middleware, Next};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use faktory::Job;
+use result_store::WorkerHeartbeat;
+use serde::Serialize;
+use service_tls::faktory::FaktoryConfig;
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// How long the health check waits for Faktory
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What the worker is up to
+pub struct Health {
+    faktory: FaktoryConfig,
+    concurrency: usize,
+    labels: WorkerLabels,
+    job_types: OnceLock<Vec<String>>,
+    /// Faktory workers in the process
+    instances: usize,
+    /// Faktory workers whose fetch loop is running
+    connected: AtomicUsize,
+    in_flight: AtomicUsize,
+    /// Jobs finished since the worker started
+    processed: AtomicU64,
+    last_fetch_at: Mutex<Option<DateTime<Utc>>>,
+}
+
+/// Body of `GET /health`
+#[derive(Debug, Serialize)]
+pub struct HealthReport {
+    status: &'static str,
+    service: &'static str,
+    faktory: FaktoryHealth,
+    /// Jobs being processed
+    in_flight: usize,
+    /// Jobs run at once, across instances
+    concurrency: usize,
+    /// Faktory workers in the process: `WORKER_INSTANCES`, times the fetcher
+    /// groups when `WORKER_BULK_QUEUES` is set
+    instances: usize,
+    /// When the last job was fetched; `null` before the first
+    last_fetch_at: Option<DateTime<Utc>>,
+    /// `WORKER_LABELS`, with `version`
+    labels: BTreeMap<String, String>,
+    /// Job types with a registered handler
+    job_types: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FaktoryHealth {
+    /// Every instance's fetch loop is running
+    connected: bool,
+    /// Instances whose fetch loop is running
+    instances_connected: usize,
+    /// `ok` if Faktory answered the probe, otherwise `error`
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    latency_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Health {
+    pub fn new(
+        faktory: FaktoryConfig,
+        concurrency: usize,
+        instances: usize,
+        labels: WorkerLabels,
+    ) -> Self {
+        Self {
+            faktory,
+            concurrency,
+            labels,
+            job_types: OnceLock::new(),
+            instances,
+            connected: AtomicUsize::new(0),
+            in_flight: AtomicUsize::new(0),
+            processed: AtomicU64::new(0),
+            last_fetch_at: Mutex::new(None),
+        }
+    }
+
+    /// Record that one instance's fetch loop started
+    pub fn instance_connected(&self) {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that one instance's fetch loop stopped
+    pub fn instance_disconnected(&self) {
+        self.connected.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Instances whose fetch loop is running, and whether that's every one
+    /// of them
+    fn instances_connected(&self) -> (usize, bool) {
+        let connected = self.connected.load(Ordering::Relaxed);
+        (connected, connected == self.instances)
+    }
+
+    /// Record the job types handlers are registered for
+    pub fn set_job_types(&self, job_types: Vec<String>) {
+        let _ = self.job_types.set(job_types);
+    }
+
+    /// Probe Faktory and report; the report is healthy if every instance's
+    /// fetch loop is running and Faktory answered
+    pub async fn check(&self) -> HealthReport {
+        let started = Instant::now();
+        let probe = async {
+            let mut client = self.faktory.client().await?;
+            client.current_info().await?;
+            Ok::<_, faktory::Error>(())
+        };
+        let probe = match tokio::time::timeout(PROBE_TIMEOUT, probe).await {
+            Ok(Ok(())) => Ok(started.elapsed().as_millis() as u64),
+            Ok(Err(e)) => Err(e.to_string()),
+            Err(_) => Err(format!(
+                "Faktory did not respond within {}ms",
+                PROBE_TIMEOUT.as_millis()
+            )),
+        };
+        if let Err(e) = &probe {
+            warn!("Health check failed to reach Faktory: {}", e);
+        }
+
+        let (instances_connected, connected) = self.instances_connected();
+        HealthReport {
+            status: if connected && probe.is_ok() {
+                "healthy"
+            } else {
+                "unhealthy"
+            },
+            service: "worker-service",
+            faktory: FaktoryHealth {
+                connected,
+                instances_connected,
+                status: if probe.is_ok() { "ok" } else { "error" },
+                latency_ms: probe.as_ref().ok().copied(),
+                error: probe.err(),
+            },
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            concurrency: self.concurrency,
+            instances: self.instances,
+            last_fetch_at: *self.last_fetch_at.lock().unwrap(),
+            labels: self.labels.as_map().clone(),
+            job_types: self.job_types.get().cloned().unwrap_or_default(),
+        }
+    }
+}
+
+impl Health {
+    /// A heartbeat for worker `worker_id`, consuming `queues`, as of now.
+    /// Throughput is left for the caller, who knows the interval.
+    pub fn heartbeat(
+        &self,
+        worker_id: &str,
+        queues: &[String],
+        started_at: DateTime<Utc>,
+    ) -> WorkerHeartbeat {
+        WorkerHeartbeat {
+            worker_id: worker_id.to_string(),
+            labels: self.labels.as_map().clone(),
+            queues: queues.to_vec(),
+            job_types: self.job_types.get().cloned().unwrap_or_default(),
+            concurrency: self.concurrency,
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            jobs_processed: self.processed.load(Ordering::Relaxed),
+            jobs_per_second: 0.0,
+            started_at,
+            last_seen_at: Utc::now(),
+        }
+    }
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.status == "healthy"
+    }
+}
+
+/// Counts a job as in flight until it finishes, however it ends, then as
+/// processed
+struct InFlight<'a>(&'a Health);
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.0.processed.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Middleware for Health {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        *self.last_fetch_at.lock().unwrap() = Some(Utc::now());
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _in_flight = InFlight(self);
+        next.run(job).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use service_config::Config;
+
+    #[test]
+    fn test_instances_connected_counts_each_instance() {
+        let faktory = FaktoryConfig::from_config(&Config::load().unwrap()).unwrap();
+        let health = Health::new(faktory, 4, 2, WorkerLabels::default());
+        health.instance_connected();
+        health.instance_connected();
+        assert_eq!(health.instances_connected(), (2, true));
+
+        // One losing its connection doesn't speak for the other
+        health.instance_disconnected();
+        assert_eq!(health.instances_connected(), (1, false));
+        health.instance_connected();
+        assert_eq!(health.instances_connected(), (2, true));
+    }
+}
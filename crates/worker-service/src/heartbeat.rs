@@ -0,0 +1,85 @@
+//! Heartbeats announcing the worker to the rest of the fleet.
+//!
+//! Every `WORKER_HEARTBEAT_INTERVAL_SECS` (default 10; 0 turns them off) the
+//! worker records a heartbeat in the result store: its id, labels, queues,
+//! job types, concurrency, jobs in flight and throughput. The API lists them
+//! at `GET /workers`. A heartbeat lasts three intervals, so a worker drops off
+//! the list soon after it stops, without having to say so.
+
+use crate::health::Health;
+use chrono::{DateTime, Utc};
+use result_store::ResultStore;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// Intervals a heartbeat outlives the one it was sent in
+const INTERVALS_TO_EXPIRY: u32 = 3;
+
+/// Characters in a generated worker id
+const WORKER_ID_LEN: usize = 16;
+
+/// A random id for this worker, shared with Faktory
+pub fn worker_id() -> String {
+    std::iter::repeat_with(fastrand::lowercase)
+        .take(WORKER_ID_LEN)
+        .collect()
+}
+
+/// Sends this worker's heartbeats
+pub struct Heartbeat {
+    worker_id: String,
+    queues: Vec<String>,
+    started_at: DateTime<Utc>,
+    interval: Duration,
+    health: Arc<Health>,
+    result_store: Arc<dyn ResultStore>,
+}
+
+impl Heartbeat {
+    pub fn new(
+        worker_id: String,
+        queues: Vec<String>,
+        interval: Duration,
+        health: Arc<Health>,
+        result_store: Arc<dyn ResultStore>,
+    ) -> Self {
+        Self {
+            worker_id,
+            queues,
+            started_at: Utc::now(),
+            interval,
+            health,
+            result_store,
+        }
+    }
+
+    /// Send a heartbeat every interval, starting now
+    pub async fn run(self) {
+        let mut ticker = tokio::time::interval(self.interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let ttl = self.interval * INTERVALS_TO_EXPIRY;
+        let mut last: Option<(Instant, u64)> = None;
+
+        loop {
+            ticker.tick().await;
+            let mut heartbeat =
+                self.health
+                    .heartbeat(&self.worker_id, &self.queues, self.started_at);
+            let now = Instant::now();
+            if let Some((at, processed)) = last {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    heartbeat.jobs_per_second =
+                        heartbeat.jobs_processed.saturating_sub(processed) as f64 / elapsed;
+                }
+            }
+            last = Some((now, heartbeat.jobs_processed));
+
+            if let Err(e) = self.result_store.save_heartbeat(&heartbeat, ttl).await {
+                warn!("Failed to send heartbeat: {:#}", e);
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! HTTP endpoint for the worker, served on `WORKER_HTTP_ADDR` when it's set.
+//!
+//! `GET /version` reports the build and the job payload schema version the
+//! worker understands, so a mixed-version fleet can be checked during a
+//! rollout. `GET /health` reports whether the worker is still fetching
+//! jobs (see `health`), and `GET /metrics` serves its Prometheus metrics.
+
+use crate::health::Health;
+use crate::metrics::Metrics;
+use anyhow::{Context, Result};
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use job_types::VersionInfo;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// Build of this binary
+pub fn version_info() -> VersionInfo {
+    VersionInfo {
+        service: "worker-service".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_sha: env!("GIT_SHA").to_string(),
+        built_at: env!("BUILD_TIMESTAMP").to_string(),
+        schema_version: job_types::SCHEMA_VERSION,
+    }
+}
+
+async fn version_handler() -> Json<VersionInfo> {
+    Json(version_info())
+}
+
+/// State of the HTTP endpoint
+#[derive(Clone)]
+struct HttpState {
+    metrics: Arc<Metrics>,
+    health: Arc<Health>,
+}
+
+async fn health_handler(State(state): State<HttpState>) -> impl IntoResponse {
+    let report = state.health.check().await;
+    let status = if report.is_healthy() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(report))
+}
+
+async fn metrics_handler(State(state): State<HttpState>) -> impl IntoResponse {
+    match state.metrics.render() {
+        Ok(body) => (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        )
+            .into_response(),
+        Err(e) => {
+            warn!("Failed to render metrics: {:#}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Bind `addr` and serve in the background until the process exits
+pub async fn serve(addr: &str, metrics: Arc<Metrics>, health: Arc<Health>) -> Result<()> {
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind WORKER_HTTP_ADDR {}", addr))?;
+    let app = Router::new()
+        .route("/version", get(version_handler))
+        .route("/health", get(health_handler))
+        .route("/metrics", get(metrics_handler))
+        .with_state(HttpState { metrics, health });
+    info!("Serving worker HTTP endpoint on {}", addr);
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            warn!("Worker HTTP endpoint stopped: {}", e);
+        }
+    });
+    Ok(())
+}
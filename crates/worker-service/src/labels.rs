@@ -0,0 +1,83 @@
+//! Labels describing a worker instance.
+//!
+//! `WORKER_LABELS` takes `key=value` pairs, e.g.
+//! `region=eu-west,capabilities=gpu`; `version` is added with the build's
+//! version unless set. They're sent to Faktory as `key:value` when the worker
+//! connects (shown in its UI next to the worker), put on every series at
+//! `/metrics` and reported by `/health`, so instances can be told apart
+//! during a rollout.
+
+use anyhow::{bail, Result};
+use service_config::Config;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// The labels of this worker, by key
+#[derive(Debug, Clone, Default)]
+pub struct WorkerLabels(BTreeMap<String, String>);
+
+impl WorkerLabels {
+    /// Read `WORKER_LABELS` (comma-separated `key=value` pairs). Keys must be
+    /// usable as Prometheus label names.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let mut labels = BTreeMap::new();
+        for entry in config
+            .string("WORKER_LABELS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            let Some((key, value)) = entry.split_once('=') else {
+                bail!(
+                    "Invalid WORKER_LABELS entry {:?} (expected key=value)",
+                    entry
+                );
+            };
+            let key = key.trim();
+            if !is_label_name(key) {
+                bail!(
+                    "Invalid WORKER_LABELS key {:?} (letters, digits and underscores, not starting with a digit)",
+                    key
+                );
+            }
+            labels.insert(key.to_string(), value.trim().to_string());
+        }
+        labels
+            .entry("version".to_string())
+            .or_insert_with(|| env!("CARGO_PKG_VERSION").to_string());
+        Ok(Self(labels))
+    }
+
+    /// As Faktory worker labels
+    pub fn faktory_labels(&self) -> Vec<String> {
+        self.0
+            .iter()
+            .map(|(key, value)| format!("{}:{}", key, value))
+            .collect()
+    }
+
+    pub fn as_map(&self) -> &BTreeMap<String, String> {
+        &self.0
+    }
+}
+
+impl fmt::Display for WorkerLabels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let labels: Vec<String> = self
+            .0
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        f.write_str(&labels.join(", "))
+    }
+}
+
+/// Whether `name` is a valid Prometheus label name. Names starting with `__`
+/// are reserved.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !name.starts_with("__")
+}
@@ -1,33 +1,132 @@
-use faktory::{Job, WorkerBuilder};
-use job_types::{JobPayload, MathArgs};
+mod autoscale;
+mod chaos;
+mod concurrency;
+mod context;
+mod cpu_pool;
+mod dead_letter;
+mod drain;
+mod dry_run;
+mod duplicates;
+mod health;
+mod heartbeat;
+mod http;
+mod labels;
+mod memo;
+mod metrics;
+mod middleware;
+mod panics;
+mod plugin;
+mod poison;
+mod priority;
+mod producer;
+mod rate_limit;
+mod reconnect;
+mod resources;
+mod result_log;
+mod retry;
+mod selection;
+mod shutdown;
+mod subprocess;
+mod supervisor;
+mod timeout;
+mod webhook;
+
+use async_trait::async_trait;
+use autoscale::{AutoscaleConfig, Autoscaler};
+use chaos::Chaos;
+use chrono::{DateTime, Utc};
+use concurrency::ConcurrencyLimits;
+use context::{JobContext, QueueWait};
+use cpu_pool::{CpuPool, CpuPoolConfig};
+use dead_letter::DeadLetters;
+use drain::Drain;
+use dry_run::DryRun;
+use duplicates::DuplicateGuard;
+use faktory::{Job, StopReason};
+use health::Health;
+use heartbeat::Heartbeat;
+use job_types::{
+    is_valid_tenant, split_tenant_queue, tenant_queue, JobKind, JobPayload, JobType, MathArgs,
+    MathBatchArgs, MathOp, MathOperation, REQUEST_ID_FIELD,
+};
+use labels::WorkerLabels;
+use memo::Memo;
+use metrics::Metrics;
+use middleware::{Chain, Handler, JobFuture};
+use panics::CatchPanics;
+use plugin::{Plugin, PluginSpec};
+use poison::{PoisonConfig, Quarantine};
+use priority::{Fetchers, PriorityConfig};
+use producer::Producer;
+use rate_limit::{RateLimitConfig, RateLimits};
+use reconnect::Reconnect;
+use resources::{memory_exceeded, JobLimits, MemoryLimits};
+use result_log::ResultLog;
+use result_store::{
+    CompletionConfig, CompletionPublisher, JobResult, ProgressReporter, ResultStore, StoreConfig,
+};
+use retry::{Retry, RetryPolicies};
+use selection::JobTypeSelection;
+use serde::de::DeserializeOwned;
+use service_config::Config;
+use service_tls::faktory::FaktoryConfig;
+use std::collections::HashMap;
+use std::fmt;
 use std::io;
+use std::str::FromStr;
 use std::sync::Arc;
-use tokio::sync::Notify;
-use tracing::{error, info, warn};
+use std::time::Duration;
+use subprocess::CommandSpec;
+use supervisor::{Supervisor, SupervisorConfig};
+use timeout::{JobTimeouts, TimeoutConfig};
+use tokio::sync::watch;
+use tracing::{info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use webhook::{WebhookConfig, WebhookNotifier, WEBHOOK_JOB_TYPE};
 
 type Result<T> = std::result::Result<T, io::Error>;
 
+/// A job type's handler behind the middleware chain
+type JobFn = Arc<dyn Fn(Job) -> JobFuture + Send + Sync>;
+
+/// Shared state passed to every job handler
+struct WorkerState {
+    result_store: Arc<dyn ResultStore>,
+    producer: Arc<Producer>,
+    webhooks: WebhookNotifier,
+    retry_policies: RetryPolicies,
+    dead_letters: Arc<DeadLetters>,
+    /// Publishes stored results (`COMPLETION_EVENTS_URL`)
+    completions: Option<CompletionPublisher>,
+    cpu_pool: CpuPool,
+    /// Faults injected into jobs (`WORKER_CHAOS_ENABLED`)
+    chaos: Option<Chaos>,
+    result_log: ResultLog,
+    /// Results of math jobs, reused for repeats (`WORKER_MEMO_CAPACITY`)
+    memo: Option<Memo>,
+}
+
 /// Handler for addition jobs
-fn handle_add(args: MathArgs) -> Result<f64> {
+fn handle_add(args: MathArgs, _progress: &ProgressReporter) -> Result<f64> {
     let result = args.a + args.b;
-    // Logging removed for performance - in production you'd log selectively
     Ok(result)
 }
 
 /// Handler for subtraction jobs
-fn handle_subtract(args: MathArgs) -> Result<f64> {
+fn handle_subtract(args: MathArgs, _progress: &ProgressReporter) -> Result<f64> {
     let result = args.a - args.b;
     Ok(result)
 }
 
 /// Handler for multiplication jobs
-fn handle_multiply(args: MathArgs) -> Result<f64> {
+fn handle_multiply(args: MathArgs, _progress: &ProgressReporter) -> Result<f64> {
     let result = args.a * args.b;
     Ok(result)
 }
 
 /// Handler for division jobs
-fn handle_divide(args: MathArgs) -> Result<f64> {
+fn handle_divide(args: MathArgs, _progress: &ProgressReporter) -> Result<f64> {
     if args.b == 0.0 {
         return Err(io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -38,131 +137,1118 @@ fn handle_divide(args: MathArgs) -> Result<f64> {
     Ok(result)
 }
 
-/// Generic job handler that dispatches to specific handlers
-async fn job_handler(job: Job) -> Result<()> {
+/// Computes a math job's value from its parsed arguments. Long computations
+/// report how far they've got to the reporter.
+type MathFn = fn(MathArgs, &ProgressReporter) -> Result<f64>;
+
+/// Handler for a job type in `JobPayload::KINDS`, if the worker has one
+fn math_fn(kind: &JobKind) -> Option<MathFn> {
+    let compute: MathFn = match kind.variant {
+        JobType::Add => handle_add,
+        JobType::Subtract => handle_subtract,
+        JobType::Multiply => handle_multiply,
+        JobType::Divide => handle_divide,
+        JobType::MathBatch => return None,
+    };
+    Some(compute)
+}
+
+/// Operations between reports of a batch's progress
+const BATCH_PROGRESS_INTERVAL: usize = 1000;
+
+/// Compute every operation of a batch, in order. The first that fails fails
+/// the batch.
+fn compute_batch(args: MathBatchArgs, progress: &ProgressReporter) -> Result<Vec<f64>> {
+    let total = args.operations.len();
+    let mut results = Vec::with_capacity(total);
+    for (index, operation) in args.operations.into_iter().enumerate() {
+        if index > 0 && index % BATCH_PROGRESS_INTERVAL == 0 {
+            progress.report((index * 100 / total) as u8, None);
+        }
+        let compute = op_fn(operation.op);
+        let args = MathArgs {
+            a: operation.a,
+            b: operation.b,
+            request_id: None,
+        };
+        let result = compute(args, progress)
+            .map_err(|e| io::Error::new(e.kind(), format!("Operation {}: {}", index, e)))?;
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Bytes a batch of `operations` takes: the parsed operations, their results
+/// and the results as JSON
+fn batch_memory(operations: usize) -> usize {
+    operations.saturating_mul(
+        size_of::<MathOperation>() + size_of::<f64>() + size_of::<serde_json::Value>(),
+    )
+}
+
+/// Operations in a `math_batch` job, counted before they're parsed
+fn batch_operations(job: &Job) -> usize {
+    job.args()
+        .first()
+        .and_then(|args| args.get("operations"))
+        .and_then(serde_json::Value::as_array)
+        .map_or(0, Vec::len)
+}
+
+/// Handler for one operation of a batch
+fn op_fn(op: MathOp) -> MathFn {
+    match op {
+        MathOp::Add => handle_add,
+        MathOp::Subtract => handle_subtract,
+        MathOp::Multiply => handle_multiply,
+        MathOp::Divide => handle_divide,
+    }
+}
+
+/// A setting of `job_type=value` pairs, e.g. `math_divide=10,webhook_delivery=20`
+fn per_type_setting<T>(config: &Config, key: &str) -> anyhow::Result<HashMap<String, T>>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    let mut values = HashMap::new();
+    let Some(setting) = config.string(key) else {
+        return Ok(values);
+    };
+    for entry in setting.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        let Some((job_type, value)) = entry.split_once('=') else {
+            anyhow::bail!(
+                "Invalid {} entry {:?} (expected job_type=value)",
+                key,
+                entry
+            );
+        };
+        let value = value
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid {} entry {:?}: {}", key, entry, e))?;
+        values.insert(job_type.trim().to_string(), value);
+    }
+    Ok(values)
+}
+
+/// ID of the API request that enqueued the job, if it was tagged with one
+fn request_id(job: &Job) -> Option<String> {
+    job.custom
+        .get(REQUEST_ID_FIELD)
+        .and_then(|v| v.as_str())
+        .map(String::from)
+}
+
+/// Tenant whose `{tenant}.{queue}` queue the job came from
+fn tenant(job: &Job) -> Option<String> {
+    split_tenant_queue(&job.queue).map(|(tenant, _)| tenant.to_string())
+}
+
+/// Store a `failed` result for a job that ended without its handler
+/// producing one (it was quarantined). Only jobs from the registry have
+/// results; deliveries and other internal jobs just fail.
+async fn store_failure(
+    result_store: &dyn ResultStore,
+    completions: Option<&CompletionPublisher>,
+    job: &Job,
+    message: &str,
+    started_at: DateTime<Utc>,
+) {
+    if !JobPayload::KINDS
+        .iter()
+        .any(|kind| kind.job_type == job.kind())
+    {
+        return;
+    }
+    let result = JobResult::failed(job.id().to_string(), job.kind(), message.to_string())
+        .with_request_id(request_id(job))
+        .with_attempt(retry::attempts(job), started_at)
+        .with_args(job.args().first().cloned())
+        .with_tenant(tenant(job));
+    store_result(result_store, completions, &result).await;
+}
+
+/// Store a job's result and publish it as a completion event. Failures are
+/// only logged: a store outage shouldn't fail (and re-run) a job that already
+/// computed its value.
+async fn store_result(
+    result_store: &dyn ResultStore,
+    completions: Option<&CompletionPublisher>,
+    result: &JobResult,
+) {
+    if let Err(e) = result_store.set(result).await {
+        warn!("Failed to store result for job {}: {:#}", result.job_id, e);
+        return;
+    }
+    if let Some(completions) = completions {
+        if let Err(e) = completions.publish(result).await {
+            warn!(
+                "Failed to publish completion of job {}: {:#}",
+                result.job_id, e
+            );
+        }
+    }
+}
+
+/// Handler for the jobs of one kind, registered under its Faktory job type
+struct MathHandler {
+    state: Arc<WorkerState>,
+    kind: JobKind,
+    compute: MathFn,
+}
+
+#[async_trait]
+impl Handler for MathHandler {
+    async fn run(&self, job: &Job, context: &JobContext) -> Result<()> {
+        run_job(&self.state, job, context, &self.kind, self.compute).await
+    }
+
+    fn plan(&self, job: &Job) -> Result<String> {
+        let args: MathArgs = job_args(job, &self.kind)?;
+        Ok(format!(
+            "compute {}({}, {})",
+            self.kind.tag(),
+            args.a,
+            args.b
+        ))
+    }
+}
+
+/// Handler for `math_batch` jobs
+struct MathBatchHandler {
+    state: Arc<WorkerState>,
+    kind: JobKind,
+    /// Bytes a batch may take; larger ones fail before they're parsed
+    memory_limit: Option<usize>,
+}
+
+#[async_trait]
+impl Handler for MathBatchHandler {
+    async fn run(&self, job: &Job, context: &JobContext) -> Result<()> {
+        let args = match self.memory_limit {
+            Some(limit) if batch_memory(batch_operations(job)) > limit => {
+                Err(memory_exceeded(limit))
+            }
+            _ => job_args(job, &self.kind),
+        };
+        run_computation(&self.state, job, context, args, compute_batch).await
+    }
+
+    fn plan(&self, job: &Job) -> Result<String> {
+        let args: MathBatchArgs = job_args(job, &self.kind)?;
+        Ok(format!("compute {} operations", args.operations.len()))
+    }
+}
+
+/// Handler for webhook deliveries
+struct WebhookHandler(Arc<WorkerState>);
+
+#[async_trait]
+impl Handler for WebhookHandler {
+    async fn run(&self, job: &Job, _context: &JobContext) -> Result<()> {
+        self.0.webhooks.deliver(job).await
+    }
+
+    fn plan(&self, job: &Job) -> Result<String> {
+        webhook::plan_delivery(job)
+    }
+}
+
+/// Handler for a job type served by a WASM plugin
+struct PluginHandler {
+    state: Arc<WorkerState>,
+    plugin: Arc<Plugin>,
+    limits: JobLimits,
+}
+
+#[async_trait]
+impl Handler for PluginHandler {
+    async fn run(&self, job: &Job, context: &JobContext) -> Result<()> {
+        let started_at = start_job(&self.state, job).await;
+        let input = job.args().first().cloned().unwrap_or_default();
+        let plugin = self.plugin.clone();
+        let limits = self.limits;
+        let result = self
+            .state
+            .cpu_pool
+            .run(job.kind(), move || plugin.call(&input, limits))
+            .await
+            .and_then(|result| result);
+        finish_job(&self.state, job, context, started_at, result).await
+    }
+
+    fn plan(&self, job: &Job) -> Result<String> {
+        let input = job.args().first().cloned().unwrap_or_default();
+        Ok(format!(
+            "call {} with {}",
+            self.plugin.describe(),
+            json_input(&input)
+        ))
+    }
+}
+
+/// Handler for a job type served by an external command
+struct CommandHandler {
+    state: Arc<WorkerState>,
+    command: CommandSpec,
+    /// Bytes the command's process may use
+    memory_limit: Option<usize>,
+}
+
+#[async_trait]
+impl Handler for CommandHandler {
+    async fn run(&self, job: &Job, context: &JobContext) -> Result<()> {
+        let started_at = start_job(&self.state, job).await;
+        let input = job.args().first().cloned().unwrap_or_default();
+        let result = self.command.run(&input, context, self.memory_limit).await;
+        finish_job(&self.state, job, context, started_at, result).await
+    }
+
+    fn plan(&self, job: &Job) -> Result<String> {
+        let input = job.args().first().cloned().unwrap_or_default();
+        Ok(format!(
+            "run `{}` with {}",
+            self.command,
+            json_input(&input)
+        ))
+    }
+}
+
+/// A job's input as JSON, for logging
+fn json_input(input: &serde_json::Value) -> String {
+    serde_json::to_string(input).unwrap_or_default()
+}
+
+/// Record that the job started, returning when
+async fn start_job(state: &WorkerState, job: &Job) -> DateTime<Utc> {
+    let started_at = Utc::now();
+    if let Err(e) = state
+        .result_store
+        .record_started(job.id(), job.kind())
+        .await
+    {
+        warn!(
+            "Failed to record start of job {}: {:#}",
+            job.id().as_str(),
+            e
+        );
+    }
+    started_at
+}
+
+async fn run_job(
+    state: &WorkerState,
+    job: &Job,
+    context: &JobContext,
+    kind: &JobKind,
+    compute: MathFn,
+) -> Result<()> {
+    // The job type already picked the handler; only the arguments are left.
+    // Unusable arguments fail the job like any other invalid input.
+    run_computation(state, job, context, job_args(job, kind), compute).await
+}
+
+/// Compute a job's value from its parsed `args` (on the CPU pool, if its
+/// type uses it) and finish the job with it
+async fn run_computation<A, T>(
+    state: &WorkerState,
+    job: &Job,
+    context: &JobContext,
+    args: Result<A>,
+    compute: fn(A, &ProgressReporter) -> Result<T>,
+) -> Result<()>
+where
+    A: Send + 'static,
+    T: serde::Serialize + Send + 'static,
+{
     let job_type = job.kind();
+    let started_at = start_job(state, job).await;
+    // Identical jobs compute the same value, so a repeat can reuse it
+    let memo = state.memo.as_ref().map(|memo| (memo, memo.key(job)));
+    if let Some((memo, key)) = &memo {
+        if let Some(value) = memo.get(job_type, key) {
+            return finish_job(state, job, context, started_at, Ok(value)).await;
+        }
+    }
+    let progress = Arc::new(ProgressReporter::new(
+        state.result_store.clone(),
+        job.id().to_string(),
+    ));
+    let result = match args {
+        Ok(args) => {
+            let progress = progress.clone();
+            state
+                .cpu_pool
+                .run(job_type, move || compute(args, &progress))
+                .await
+                .and_then(|result| result)
+        }
+        Err(e) => Err(e),
+    };
+    // Let the last progress update land before the result
+    if let Some(progress) = Arc::into_inner(progress) {
+        if let Err(e) = progress.finish().await {
+            warn!(
+                "Failed to record progress of job {}: {:#}",
+                job.id().as_str(),
+                e
+            );
+        }
+    }
 
-    // Get the first argument (our job payload)
-    let args_value = job
-        .args()
-        .get(0)
-        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Job missing arguments"))?
-        .clone();
+    let result = result.map(|value| serde_json::json!(value));
+    if let (Some((memo, key)), Ok(value)) = (memo, &result) {
+        memo.insert(key, value.clone());
+    }
+    finish_job(state, job, context, started_at, result).await
+}
 
-    // Parse into our typed JobPayload
-    let payload = JobPayload::from_job_type(job_type, args_value).map_err(|e| {
-        io::Error::new(
-            io::ErrorKind::InvalidInput,
-            format!("Failed to parse job payload: {}", e),
-        )
-    })?;
-
-    // Dispatch to the appropriate handler
-    let result = match payload {
-        JobPayload::Add(args) => handle_add(args),
-        JobPayload::Subtract(args) => handle_subtract(args),
-        JobPayload::Multiply(args) => handle_multiply(args),
-        JobPayload::Divide(args) => handle_divide(args),
+/// Parse a registry job's arguments
+fn job_args<A: DeserializeOwned>(job: &Job, kind: &JobKind) -> Result<A> {
+    job.args()
+        .first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Job missing arguments"))
+        .and_then(|args| {
+            serde_json::from_value::<A>(args.clone()).map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Failed to parse {} job args: {}", kind.tag(), e),
+                )
+            })
+        })
+}
+
+/// Store a job's result, notify its caller, and retry or bury it if it
+/// failed. The `Err` returned fails the job back to Faktory.
+async fn finish_job(
+    state: &WorkerState,
+    job: &Job,
+    context: &JobContext,
+    started_at: DateTime<Utc>,
+    result: Result<serde_json::Value>,
+) -> Result<()> {
+    let result = match &state.chaos {
+        Some(chaos) => chaos.inject(result).await,
+        None => result,
     };
+    settle_job(state, job, context, started_at, result).await
+}
 
-    match result {
-        Ok(_value) => {
-            // Job completed successfully - only log errors in production
-            Ok(())
+/// `finish_job` without injected faults
+async fn settle_job(
+    state: &WorkerState,
+    job: &Job,
+    context: &JobContext,
+    started_at: DateTime<Utc>,
+    result: Result<serde_json::Value>,
+) -> Result<()> {
+    state.result_log.record(context, started_at, &result);
+    let retry = result
+        .as_ref()
+        .err()
+        .map(|e| state.retry_policies.for_failure(job, e));
+    let last_attempt = retry.is_none_or(|retry| retry.is_last_attempt(job));
+
+    let job_type = job.kind();
+    let job_result = match &result {
+        Ok(value) => JobResult::completed(job.id().to_string(), job_type, value.clone()),
+        Err(e) => JobResult::failed(job.id().to_string(), job_type, e.to_string()),
+    }
+    .with_request_id(context.request_id().map(String::from))
+    .with_attempt(context.attempt, started_at)
+    .with_args(job.args().first().cloned())
+    .with_tenant(tenant(job))
+    .with_retrying(!last_attempt);
+
+    store_result(
+        state.result_store.as_ref(),
+        state.completions.as_ref(),
+        &job_result,
+    )
+    .await;
+
+    // Notify the caller once the job is done for good (not between retries)
+    if let Some(url) = WebhookNotifier::callback_url(job) {
+        if last_attempt {
+            if let Err(e) = state.webhooks.schedule(url, job_result).await {
+                warn!(
+                    "Failed to schedule webhook for job {}: {:#}",
+                    job.id().as_str(),
+                    e
+                );
+            }
         }
+    }
+
+    match result {
+        Ok(_value) => Ok(()),
         Err(e) => {
-            error!("Job failed: {:#}", e);
+            if let Err(e) = state.dead_letters.record_failure(job, &e.to_string()).await {
+                warn!(
+                    "Failed to record failure of job {}: {:#}",
+                    job.id().as_str(),
+                    e
+                );
+            }
+
+            // Jobs with a backoff policy are retried by us, not Faktory
+            if let Some(Retry::Backoff(policy)) = retry {
+                match retry::schedule_retry(&state.producer, job, policy).await {
+                    // Ack this attempt; the retry is already queued
+                    Ok(true) => return Ok(()),
+                    Ok(false) => {}
+                    Err(e) => warn!(
+                        "Failed to schedule retry of job {}: {:#}",
+                        job.id().as_str(),
+                        e
+                    ),
+                }
+            }
+
+            // Keep the job for `GET /dead` (and the dead queue) once it's
+            // out of retries
+            if last_attempt {
+                if let Err(e) = state.dead_letters.bury(job, &e.to_string()).await {
+                    warn!("Failed to record dead job {}: {:#}", job.id().as_str(), e);
+                }
+
+                // Faktory would retry a job we gave up on
+                if retry != Some(Retry::Faktory) && retry::faktory_would_retry(job) {
+                    return Ok(());
+                }
+            }
             Err(e)
         }
     }
 }
 
+/// Fails jobs that a middleware ended without their handler returning (they
+/// timed out or panicked) as if the handler had returned the error, so they
+/// are stored, retried and buried like any other failure. Webhook deliveries
+/// have no result and just fail.
+#[derive(Clone)]
+struct JobFailures(Arc<WorkerState>);
+
+impl JobFailures {
+    async fn fail(
+        &self,
+        job: &Job,
+        context: &JobContext,
+        started_at: DateTime<Utc>,
+        error: io::Error,
+    ) -> Result<()> {
+        if job.kind() == WEBHOOK_JOB_TYPE {
+            return Err(error);
+        }
+        settle_job(&self.0, job, context, started_at, Err(error)).await
+    }
+}
+
+/// Resolves once a signal asks the worker to shut down
+async fn shutdown_signalled(mut shutdown: watch::Receiver<bool>) {
+    // Without a signal handler, no signal is coming
+    if shutdown.wait_for(|stop| *stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Fail if `job_type`, configured in `key`, would take over a built-in
+/// handler
+fn check_custom_job_type(key: &str, job_type: &str) -> anyhow::Result<()> {
+    if job_type == WEBHOOK_JOB_TYPE
+        || JobPayload::KINDS
+            .iter()
+            .any(|kind| kind.job_type == job_type)
+    {
+        anyhow::bail!("{} can't replace the {} handler", key, job_type);
+    }
+    Ok(())
+}
+
+/// Compile the WASM plugins, sorted by job type
+fn load_plugins(specs: HashMap<String, PluginSpec>) -> anyhow::Result<Vec<(String, Arc<Plugin>)>> {
+    let engine = plugin::engine()?;
+    let mut plugins = Vec::new();
+    for (job_type, spec) in specs {
+        check_custom_job_type("WORKER_WASM_PLUGINS", &job_type)?;
+        let plugin = Plugin::load(&engine, spec)?;
+        info!(
+            "Loaded WASM plugin for {} jobs: {}",
+            job_type,
+            plugin.describe()
+        );
+        plugins.push((job_type, Arc::new(plugin)));
+    }
+    plugins.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(plugins)
+}
+
+/// Install the global log subscriber, in the format named by `LOG_FORMAT`
+/// (`text` or `json`)
+fn init_logging(config: &Config) -> anyhow::Result<()> {
+    let fmt_layer = match config.string_or("LOG_FORMAT", "text").as_str() {
+        "text" => tracing_subscriber::fmt::layer().boxed(),
+        "json" => tracing_subscriber::fmt::layer().json().boxed(),
+        other => anyhow::bail!("Invalid LOG_FORMAT {:?} (expected text or json)", other),
+    };
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(fmt_layer)
+        .init();
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+    // Configuration from the environment and CONFIG_FILE
+    let config = Config::load()?;
+    init_logging(&config)?;
+    let faktory = FaktoryConfig::from_config(&config)?;
+
+    let store_config = StoreConfig::from_config(&config)?;
+    let completion_config = CompletionConfig::from_config(&config);
+
+    // Webhook delivery configuration
+    let webhook_max_retries = config.parse_or("WEBHOOK_MAX_RETRIES", 10);
+    let webhook_timeout_secs = config.parse_or("WEBHOOK_TIMEOUT_SECS", 10);
 
-    // Configuration
-    let faktory_url =
-        std::env::var("FAKTORY_URL").unwrap_or_else(|_| "tcp://localhost:7419".to_string());
+    // Queues to fetch from, in priority order (Faktory drains earlier queues
+    // first); by default those of the handled job types
+    let worker_queues: Option<Vec<String>> = config.string("WORKER_QUEUES").map(|v| {
+        v.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect()
+    });
+
+    // Tenants this worker serves; their `{tenant}.{queue}` queues replace the
+    // shared ones, with all tenants' copies of a queue ahead of the next queue
+    let worker_tenants: Vec<String> = config
+        .string("WORKER_TENANTS")
+        .map(|v| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    if let Some(tenant) = worker_tenants.iter().find(|t| !is_valid_tenant(t)) {
+        anyhow::bail!("Invalid tenant {:?} in WORKER_TENANTS", tenant);
+    }
+
+    // Worker concurrency; high by default to hide network latency
+    let worker_concurrency = config.parse_or("WORKER_CONCURRENCY", 500);
+    // With autoscaling, fetch with the most slots it may scale to
+    let autoscale = AutoscaleConfig::from_config(&config, worker_concurrency)?;
+    let worker_concurrency = autoscale
+        .as_ref()
+        .map_or(worker_concurrency, AutoscaleConfig::max);
+    let type_concurrency = ConcurrencyLimits::from_config(&config)?;
+    let rate_limits = RateLimitConfig::from_config(&config)?;
+    let timeouts = TimeoutConfig::from_config(&config)?;
+    let memory_limits = MemoryLimits::from_config(&config)?;
+    let retry_policies = RetryPolicies::from_config(&config)?;
+    // Queue jobs are copied to once out of retries (off when unset)
+    let dead_queue = config.string("WORKER_DEAD_QUEUE");
+    let poison = PoisonConfig::from_config(&config)?;
+    // Skip redelivered jobs that already completed
+    let duplicate_guard = config.parse_or("WORKER_DUPLICATE_GUARD", true);
+    // Job types handled by WASM modules, as `job_type=path.wasm#export`
+    let mut plugin_specs: HashMap<String, PluginSpec> =
+        per_type_setting(&config, "WORKER_WASM_PLUGINS")?;
+    // Job types handled by external commands, as `job_type=program args...`
+    let mut commands: HashMap<String, CommandSpec> = per_type_setting(&config, "WORKER_COMMANDS")?;
+    for job_type in commands.keys() {
+        check_custom_job_type("WORKER_COMMANDS", job_type)?;
+        if plugin_specs.contains_key(job_type) {
+            anyhow::bail!(
+                "{} jobs can't be handled by both WORKER_WASM_PLUGINS and WORKER_COMMANDS",
+                job_type
+            );
+        }
+    }
+    // Job types to register handlers for (all when unset), and the API's
+    // routing of job types to queues, which picks the queues to consume
+    let job_selection = JobTypeSelection::from_config(&config)?;
+    let queue_routes: HashMap<String, String> = per_type_setting(&config, "QUEUE_DEFAULTS")?;
+    job_selection.check(
+        JobPayload::KINDS
+            .iter()
+            .map(|kind| kind.job_type)
+            .chain(plugin_specs.keys().map(String::as_str))
+            .chain(commands.keys().map(String::as_str))
+            .chain([WEBHOOK_JOB_TYPE]),
+    )?;
+    memory_limits.check(
+        plugin_specs
+            .keys()
+            .chain(commands.keys())
+            .map(String::as_str)
+            .chain(["math_batch"]),
+    )?;
+    // Handlers of other types aren't set up at all
+    plugin_specs.retain(|job_type, _| job_selection.contains(job_type));
+    commands.retain(|job_type, _| job_selection.contains(job_type));
+    let worker_queues = worker_queues
+        .or_else(|| job_selection.queues(&queue_routes))
+        .unwrap_or_else(|| vec!["default".to_string()]);
+    let tenant_queues = |queues: &[String]| -> Vec<String> {
+        if worker_tenants.is_empty() {
+            return queues.to_vec();
+        }
+        queues
+            .iter()
+            .flat_map(|queue| worker_tenants.iter().map(move |t| tenant_queue(t, queue)))
+            .collect()
+    };
+    // Fetchers reserved for priority and bulk queues (all fetch in queue
+    // order when no bulk queues are named)
+    let priority = PriorityConfig::from_config(&config);
+    let fetchers = match &priority {
+        Some(priority) => priority.fetchers(&worker_queues, worker_concurrency)?,
+        None => vec![Fetchers {
+            concurrency: worker_concurrency,
+            queues: worker_queues.clone(),
+        }],
+    };
+    let fetchers: Vec<Fetchers> = fetchers
+        .into_iter()
+        .map(|fetchers| Fetchers {
+            queues: tenant_queues(&fetchers.queues),
+            ..fetchers
+        })
+        .collect();
+    let worker_queues = tenant_queues(&worker_queues);
+    let cpu_pool = CpuPoolConfig::from_config(&config);
+    // Log what jobs would do and requeue them instead of running them
+    let dry_run = config.parse_or("WORKER_DRY_RUN", false);
+    // Random faults injected into jobs, for testing (off unless enabled)
+    let chaos = Chaos::from_config(&config)?;
+    let result_log = ResultLog::from_config(&config);
+    // Math results cached for identical jobs (off when 0)
+    let memo_capacity = config.parse_or("WORKER_MEMO_CAPACITY", 0usize);
 
-    info!("Starting worker service");
-    info!("Connecting to Faktory at: {}", faktory_url);
+    // HTTP endpoint (`GET /version`, `/health`, `/metrics`); off unless an
+    // address is given
+    let http_addr = config.string("WORKER_HTTP_ADDR");
+    // How long a signal waits for in-flight jobs before abandoning them
+    let shutdown_timeout = Duration::from_secs(config.parse_or("WORKER_SHUTDOWN_TIMEOUT_SECS", 30));
+    // How often the worker records a heartbeat for `GET /workers` (off when 0)
+    let heartbeat_interval =
+        Duration::from_secs(config.parse_or("WORKER_HEARTBEAT_INTERVAL_SECS", 10));
+    // Backoff between attempts to reconnect to Faktory
+    let reconnect = Reconnect::from_config(&config);
+    // Independent worker instances run in the process
+    let supervisor_config = SupervisorConfig::from_config(&config)?;
+    let labels = WorkerLabels::from_config(&config)?;
 
-    // Set FAKTORY_URL environment variable for the client
-    std::env::set_var("FAKTORY_URL", &faktory_url);
+    config.validate()?;
+    config.log_effective();
+
+    let version = http::version_info();
+    info!(
+        "Starting worker service {} ({})",
+        version.version, version.git_sha
+    );
+    let metrics = Arc::new(Metrics::new(&labels)?);
+    let health = Arc::new(Health::new(
+        faktory.clone(),
+        worker_concurrency * supervisor_config.instances,
+        supervisor_config.instances * fetchers.len(),
+        labels.clone(),
+    ));
+    if let Some(addr) = &http_addr {
+        http::serve(addr, metrics.clone(), health.clone()).await?;
+    }
+    info!(
+        "Connecting to Faktory at: {}{}",
+        faktory.url(),
+        if faktory.is_tls() { " (TLS)" } else { "" }
+    );
+
+    info!("Connecting to {:?} result store", store_config.backend);
+    let result_store = result_store::connect(&store_config).await?;
+    let plugins = load_plugins(plugin_specs)?;
+    let completions = CompletionPublisher::connect(&completion_config).await?;
+    if let Some(completions) = &completions {
+        info!("Publishing completion events to {}", completions.channel());
+    }
+    let rate_limits = RateLimits::new(rate_limits, metrics.clone()).await?;
 
     // Setup graceful shutdown
-    let shutdown = Arc::new(Notify::new());
-    let shutdown_clone = shutdown.clone();
+    // Every connection to Faktory waits on it, so it's a watch rather than a
+    // one-off notification
+    let (shutdown_sender, shutdown) = watch::channel(false);
 
-    // Handle SIGTERM and SIGINT for graceful shutdown
+    // Handle SIGTERM and SIGINT (Ctrl+C elsewhere) for graceful shutdown
     tokio::spawn(async move {
-        let mut sigterm = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
-            .expect("Failed to setup SIGTERM handler");
-        let mut sigint = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::interrupt())
-            .expect("Failed to setup SIGINT handler");
-
-        tokio::select! {
-            _ = sigterm.recv() => {
-                warn!("Received SIGTERM, initiating graceful shutdown...");
-            }
-            _ = sigint.recv() => {
-                warn!("Received SIGINT (Ctrl+C), initiating graceful shutdown...");
-            }
-        }
-        shutdown_clone.notify_one();
+        shutdown::signalled().await;
+        let _ = shutdown_sender.send(true);
     });
 
-    // Get worker concurrency from environment or use high default for network efficiency
-    let worker_concurrency = std::env::var("WORKER_CONCURRENCY")
-        .ok()
-        .and_then(|v| v.parse().ok())
-        .unwrap_or(500); // High concurrency to hide network latency
-
-    // Build worker and register handlers with balanced concurrency
-    let mut worker = WorkerBuilder::default()
-        .hostname("worker-service".to_string())
-        .workers(worker_concurrency) // High concurrency masks network fetch latency
-        .register_fn("math_add", job_handler)
-        .register_fn("math_subtract", job_handler)
-        .register_fn("math_multiply", job_handler)
-        .register_fn("math_divide", job_handler)
-        .connect()
-        .await?;
-
-    info!("Worker connected and ready to process jobs");
-    info!("Concurrency: {} jobs per worker", worker_concurrency);
-    info!("Registered handlers: math_add, math_subtract, math_multiply, math_divide");
+    let producer = Arc::new(Producer::new(faktory.clone()));
+    let state = Arc::new(WorkerState {
+        dead_letters: Arc::new(DeadLetters::new(
+            result_store.clone(),
+            producer.clone(),
+            dead_queue,
+        )),
+        result_store,
+        producer: producer.clone(),
+        webhooks: WebhookNotifier::new(
+            WebhookConfig {
+                max_retries: webhook_max_retries,
+                timeout: Duration::from_secs(webhook_timeout_secs),
+            },
+            producer,
+        )?,
+        retry_policies,
+        completions,
+        cpu_pool: CpuPool::new(cpu_pool, metrics.clone()),
+        chaos,
+        result_log,
+        memo: (memo_capacity > 0).then(|| Memo::new(memo_capacity, metrics.clone())),
+    });
 
-    // Run worker with graceful shutdown support
-    let worker_handle = tokio::spawn(async move {
-        if let Err(e) = worker.run(&["default"]).await {
-            error!("Worker error: {:#}", e);
-            Err::<(), _>(e)
-        } else {
-            Ok(())
+    // Every handler shares the same result store connection, and runs behind
+    // the same middleware
+    let type_limits = type_concurrency.describe();
+    let chaos_described = state.chaos.as_ref().map(Chaos::describe);
+    let result_log_described = state.result_log.describe();
+    let rate_limits_described = rate_limits.describe();
+    let autoscaler = autoscale.map(|config| Arc::new(Autoscaler::new(config, metrics.clone())));
+    let cpu_job_types = state.cpu_pool.job_types().join(", ");
+    let dead_queue = state.dead_letters.queue().map(str::to_string);
+    // Outside panic isolation, so panics count as failures of the payload
+    let dry_run = DryRun::new(dry_run, state.producer.clone());
+    let quarantine = poison.filter(|_| !dry_run.is_enabled()).map(|config| {
+        Quarantine::new(
+            config,
+            state.result_store.clone(),
+            state.completions.clone(),
+            state.dead_letters.clone(),
+            metrics.clone(),
+        )
+    });
+    let quarantine_described = quarantine.as_ref().map(Quarantine::describe);
+    let drain = Arc::new(Drain::new(state.producer.clone()));
+    // Plugins also interrupt themselves at their timeout, which the
+    // middleware can't do to a call running on a CPU pool thread
+    let plugin_timeouts = timeouts.clone();
+    let chain = Chain::new()
+        .layer(drain.clone())
+        .layer(health.clone())
+        .layer(QueueWait(metrics.clone()))
+        .layer(middleware::Tracing)
+        .layer(
+            duplicate_guard
+                .then(|| DuplicateGuard::new(state.result_store.clone(), metrics.clone())),
+        )
+        .layer(quarantine)
+        .layer(CatchPanics::new(
+            JobFailures(state.clone()),
+            metrics.clone(),
+        ))
+        .layer(rate_limits)
+        .layer(autoscaler.clone())
+        .layer(type_concurrency)
+        .layer(JobTimeouts::new(
+            timeouts,
+            JobFailures(state.clone()),
+            metrics.clone(),
+        ))
+        .layer(middleware::Timing);
+
+    // A handler per job type behind the middleware chain, registered again
+    // on every connection to Faktory
+    let mut handlers: Vec<(String, JobFn)> = Vec::new();
+    for kind in JobPayload::KINDS {
+        if !job_selection.contains(kind.job_type) {
+            continue;
         }
+        if kind.variant == JobType::MathBatch {
+            let handler = MathBatchHandler {
+                state: state.clone(),
+                kind: *kind,
+                memory_limit: memory_limits.limit(kind.job_type),
+            };
+            handlers.push((
+                kind.job_type.to_string(),
+                Arc::new(chain.wrap(dry_run.wrap(handler))),
+            ));
+            continue;
+        }
+        let compute = math_fn(kind)
+            .ok_or_else(|| anyhow::anyhow!("No worker handler for {} jobs", kind.tag()))?;
+        let handler = MathHandler {
+            state: state.clone(),
+            kind: *kind,
+            compute,
+        };
+        handlers.push((
+            kind.job_type.to_string(),
+            Arc::new(chain.wrap(dry_run.wrap(handler))),
+        ));
+    }
+    for (job_type, plugin) in &plugins {
+        let handler = PluginHandler {
+            state: state.clone(),
+            plugin: plugin.clone(),
+            limits: JobLimits {
+                memory: memory_limits.limit(job_type),
+                time: plugin_timeouts.timeout(job_type),
+            },
+        };
+        handlers.push((
+            job_type.clone(),
+            Arc::new(chain.wrap(dry_run.wrap(handler))),
+        ));
+    }
+    let mut commands: Vec<(String, CommandSpec)> = commands.into_iter().collect();
+    commands.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    for (job_type, command) in &commands {
+        info!("Running {} jobs with: {}", job_type, command);
+        let handler = CommandHandler {
+            state: state.clone(),
+            command: command.clone(),
+            memory_limit: memory_limits.limit(job_type),
+        };
+        handlers.push((
+            job_type.clone(),
+            Arc::new(chain.wrap(dry_run.wrap(handler))),
+        ));
+    }
+    let worker_id = heartbeat::worker_id();
+    let result_store = state.result_store.clone();
+    if job_selection.contains(WEBHOOK_JOB_TYPE) {
+        handlers.push((
+            WEBHOOK_JOB_TYPE.to_string(),
+            Arc::new(chain.wrap(dry_run.wrap(WebhookHandler(state)))),
+        ));
+    }
+    let registered: Vec<String> = handlers.iter().map(|(t, _)| t.clone()).collect();
+    health.set_job_types(registered.clone());
+
+    // Drain in-flight jobs on a shutdown signal, then let the instances stop
+    let (drained_sender, drained) = watch::channel(false);
+    let signalled = shutdown_signalled(shutdown.clone());
+    let draining = drain.clone();
+    tokio::spawn(async move {
+        signalled.await;
+        draining.drain(shutdown_timeout).await;
+        let _ = drained_sender.send(true);
     });
+    let supervisor = Arc::new(Supervisor::new(
+        supervisor_config,
+        faktory.clone(),
+        worker_id.clone(),
+        labels.faktory_labels(),
+        fetchers,
+        handlers,
+        reconnect,
+        shutdown,
+        drained,
+        health.clone(),
+        metrics.clone(),
+    ));
+    let connected = supervisor.start().await?;
 
-    // Wait for shutdown signal
-    shutdown.notified().await;
-    info!("Shutdown signal received, stopping worker...");
-
-    // Give worker time to finish current jobs (up to 30 seconds)
-    tokio::select! {
-        result = worker_handle => {
-            match result {
-                Ok(Ok(())) => info!("Worker shut down cleanly"),
-                Ok(Err(e)) => error!("Worker error during shutdown: {:#}", e),
-                Err(e) => error!("Worker task panicked: {:#}", e),
+    info!("Worker {} connected and ready to process jobs", worker_id);
+    if let Some(chaos) = chaos_described {
+        warn!("Chaos mode: injecting {}", chaos);
+    }
+    if dry_run.is_enabled() {
+        warn!("Dry run: jobs are checked and requeued, not run");
+    }
+    info!("Concurrency: {} jobs per worker", worker_concurrency);
+    info!("Worker instances: {}", supervisor.describe());
+    info!("Labels: {}", labels);
+    info!("Logging results of {}", result_log_described);
+    if memo_capacity > 0 {
+        info!("Memoizing up to {} math results", memo_capacity);
+    }
+    if let Some(autoscaler) = autoscaler {
+        info!("Autoscaling concurrency: {}", autoscaler.describe());
+        tokio::spawn(autoscaler.run(faktory.clone(), worker_queues.clone()));
+    }
+    if !type_limits.is_empty() {
+        info!("Job type concurrency limits: {}", type_limits.join(", "));
+    }
+    if let Some(rate_limits) = rate_limits_described {
+        info!("Job type rate limits: {}", rate_limits);
+    }
+    if let Some(queue) = dead_queue {
+        info!("Copying dead jobs to queue: {}", queue);
+    }
+    if let Some(quarantine) = quarantine_described {
+        info!("Quarantining payloads after {}", quarantine);
+    }
+    if !cpu_job_types.is_empty() {
+        info!("Computing on the CPU pool: {}", cpu_job_types);
+    }
+    if let Some(job_types) = job_selection.describe() {
+        info!("Handling only: {}", job_types);
+    }
+    info!("Consuming queues: {}", worker_queues.join(", "));
+    if let Some(priority) = &priority {
+        info!("Fetching by priority: {}", priority.describe());
+    }
+    info!("Registered handlers: {}", registered.join(", "));
+    if !heartbeat_interval.is_zero() {
+        let heartbeat = Heartbeat::new(
+            worker_id.clone(),
+            worker_queues.clone(),
+            heartbeat_interval,
+            health.clone(),
+            result_store,
+        );
+        tokio::spawn(heartbeat.run());
+    }
+
+    // Runs until a shutdown signal or Faktory tells every instance to stop
+    for details in supervisor.supervise(connected).await {
+        match details.reason {
+            StopReason::ServerInstruction => {
+                warn!("Faktory told the worker to terminate, stopping...")
             }
+            _ => info!("Shutdown signal received, stopping worker..."),
         }
-        _ = tokio::time::sleep(tokio::time::Duration::from_secs(30)) => {
-            warn!("Worker shutdown timeout after 30s, some jobs may be re-queued by Faktory");
+        if details.workers_still_running == 0 {
+            info!("Worker shut down cleanly");
+        } else {
+            warn!(
+                "Failed {} abandoned jobs so Faktory re-queues them",
+                details.workers_still_running
+            );
         }
     }
 
     info!("Worker service terminated");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use job_types::{BackoffStrategy, RetryPolicy};
+    use result_store::{JobStatus, MemoryResultStore};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+    use tokio::sync::mpsc;
+
+    /// Accept a producer connection the way Faktory does, passing on the
+    /// jobs pushed over it. Returns the server's URL.
+    async fn fake_faktory() -> (String, mpsc::UnboundedReceiver<Job>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("tcp://{}", listener.local_addr().unwrap());
+        let (pushed, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            writer.write_all(b"+HI {\"v\":2}\r\n").await.unwrap();
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                if let Some(job) = line.strip_prefix("PUSH ") {
+                    let _ = pushed.send(serde_json::from_str(job).unwrap());
+                }
+                writer.write_all(b"+OK\r\n").await.unwrap();
+            }
+        });
+        (url, received)
+    }
+
+    /// Runs jobs that never finish
+    struct Stuck;
+
+    #[async_trait]
+    impl Handler for Stuck {
+        async fn run(&self, _job: &Job, _context: &JobContext) -> Result<()> {
+            std::future::pending().await
+        }
+
+        fn plan(&self, _job: &Job) -> Result<String> {
+            Ok("never finish".to_string())
+        }
+    }
+
+    fn worker_state(config: &Config, metrics: Arc<Metrics>) -> Arc<WorkerState> {
+        let result_store: Arc<dyn ResultStore> =
+            Arc::new(MemoryResultStore::new(Duration::from_secs(60)));
+        let producer = Arc::new(Producer::new(FaktoryConfig::from_config(config).unwrap()));
+        Arc::new(WorkerState {
+            dead_letters: Arc::new(DeadLetters::new(
+                result_store.clone(),
+                producer.clone(),
+                None,
+            )),
+            result_store,
+            producer: producer.clone(),
+            webhooks: WebhookNotifier::new(
+                WebhookConfig {
+                    max_retries: 0,
+                    timeout: Duration::from_secs(1),
+                },
+                producer,
+            )
+            .unwrap(),
+            retry_policies: RetryPolicies::default(),
+            completions: None,
+            cpu_pool: CpuPool::new(CpuPoolConfig::from_config(config), metrics),
+            chaos: None,
+            result_log: ResultLog::from_config(config),
+            memo: None,
+        })
+    }
+
+    // Dropping a Faktory client blocks in place, which needs worker threads
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_timed_out_backoff_job_is_pushed_again() {
+        let (url, mut pushed) = fake_faktory().await;
+        let dir = std::env::temp_dir().join(format!("worker-timeout-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("worker.toml");
+        std::fs::write(
+            &path,
+            format!("faktory_url = {:?}\nworker_job_timeout_secs = 1\n", url),
+        )
+        .unwrap();
+        let config = Config::from_file(&path).unwrap();
+        let metrics = Arc::new(Metrics::new(&WorkerLabels::default()).unwrap());
+        let state = worker_state(&config, metrics.clone());
+        let chain = Chain::new().layer(JobTimeouts::new(
+            TimeoutConfig::from_config(&config).unwrap(),
+            JobFailures(state.clone()),
+            metrics,
+        ));
+
+        let mut job = Job::new("math_add", vec![serde_json::json!({"a": 1.0, "b": 2.0})]);
+        job.retry = Some(-1);
+        let policy = RetryPolicy {
+            max_retries: 3,
+            strategy: BackoffStrategy::Fixed,
+            delay_seconds: 5,
+        };
+        job.custom.insert(
+            RetryPolicy::FIELD.to_string(),
+            serde_json::to_value(policy).unwrap(),
+        );
+        let job_id = job.id().to_string();
+
+        // Acked, since the retry is already queued
+        chain.wrap(Stuck)(job).await.unwrap();
+
+        let retry = pushed.recv().await.unwrap();
+        assert_eq!(retry.id().as_str(), job_id);
+        assert_eq!(retry.custom.get("retry_count"), Some(&1.into()));
+        assert!(retry.at.is_some());
+        let result = state.result_store.get(&job_id).await.unwrap().unwrap();
+        assert_eq!(result.status, JobStatus::Failed);
+        assert_eq!(result.error.as_deref(), Some("Job timed out after 1s"));
+        assert!(result.retrying);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
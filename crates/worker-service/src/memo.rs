@@ -0,0 +1,61 @@
+//! Memoized results of pure computations.
+//!
+//! Math jobs are pure: the same type and arguments always give the same
+//! value. With `WORKER_MEMO_CAPACITY` set, a worker keeps that many of their
+//! values in an LRU cache, keyed by a hash of the job type and arguments
+//! (less `request_id`, which only tags the job), and completes repeats from
+//! it without computing them again. Failures aren't cached, so they're
+//! retried as usual. `worker_memo_lookups_total` counts hits and misses by
+//! job type, for the hit rate.
+
+use crate::metrics::Metrics;
+use faktory::Job;
+use hashlink::LruCache;
+use sha2::{Digest, Sha256};
+use std::sync::{Arc, Mutex};
+
+/// Hash of a job's type and arguments
+pub type MemoKey = [u8; 32];
+
+/// The most recently used results, by the hash of what produced them
+pub struct Memo {
+    cache: Mutex<LruCache<MemoKey, serde_json::Value>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Memo {
+    /// A cache of up to `capacity` results
+    pub fn new(capacity: usize, metrics: Arc<Metrics>) -> Self {
+        Self {
+            cache: Mutex::new(LruCache::new(capacity)),
+            metrics,
+        }
+    }
+
+    /// The cache key for `job`'s result
+    pub fn key(&self, job: &Job) -> MemoKey {
+        let mut hasher = Sha256::new();
+        hasher.update(job.kind().as_bytes());
+        for arg in job.args() {
+            let mut arg = arg.clone();
+            if let Some(args) = arg.as_object_mut() {
+                args.remove("request_id");
+            }
+            hasher.update([0]);
+            // Serializing a `Value` can't fail
+            hasher.update(serde_json::to_vec(&arg).unwrap_or_default());
+        }
+        hasher.finalize().into()
+    }
+
+    /// The cached result for `key`, if any
+    pub fn get(&self, job_type: &str, key: &MemoKey) -> Option<serde_json::Value> {
+        let value = self.cache.lock().unwrap().get(key).cloned();
+        self.metrics.memo_lookup(job_type, value.is_some());
+        value
+    }
+
+    pub fn insert(&self, key: MemoKey, value: serde_json::Value) {
+        self.cache.lock().unwrap().insert(key, value);
+    }
+}
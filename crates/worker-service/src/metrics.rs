@@ -0,0 +1,210 @@
+//! Prometheus metrics for the worker, served at `/metrics` on
+//! `WORKER_HTTP_ADDR`. Every series carries the worker's labels (see
+//! [`crate::labels`]).
+
+use crate::labels::WorkerLabels;
+use anyhow::{Context, Result};
+use prometheus::{
+    exponential_buckets, histogram_opts, opts, Encoder, HistogramVec, IntCounterVec, IntGauge,
+    Registry, TextEncoder,
+};
+use std::time::Duration;
+
+/// Worker-side metrics, registered in a private registry
+pub struct Metrics {
+    registry: Registry,
+    jobs_timed_out: IntCounterVec,
+    jobs_panicked: IntCounterVec,
+    jobs_rate_limited: IntCounterVec,
+    jobs_quarantined: IntCounterVec,
+    jobs_duplicate: IntCounterVec,
+    memo_lookups: IntCounterVec,
+    job_queue_wait: HistogramVec,
+    cpu_pool_queue_depth: IntGauge,
+    cpu_pool_busy_threads: IntGauge,
+    concurrency_target: IntGauge,
+    instances_connected: IntGauge,
+    instance_restarts: IntCounterVec,
+}
+
+impl Metrics {
+    pub fn new(labels: &WorkerLabels) -> Result<Self> {
+        let registry = Registry::new_custom(
+            Some("worker".to_string()),
+            Some(labels.as_map().clone().into_iter().collect()),
+        )
+        .context("Failed to create metrics registry")?;
+
+        let jobs_timed_out = IntCounterVec::new(
+            opts!(
+                "jobs_timed_out_total",
+                "Jobs failed for running longer than their timeout"
+            ),
+            &["job_type"],
+        )?;
+        let jobs_panicked = IntCounterVec::new(
+            opts!(
+                "jobs_panicked_total",
+                "Jobs failed because their handler panicked"
+            ),
+            &["job_type"],
+        )?;
+        let jobs_rate_limited = IntCounterVec::new(
+            opts!(
+                "jobs_rate_limited_total",
+                "Jobs that waited for their type's rate limit"
+            ),
+            &["job_type"],
+        )?;
+        let jobs_quarantined = IntCounterVec::new(
+            opts!(
+                "jobs_quarantined_total",
+                "Jobs skipped because their payload kept failing"
+            ),
+            &["job_type"],
+        )?;
+        let jobs_duplicate = IntCounterVec::new(
+            opts!(
+                "jobs_duplicate_total",
+                "Redelivered jobs acknowledged without running, as they'd already completed"
+            ),
+            &["job_type"],
+        )?;
+        let memo_lookups = IntCounterVec::new(
+            opts!(
+                "memo_lookups_total",
+                "Lookups of memoized results, by whether one was found"
+            ),
+            &["job_type", "result"],
+        )?;
+        let job_queue_wait = HistogramVec::new(
+            histogram_opts!(
+                "job_queue_wait_seconds",
+                "Time jobs waited in their queue before a worker fetched them",
+                exponential_buckets(0.001, 2.0, 20)?
+            ),
+            &["job_type"],
+        )?;
+        let cpu_pool_queue_depth = IntGauge::new(
+            "cpu_pool_queue_depth",
+            "Jobs of CPU-bound types waiting for a CPU pool thread",
+        )?;
+        let cpu_pool_busy_threads =
+            IntGauge::new("cpu_pool_busy_threads", "CPU pool threads computing a job")?;
+        let concurrency_target = IntGauge::new(
+            "concurrency_target",
+            "Jobs the autoscaler currently lets run at once",
+        )?;
+        let instances_connected = IntGauge::new(
+            "instances_connected",
+            "Worker instances in the process whose fetch loop is running",
+        )?;
+        let instance_restarts = IntCounterVec::new(
+            opts!(
+                "instance_restarts_total",
+                "Worker instances replaced by the supervisor, by reason"
+            ),
+            &["reason"],
+        )?;
+
+        registry.register(Box::new(jobs_timed_out.clone()))?;
+        registry.register(Box::new(jobs_panicked.clone()))?;
+        registry.register(Box::new(jobs_rate_limited.clone()))?;
+        registry.register(Box::new(jobs_quarantined.clone()))?;
+        registry.register(Box::new(jobs_duplicate.clone()))?;
+        registry.register(Box::new(memo_lookups.clone()))?;
+        registry.register(Box::new(job_queue_wait.clone()))?;
+        registry.register(Box::new(cpu_pool_queue_depth.clone()))?;
+        registry.register(Box::new(cpu_pool_busy_threads.clone()))?;
+        registry.register(Box::new(concurrency_target.clone()))?;
+        registry.register(Box::new(instances_connected.clone()))?;
+        registry.register(Box::new(instance_restarts.clone()))?;
+
+        Ok(Self {
+            registry,
+            jobs_timed_out,
+            jobs_panicked,
+            jobs_rate_limited,
+            jobs_quarantined,
+            jobs_duplicate,
+            memo_lookups,
+            job_queue_wait,
+            cpu_pool_queue_depth,
+            cpu_pool_busy_threads,
+            concurrency_target,
+            instances_connected,
+            instance_restarts,
+        })
+    }
+
+    pub fn job_timed_out(&self, job_type: &str) {
+        self.jobs_timed_out.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn job_panicked(&self, job_type: &str) {
+        self.jobs_panicked.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn job_rate_limited(&self, job_type: &str) {
+        self.jobs_rate_limited.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn job_quarantined(&self, job_type: &str) {
+        self.jobs_quarantined.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn job_duplicate(&self, job_type: &str) {
+        self.jobs_duplicate.with_label_values(&[job_type]).inc();
+    }
+
+    pub fn memo_lookup(&self, job_type: &str, hit: bool) {
+        let result = if hit { "hit" } else { "miss" };
+        self.memo_lookups
+            .with_label_values(&[job_type, result])
+            .inc();
+    }
+
+    pub fn job_queue_wait(&self, job_type: &str, wait: Duration) {
+        self.job_queue_wait
+            .with_label_values(&[job_type])
+            .observe(wait.as_secs_f64());
+    }
+
+    /// Change the jobs waiting for a CPU pool thread by `delta`
+    pub fn cpu_pool_queued(&self, delta: i64) {
+        self.cpu_pool_queue_depth.add(delta);
+    }
+
+    /// Change the busy CPU pool threads by `delta`
+    pub fn cpu_pool_busy(&self, delta: i64) {
+        self.cpu_pool_busy_threads.add(delta);
+    }
+
+    /// Set the autoscaler's concurrency target
+    pub fn concurrency_target(&self, target: usize) {
+        self.concurrency_target.set(target as i64);
+    }
+
+    /// Record that an instance's fetch loop started or stopped
+    pub fn instance_connected(&self, connected: bool) {
+        if connected {
+            self.instances_connected.inc();
+        } else {
+            self.instances_connected.dec();
+        }
+    }
+
+    /// Count an instance replaced for `reason` (`panic` or `stalled`)
+    pub fn instance_restart(&self, reason: &str) {
+        self.instance_restarts.with_label_values(&[reason]).inc();
+    }
+
+    /// Encode every metric in the Prometheus text format
+    pub fn render(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .context("Failed to encode metrics")?;
+        String::from_utf8(buffer).context("Metrics are not valid UTF-8")
+    }
+}
@@ -0,0 +1,194 @@
+//! Middleware around job handlers.
+//!
+//! Every job runs through a [`Chain`] of [`Middleware`] on its way to the
+//! handler for its type. A middleware sees the job before and after the rest
+//! of the chain runs (or can skip it), so cross-cutting concerns such as
+//! tracing, timing and logging are layered once in `main` instead of being
+//! spliced into each handler.
+
+use crate::context::JobContext;
+use crate::request_id;
+use async_trait::async_trait;
+use faktory::Job;
+use job_types::{TraceParent, TRACE_CONTEXT_FIELD};
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, info_span, Instrument, Span};
+
+/// Runs jobs of one or more types
+#[async_trait]
+pub trait Handler: Send + Sync {
+    async fn run(&self, job: &Job, context: &JobContext) -> io::Result<()>;
+
+    /// What `run` would do with the job, checking its arguments like `run`
+    /// does but without any side effects; for dry runs
+    fn plan(&self, job: &Job) -> io::Result<String>;
+}
+
+/// A layer of the chain; calls `next.run(job)` to continue towards the
+/// handler
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()>;
+}
+
+/// A middleware shared with the rest of the worker, e.g. one it reads
+/// state back from
+#[async_trait]
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        self.as_ref().handle(job, next).await
+    }
+}
+
+/// A middleware that's only layered if configured; `None` passes jobs
+/// straight through
+#[async_trait]
+impl<M: Middleware> Middleware for Option<M> {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        match self {
+            Some(middleware) => middleware.handle(job, next).await,
+            None => next.run(job).await,
+        }
+    }
+}
+
+/// The rest of the chain, ending in the handler
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middleware: &'a [Arc<dyn Middleware>],
+    handler: &'a dyn Handler,
+    context: &'a JobContext,
+}
+
+impl<'a> Next<'a> {
+    pub async fn run(self, job: &Job) -> io::Result<()> {
+        match self.middleware.split_first() {
+            Some((first, rest)) => {
+                let next = Next {
+                    middleware: rest,
+                    ..self
+                };
+                first.handle(job, next).await
+            }
+            None => self.handler.run(job, self.context).await,
+        }
+    }
+
+    /// The context the handler will get
+    pub fn context(&self) -> &'a JobContext {
+        self.context
+    }
+}
+
+/// Job future returned by [`Chain::wrap`]
+pub type JobFuture = Pin<Box<dyn Future<Output = io::Result<()>> + Send>>;
+
+/// Middleware applied to every handler, outermost first
+#[derive(Clone, Default)]
+pub struct Chain {
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `middleware` inside the ones added so far
+    pub fn layer(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// `handler` behind the chain, for `WorkerBuilder::register_fn`
+    pub fn wrap(
+        &self,
+        handler: impl Handler + 'static,
+    ) -> impl Fn(Job) -> JobFuture + Clone + Send + Sync + 'static {
+        let middleware: Arc<[Arc<dyn Middleware>]> = self.middleware.clone().into();
+        let handler = Arc::new(handler);
+        move |job: Job| {
+            let middleware = middleware.clone();
+            let handler = handler.clone();
+            Box::pin(async move {
+                let context = JobContext::new(&job);
+                let next = Next {
+                    middleware: &middleware,
+                    handler: handler.as_ref(),
+                    context: &context,
+                };
+                next.run(&job).await
+            })
+        }
+    }
+}
+
+/// Runs the rest of the chain in a span carrying the job's id and type, the
+/// ID of the API request that enqueued it, the `request_id` the caller gave
+/// in the payload, and the trace the job was enqueued in
+pub struct Tracing;
+
+#[async_trait]
+impl Middleware for Tracing {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let span = job_span(job, next.context());
+        next.run(job).instrument(span).await
+    }
+}
+
+/// Span for running `job`
+fn job_span(job: &Job, context: &JobContext) -> Span {
+    let span = info_span!(
+        "job",
+        job_id = job.id().as_str(),
+        job_type = job.kind(),
+        attempt = context.attempt,
+        request_id = tracing::field::Empty,
+        payload_request_id = tracing::field::Empty,
+        trace_id = tracing::field::Empty,
+        parent_span_id = tracing::field::Empty,
+    );
+    if let Some(request_id) = request_id(job) {
+        span.record("request_id", request_id);
+    }
+    let payload_request_id = job
+        .args()
+        .first()
+        .and_then(|args| args.get("request_id"))
+        .and_then(|v| v.as_str());
+    if let Some(request_id) = payload_request_id {
+        span.record("payload_request_id", request_id);
+    }
+    let trace_parent = job
+        .custom
+        .get(TRACE_CONTEXT_FIELD)
+        .and_then(|context| context.get("traceparent"))
+        .and_then(|v| v.as_str())
+        .and_then(TraceParent::parse);
+    if let Some(trace_parent) = trace_parent {
+        span.record("trace_id", trace_parent.trace_id);
+        span.record("parent_span_id", trace_parent.parent_id);
+    }
+    span
+}
+
+/// Logs how long each job took, at debug level
+pub struct Timing;
+
+#[async_trait]
+impl Middleware for Timing {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let started = Instant::now();
+        let result = next.run(job).await;
+        debug!(
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            ok = result.is_ok(),
+            "Job finished"
+        );
+        result
+    }
+}
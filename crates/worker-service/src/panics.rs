@@ -0,0 +1,62 @@
+//! Panic isolation for job handlers.
+//!
+//! A handler that panics fails its job with the panic message, as if it had
+//! returned an error, instead of unwinding into the worker:
+//! `worker_jobs_panicked_total` counts it, and its result, retries, webhook
+//! and dead-lettering go the way of any other failure.
+
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::JobFailures;
+use async_trait::async_trait;
+use chrono::Utc;
+use faktory::Job;
+use futures_util::FutureExt;
+use std::any::Any;
+use std::io;
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use tracing::error;
+
+/// Turns panics in the rest of the chain into job failures
+pub struct CatchPanics {
+    failures: JobFailures,
+    metrics: Arc<Metrics>,
+}
+
+impl CatchPanics {
+    pub fn new(failures: JobFailures, metrics: Arc<Metrics>) -> Self {
+        Self { failures, metrics }
+    }
+}
+
+#[async_trait]
+impl Middleware for CatchPanics {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let started_at = Utc::now();
+        // Whatever the handler was in the middle of is dropped with it, so
+        // no half-updated state is observed after the unwind
+        match AssertUnwindSafe(next.run(job)).catch_unwind().await {
+            Ok(result) => result,
+            Err(payload) => {
+                let message = format!("Job panicked: {}", panic_message(payload.as_ref()));
+                error!("{}", message);
+                self.metrics.job_panicked(job.kind());
+                self.failures
+                    .fail(job, next.context(), started_at, io::Error::other(message))
+                    .await
+            }
+        }
+    }
+}
+
+/// The message `panic!` was given, if it was a string
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
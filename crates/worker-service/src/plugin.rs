@@ -0,0 +1,225 @@
+//! Job handlers loaded from WebAssembly modules.
+//!
+//! `WORKER_WASM_PLUGINS` maps job types to functions exported by `.wasm`
+//! files, e.g. `geo_lookup=/plugins/geo.wasm#lookup` (the function defaults
+//! to `handle`), so new job logic ships as a module instead of a new worker
+//! build. Modules are compiled once at startup, and every job runs in a fresh
+//! instance, so nothing leaks between jobs.
+//!
+//! Input and output are JSON, passed through the module's memory. A module
+//! exports:
+//!
+//! - `memory`;
+//! - `alloc(len: i32) -> i32`, returning where the worker may write `len`
+//!   bytes;
+//! - the handler, `(ptr: i32, len: i32) -> i64`, called with the job's first
+//!   argument as JSON and returning `(out_ptr << 32) | out_len` of its
+//!   output: `{"result": ...}` to complete the job with that value, or
+//!   `{"error": "..."}` to fail it.
+//!
+//! A trap fails the job with the trap's message. Handlers run like any
+//! other: through the middleware chain, on the CPU pool if their type is in
+//! `WORKER_CPU_JOB_TYPES`, with their result stored and retried on failure.
+//! Each call is held to its type's [`JobLimits`]: growing memory past the
+//! limit or running past the timeout traps.
+
+use crate::resources::{memory_exceeded, JobLimits};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+use wasmtime::{Config, Engine, InstancePre, Linker, Module, ResourceLimiter, Store, Trap};
+
+/// Handler export used when a plugin entry doesn't name one
+const DEFAULT_EXPORT: &str = "handle";
+
+/// How often running plugins check their deadline
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// An engine whose plugins can be interrupted, with a thread advancing its
+/// epoch for as long as the worker runs
+pub fn engine() -> Result<Engine> {
+    let mut config = Config::new();
+    config.epoch_interruption(true);
+    let engine = Engine::new(&config)?;
+    let ticking = engine.clone();
+    thread::Builder::new()
+        .name("wasm-epoch".to_string())
+        .spawn(move || loop {
+            thread::sleep(EPOCH_TICK);
+            ticking.increment_epoch();
+        })
+        .context("Failed to start WASM epoch thread")?;
+    Ok(engine)
+}
+
+/// A `WORKER_WASM_PLUGINS` value: `path[#export]`
+#[derive(Debug, Clone)]
+pub struct PluginSpec {
+    path: String,
+    export: String,
+}
+
+impl FromStr for PluginSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (path, export) = s.split_once('#').unwrap_or((s, DEFAULT_EXPORT));
+        if path.is_empty() || export.is_empty() {
+            return Err("expected path/to/module.wasm#export".to_string());
+        }
+        Ok(Self {
+            path: path.to_string(),
+            export: export.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for PluginSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}#{}", self.path, self.export)
+    }
+}
+
+/// What a handler returns
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Output {
+    Result(serde_json::Value),
+    Error(String),
+}
+
+/// Store data: enforces a call's memory limit and remembers hitting it
+#[derive(Default)]
+struct MemoryLimiter {
+    limit: Option<usize>,
+    exceeded: bool,
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(
+        &mut self,
+        _current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        if self.limit.is_some_and(|limit| desired > limit) {
+            self.exceeded = true;
+            bail!("Memory limit exceeded");
+        }
+        Ok(true)
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+/// A compiled module and the handler it exports
+pub struct Plugin {
+    spec: PluginSpec,
+    engine: Engine,
+    instance: InstancePre<MemoryLimiter>,
+}
+
+impl Plugin {
+    /// Compile the module and check it exports what a handler needs
+    pub fn load(engine: &Engine, spec: PluginSpec) -> Result<Self> {
+        let module = Module::from_file(engine, &spec.path)
+            .with_context(|| format!("Failed to load WASM plugin {}", spec.path))?;
+        for export in ["memory", "alloc", spec.export.as_str()] {
+            if module.get_export(export).is_none() {
+                bail!("WASM plugin {} doesn't export {}", spec.path, export);
+            }
+        }
+        // Plugins get no host functions: input and output go through memory
+        let instance = Linker::new(engine)
+            .instantiate_pre(&module)
+            .with_context(|| format!("WASM plugin {} has unresolved imports", spec.path))?;
+        Ok(Self {
+            spec,
+            engine: engine.clone(),
+            instance,
+        })
+    }
+
+    /// `path#export`, for logging
+    pub fn describe(&self) -> String {
+        self.spec.to_string()
+    }
+
+    /// Run the handler on `input` in a fresh instance, within `limits`.
+    /// `Err` is a failure of the job, whether the handler reported it,
+    /// trapped or went over a limit.
+    pub fn call(
+        &self,
+        input: &serde_json::Value,
+        limits: JobLimits,
+    ) -> io::Result<serde_json::Value> {
+        let mut store = Store::new(
+            &self.engine,
+            MemoryLimiter {
+                limit: limits.memory,
+                exceeded: false,
+            },
+        );
+        store.limiter(|limiter| limiter);
+        // Without a timeout, a deadline no call will reach
+        let ticks = limits.time.map_or(u64::MAX / 2, |time| {
+            (time.as_millis() / EPOCH_TICK.as_millis()).max(1) as u64
+        });
+        store.set_epoch_deadline(ticks);
+
+        self.try_call(&mut store, input).map_err(|e| {
+            if let (Some(limit), true) = (limits.memory, store.data().exceeded) {
+                return memory_exceeded(limit);
+            }
+            if let (Some(time), Some(Trap::Interrupt)) = (limits.time, e.downcast_ref::<Trap>()) {
+                return io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Plugin interrupted after {}s", time.as_secs()),
+                );
+            }
+            io::Error::other(format!("{:#}", e))
+        })
+    }
+
+    fn try_call(
+        &self,
+        store: &mut Store<MemoryLimiter>,
+        input: &serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let input = serde_json::to_vec(input).context("Failed to serialize plugin input")?;
+        let len = i32::try_from(input.len()).context("Plugin input too large")?;
+
+        let instance = self.instance.instantiate(&mut *store)?;
+        let memory = instance
+            .get_memory(&mut *store, "memory")
+            .ok_or_else(|| anyhow!("Plugin memory export isn't a memory"))?;
+        let alloc = instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+        let handler = instance.get_typed_func::<(i32, i32), i64>(&mut *store, &self.spec.export)?;
+
+        let ptr = alloc.call(&mut *store, len)?;
+        memory
+            .write(&mut *store, ptr as u32 as usize, &input)
+            .context("Plugin allocated out of bounds")?;
+        let packed = handler.call(&mut *store, (ptr, len))? as u64;
+
+        let mut output = vec![0; (packed & 0xffff_ffff) as usize];
+        memory
+            .read(&*store, (packed >> 32) as usize, &mut output)
+            .context("Plugin returned output out of bounds")?;
+        match serde_json::from_slice(&output).context("Invalid plugin output")? {
+            Output::Result(value) => Ok(value),
+            Output::Error(message) => Err(anyhow!(message)),
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Poison-pill quarantine.
+//!
+//! With `WORKER_POISON_THRESHOLD` set, each failure handed back to Faktory is
+//! counted against a hash of the job's type and arguments, in the result
+//! store so every worker sees the same counts. Once a payload has failed that
+//! many times within `WORKER_POISON_WINDOW_SECS`, jobs carrying it are no
+//! longer run: they're recorded as dead (see [`crate::dead_letter`]) with a
+//! `failed` result and acknowledged, instead of cycling through Faktory's
+//! retries and inflating error metrics. This also catches the same payload
+//! resubmitted under a new job id. `worker_jobs_quarantined_total` counts
+//! them.
+
+use crate::dead_letter::DeadLetters;
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::store_failure;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use faktory::Job;
+use result_store::{CompletionPublisher, ResultStore};
+use service_config::Config;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// When a payload counts as poison
+#[derive(Debug, Clone)]
+pub struct PoisonConfig {
+    threshold: u64,
+    window: Duration,
+}
+
+impl PoisonConfig {
+    /// Read `WORKER_POISON_THRESHOLD` (unset disables quarantine) and
+    /// `WORKER_POISON_WINDOW_SECS` (default 86400)
+    pub fn from_config(config: &Config) -> Result<Option<Self>> {
+        let threshold = config.parse::<u64>("WORKER_POISON_THRESHOLD");
+        let window_secs = config.parse_or("WORKER_POISON_WINDOW_SECS", 86400u64);
+        let Some(threshold) = threshold else {
+            return Ok(None);
+        };
+        if threshold == 0 {
+            bail!("WORKER_POISON_THRESHOLD must be above 0");
+        }
+        if window_secs == 0 {
+            bail!("WORKER_POISON_WINDOW_SECS must be above 0");
+        }
+        Ok(Some(Self {
+            threshold,
+            window: Duration::from_secs(window_secs),
+        }))
+    }
+}
+
+/// Counts payload failures and skips payloads past the threshold
+pub struct Quarantine {
+    config: PoisonConfig,
+    result_store: Arc<dyn ResultStore>,
+    completions: Option<CompletionPublisher>,
+    dead_letters: Arc<DeadLetters>,
+    metrics: Arc<Metrics>,
+}
+
+impl Quarantine {
+    pub fn new(
+        config: PoisonConfig,
+        result_store: Arc<dyn ResultStore>,
+        completions: Option<CompletionPublisher>,
+        dead_letters: Arc<DeadLetters>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            config,
+            result_store,
+            completions,
+            dead_letters,
+            metrics,
+        }
+    }
+
+    /// `threshold failures within window`, for logging
+    pub fn describe(&self) -> String {
+        format!(
+            "{} failures within {}s",
+            self.config.threshold,
+            self.config.window.as_secs()
+        )
+    }
+
+    /// Skip the job and record it as dead
+    async fn quarantine(&self, job: &Job, failures: u64) {
+        let message = format!(
+            "Quarantined: the same payload failed {} times within {}s",
+            failures,
+            self.config.window.as_secs()
+        );
+        warn!("{}", message);
+        self.metrics.job_quarantined(job.kind());
+        store_failure(
+            self.result_store.as_ref(),
+            self.completions.as_ref(),
+            job,
+            &message,
+            Utc::now(),
+        )
+        .await;
+        if let Err(e) = self.dead_letters.bury(job, &message).await {
+            warn!(
+                "Failed to record quarantined job {}: {:#}",
+                job.id().as_str(),
+                e
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for Quarantine {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let hash = match payload_hash(job) {
+            Ok(hash) => hash,
+            Err(e) => {
+                warn!(
+                    "Failed to hash payload of job {}: {:#}",
+                    job.id().as_str(),
+                    e
+                );
+                return next.run(job).await;
+            }
+        };
+
+        // A store outage shouldn't stop healthy jobs from running
+        match self.result_store.payload_failures(&hash).await {
+            Ok(failures) if failures >= self.config.threshold => {
+                self.quarantine(job, failures).await;
+                return Ok(());
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to read failures of job {}'s payload: {:#}",
+                job.id().as_str(),
+                e
+            ),
+        }
+
+        let result = next.run(job).await;
+        if result.is_err() {
+            if let Err(e) = self
+                .result_store
+                .add_payload_failure(&hash, self.config.window)
+                .await
+            {
+                warn!(
+                    "Failed to count failure of job {}'s payload: {:#}",
+                    job.id().as_str(),
+                    e
+                );
+            }
+        }
+        result
+    }
+}
+
+/// Hex SHA-256 of the job type and arguments
+fn payload_hash(job: &Job) -> Result<String> {
+    let args = serde_json::to_vec(job.args()).context("Failed to serialize job args")?;
+    let mut hasher = Sha256::new();
+    hasher.update(job.kind().as_bytes());
+    hasher.update([0]);
+    hasher.update(&args);
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,196 @@
+//! Priority-aware fetching.
+//!
+//! Faktory checks a fetch's queues in the order given, so a worker drains its
+//! earlier queues before touching later ones. That alone doesn't keep
+//! interactive jobs moving during a big backfill: whenever the priority
+//! queues run dry every fetcher picks up bulk work, and the next interactive
+//! job waits for one to finish; while interactive jobs keep coming, bulk ones
+//! never run at all.
+//!
+//! `WORKER_BULK_QUEUES` names the worker's bulk queues and splits each
+//! instance's `WORKER_CONCURRENCY` fetchers three ways, each group its own
+//! Faktory worker:
+//!
+//! - `WORKER_PRIORITY_RESERVED` (default 1) fetch only from the other,
+//!   priority queues, so an interactive job never waits behind bulk ones;
+//! - `WORKER_BULK_RESERVED` (default 1) fetch from the bulk queues first, so
+//!   a backfill keeps moving however busy the priority queues are;
+//! - the rest drain the priority queues and only then fetch bulk work.
+
+use anyhow::{bail, Result};
+use service_config::Config;
+
+/// Fetchers sharing a queue order
+#[derive(Debug, Clone)]
+pub struct Fetchers {
+    pub concurrency: usize,
+    pub queues: Vec<String>,
+}
+
+/// The bulk queues and the fetchers reserved on either side of them
+#[derive(Debug, Clone)]
+pub struct PriorityConfig {
+    bulk_queues: Vec<String>,
+    priority_reserved: usize,
+    bulk_reserved: usize,
+}
+
+impl PriorityConfig {
+    /// Read `WORKER_BULK_QUEUES` (comma-separated; unset to fetch in plain
+    /// queue order), `WORKER_PRIORITY_RESERVED` and `WORKER_BULK_RESERVED`
+    /// (default 1 each)
+    pub fn from_config(config: &Config) -> Option<Self> {
+        let bulk_queues = config.string("WORKER_BULK_QUEUES");
+        let priority_reserved = config.parse_or("WORKER_PRIORITY_RESERVED", 1usize);
+        let bulk_reserved = config.parse_or("WORKER_BULK_RESERVED", 1usize);
+        let bulk_queues: Vec<String> = bulk_queues?
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(String::from)
+            .collect();
+        (!bulk_queues.is_empty()).then_some(Self {
+            bulk_queues,
+            priority_reserved,
+            bulk_reserved,
+        })
+    }
+
+    /// Split `concurrency` fetchers of `queues` (in priority order) into
+    /// groups, skipping empty ones
+    pub fn fetchers(&self, queues: &[String], concurrency: usize) -> Result<Vec<Fetchers>> {
+        if let Some(unknown) = self.bulk_queues.iter().find(|q| !queues.contains(q)) {
+            bail!(
+                "WORKER_BULK_QUEUES names {}, which the worker doesn't consume",
+                unknown
+            );
+        }
+        let (bulk, priority): (Vec<String>, Vec<String>) = queues
+            .iter()
+            .cloned()
+            .partition(|queue| self.bulk_queues.contains(queue));
+        if priority.is_empty() {
+            bail!("WORKER_BULK_QUEUES leaves the worker no priority queues");
+        }
+        let reserved = self.priority_reserved + self.bulk_reserved;
+        if reserved > concurrency {
+            bail!(
+                "WORKER_PRIORITY_RESERVED and WORKER_BULK_RESERVED add up to more than the {} fetchers of WORKER_CONCURRENCY",
+                concurrency
+            );
+        }
+
+        let groups = [
+            (self.priority_reserved, priority.clone()),
+            (concurrency - reserved, [&priority[..], &bulk[..]].concat()),
+            (self.bulk_reserved, [&bulk[..], &priority[..]].concat()),
+        ];
+        Ok(groups
+            .into_iter()
+            .filter(|(concurrency, _)| *concurrency > 0)
+            .map(|(concurrency, queues)| Fetchers {
+                concurrency,
+                queues,
+            })
+            .collect())
+    }
+
+    /// The bulk queues and reservations, for logging
+    pub fn describe(&self) -> String {
+        format!(
+            "bulk queues {}, {} fetchers reserved for priority queues and {} for bulk",
+            self.bulk_queues.join(", "),
+            self.priority_reserved,
+            self.bulk_reserved
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        bulk_queues: &[&str],
+        priority_reserved: usize,
+        bulk_reserved: usize,
+    ) -> PriorityConfig {
+        PriorityConfig {
+            bulk_queues: queues(bulk_queues),
+            priority_reserved,
+            bulk_reserved,
+        }
+    }
+
+    fn queues(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn groups(fetchers: &[Fetchers]) -> Vec<(usize, Vec<&str>)> {
+        fetchers
+            .iter()
+            .map(|group| {
+                let queues = group.queues.iter().map(String::as_str).collect();
+                (group.concurrency, queues)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fetcher_groups() {
+        let fetchers = config(&["backfill"], 2, 1)
+            .fetchers(&queues(&["critical", "default", "backfill"]), 10)
+            .unwrap();
+        assert_eq!(
+            groups(&fetchers),
+            [
+                (2, vec!["critical", "default"]),
+                (7, vec!["critical", "default", "backfill"]),
+                (1, vec!["backfill", "critical", "default"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_groups_are_skipped() {
+        // Every fetcher reserved: no shared group
+        let fetchers = config(&["backfill"], 1, 1)
+            .fetchers(&queues(&["default", "backfill"]), 2)
+            .unwrap();
+        assert_eq!(
+            groups(&fetchers),
+            [(1, vec!["default"]), (1, vec!["backfill", "default"])]
+        );
+
+        // Nothing reserved: plain queue order
+        let fetchers = config(&["backfill"], 0, 0)
+            .fetchers(&queues(&["default", "backfill"]), 5)
+            .unwrap();
+        assert_eq!(groups(&fetchers), [(5, vec!["default", "backfill"])]);
+    }
+
+    #[test]
+    fn test_unknown_bulk_queue() {
+        let err = config(&["backfill", "archive"], 1, 1)
+            .fetchers(&queues(&["default", "backfill"]), 10)
+            .unwrap_err();
+        assert!(err.to_string().contains("names archive"));
+    }
+
+    #[test]
+    fn test_no_priority_queues() {
+        let err = config(&["default", "backfill"], 1, 1)
+            .fetchers(&queues(&["default", "backfill"]), 10)
+            .unwrap_err();
+        assert!(err.to_string().contains("no priority queues"));
+    }
+
+    #[test]
+    fn test_reservations_over_concurrency() {
+        let config = config(&["backfill"], 2, 2);
+        let queues = queues(&["default", "backfill"]);
+        let err = config.fetchers(&queues, 3).unwrap_err();
+        assert!(err.to_string().contains("more than the 3 fetchers"));
+        assert!(config.fetchers(&queues, 4).is_ok());
+    }
+}
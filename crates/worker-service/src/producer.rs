@@ -0,0 +1,42 @@
+//! Producer connection the worker uses to push follow-up jobs (webhook
+//! deliveries, worker-scheduled retries).
+
+use anyhow::{Context, Result};
+use faktory::{Client, Job};
+use service_tls::faktory::FaktoryConfig;
+use tokio::sync::Mutex;
+
+/// Lazily connected Faktory client shared by all handlers
+pub struct Producer {
+    faktory: FaktoryConfig,
+    client: Mutex<Option<Client>>,
+}
+
+impl Producer {
+    pub fn new(faktory: FaktoryConfig) -> Self {
+        Self {
+            faktory,
+            client: Mutex::new(None),
+        }
+    }
+
+    /// Push a job, connecting on first use and reconnecting after errors
+    pub async fn enqueue(&self, job: Job) -> Result<()> {
+        let mut client = self.client.lock().await;
+        if client.is_none() {
+            *client = Some(
+                self.faktory
+                    .client()
+                    .await
+                    .context("Failed to connect to Faktory as a producer")?,
+            );
+        }
+
+        if let Err(e) = client.as_mut().unwrap().enqueue(job).await {
+            // Drop the connection so the next push reconnects
+            *client = None;
+            return Err(e).context("Failed to enqueue job");
+        }
+        Ok(())
+    }
+}
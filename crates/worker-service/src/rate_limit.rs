@@ -0,0 +1,198 @@
+//! Per-job-type rate limits.
+//!
+//! `WORKER_RATE_LIMITS` caps how many jobs of a type start per second, e.g.
+//! `webhook_delivery=100` so a worker running hundreds of jobs at once
+//! doesn't hammer the endpoints they call. Each type gets a token bucket
+//! holding up to one second's worth of jobs; a job that finds it empty waits
+//! in the worker, still holding the slot it was fetched into.
+//!
+//! Buckets are per worker process unless `WORKER_RATE_LIMIT_REDIS_URL` is
+//! set, in which case every worker pointed at the same Redis shares them and
+//! the limit holds across the fleet. If Redis can't be reached, jobs run
+//! unlimited rather than stall.
+
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::per_type_setting;
+use anyhow::{bail, Context, Result};
+use async_trait::async_trait;
+use faktory::Job;
+use redis::aio::ConnectionManager;
+use service_config::Config;
+use std::collections::HashMap;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Prefix for the shared buckets' Redis keys
+const BUCKET_KEY_PREFIX: &str = "worker_rate_limit:";
+
+/// Takes a token from the bucket in `KEYS[1]`, refilled at `ARGV[1]` tokens
+/// per second up to `ARGV[2]`. Returns 0 if it got one, otherwise how many
+/// milliseconds until one is due. Uses the Redis clock so workers' clocks
+/// don't have to agree.
+const TAKE_TOKEN_SCRIPT: &str = r"
+local rate = tonumber(ARGV[1])
+local capacity = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'at')
+local tokens = tonumber(bucket[1]) or capacity
+local at = tonumber(bucket[2]) or now
+tokens = math.min(capacity, tokens + math.max(0, now - at) * rate / 1000)
+local wait = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+else
+    wait = math.ceil((1 - tokens) * 1000 / rate)
+end
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'at', now)
+redis.call('PEXPIRE', KEYS[1], math.ceil(capacity * 1000 / rate) + 1000)
+return wait
+";
+
+/// Jobs per second allowed for each limited type, and where the buckets live
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitConfig {
+    rates: HashMap<String, f64>,
+    redis_url: Option<String>,
+}
+
+impl RateLimitConfig {
+    /// Read `WORKER_RATE_LIMITS` (`job_type=per_second` pairs, e.g.
+    /// `webhook_delivery=100,send_email=10`) and `WORKER_RATE_LIMIT_REDIS_URL`
+    /// (unset for per-worker buckets)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let rates: HashMap<String, f64> = per_type_setting(config, "WORKER_RATE_LIMITS")?;
+        if let Some(job_type) = rates
+            .iter()
+            .find_map(|(job_type, &rate)| (!(rate > 0.0 && rate.is_finite())).then_some(job_type))
+        {
+            bail!("WORKER_RATE_LIMITS for {} must be above 0", job_type);
+        }
+        Ok(Self {
+            rates,
+            redis_url: config.string("WORKER_RATE_LIMIT_REDIS_URL"),
+        })
+    }
+}
+
+/// Where tokens are taken from
+enum Buckets {
+    Local(Mutex<HashMap<String, LocalBucket>>),
+    Redis(ConnectionManager),
+}
+
+struct LocalBucket {
+    tokens: f64,
+    at: Instant,
+}
+
+/// Delays jobs of rate-limited types until their bucket has a token
+pub struct RateLimits {
+    rates: HashMap<String, f64>,
+    buckets: Buckets,
+    metrics: Arc<Metrics>,
+}
+
+impl RateLimits {
+    /// Connects to Redis if the buckets are shared
+    pub async fn new(config: RateLimitConfig, metrics: Arc<Metrics>) -> Result<Self> {
+        let buckets = match &config.redis_url {
+            Some(url) if !config.rates.is_empty() => {
+                let client = redis::Client::open(url.as_str())
+                    .context("Invalid WORKER_RATE_LIMIT_REDIS_URL")?;
+                let conn = ConnectionManager::new(client)
+                    .await
+                    .context("Failed to connect to Redis for rate limits")?;
+                Buckets::Redis(conn)
+            }
+            _ => Buckets::Local(Mutex::new(HashMap::new())),
+        };
+        Ok(Self {
+            rates: config.rates,
+            buckets,
+            metrics,
+        })
+    }
+
+    /// The limits as `job_type=per_second`, and whether they're shared, for
+    /// logging
+    pub fn describe(&self) -> Option<String> {
+        if self.rates.is_empty() {
+            return None;
+        }
+        let mut rates: Vec<String> = self
+            .rates
+            .iter()
+            .map(|(job_type, rate)| format!("{}={}/s", job_type, rate))
+            .collect();
+        rates.sort_unstable();
+        let scope = match self.buckets {
+            Buckets::Local(_) => "per worker",
+            Buckets::Redis(_) => "shared through Redis",
+        };
+        Some(format!("{} ({})", rates.join(", "), scope))
+    }
+
+    /// Take a token for `job_type`, or how long until one is due
+    async fn take(&self, job_type: &str, rate: f64) -> Duration {
+        match &self.buckets {
+            Buckets::Local(buckets) => {
+                let now = Instant::now();
+                let mut buckets = buckets.lock().unwrap();
+                let bucket = buckets.entry(job_type.to_string()).or_insert(LocalBucket {
+                    tokens: rate.max(1.0),
+                    at: now,
+                });
+                let refill = now.duration_since(bucket.at).as_secs_f64() * rate;
+                bucket.tokens = (bucket.tokens + refill).min(rate.max(1.0));
+                bucket.at = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    Duration::ZERO
+                } else {
+                    Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+                }
+            }
+            Buckets::Redis(conn) => {
+                let mut conn = conn.clone();
+                let wait_ms: Result<u64, _> = redis::Script::new(TAKE_TOKEN_SCRIPT)
+                    .key(format!("{}{}", BUCKET_KEY_PREFIX, job_type))
+                    .arg(rate)
+                    .arg(rate.max(1.0))
+                    .invoke_async(&mut conn)
+                    .await;
+                match wait_ms {
+                    Ok(wait_ms) => Duration::from_millis(wait_ms),
+                    Err(e) => {
+                        warn!("Failed to take a rate limit token from Redis: {}", e);
+                        Duration::ZERO
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimits {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        if let Some(&rate) = self.rates.get(job.kind()) {
+            let mut limited = false;
+            loop {
+                let wait = self.take(job.kind(), rate).await;
+                if wait.is_zero() {
+                    break;
+                }
+                limited = true;
+                tokio::time::sleep(wait).await;
+            }
+            if limited {
+                self.metrics.job_rate_limited(job.kind());
+            }
+        }
+        next.run(job).await
+    }
+}
@@ -0,0 +1,79 @@
+//! Reconnecting to Faktory after losing it.
+//!
+//! When the worker's connection to Faktory fails (say, Faktory restarted),
+//! the worker connects again instead of exiting, so a Faktory restart doesn't
+//! take the fleet down with it. Attempts are spaced by a random delay up to
+//! an exponentially growing cap (full jitter, so workers don't reconnect in
+//! lockstep): `WORKER_RECONNECT_BASE_MS` (default 500) for the first,
+//! doubling up to `WORKER_RECONNECT_MAX_SECS` (default 30). The worker keeps
+//! trying until it connects or gets a shutdown signal.
+
+use service_config::Config;
+use std::fmt;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{info, warn};
+
+/// How reconnects are spaced
+#[derive(Debug, Clone)]
+pub struct Reconnect {
+    /// Cap on the delay before the first attempt, doubled for each one after
+    base_delay: Duration,
+    /// Cap on any single delay
+    max_delay: Duration,
+}
+
+impl Reconnect {
+    /// Read `WORKER_RECONNECT_BASE_MS` and `WORKER_RECONNECT_MAX_SECS`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            base_delay: Duration::from_millis(config.parse_or("WORKER_RECONNECT_BASE_MS", 500)),
+            max_delay: Duration::from_secs(config.parse_or("WORKER_RECONNECT_MAX_SECS", 30)),
+        }
+    }
+
+    /// Random delay before attempt number `attempt` (0 for the first)
+    fn backoff(&self, attempt: u32) -> Duration {
+        let cap = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(self.max_delay);
+        cap.mul_f64(fastrand::f64())
+    }
+
+    /// Run `connect` until it succeeds, backing off before each attempt.
+    /// `None` if `stop` resolves first.
+    pub async fn connect<T, E, Fut>(
+        &self,
+        mut connect: impl FnMut() -> Fut,
+        stop: impl Future<Output = ()>,
+    ) -> Option<T>
+    where
+        E: fmt::Display,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        tokio::pin!(stop);
+        let mut attempt = 0;
+        loop {
+            let delay = self.backoff(attempt);
+            info!(
+                "Reconnecting to Faktory in {}ms (attempt {})",
+                delay.as_millis(),
+                attempt + 1
+            );
+            tokio::select! {
+                _ = sleep(delay) => {}
+                _ = &mut stop => return None,
+            }
+            tokio::select! {
+                result = connect() => match result {
+                    Ok(connected) => return Some(connected),
+                    Err(e) => warn!("Failed to reconnect to Faktory: {}", e),
+                },
+                _ = &mut stop => return None,
+            }
+            attempt = attempt.saturating_add(1);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Per-job resource ceilings.
+//!
+//! `WORKER_JOB_MEMORY_MB`, or a type's entry in `WORKER_TYPE_MEMORY_LIMITS`,
+//! caps the memory of jobs run by WASM plugins (their linear memory),
+//! external commands (their resident set) and `math_batch` jobs (their
+//! operations and results, checked before any are parsed). A job that goes
+//! over is stopped and fails with a resource-exceeded error, which isn't
+//! retried, instead of taking the worker down with it. Plugins are also
+//! interrupted once past their type's timeout (see [`crate::timeout`]),
+//! since the timeout alone can't stop a computation already running on a CPU
+//! pool thread; commands are killed by it.
+
+use crate::per_type_setting;
+use anyhow::{bail, Result};
+use service_config::Config;
+use std::collections::HashMap;
+use std::io;
+use std::time::Duration;
+
+/// What one job may use
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JobLimits {
+    /// Bytes of memory
+    pub memory: Option<usize>,
+    /// Wall-clock time
+    pub time: Option<Duration>,
+}
+
+/// How much memory jobs may use
+#[derive(Debug, Clone)]
+pub struct MemoryLimits {
+    /// MiB for types without their own entry; 0 for no limit
+    default_mb: usize,
+    per_type_mb: HashMap<String, usize>,
+}
+
+impl MemoryLimits {
+    /// Read `WORKER_JOB_MEMORY_MB` (default 0) and `WORKER_TYPE_MEMORY_LIMITS`
+    /// (`job_type=mb` pairs). 0 means no limit.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            default_mb: config.parse_or("WORKER_JOB_MEMORY_MB", 0),
+            per_type_mb: per_type_setting(config, "WORKER_TYPE_MEMORY_LIMITS")?,
+        })
+    }
+
+    /// Bytes a job of `job_type` may use, if limited
+    pub fn limit(&self, job_type: &str) -> Option<usize> {
+        let mb = self
+            .per_type_mb
+            .get(job_type)
+            .copied()
+            .unwrap_or(self.default_mb);
+        (mb > 0).then(|| mb.saturating_mul(1024 * 1024))
+    }
+
+    /// Reject entries for types whose handlers can't hold to a limit, i.e.
+    /// any not among `limited`
+    pub fn check<'a>(&self, limited: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let limited: Vec<&str> = limited.into_iter().collect();
+        if let Some(job_type) = self
+            .per_type_mb
+            .keys()
+            .find(|t| !limited.contains(&t.as_str()))
+        {
+            bail!(
+                "WORKER_TYPE_MEMORY_LIMITS names {}, whose jobs can't be held to a memory limit",
+                job_type
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The error failing a job that went over its memory limit
+pub fn memory_exceeded(limit: usize) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::OutOfMemory,
+        format!(
+            "Resource exceeded: job went over its memory limit of {} MiB",
+            limit / (1024 * 1024)
+        ),
+    )
+}
@@ -0,0 +1,91 @@
+//! Logging job outcomes.
+//!
+//! Every failure is logged, but logging every success would swamp the logs
+//! (and cost throughput) at the rates math jobs run at, so only 1 in
+//! `WORKER_LOG_SUCCESS_SAMPLE` of them is (default 100; 1 logs all of them,
+//! 0 none). Either way the line carries the job's ID, type, attempt, queue
+//! wait and run time as fields, for `LOG_FORMAT=json` to pick up.
+
+use crate::context::JobContext;
+use chrono::{DateTime, Utc};
+use service_config::Config;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{error, info};
+
+/// Logs failed jobs and a sample of completed ones
+#[derive(Debug)]
+pub struct ResultLog {
+    /// Log every this many successes; 0 for none
+    success_sample: u64,
+    successes: AtomicU64,
+}
+
+impl ResultLog {
+    /// Read `WORKER_LOG_SUCCESS_SAMPLE`
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            success_sample: config.parse_or("WORKER_LOG_SUCCESS_SAMPLE", 100),
+            successes: AtomicU64::new(0),
+        }
+    }
+
+    /// Which successes are logged, for logging
+    pub fn describe(&self) -> String {
+        match self.success_sample {
+            0 => "failures only".to_string(),
+            1 => "every job".to_string(),
+            n => format!("failures and 1 in {} successes", n),
+        }
+    }
+
+    /// Log how the job in `context`, started at `started_at`, ended
+    pub fn record<T>(
+        &self,
+        context: &JobContext,
+        started_at: DateTime<Utc>,
+        result: &io::Result<T>,
+    ) {
+        let elapsed_ms = (Utc::now() - started_at).num_milliseconds().max(0);
+        let queue_wait_ms = context
+            .fetch_latency()
+            .map(|latency| latency.as_millis() as u64);
+        match result {
+            Ok(_) => {
+                if !self.sampled() {
+                    return;
+                }
+                info!(
+                    job_id = %context.jid,
+                    job_type = %context.job_type,
+                    attempt = context.attempt,
+                    elapsed_ms,
+                    queue_wait_ms,
+                    sample = self.success_sample,
+                    "Job completed"
+                );
+            }
+            Err(e) => error!(
+                job_id = %context.jid,
+                job_type = %context.job_type,
+                attempt = context.attempt,
+                elapsed_ms,
+                queue_wait_ms,
+                error = %e,
+                "Job failed: {:#}",
+                e
+            ),
+        }
+    }
+
+    /// Whether to log the next success
+    fn sampled(&self) -> bool {
+        match self.success_sample {
+            0 => false,
+            n => self
+                .successes
+                .fetch_add(1, Ordering::Relaxed)
+                .is_multiple_of(n),
+        }
+    }
+}
@@ -0,0 +1,204 @@
+//! Worker-managed retries for jobs submitted with a backoff policy.
+//!
+//! Faktory retries failed jobs on its own fixed schedule. Jobs submitted with
+//! a `backoff` instead carry a [`RetryPolicy`] and `retry: -1`; when one fails
+//! the worker pushes it again (same job id) with the requested delay, and the
+//! final failure goes straight to Faktory's dead set.
+//!
+//! Job types can get a policy of their own from `WORKER_RETRY_POLICIES`,
+//! used for their jobs that weren't submitted with one: a backoff, retried
+//! the same way, or `none` to give up after the first failure. Failures from
+//! invalid input (unparseable arguments, division by zero) are never retried,
+//! since every attempt would fail the same way. Jobs the worker gives up on
+//! are acknowledged rather than failed back to Faktory, which would
+//! otherwise retry them on its own schedule.
+
+use crate::per_type_setting;
+use crate::producer::Producer;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use faktory::Job;
+use job_types::{BackoffStrategy, RetryPolicy};
+use result_store::{DeadJob, JobFailure};
+use service_config::Config;
+use std::collections::HashMap;
+use std::io;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Custom job field counting worker-scheduled retries so far
+const RETRY_COUNT_FIELD: &str = "retry_count";
+
+/// Longest delay the worker will schedule a retry for
+const MAX_DELAY: Duration = Duration::from_secs(24 * 3600);
+
+/// Faktory's retry count for jobs that don't set one
+const FAKTORY_DEFAULT_RETRIES: isize = 25;
+
+/// How a failed job is retried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Retry {
+    /// Failed back to Faktory, which retries on its own schedule
+    Faktory,
+    /// Pushed again by the worker after the policy's delay
+    Backoff(RetryPolicy),
+    /// Given up on after this attempt
+    Never,
+}
+
+impl Retry {
+    /// Whether this execution is the job's last chance before it's given up
+    /// on
+    pub fn is_last_attempt(&self, job: &Job) -> bool {
+        match self {
+            Retry::Faktory => {
+                let max_retries = job.retry.unwrap_or(FAKTORY_DEFAULT_RETRIES);
+                let failed_attempts = job
+                    .failure()
+                    .as_ref()
+                    .map_or(0, |failure| failure.retry_count as isize + 1);
+                failed_attempts >= max_retries
+            }
+            Retry::Backoff(policy) => retries_so_far(job) >= policy.max_retries,
+            Retry::Never => true,
+        }
+    }
+}
+
+impl FromStr for Retry {
+    type Err = String;
+
+    /// `faktory`, `none`, or `{fixed|exponential}:{delay_secs}:{max_retries}`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let expected = || {
+            "expected faktory, none or {fixed|exponential}:{delay_secs}:{max_retries}".to_string()
+        };
+        match s {
+            "faktory" => return Ok(Retry::Faktory),
+            "none" => return Ok(Retry::Never),
+            _ => {}
+        }
+        let mut parts = s.split(':');
+        let strategy = match parts.next() {
+            Some("fixed") => BackoffStrategy::Fixed,
+            Some("exponential") => BackoffStrategy::Exponential,
+            _ => return Err(expected()),
+        };
+        let (Some(delay), Some(max_retries), None) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(expected());
+        };
+        Ok(Retry::Backoff(RetryPolicy {
+            strategy,
+            delay_seconds: delay.parse().map_err(|_| expected())?,
+            max_retries: max_retries.parse().map_err(|_| expected())?,
+        }))
+    }
+}
+
+/// Retry policies of job types
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicies {
+    per_type: HashMap<String, Retry>,
+}
+
+impl RetryPolicies {
+    /// Read `WORKER_RETRY_POLICIES` (`job_type=policy` pairs, e.g.
+    /// `math_divide=none,math_add=exponential:5:10`; other types are retried
+    /// by Faktory)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            per_type: per_type_setting(config, "WORKER_RETRY_POLICIES")?,
+        })
+    }
+
+    /// How to retry `job` after it failed with `error`
+    pub fn for_failure(&self, job: &Job, error: &io::Error) -> Retry {
+        // Neither would go any differently next time
+        if matches!(
+            error.kind(),
+            io::ErrorKind::InvalidInput | io::ErrorKind::OutOfMemory
+        ) {
+            return Retry::Never;
+        }
+        if let Some(policy) = policy(job) {
+            return Retry::Backoff(policy);
+        }
+        self.per_type
+            .get(job.kind())
+            .copied()
+            .unwrap_or(Retry::Faktory)
+    }
+}
+
+/// Retry policy requested for a job, if any
+pub fn policy(job: &Job) -> Option<RetryPolicy> {
+    job.custom
+        .get(RetryPolicy::FIELD)
+        .and_then(|v| serde_json::from_value(v.clone()).ok())
+}
+
+fn retries_so_far(job: &Job) -> u32 {
+    job.custom
+        .get(RETRY_COUNT_FIELD)
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0) as u32
+}
+
+/// Push the job again per its policy. Returns `false` once the policy's
+/// retries are used up.
+pub async fn schedule_retry(producer: &Producer, job: &Job, policy: RetryPolicy) -> Result<bool> {
+    let retries = retries_so_far(job);
+    if retries >= policy.max_retries {
+        return Ok(false);
+    }
+
+    let delay = policy.delay(retries, MAX_DELAY);
+    let mut retry = job.clone();
+    retry.at = Some(Utc::now() + chrono::Duration::from_std(delay)?);
+    retry
+        .custom
+        .insert(RETRY_COUNT_FIELD.to_string(), (retries + 1).into());
+    producer.enqueue(retry).await?;
+    Ok(true)
+}
+
+/// Whether Faktory retries the job if this attempt fails it, rather than
+/// moving it to the dead set or discarding it
+pub fn faktory_would_retry(job: &Job) -> bool {
+    job.retry.unwrap_or(FAKTORY_DEFAULT_RETRIES) > 0
+}
+
+/// How many times the job has run, counting this execution
+pub fn attempts(job: &Job) -> u32 {
+    if policy(job).is_some() || job.custom.contains_key(RETRY_COUNT_FIELD) {
+        return retries_so_far(job) + 1;
+    }
+    job.failure()
+        .as_ref()
+        .map_or(1, |failure| failure.retry_count as u32 + 2)
+}
+
+/// Record of a job that failed its last attempt, with the `errors` of its
+/// earlier attempts. The stored job has its retry state cleared, so pushing
+/// it again gives it a full set of retries.
+pub fn dead_job(job: &Job, error: &str, errors: Vec<JobFailure>) -> Result<DeadJob> {
+    let mut fresh = job.clone();
+    fresh.at = None;
+    fresh.custom.remove(RETRY_COUNT_FIELD);
+    let mut value = serde_json::to_value(&fresh).context("Failed to serialize job")?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("failure");
+    }
+
+    Ok(DeadJob {
+        job_id: job.id().to_string(),
+        job_type: job.kind().to_string(),
+        queue: job.queue.clone(),
+        job: value,
+        error: error.to_string(),
+        errors,
+        attempts: attempts(job),
+        failed_at: Utc::now(),
+    })
+}
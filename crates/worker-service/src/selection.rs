@@ -0,0 +1,86 @@
+//! Which job types a worker handles.
+//!
+//! `WORKER_JOB_TYPES` restricts a worker to some of its handlers, e.g.
+//! `math_factorial,math_fibonacci` for a pool of CPU-heavy machines, so
+//! different kinds of job can run on differently sized workers. Handlers for
+//! other types aren't registered (nor their plugins loaded). Unless
+//! `WORKER_QUEUES` says otherwise, the worker consumes only the queues those
+//! types are routed to by `QUEUE_DEFAULTS`, the API's `job_type=queue`
+//! routing, falling back to `default` like the API does.
+//!
+//! Faktory fails jobs fetched by a worker with no handler for them, so pools
+//! serving different types need their own queues.
+
+use anyhow::{bail, Result};
+use service_config::Config;
+use std::collections::HashMap;
+
+/// Queue jobs go to when `QUEUE_DEFAULTS` doesn't route their type
+const DEFAULT_QUEUE: &str = "default";
+
+/// The job types this worker registers handlers for
+#[derive(Debug, Clone, Default)]
+pub struct JobTypeSelection {
+    /// `None` for every type
+    job_types: Option<Vec<String>>,
+}
+
+impl JobTypeSelection {
+    /// Read `WORKER_JOB_TYPES` (comma-separated; unset for all)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let Some(value) = config.string("WORKER_JOB_TYPES") else {
+            return Ok(Self::default());
+        };
+        let mut job_types: Vec<String> = Vec::new();
+        for job_type in value.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            if !job_types.iter().any(|t| t == job_type) {
+                job_types.push(job_type.to_string());
+            }
+        }
+        if job_types.is_empty() {
+            bail!("WORKER_JOB_TYPES must name at least one job type");
+        }
+        Ok(Self {
+            job_types: Some(job_types),
+        })
+    }
+
+    /// Reject selected types with no handler among `available`
+    pub fn check<'a>(&self, available: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        let Some(job_types) = &self.job_types else {
+            return Ok(());
+        };
+        let available: Vec<&str> = available.into_iter().collect();
+        if let Some(unknown) = job_types.iter().find(|t| !available.contains(&t.as_str())) {
+            bail!("WORKER_JOB_TYPES names {}, which has no handler", unknown);
+        }
+        Ok(())
+    }
+
+    pub fn contains(&self, job_type: &str) -> bool {
+        self.job_types
+            .as_ref()
+            .is_none_or(|job_types| job_types.iter().any(|t| t == job_type))
+    }
+
+    /// The queues the selected types are routed to, in `WORKER_JOB_TYPES`
+    /// order; `None` when every type is handled
+    pub fn queues(&self, routes: &HashMap<String, String>) -> Option<Vec<String>> {
+        let job_types = self.job_types.as_ref()?;
+        let mut queues: Vec<String> = Vec::new();
+        for job_type in job_types {
+            let queue = routes.get(job_type).map_or(DEFAULT_QUEUE, String::as_str);
+            if !queues.iter().any(|q| q == queue) {
+                queues.push(queue.to_string());
+            }
+        }
+        Some(queues)
+    }
+
+    /// The selected types, for logging
+    pub fn describe(&self) -> Option<String> {
+        self.job_types
+            .as_ref()
+            .map(|job_types| job_types.join(", "))
+    }
+}
@@ -0,0 +1,34 @@
+//! Signals that shut the worker down.
+//!
+//! On Unix, `SIGTERM` (sent by orchestrators) and `SIGINT` (Ctrl+C) both
+//! start a graceful shutdown. Other platforms have no Unix signals, so there
+//! Ctrl+C does, which is enough to run the worker locally on Windows.
+
+use tracing::warn;
+
+/// Resolves once a signal asks the worker to shut down
+#[cfg(unix)]
+pub async fn signalled() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Failed to setup SIGTERM handler");
+    let mut sigint = signal(SignalKind::interrupt()).expect("Failed to setup SIGINT handler");
+
+    tokio::select! {
+        _ = sigterm.recv() => {
+            warn!("Received SIGTERM, initiating graceful shutdown...");
+        }
+        _ = sigint.recv() => {
+            warn!("Received SIGINT (Ctrl+C), initiating graceful shutdown...");
+        }
+    }
+}
+
+/// Resolves once a signal asks the worker to shut down
+#[cfg(not(unix))]
+pub async fn signalled() {
+    tokio::signal::ctrl_c()
+        .await
+        .expect("Failed to setup Ctrl+C handler");
+    warn!("Received Ctrl+C, initiating graceful shutdown...");
+}
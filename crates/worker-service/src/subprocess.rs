@@ -0,0 +1,219 @@
+//! Job handlers that run an external command.
+//!
+//! `WORKER_COMMANDS` maps job types to commands, e.g.
+//! `churn_score=python3 /opt/models/churn.py`, so logic written in any
+//! language runs through the same queues. Each job spawns the command (no
+//! shell; arguments are split on whitespace) with the job's first argument
+//! as JSON on stdin, and `JOB_ID`, `JOB_TYPE` and `JOB_ATTEMPT` in its
+//! environment so it can tell retries apart:
+//!
+//! - exit code 0 completes the job with stdout, parsed as JSON if it is, or
+//!   as a string otherwise;
+//! - exit code 65 (`EX_DATAERR`) fails it as invalid input, which is never
+//!   retried;
+//! - any other exit code, or being killed by a signal, fails it with the end
+//!   of stderr as the error, retried like any other failure.
+//!
+//! A job that's abandoned (e.g. on a timeout) kills its process, and so does
+//! one whose process goes over its type's memory limit (see
+//! [`crate::resources`]), which fails it as resource-exceeded. Memory is
+//! measured as the process's resident set, sampled from `/proc`, so it's
+//! only enforced on Linux.
+
+use crate::context::JobContext;
+use crate::resources::memory_exceeded;
+use anyhow::{Context, Result};
+use std::fmt;
+use std::io;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+/// Exit code that marks the input as invalid (`EX_DATAERR` in sysexits.h)
+const INVALID_INPUT_EXIT_CODE: i32 = 65;
+
+/// Most bytes of stderr kept in a failed job's error
+const MAX_ERROR_BYTES: usize = 2048;
+
+/// How often a memory-limited process's memory is checked
+const MEMORY_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A `WORKER_COMMANDS` value: the program and its arguments
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    program: String,
+    args: Vec<String>,
+}
+
+impl FromStr for CommandSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut words = s.split_whitespace().map(String::from);
+        let program = words.next().ok_or("expected a command")?;
+        Ok(Self {
+            program,
+            args: words.collect(),
+        })
+    }
+}
+
+impl fmt::Display for CommandSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.program)?;
+        for arg in &self.args {
+            write!(f, " {}", arg)?;
+        }
+        Ok(())
+    }
+}
+
+impl CommandSpec {
+    /// Run the command on `input` for the job in `context`, killing it if it
+    /// uses more than `memory_limit` bytes. `Err` is a failure of the job.
+    pub async fn run(
+        &self,
+        input: &serde_json::Value,
+        context: &JobContext,
+        memory_limit: Option<usize>,
+    ) -> io::Result<serde_json::Value> {
+        self.try_run(input, context, memory_limit)
+            .await
+            .map_err(|e| {
+                // Keep invalid input and exceeded limits apart so they aren't
+                // retried
+                let kind = match e.downcast_ref::<io::Error>().map(io::Error::kind) {
+                    Some(kind @ (io::ErrorKind::InvalidInput | io::ErrorKind::OutOfMemory)) => kind,
+                    _ => io::ErrorKind::Other,
+                };
+                io::Error::new(kind, format!("{:#}", e))
+            })
+    }
+
+    async fn try_run(
+        &self,
+        input: &serde_json::Value,
+        context: &JobContext,
+        memory_limit: Option<usize>,
+    ) -> Result<serde_json::Value> {
+        let input = serde_json::to_vec(input).context("Failed to serialize command input")?;
+        let mut child = Command::new(&self.program)
+            .args(&self.args)
+            .env("JOB_ID", &context.jid)
+            .env("JOB_TYPE", &context.job_type)
+            .env("JOB_ATTEMPT", context.attempt.to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to start {}", self.program))?;
+
+        // Written in the background so a command that writes a lot before
+        // reading its input can't deadlock against us
+        let mut stdin = child.stdin.take().context("Command stdin not piped")?;
+        let write = tokio::spawn(async move {
+            stdin.write_all(&input).await?;
+            stdin.shutdown().await
+        });
+        let stdout = tokio::spawn(read_all(child.stdout.take()));
+        let stderr = tokio::spawn(read_all(child.stderr.take()));
+
+        let pid = child.id();
+        let status = tokio::select! {
+            status = child.wait() => {
+                status.with_context(|| format!("Failed to run {}", self.program))?
+            }
+            limit = exceeds_memory(pid, memory_limit) => {
+                // Dropping the child kills it
+                return Err(memory_exceeded(limit).into());
+            }
+        };
+        // A command that exits without reading its input is its business
+        let _ = write.await;
+        let stdout = stdout.await?.context("Failed to read command output")?;
+        let stderr = stderr.await?.context("Failed to read command output")?;
+
+        match status.code() {
+            Some(0) => {
+                let stdout = String::from_utf8_lossy(&stdout);
+                let stdout = stdout.trim();
+                Ok(serde_json::from_str(stdout)
+                    .unwrap_or_else(|_| serde_json::Value::String(stdout.to_string())))
+            }
+            Some(INVALID_INPUT_EXIT_CODE) => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                failure_message(&self.program, "rejected its input", &stderr),
+            )
+            .into()),
+            Some(code) => Err(anyhow::anyhow!(failure_message(
+                &self.program,
+                &format!("exited with code {}", code),
+                &stderr,
+            ))),
+            None => Err(anyhow::anyhow!(failure_message(
+                &self.program,
+                "was killed by a signal",
+                &stderr,
+            ))),
+        }
+    }
+}
+
+/// Everything a child writes to one of its pipes
+async fn read_all(pipe: Option<impl AsyncRead + Unpin>) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    if let Some(mut pipe) = pipe {
+        pipe.read_to_end(&mut output).await?;
+    }
+    Ok(output)
+}
+
+/// Resolves with the limit once process `pid` uses more than `limit` bytes;
+/// never without a limit or a way to measure
+async fn exceeds_memory(pid: Option<u32>, limit: Option<usize>) -> usize {
+    let (Some(pid), Some(limit)) = (pid, limit) else {
+        return std::future::pending().await;
+    };
+    let mut interval = tokio::time::interval(MEMORY_CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        match resident_bytes(pid).await {
+            Some(bytes) if bytes > limit => return limit,
+            Some(_) => {}
+            None => return std::future::pending().await,
+        }
+    }
+}
+
+/// A process's resident set size, from `/proc/{pid}/status`
+async fn resident_bytes(pid: u32) -> Option<usize> {
+    let status = tokio::fs::read_to_string(format!("/proc/{}/status", pid))
+        .await
+        .ok()?;
+    let kb = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+    Some(kb * 1024)
+}
+
+/// `{program} {what}: {end of stderr}`
+fn failure_message(program: &str, what: &str, stderr: &[u8]) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    if stderr.is_empty() {
+        return format!("{} {}", program, what);
+    }
+    let mut start = stderr.len().saturating_sub(MAX_ERROR_BYTES);
+    while !stderr.is_char_boundary(start) {
+        start += 1;
+    }
+    format!("{} {}: {}", program, what, &stderr[start..])
+}
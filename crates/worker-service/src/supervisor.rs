@@ -0,0 +1,372 @@
+//! Several worker instances in one process.
+//!
+//! `WORKER_INSTANCES` (default 1) runs that many independent workers in the
+//! process, each with its own Faktory connection, worker id and
+//! `WORKER_CONCURRENCY` fetchers, sharing handlers, middleware, metrics and
+//! health. One instance losing its connection or wedging doesn't hold up the
+//! others, without the overhead of a container per worker.
+//!
+//! A supervisor watches them. An instance whose connection fails reconnects
+//! (see [`crate::reconnect`]), and one that panics is replaced. With
+//! `WORKER_INSTANCE_STALL_SECS` set, an instance that has jobs in flight but
+//! hasn't started or finished one for that long counts as wedged and is
+//! replaced too; the jobs it was running are left for Faktory to hand out
+//! again once their reservation expires, so the stall must be longer than
+//! any job's timeout. `worker_instance_restarts_total` counts replacements by
+//! reason and `worker_instances_connected` the instances fetching jobs.
+//!
+//! With `WORKER_BULK_QUEUES` set, each instance is itself a few Faktory
+//! workers, one per group of fetchers (see [`crate::priority`]), supervised
+//! like separate instances.
+
+use crate::health::Health;
+use crate::metrics::Metrics;
+use crate::middleware::JobFuture;
+use crate::priority::Fetchers;
+use crate::reconnect::Reconnect;
+use crate::{shutdown_signalled, JobFn};
+use anyhow::{bail, Result};
+use faktory::{StopDetails, Worker, WorkerBuilder, WorkerId};
+use service_config::Config;
+use service_tls::faktory::FaktoryConfig;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{error, info, warn};
+
+/// How often the supervisor checks on its instances
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How many instances run and when one counts as wedged
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    pub instances: usize,
+    /// `None` to never replace a wedged instance
+    pub stall_timeout: Option<Duration>,
+}
+
+impl SupervisorConfig {
+    /// Read `WORKER_INSTANCES` (default 1) and `WORKER_INSTANCE_STALL_SECS`
+    /// (default 0, off)
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let instances = config.parse_or("WORKER_INSTANCES", 1usize);
+        let stall_secs = config.parse_or("WORKER_INSTANCE_STALL_SECS", 0u64);
+        if instances == 0 {
+            bail!("WORKER_INSTANCES must be at least 1");
+        }
+        Ok(Self {
+            instances,
+            stall_timeout: (stall_secs > 0).then(|| Duration::from_secs(stall_secs)),
+        })
+    }
+}
+
+/// What an instance is doing, for spotting one that's wedged
+struct Activity {
+    in_flight: AtomicUsize,
+    /// When a job last started or finished
+    last_progress: Mutex<Instant>,
+}
+
+impl Activity {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            in_flight: AtomicUsize::new(0),
+            last_progress: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// `job`, counted while it runs
+    fn track(self: &Arc<Self>, job: JobFuture) -> JobFuture {
+        let activity = self.clone();
+        Box::pin(async move {
+            activity.in_flight.fetch_add(1, Ordering::Relaxed);
+            *activity.last_progress.lock().unwrap() = Instant::now();
+            let _running = Running(&activity);
+            job.await
+        })
+    }
+
+    /// Whether jobs are in flight but none has started or finished within
+    /// `timeout`
+    fn stalled(&self, timeout: Duration) -> bool {
+        self.in_flight.load(Ordering::Relaxed) > 0
+            && self.last_progress.lock().unwrap().elapsed() > timeout
+    }
+}
+
+/// Counts a job as in flight until it finishes, however it ends
+struct Running<'a>(&'a Activity);
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+        *self.0.last_progress.lock().unwrap() = Instant::now();
+    }
+}
+
+/// Instances connected at startup, ready to be supervised
+pub struct Connected(Vec<(Arc<Activity>, Worker<io::Error>)>);
+
+/// Counts an instance as connected until it stops fetching, however it
+/// stops
+struct Fetching<'a>(&'a Supervisor);
+
+impl<'a> Fetching<'a> {
+    fn start(supervisor: &'a Supervisor) -> Self {
+        supervisor.health.instance_connected();
+        supervisor.metrics.instance_connected(true);
+        Self(supervisor)
+    }
+}
+
+impl Drop for Fetching<'_> {
+    fn drop(&mut self) {
+        self.0.health.instance_disconnected();
+        self.0.metrics.instance_connected(false);
+    }
+}
+
+/// An instance being supervised
+struct Instance {
+    activity: Arc<Activity>,
+    task: JoinHandle<Option<StopDetails>>,
+}
+
+/// Builds, connects and supervises the worker instances
+pub struct Supervisor {
+    config: SupervisorConfig,
+    faktory: FaktoryConfig,
+    /// Shared by the instances, suffixed with their number when there are
+    /// several
+    worker_id: String,
+    labels: Vec<String>,
+    /// Each instance's fetchers, a Faktory worker per group
+    fetchers: Vec<Fetchers>,
+    handlers: Vec<(String, JobFn)>,
+    reconnect: Reconnect,
+    /// Set by a shutdown signal
+    shutdown: watch::Receiver<bool>,
+    /// Set once in-flight jobs have drained after a signal
+    drained: watch::Receiver<bool>,
+    health: Arc<Health>,
+    metrics: Arc<Metrics>,
+}
+
+impl Supervisor {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        config: SupervisorConfig,
+        faktory: FaktoryConfig,
+        worker_id: String,
+        labels: Vec<String>,
+        fetchers: Vec<Fetchers>,
+        handlers: Vec<(String, JobFn)>,
+        reconnect: Reconnect,
+        shutdown: watch::Receiver<bool>,
+        drained: watch::Receiver<bool>,
+        health: Arc<Health>,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            config,
+            faktory,
+            worker_id,
+            labels,
+            fetchers,
+            handlers,
+            reconnect,
+            shutdown,
+            drained,
+            health,
+            metrics,
+        }
+    }
+
+    /// The instances, for logging
+    pub fn describe(&self) -> String {
+        let concurrency: usize = self.fetchers.iter().map(|f| f.concurrency).sum();
+        let mut described = format!("{} of {} jobs each", self.config.instances, concurrency);
+        if let Some(timeout) = self.config.stall_timeout {
+            described.push_str(&format!(
+                ", replaced after stalling for {}s",
+                timeout.as_secs()
+            ));
+        }
+        described
+    }
+
+    /// Faktory workers across the instances
+    pub fn workers(&self) -> usize {
+        self.config.instances * self.fetchers.len()
+    }
+
+    /// The fetchers of Faktory worker `index`
+    fn fetchers(&self, index: usize) -> &Fetchers {
+        &self.fetchers[index % self.fetchers.len()]
+    }
+
+    fn worker_id(&self, index: usize) -> String {
+        if self.workers() == 1 {
+            self.worker_id.clone()
+        } else {
+            format!("{}-{}", self.worker_id, index)
+        }
+    }
+
+    /// Connect Faktory worker `index`. A signal drains in-flight jobs for up to
+    /// the shutdown timeout before it stops, and Faktory is sent a FAIL for
+    /// any abandoned. A "terminate" sent from the Faktory UI fails them right
+    /// away. "Quiet" only stops fetching; the instance keeps running until
+    /// one of the two.
+    async fn connect(
+        &self,
+        index: usize,
+        activity: &Arc<Activity>,
+    ) -> Result<Worker<io::Error>, faktory::Error> {
+        let mut builder = WorkerBuilder::default()
+            .hostname("worker-service".to_string())
+            .wid(WorkerId::new(self.worker_id(index)))
+            .add_to_labels(self.labels.clone())
+            .workers(self.fetchers(index).concurrency); // High concurrency masks network fetch latency
+        for (job_type, handler) in &self.handlers {
+            let handler = handler.clone();
+            let activity = activity.clone();
+            builder =
+                builder.register_fn(job_type.as_str(), move |job| activity.track(handler(job)));
+        }
+        let mut drained = self.drained.clone();
+        let builder = builder.with_graceful_shutdown(async move {
+            let _ = drained.wait_for(|drained| *drained).await;
+        });
+        self.faktory.worker(builder).await
+    }
+
+    /// Connect every instance; failing to is fatal
+    pub async fn start(&self) -> Result<Connected> {
+        let mut workers = Vec::with_capacity(self.workers());
+        for index in 0..self.workers() {
+            let activity = Activity::new();
+            let worker = self.connect(index, &activity).await?;
+            workers.push((activity, worker));
+        }
+        Ok(Connected(workers))
+    }
+
+    /// Run Faktory worker `index` on `worker` (connecting a new one if `None`)
+    /// until it's told to stop, reconnecting whenever its connection fails.
+    /// `None` if a signal came while it was reconnecting.
+    async fn run_instance(
+        self: Arc<Self>,
+        index: usize,
+        activity: Arc<Activity>,
+        mut worker: Option<Worker<io::Error>>,
+    ) -> Option<StopDetails> {
+        loop {
+            let mut connected = match worker.take() {
+                Some(connected) => connected,
+                None => {
+                    let connect = || self.connect(index, &activity);
+                    let stop = shutdown_signalled(self.shutdown.clone());
+                    let connected = self.reconnect.connect(connect, stop).await?;
+                    info!("Worker {} reconnected to Faktory", self.worker_id(index));
+                    connected
+                }
+            };
+            let fetching = Fetching::start(&self);
+            let run = connected.run(&self.fetchers(index).queues).await;
+            // Nothing is fetched from here on; `/health` reports it
+            drop(fetching);
+            match run {
+                Ok(details) => return Some(details),
+                Err(e) => {
+                    error!(
+                        "Worker {} lost connection to Faktory: {:#}",
+                        self.worker_id(index),
+                        e
+                    );
+                    // Dropping a worker says goodbye over its connection,
+                    // which panics once the connection is gone. Outages are
+                    // rare enough to leak it instead.
+                    let _ = std::mem::ManuallyDrop::new(connected);
+                }
+            }
+        }
+    }
+
+    /// Run the connected instances until every one has stopped, replacing
+    /// any that panic or wedge. Returns how each stopped.
+    pub async fn supervise(self: Arc<Self>, connected: Connected) -> Vec<StopDetails> {
+        let mut instances: Vec<Option<Instance>> = connected
+            .0
+            .into_iter()
+            .enumerate()
+            .map(|(index, (activity, worker))| {
+                Some(self.clone().spawn(index, activity, Some(worker)))
+            })
+            .collect();
+        let mut stops = Vec::new();
+        let mut ticker = tokio::time::interval(SUPERVISE_INTERVAL);
+
+        while instances.iter().any(Option::is_some) {
+            ticker.tick().await;
+            let shutting_down = *self.shutdown.borrow();
+            for (index, slot) in instances.iter_mut().enumerate() {
+                let Some(instance) = slot else {
+                    continue;
+                };
+                if instance.task.is_finished() {
+                    let Some(instance) = slot.take() else {
+                        continue;
+                    };
+                    match instance.task.await {
+                        Ok(Some(details)) => stops.push(details),
+                        Ok(None) => {}
+                        Err(e) if shutting_down => {
+                            error!("Worker {} failed: {}", self.worker_id(index), e)
+                        }
+                        Err(e) => {
+                            error!(
+                                "Worker {} failed, replacing it: {}",
+                                self.worker_id(index),
+                                e
+                            );
+                            self.metrics.instance_restart("panic");
+                            *slot = Some(self.clone().spawn(index, Activity::new(), None));
+                        }
+                    }
+                    continue;
+                }
+                let stalled = self
+                    .config
+                    .stall_timeout
+                    .is_some_and(|timeout| instance.activity.stalled(timeout));
+                if stalled && !shutting_down {
+                    warn!(
+                        "Worker {} has made no progress on its {} jobs for {}s, replacing it",
+                        self.worker_id(index),
+                        instance.activity.in_flight.load(Ordering::Relaxed),
+                        self.config.stall_timeout.unwrap_or_default().as_secs()
+                    );
+                    instance.task.abort();
+                    self.metrics.instance_restart("stalled");
+                    *slot = Some(self.clone().spawn(index, Activity::new(), None));
+                }
+            }
+        }
+        stops
+    }
+
+    fn spawn(
+        self: Arc<Self>,
+        index: usize,
+        activity: Arc<Activity>,
+        worker: Option<Worker<io::Error>>,
+    ) -> Instance {
+        let task = tokio::spawn(self.run_instance(index, activity.clone(), worker));
+        Instance { activity, task }
+    }
+}
@@ -0,0 +1,91 @@
+//! Execution timeouts.
+//!
+//! Each job gets `WORKER_JOB_TIMEOUT_SECS`, or its type's entry in
+//! `WORKER_TYPE_TIMEOUTS`, to finish. The default matches Faktory's 30 minute
+//! reservation, after which the server hands the job to another worker
+//! anyway. A job that runs over is abandoned: its handler is dropped,
+//! `worker_jobs_timed_out_total` counts it, and it fails with a timeout error
+//! as if its handler had returned one, so its result, retries, webhook and
+//! dead-lettering go the way of any other failure.
+
+use crate::metrics::Metrics;
+use crate::middleware::{Middleware, Next};
+use crate::{per_type_setting, JobFailures};
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use faktory::Job;
+use service_config::Config;
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// How long jobs may run
+#[derive(Debug, Clone)]
+pub struct TimeoutConfig {
+    /// Seconds for types without their own entry; 0 for no limit
+    default_secs: u64,
+    per_type_secs: HashMap<String, u64>,
+}
+
+impl TimeoutConfig {
+    /// Read `WORKER_JOB_TIMEOUT_SECS` (default 1800) and
+    /// `WORKER_TYPE_TIMEOUTS` (`job_type=secs` pairs, e.g.
+    /// `webhook_delivery=60`). 0 means no timeout.
+    pub fn from_config(config: &Config) -> Result<Self> {
+        Ok(Self {
+            default_secs: config.parse_or("WORKER_JOB_TIMEOUT_SECS", 1800),
+            per_type_secs: per_type_setting(config, "WORKER_TYPE_TIMEOUTS")?,
+        })
+    }
+
+    /// Time a job of `job_type` may run, if limited
+    pub fn timeout(&self, job_type: &str) -> Option<Duration> {
+        let secs = self
+            .per_type_secs
+            .get(job_type)
+            .copied()
+            .unwrap_or(self.default_secs);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+}
+
+/// Fails jobs that run past their timeout
+pub struct JobTimeouts {
+    config: TimeoutConfig,
+    failures: JobFailures,
+    metrics: Arc<Metrics>,
+}
+
+impl JobTimeouts {
+    pub fn new(config: TimeoutConfig, failures: JobFailures, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            failures,
+            metrics,
+        }
+    }
+}
+
+#[async_trait]
+impl Middleware for JobTimeouts {
+    async fn handle(&self, job: &Job, next: Next<'_>) -> io::Result<()> {
+        let Some(limit) = self.config.timeout(job.kind()) else {
+            return next.run(job).await;
+        };
+        let started_at = Utc::now();
+        let Ok(result) = tokio::time::timeout(limit, next.run(job)).await else {
+            let message = format!("Job timed out after {}s", limit.as_secs());
+            warn!("{}", message);
+            self.metrics.job_timed_out(job.kind());
+            let error = io::Error::new(io::ErrorKind::TimedOut, message);
+            return self
+                .failures
+                .fail(job, next.context(), started_at, error)
+                .await;
+        };
+        result
+    }
+}
@@ -0,0 +1,130 @@
+//! Completion callbacks for jobs submitted with a `callback_url`.
+//!
+//! Notifications are delivered by a dedicated `webhook_delivery` job rather
+//! than inline, so a slow or failing endpoint doesn't hold up the job that
+//! produced the result and Faktory takes care of retrying delivery.
+
+use crate::producer::Producer;
+use anyhow::Context;
+use faktory::Job;
+use result_store::JobResult;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+/// Faktory job type for webhook deliveries
+pub const WEBHOOK_JOB_TYPE: &str = "webhook_delivery";
+
+/// Custom job field carrying the callback URL
+const CALLBACK_URL_FIELD: &str = "callback_url";
+
+/// Configuration for webhook delivery
+#[derive(Clone)]
+pub struct WebhookConfig {
+    /// How many times Faktory retries a failed delivery
+    pub max_retries: isize,
+    /// Timeout for each delivery attempt
+    pub timeout: Duration,
+}
+
+/// Arguments of a `webhook_delivery` job
+#[derive(Debug, Serialize, Deserialize)]
+struct WebhookDelivery {
+    url: String,
+    notification: JobResult,
+}
+
+/// Schedules and performs webhook deliveries
+pub struct WebhookNotifier {
+    config: WebhookConfig,
+    http: reqwest::Client,
+    producer: Arc<Producer>,
+}
+
+impl WebhookNotifier {
+    pub fn new(config: WebhookConfig, producer: Arc<Producer>) -> anyhow::Result<Self> {
+        let http = reqwest::Client::builder()
+            .timeout(config.timeout)
+            .build()
+            .context("Failed to build webhook HTTP client")?;
+        Ok(Self {
+            config,
+            http,
+            producer,
+        })
+    }
+
+    /// Callback URL requested for a job, if any
+    pub fn callback_url(job: &Job) -> Option<&str> {
+        job.custom.get(CALLBACK_URL_FIELD).and_then(|v| v.as_str())
+    }
+
+    /// Enqueue a delivery of `notification` to `url`
+    pub async fn schedule(&self, url: &str, notification: JobResult) -> anyhow::Result<()> {
+        let delivery = WebhookDelivery {
+            url: url.to_string(),
+            notification,
+        };
+        let mut job = Job::new(WEBHOOK_JOB_TYPE, vec![serde_json::to_value(&delivery)?]);
+        job.retry = Some(self.config.max_retries);
+
+        self.producer
+            .enqueue(job)
+            .await
+            .context("Failed to enqueue webhook delivery")
+    }
+
+    /// Handler for `webhook_delivery` jobs. Errors make Faktory retry the job.
+    pub async fn deliver(&self, job: &Job) -> io::Result<()> {
+        let delivery = parse_delivery(job)?;
+
+        let response = self
+            .http
+            .post(&delivery.url)
+            .json(&delivery.notification)
+            .send()
+            .await
+            .map_err(|e| {
+                warn!("Webhook delivery to {} failed: {}", delivery.url, e);
+                io::Error::other(format!("Webhook request failed: {}", e))
+            })?;
+
+        if !response.status().is_success() {
+            warn!(
+                "Webhook delivery to {} returned {}",
+                delivery.url,
+                response.status()
+            );
+            return Err(io::Error::other(format!(
+                "Webhook endpoint returned {}",
+                response.status()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What delivering a `webhook_delivery` job would do, for dry runs
+pub fn plan_delivery(job: &Job) -> io::Result<String> {
+    let delivery = parse_delivery(job)?;
+    Ok(format!(
+        "deliver the result of job {} to {}",
+        delivery.notification.job_id, delivery.url
+    ))
+}
+
+fn parse_delivery(job: &Job) -> io::Result<WebhookDelivery> {
+    let args = job
+        .args()
+        .first()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Job missing arguments"))?
+        .clone();
+    serde_json::from_value(args).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Failed to parse webhook delivery: {}", e),
+        )
+    })
+}